@@ -6,4 +6,6 @@ pub enum ErrorCode {
     AccessFailure,
     VulkanFailure,
     IO,
+    Cancelled,
+    NotFound,
 }
@@ -37,8 +37,7 @@ impl ApplicationHandler for Application<'_> {
         }
 
         // Handle event for the gui
-        if self.vulkan_context.is_some() {
-            let vulkan_context = self.vulkan_context.as_mut().unwrap();
+        if let Some(vulkan_context) = self.vulkan_context.as_mut() {
             if let Err(err) = vulkan_context.on_new_event_gui(self.delta_time) {
                 panic!("Failed to handle gui new events: {:?}", err);
             }
@@ -52,8 +51,7 @@ impl ApplicationHandler for Application<'_> {
         event: DeviceEvent,
     ) {
         // Handle event for the gui
-        if self.vulkan_context.is_some() {
-            let vulkan_context = self.vulkan_context.as_mut().unwrap();
+        if let Some(vulkan_context) = self.vulkan_context.as_mut() {
             if let Err(err) = vulkan_context.on_device_event_gui(&event) {
                 panic!("Failed to handle gui device event: {:?}", err);
             }
@@ -67,9 +65,9 @@ impl ApplicationHandler for Application<'_> {
         event: WindowEvent,
     ) {
         // Handle event for the gui
-        if self.window.is_some() && self.vulkan_context.is_some() {
-            let window = self.window.as_ref().unwrap();
-            let vulkan_context = self.vulkan_context.as_mut().unwrap();
+        if let (Some(window), Some(vulkan_context)) =
+            (self.window.as_ref(), self.vulkan_context.as_mut())
+        {
             if let Err(err) = vulkan_context.on_window_event_gui(window, &event) {
                 panic!("Failed to handle gui window event: {:?}", err);
             }
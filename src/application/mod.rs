@@ -19,11 +19,12 @@ use winit::{
     window::Window,
 };
 
-mod core;
+pub mod core;
 mod handler;
-mod parameters;
+pub mod parameters;
 mod pipelines;
-mod scene;
+pub mod raytracer;
+pub mod scene;
 mod vulkan;
 mod window;
 
@@ -58,7 +59,13 @@ impl Default for Application<'_> {
 impl Application<'_> {
     fn init(&mut self, event_loop: &ActiveEventLoop) -> Result<(), ErrorCode> {
         debug!("Initializing parameters...");
-        let parameters = ApplicationParameters::default();
+        let parameters = match ApplicationParameters::from_args(std::env::args().skip(1)) {
+            Ok(parameters) => parameters,
+            Err(err) => {
+                error!("Failed to read the command line arguments: {:?}", err);
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
 
         debug!("Initializing the window...");
         let window = match WindowContext::init(&parameters, event_loop) {
@@ -140,8 +147,28 @@ impl Application<'_> {
                 );
                 return Err(ErrorCode::Unknown);
             }
-            // Update the bvh
-            if scene.bvh_last_type != scene.bvh_type {
+            // Send the edited scene
+            if scene.is_gpu_data_dirty {
+                if let (Some(pipelines), Some(vulkan_context)) =
+                    (&mut self.pipelines, &self.vulkan_context)
+                {
+                    if let Err(err) = pipelines
+                        .raytracing_pipeline
+                        .update_scene_buffers(vulkan_context, scene)
+                    {
+                        error!(
+                            "Failed to update the scene buffers in the raytracing pipeline when updating the application: {:?}",
+                            err
+                        );
+                        return Err(ErrorCode::Unknown);
+                    }
+                    scene.is_gpu_data_dirty = false;
+                } else {
+                    warn!("The pipelines or the vulkan context are not initialized correctly...");
+                }
+            }
+            // Update the bvh once it has been built
+            if scene.bvh_last_type != scene.bvh_type && scene.bvhs.contains_key(&scene.bvh_type) {
                 if let Some(ref mut pipelines) = &mut self.pipelines {
                     if let Some(vulkan_context) = &self.vulkan_context {
                         if let Err(err) = pipelines
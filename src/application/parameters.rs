@@ -1,8 +1,20 @@
+use std::path::PathBuf;
+
+use log::error;
+
+use super::{core::error::ErrorCode, raytracer::integrator::Integrator};
+
 #[derive(Debug, Clone)]
 pub struct ApplicationParameters {
     pub window_title: String,
     pub window_width: u16,
     pub window_height: u16,
+    // Searched for the model files, see `AssetResolver`
+    pub asset_directories: Vec<PathBuf>,
+    pub should_use_asset_placeholders: bool,
+    pub integrator: Integrator,
+    // Equirectangular HDR or EXR image, see `EnvironmentMap`
+    pub environment_path: Option<PathBuf>,
 }
 
 impl Default for ApplicationParameters {
@@ -11,6 +23,45 @@ impl Default for ApplicationParameters {
             window_title: String::from("untitled"),
             window_width: 1600,
             window_height: 900,
+            asset_directories: Vec::new(),
+            should_use_asset_placeholders: true,
+            integrator: Integrator::default(),
+            environment_path: None,
+        }
+    }
+}
+
+impl ApplicationParameters {
+    /// Default parameters overridden by the command line, `--integrator <name>` selects one
+    /// of `Integrator::ALL` and `--environment <path>` loads an environment map
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, ErrorCode> {
+        let mut parameters = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--integrator" => {
+                    let name = args.next().unwrap_or_default();
+                    parameters.integrator = match Integrator::from_name(&name) {
+                        Some(integrator) => integrator,
+                        None => {
+                            let names = Integrator::ALL.map(|integrator| integrator.get_name());
+                            error!("Unknown integrator `{}', expected one of {:?}", name, names);
+                            return Err(ErrorCode::InitializationFailure);
+                        }
+                    };
+                }
+                "--environment" => match args.next() {
+                    Some(path) => parameters.environment_path = Some(PathBuf::from(path)),
+                    None => {
+                        error!("Missing the path of the environment map after `{}'", arg);
+                        return Err(ErrorCode::InitializationFailure);
+                    }
+                },
+                _ => {
+                    error!("Unknown command line argument `{}'", arg);
+                    return Err(ErrorCode::InitializationFailure);
+                }
+            }
         }
+        Ok(parameters)
     }
 }
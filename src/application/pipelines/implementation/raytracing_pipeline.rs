@@ -1,11 +1,15 @@
 // use std::time::Instant;
 
+use std::path::PathBuf;
+
 use ash::vk::{
     BufferUsageFlags, DescriptorBufferInfo, DescriptorImageInfo, DescriptorSet,
-    DescriptorSetLayoutCreateFlags, DescriptorType, ImageLayout, Pipeline, PipelineBindPoint,
-    PipelineLayout, PushConstantRange, ShaderStageFlags, WriteDescriptorSet, WHOLE_SIZE,
+    DescriptorSetLayoutCreateFlags, DescriptorType, Format, ImageLayout, Pipeline,
+    PipelineBindPoint, PipelineLayout, PushConstantRange, Sampler, ShaderStageFlags,
+    WriteDescriptorSet, WHOLE_SIZE,
 };
-use log::{error, info};
+use log::{error, info, warn};
+use vk_mem::{AllocationCreateFlags, MemoryUsage};
 
 use crate::application::{
     core::error::ErrorCode,
@@ -14,27 +18,73 @@ use crate::application::{
         descriptor::Descriptor,
         push_constant::PushConstant,
     },
-    scene::{bvh::BvhType, Scene},
+    raytracer::{heatmap::HeatmapStats, integrator::IntegratorSettingsGPU},
+    scene::{
+        bvh::BvhType,
+        environment::{EnvironmentMap, EnvironmentSettingsGPU},
+        Scene,
+    },
     vulkan::{
         descriptors_helper::{
             allocator::DescriptorPoolSizeRatio, buffer::AllocatedBuffer,
             layout_builder::DescriptorLayoutBuilder,
         },
+        setup::{draw_resources::AllocatedImage, frame_data::FRAME_OVERLAP},
         types::VulkanContext,
     },
 };
 
+/// Number of u32 in the heatmap stats of one frame, should match the shader
+/// see src/shaders/raytracer/heatmap.slang
+const HEATMAP_STATS_SIZE: usize = 8;
+
+/// Size of the array of sampled images, should match the shader
+/// see src/shaders/scene/material.slang
+const MAX_TEXTURES: usize = 256;
+
+/// Full precision so that the average of many frames doesn't lose the small contributions
+const ACCUMULATION_IMAGE_FORMAT: Format = Format::R32G32B32A32_SFLOAT;
+/// The environment maps keep their high dynamic range
+const ENVIRONMENT_IMAGE_FORMAT: Format = Format::R32G32B32A32_SFLOAT;
+
 pub struct RaytracingPipeline {
     pub base: PipelineAttributes,
     pub buffers: RaytracingBuffers,
+    // Set 1 of each frame in flight, so the bvh can change while the other frame still runs
+    pub bvh_sets: [DescriptorSet; FRAME_OVERLAP],
+    // The set of the frame doesn't point to the current bvhs ssbo yet
+    pub are_bvh_sets_stale: [bool; FRAME_OVERLAP],
 }
 
 pub struct RaytracingBuffers {
+    pub vertices_ssbo: AllocatedBuffer,
     pub triangles_ssbo: AllocatedBuffer,
+    pub spheres_ssbo: AllocatedBuffer,
+    pub cuboids_ssbo: AllocatedBuffer,
     pub models_ssbo: AllocatedBuffer,
     pub materials_ssbo: AllocatedBuffer,
-    pub bvhs_ssbo: Option<AllocatedBuffer>,
+    pub lights_ssbo: AllocatedBuffer,
+    pub emissive_triangles_ssbo: AllocatedBuffer,
+    // The buffer holds a default element when there is none
+    pub nb_emissive_triangles: u32,
+    // A single default node when no bvh is built, the set 1 always points to a buffer
+    pub bvhs_ssbo: AllocatedBuffer,
+    // Replaced bvhs with the number of frames in flight that may still read them
+    pub retired_bvhs_ssbos: Vec<(AllocatedBuffer, usize)>,
     pub camera_ubo: AllocatedBuffer,
+    // Written by the shader and read back on the CPU, one slot per frame in flight
+    pub heatmap_stats_ssbo: AllocatedBuffer,
+    // The first `MAX_TEXTURES` textures of the scene
+    pub textures: Vec<AllocatedImage>,
+    pub texture_sampler: Sampler,
+    // Running average of the frames, the size of the draw image
+    pub accumulation_image: AllocatedImage,
+    // A black pixel when the scene has no environment map
+    pub environment_image: AllocatedImage,
+    // Cdfs used to sample the environment map, see `EnvironmentMap::distribution`
+    pub environment_distribution_ssbo: AllocatedBuffer,
+    // Of the uploaded environment map, empty for the placeholder
+    pub environment_path: PathBuf,
 }
 
 #[derive(Default)]
@@ -46,6 +96,19 @@ pub struct RaytracingPushConstant {
     pub should_display_bvh: u32,
     pub bvh_depth_to_display: u32,
     pub time: f32,
+    pub heatmap_type: u32,
+    pub heatmap_max: u32,
+    pub heatmap_stats_offset: u32,
+    pub nb_spheres: u32,
+    pub nb_cuboids: u32,
+    pub nb_textures: u32,
+    pub nb_lights: u32,
+    pub nb_emissive_triangles: u32,
+    pub has_transparent_materials: u32,
+    pub frame_index: u32,
+    pub nb_accumulated_frames: u32,
+    pub integrator_settings: IntegratorSettingsGPU,
+    pub environment_settings: EnvironmentSettingsGPU,
 }
 
 impl RaytracingPipeline {
@@ -68,29 +131,67 @@ impl RaytracingPipeline {
         Ok(())
     }
 
+    /// Read the heatmap stats written by the last frame that used the current frame slot
+    /// The render fence of the current frame must have been waited for
+    pub fn read_heatmap_stats(
+        &self,
+        vulkan_context: &VulkanContext,
+    ) -> Result<Option<HeatmapStats>, ErrorCode> {
+        let offset = (vulkan_context.frame_index % FRAME_OVERLAP) * HEATMAP_STATS_SIZE;
+        let allocator = vulkan_context.get_allocator()?.allocator.lock().unwrap();
+        let allocation = &self.buffers.heatmap_stats_ssbo.allocation;
+        if let Err(err) = allocator.invalidate_allocation(allocation, 0, WHOLE_SIZE) {
+            error!("Failed to invalidate the heatmap stats buffer: {:?}", err);
+            return Err(ErrorCode::VulkanFailure);
+        }
+        let mapped_data = allocator.get_allocation_info(allocation).mapped_data as *const u32;
+        let stats =
+            unsafe { std::slice::from_raw_parts(mapped_data.add(offset), HEATMAP_STATS_SIZE) };
+
+        // [min, max, sum low, sum high, nb pixels, ...]
+        let nb_pixels = stats[4];
+        if nb_pixels == 0 {
+            return Ok(None);
+        }
+        let sum = ((stats[3] as u64) << 32) | stats[2] as u64;
+        Ok(Some(HeatmapStats {
+            min: stats[0],
+            max: stats[1],
+            avg: (sum as f64 / nb_pixels as f64) as f32,
+        }))
+    }
+
+    /// Reset the heatmap stats of a frame slot, the GPU must not be using it
+    fn reset_heatmap_stats(
+        &self,
+        vulkan_context: &VulkanContext,
+        frame_slot: usize,
+    ) -> Result<(), ErrorCode> {
+        let offset = frame_slot * HEATMAP_STATS_SIZE;
+        let allocator = vulkan_context.get_allocator()?.allocator.lock().unwrap();
+        let allocation = &self.buffers.heatmap_stats_ssbo.allocation;
+        let mapped_data = allocator.get_allocation_info(allocation).mapped_data as *mut u32;
+        let stats =
+            unsafe { std::slice::from_raw_parts_mut(mapped_data.add(offset), HEATMAP_STATS_SIZE) };
+        stats.fill(0);
+        stats[0] = u32::MAX;
+        if let Err(err) = allocator.flush_allocation(allocation, 0, WHOLE_SIZE) {
+            error!("Failed to flush the heatmap stats buffer: {:?}", err);
+            return Err(ErrorCode::VulkanFailure);
+        }
+        Ok(())
+    }
+
     pub fn update_bvhs_buffer(
         &mut self,
         vulkan_context: &VulkanContext,
         scene: &Scene,
     ) -> Result<(), ErrorCode> {
-        // Clean old buffer
-        vulkan_context.device_wait_idle()?;
-        let allocator = &vulkan_context.get_allocator()?.allocator;
-        if let Some(bvh_ssbo) = self.buffers.bvhs_ssbo.as_mut() {
-            if let Err(err) = bvh_ssbo.clean(allocator) {
-                error!(
-                    "Failed to clean the bvhs buffer in the raytracing pipeline: {:?}",
-                    err
-                );
-                return Err(ErrorCode::CleaningFailure);
-            }
-        };
-
-        // Create new buffer
-        self.buffers.bvhs_ssbo = if scene.bvh_type == BvhType::None {
-            None
+        // Create new buffer while the old one may still be in use by the GPU
+        let data = if scene.bvh_type == BvhType::None {
+            &[]
         } else {
-            let data = match scene.get_bvh() {
+            match scene.get_bvh() {
                 Ok(bvh) => {
                     if bvh.is_empty() {
                         error!("Can't get the bvh from the scene in the raytracing pipeline, BVH tree is empty");
@@ -106,119 +207,201 @@ impl RaytracingPipeline {
                     );
                     return Err(ErrorCode::Unknown);
                 }
-            };
-            match vulkan_context.map_data_to_buffer(data, BufferUsageFlags::STORAGE_BUFFER) {
-                Ok(buffer) => Some(buffer),
-                Err(err) => {
-                    error!(
-                        "Failed to create the bvhs ssbo for the raytracing pipeline: {:?}",
-                        err
-                    );
-                    return Err(ErrorCode::InitializationFailure);
-                }
             }
         };
+        let new_bvhs_ssbo = map_scene_data(vulkan_context, data, "bvhs")?;
 
-        // Create new set
-        if scene.bvh_type != BvhType::None {
-            let descriptor_bvhs_info = [DescriptorBufferInfo::default()
-                .buffer(self.buffers.bvhs_ssbo.as_ref().unwrap().buffer)
-                .range(WHOLE_SIZE)
-                .offset(0)];
-
-            // Get the corresponding descriptor set (bvhs are on set 1)
-            let set = 1;
-            let descriptor_set = self.base.descriptors[set].set;
-
-            // Updates to perform
-            let writes_descriptor_set = [
-                // TODO: add other things
-                // BVHs
-                WriteDescriptorSet::default()
-                    .dst_set(descriptor_set)
-                    .dst_binding(0)
-                    .descriptor_count(1)
-                    .descriptor_type(DescriptorType::STORAGE_BUFFER)
-                    .buffer_info(&descriptor_bvhs_info),
-            ];
+        // The frames in flight keep their set until their render fence has been waited for
+        let old_bvhs_ssbo = std::mem::replace(&mut self.buffers.bvhs_ssbo, new_bvhs_ssbo);
+        self.buffers
+            .retired_bvhs_ssbos
+            .push((old_bvhs_ssbo, FRAME_OVERLAP));
+        self.are_bvh_sets_stale = [true; FRAME_OVERLAP];
+        Ok(())
+    }
 
-            let device = vulkan_context.get_device()?;
-            vulkan_context.device_wait_idle()?;
-            unsafe { device.update_descriptor_sets(&writes_descriptor_set, &[]) };
-            info!("BVH descriptor set updated in the raytracing pipeline");
+    /// Point the set 1 of the current frame to the current bvhs and clean the ones no frame
+    /// uses anymore, the render fence of the current frame must have been waited for
+    fn update_bvh_set(&mut self, vulkan_context: &VulkanContext) -> Result<(), ErrorCode> {
+        // Rewritten before the buffer it pointed to can be cleaned
+        self.write_bvh_set(vulkan_context)?;
+
+        for (_, nb_frames) in &mut self.buffers.retired_bvhs_ssbos {
+            *nb_frames -= 1;
         }
+        let (mut unused_bvhs_ssbos, retired_bvhs_ssbos) =
+            std::mem::take(&mut self.buffers.retired_bvhs_ssbos)
+                .into_iter()
+                .partition(|(_, nb_frames)| *nb_frames == 0);
+        self.buffers.retired_bvhs_ssbos = retired_bvhs_ssbos;
+        let allocator = &vulkan_context.get_allocator()?.allocator;
+        for (bvhs_ssbo, _) in &mut unused_bvhs_ssbos {
+            if let Err(err) = bvhs_ssbo.clean(allocator) {
+                error!(
+                    "Failed to clean a replaced bvhs buffer in the raytracing pipeline: {:?}",
+                    err
+                );
+                return Err(ErrorCode::CleaningFailure);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_bvh_set(&mut self, vulkan_context: &VulkanContext) -> Result<(), ErrorCode> {
+        let frame_slot = vulkan_context.frame_index % FRAME_OVERLAP;
+        if !self.are_bvh_sets_stale[frame_slot] {
+            return Ok(());
+        }
+        self.are_bvh_sets_stale[frame_slot] = false;
+        let descriptor_bvhs_info = [DescriptorBufferInfo::default()
+            .buffer(self.buffers.bvhs_ssbo.buffer)
+            .range(WHOLE_SIZE)
+            .offset(0)];
+
+        // Updates to perform
+        let writes_descriptor_set = [
+            // TODO: add other things
+            // BVHs
+            WriteDescriptorSet::default()
+                .dst_set(self.bvh_sets[frame_slot])
+                .dst_binding(0)
+                .descriptor_count(1)
+                .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&descriptor_bvhs_info),
+        ];
 
+        let device = vulkan_context.get_device()?;
+        unsafe { device.update_descriptor_sets(&writes_descriptor_set, &[]) };
+        info!(
+            "BVH descriptor set of the frame {} updated in the raytracing pipeline",
+            frame_slot
+        );
         Ok(())
     }
 
-    fn init_buffers(
+    /// Send the edited primitives, models and materials with buffers of the new sizes
+    pub fn update_scene_buffers(
+        &mut self,
         vulkan_context: &VulkanContext,
         scene: &Scene,
-    ) -> Result<RaytracingBuffers, ErrorCode> {
-        // TODO: add other things
-        let triangles_ssbo = match vulkan_context
-            .map_data_to_buffer(scene.triangles.as_slice(), BufferUsageFlags::STORAGE_BUFFER)
-        {
-            Ok(buffer) => buffer,
-            Err(err) => {
+    ) -> Result<(), ErrorCode> {
+        // Create new buffers while the old ones may still be in use by the GPU
+        let emissive_triangles = scene.get_emissive_triangles();
+        let mut new_buffers = [
+            map_scene_data(vulkan_context, &scene.mesh.vertices, "vertices")?,
+            map_scene_data(vulkan_context, &scene.mesh.triangles, "triangles")?,
+            map_scene_data(vulkan_context, &scene.spheres, "spheres")?,
+            map_scene_data(vulkan_context, &scene.cuboids, "cuboids")?,
+            map_scene_data(vulkan_context, &scene.models, "models")?,
+            map_scene_data(vulkan_context, &scene.materials, "materials")?,
+            map_scene_data(vulkan_context, &scene.get_lights_gpu_data(), "lights")?,
+            map_scene_data(vulkan_context, &emissive_triangles, "emissive triangles")?,
+        ];
+
+        // The textures of the scene are only ever added
+        upload_new_textures(vulkan_context, scene, &mut self.buffers.textures)?;
+        let new_environment = if get_environment_path(scene) != self.buffers.environment_path {
+            Some(upload_environment(vulkan_context, scene)?)
+        } else {
+            None
+        };
+
+        // Wait only once the new buffers are ready, the old ones can then be replaced
+        vulkan_context.device_wait_idle()?;
+        let buffers = [
+            &mut self.buffers.vertices_ssbo,
+            &mut self.buffers.triangles_ssbo,
+            &mut self.buffers.spheres_ssbo,
+            &mut self.buffers.cuboids_ssbo,
+            &mut self.buffers.models_ssbo,
+            &mut self.buffers.materials_ssbo,
+            &mut self.buffers.lights_ssbo,
+            &mut self.buffers.emissive_triangles_ssbo,
+        ];
+        for (buffer, new_buffer) in std::iter::zip(buffers, &mut new_buffers) {
+            std::mem::swap(buffer, new_buffer);
+        }
+        self.buffers.nb_emissive_triangles = emissive_triangles.len() as u32;
+        let old_environment = new_environment.map(|(image, distribution_ssbo, path)| {
+            self.buffers.environment_path = path;
+            (
+                std::mem::replace(&mut self.buffers.environment_image, image),
+                std::mem::replace(
+                    &mut self.buffers.environment_distribution_ssbo,
+                    distribution_ssbo,
+                ),
+            )
+        });
+
+        // Scene data is on set 0
+        let set = 0;
+        self.write_set_0(vulkan_context, self.base.descriptors[set].set)?;
+        info!("Scene descriptor set updated in the raytracing pipeline");
+
+        // Clean old buffers, swapped with the new ones
+        let allocator = &vulkan_context.get_allocator()?.allocator;
+        for mut old_buffer in new_buffers {
+            if let Err(err) = old_buffer.clean(allocator) {
                 error!(
-                    "Failed to create the triangles ssbo for the raytracing pipeline: {:?}",
+                    "Failed to clean an old scene buffer in the raytracing pipeline: {:?}",
                     err
                 );
-                return Err(ErrorCode::InitializationFailure);
+                return Err(ErrorCode::CleaningFailure);
             }
-        };
-
-        let models_ssbo = match vulkan_context
-            .map_data_to_buffer(scene.models.as_slice(), BufferUsageFlags::STORAGE_BUFFER)
-        {
-            Ok(buffer) => buffer,
-            Err(err) => {
+        }
+        if let Some((mut old_image, mut old_distribution_ssbo)) = old_environment {
+            if let Err(err) = old_image.clean(vulkan_context) {
                 error!(
-                    "Failed to create the models ssbo for the raytracing pipeline: {:?}",
+                    "Failed to clean the old environment image in the raytracing pipeline: {:?}",
                     err
                 );
-                return Err(ErrorCode::InitializationFailure);
+                return Err(ErrorCode::CleaningFailure);
             }
-        };
-
-        let materials_ssbo = match vulkan_context
-            .map_data_to_buffer(scene.materials.as_slice(), BufferUsageFlags::STORAGE_BUFFER)
-        {
-            Ok(buffer) => buffer,
-            Err(err) => {
+            if let Err(err) = old_distribution_ssbo.clean(allocator) {
                 error!(
-                    "Failed to create the materials ssbo for the raytracing pipeline: {:?}",
+                    "Failed to clean the old environment distribution buffer in the raytracing pipeline: {:?}",
                     err
                 );
-                return Err(ErrorCode::InitializationFailure);
+                return Err(ErrorCode::CleaningFailure);
             }
-        };
+        }
+
+        Ok(())
+    }
+
+    fn init_buffers(
+        vulkan_context: &VulkanContext,
+        scene: &Scene,
+    ) -> Result<RaytracingBuffers, ErrorCode> {
+        // TODO: add other things
+        let vertices_ssbo = map_scene_data(vulkan_context, &scene.mesh.vertices, "vertices")?;
+        let triangles_ssbo = map_scene_data(vulkan_context, &scene.mesh.triangles, "triangles")?;
+        let spheres_ssbo = map_scene_data(vulkan_context, &scene.spheres, "spheres")?;
+        let cuboids_ssbo = map_scene_data(vulkan_context, &scene.cuboids, "cuboids")?;
+        let models_ssbo = map_scene_data(vulkan_context, &scene.models, "models")?;
+        let materials_ssbo = map_scene_data(vulkan_context, &scene.materials, "materials")?;
+        let lights_ssbo = map_scene_data(vulkan_context, &scene.get_lights_gpu_data(), "lights")?;
+        let emissive_triangles = scene.get_emissive_triangles();
+        let emissive_triangles_ssbo =
+            map_scene_data(vulkan_context, &emissive_triangles, "emissive triangles")?;
 
         // let start = Instant::now();
-        let bvhs_ssbo = if scene.bvh_type == BvhType::None {
-            None
+        // Only an already built bvh can be sent, the others are uploaded once their build is done
+        let data = if scene.bvh_last_type == BvhType::None {
+            &[]
         } else {
-            let data = match scene.get_bvh() {
-                Ok(bvh) => bvh,
-                Err(err) => {
-                    error!("Failed to get the bvh from the scene when initializing the bvhs ssbo in the raytracing pipeline: {:?}", err);
-                    return Err(ErrorCode::Unknown);
-                }
-            };
-            match vulkan_context
-                .map_data_to_buffer(data.as_slice(), BufferUsageFlags::STORAGE_BUFFER)
-            {
-                Ok(buffer) => Some(buffer),
-                Err(err) => {
+            match scene.bvhs.get(&scene.bvh_last_type) {
+                Some(bvh) => bvh.as_slice(),
+                None => {
                     error!(
-                        "Failed to create the bvhs ssbo for the raytracing pipeline: {:?}",
-                        err
+                        "The `{:?}' bvh has not been built when initializing the bvhs ssbo in the raytracing pipeline",
+                        scene.bvh_last_type
                     );
-                    return Err(ErrorCode::InitializationFailure);
+                    return Err(ErrorCode::Unknown);
                 }
             }
         };
+        let bvhs_ssbo = map_scene_data(vulkan_context, data, "bvhs")?;
         // panic!("time: {}", (Instant::now()-start).as_nanos());
 
         let camera_ubo = match vulkan_context.map_data_to_buffer(
@@ -235,17 +418,75 @@ impl RaytracingPipeline {
             }
         };
 
+        let heatmap_stats_size = (FRAME_OVERLAP * HEATMAP_STATS_SIZE * size_of::<u32>()) as u64;
+        let heatmap_stats_ssbo = match AllocatedBuffer::from_usage(
+            &vulkan_context.get_allocator()?.allocator,
+            heatmap_stats_size,
+            BufferUsageFlags::STORAGE_BUFFER,
+            MemoryUsage::AutoPreferHost,
+            AllocationCreateFlags::HOST_ACCESS_RANDOM,
+        ) {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                error!(
+                    "Failed to create the heatmap stats ssbo for the raytracing pipeline: {:?}",
+                    err
+                );
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+
+        let mut textures = Vec::new();
+        upload_new_textures(vulkan_context, scene, &mut textures)?;
+        let texture_sampler = vulkan_context.create_texture_sampler()?;
+        let accumulation_image = match vulkan_context.create_storage_image(
+            vulkan_context.get_draw_image()?.image_extent,
+            ACCUMULATION_IMAGE_FORMAT,
+        ) {
+            Ok(image) => image,
+            Err(err) => {
+                error!(
+                    "Failed to create the accumulation image for the raytracing pipeline: {:?}",
+                    err
+                );
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+        let (environment_image, environment_distribution_ssbo, environment_path) =
+            upload_environment(vulkan_context, scene)?;
+
         Ok(RaytracingBuffers {
+            vertices_ssbo,
             triangles_ssbo,
+            spheres_ssbo,
+            cuboids_ssbo,
             models_ssbo,
             materials_ssbo,
+            lights_ssbo,
+            emissive_triangles_ssbo,
+            nb_emissive_triangles: emissive_triangles.len() as u32,
             bvhs_ssbo,
+            retired_bvhs_ssbos: Vec::new(),
             camera_ubo,
+            heatmap_stats_ssbo,
+            textures,
+            texture_sampler,
+            accumulation_image,
+            environment_image,
+            environment_distribution_ssbo,
+            environment_path,
         })
     }
 
     fn clean_buffers(&mut self, vulkan_context: &VulkanContext) -> Result<(), ErrorCode> {
         let allocator = &vulkan_context.get_allocator()?.allocator;
+        if let Err(err) = self.buffers.vertices_ssbo.clean(allocator) {
+            error!(
+                "Failed to clean the vertices buffer in the raytracing pipeline: {:?}",
+                err
+            );
+            return Err(ErrorCode::CleaningFailure);
+        }
         if let Err(err) = self.buffers.triangles_ssbo.clean(allocator) {
             error!(
                 "Failed to clean the triangles buffer in the raytracing pipeline: {:?}",
@@ -253,6 +494,20 @@ impl RaytracingPipeline {
             );
             return Err(ErrorCode::CleaningFailure);
         }
+        if let Err(err) = self.buffers.spheres_ssbo.clean(allocator) {
+            error!(
+                "Failed to clean the spheres buffer in the raytracing pipeline: {:?}",
+                err
+            );
+            return Err(ErrorCode::CleaningFailure);
+        }
+        if let Err(err) = self.buffers.cuboids_ssbo.clean(allocator) {
+            error!(
+                "Failed to clean the cuboids buffer in the raytracing pipeline: {:?}",
+                err
+            );
+            return Err(ErrorCode::CleaningFailure);
+        }
         if let Err(err) = self.buffers.models_ssbo.clean(allocator) {
             error!(
                 "Failed to clean the models buffer in the raytracing pipeline: {:?}",
@@ -267,7 +522,26 @@ impl RaytracingPipeline {
             );
             return Err(ErrorCode::CleaningFailure);
         }
-        if let Some(bvh_ssbo) = self.buffers.bvhs_ssbo.as_mut() {
+        if let Err(err) = self.buffers.lights_ssbo.clean(allocator) {
+            error!(
+                "Failed to clean the lights buffer in the raytracing pipeline: {:?}",
+                err
+            );
+            return Err(ErrorCode::CleaningFailure);
+        }
+        if let Err(err) = self.buffers.emissive_triangles_ssbo.clean(allocator) {
+            error!(
+                "Failed to clean the emissive triangles buffer in the raytracing pipeline: {:?}",
+                err
+            );
+            return Err(ErrorCode::CleaningFailure);
+        }
+        let retired_bvhs_ssbos = self
+            .buffers
+            .retired_bvhs_ssbos
+            .iter_mut()
+            .map(|(bvh_ssbo, _)| bvh_ssbo);
+        for bvh_ssbo in std::iter::once(&mut self.buffers.bvhs_ssbo).chain(retired_bvhs_ssbos) {
             if let Err(err) = bvh_ssbo.clean(allocator) {
                 error!(
                     "Failed to clean the bvhs buffer in the raytracing pipeline: {:?}",
@@ -283,13 +557,65 @@ impl RaytracingPipeline {
             );
             return Err(ErrorCode::CleaningFailure);
         }
+        if let Err(err) = self.buffers.heatmap_stats_ssbo.clean(allocator) {
+            error!(
+                "Failed to clean the heatmap stats buffer in the raytracing pipeline: {:?}",
+                err
+            );
+            return Err(ErrorCode::CleaningFailure);
+        }
+        for texture in &mut self.buffers.textures {
+            if let Err(err) = texture.clean(vulkan_context) {
+                error!(
+                    "Failed to clean a texture in the raytracing pipeline: {:?}",
+                    err
+                );
+                return Err(ErrorCode::CleaningFailure);
+            }
+        }
+        unsafe {
+            vulkan_context.get_device()?.destroy_sampler(
+                self.buffers.texture_sampler,
+                vulkan_context.get_allocation_callback()?,
+            )
+        };
+        if let Err(err) = self.buffers.accumulation_image.clean(vulkan_context) {
+            error!(
+                "Failed to clean the accumulation image in the raytracing pipeline: {:?}",
+                err
+            );
+            return Err(ErrorCode::CleaningFailure);
+        }
+        if let Err(err) = self.buffers.environment_image.clean(vulkan_context) {
+            error!(
+                "Failed to clean the environment image in the raytracing pipeline: {:?}",
+                err
+            );
+            return Err(ErrorCode::CleaningFailure);
+        }
+        if let Err(err) = self.buffers.environment_distribution_ssbo.clean(allocator) {
+            error!(
+                "Failed to clean the environment distribution buffer in the raytracing pipeline: {:?}",
+                err
+            );
+            return Err(ErrorCode::CleaningFailure);
+        }
         Ok(())
     }
 
     pub fn new(vulkan_context: &VulkanContext, scene: &Scene) -> Result<Self, ErrorCode> {
         let base = PipelineAttributes::default();
         let buffers = Self::init_buffers(vulkan_context, scene)?;
-        Ok(RaytracingPipeline { base, buffers })
+        let raytracing_pipeline = RaytracingPipeline {
+            base,
+            buffers,
+            bvh_sets: [DescriptorSet::null(); FRAME_OVERLAP],
+            are_bvh_sets_stale: [true; FRAME_OVERLAP],
+        };
+        for frame_slot in 0..FRAME_OVERLAP {
+            raytracing_pipeline.reset_heatmap_stats(vulkan_context, frame_slot)?;
+        }
+        Ok(raytracing_pipeline)
     }
 
     fn init_set_0(
@@ -308,6 +634,32 @@ impl RaytracingPipeline {
         layout_builder.add_binding(2, DescriptorType::STORAGE_BUFFER)?;
         // Materials
         layout_builder.add_binding(3, DescriptorType::STORAGE_BUFFER)?;
+        // Heatmap stats
+        layout_builder.add_binding(4, DescriptorType::STORAGE_BUFFER)?;
+        // Spheres
+        layout_builder.add_binding(5, DescriptorType::STORAGE_BUFFER)?;
+        // Cuboids
+        layout_builder.add_binding(6, DescriptorType::STORAGE_BUFFER)?;
+        // Vertices
+        layout_builder.add_binding(7, DescriptorType::STORAGE_BUFFER)?;
+        // Textures
+        layout_builder.add_partially_bound_array(
+            8,
+            DescriptorType::SAMPLED_IMAGE,
+            MAX_TEXTURES as u32,
+        )?;
+        // Texture sampler
+        layout_builder.add_binding(9, DescriptorType::SAMPLER)?;
+        // Lights
+        layout_builder.add_binding(10, DescriptorType::STORAGE_BUFFER)?;
+        // Emissive triangles
+        layout_builder.add_binding(11, DescriptorType::STORAGE_BUFFER)?;
+        // Accumulation image
+        layout_builder.add_binding(12, DescriptorType::STORAGE_IMAGE)?;
+        // Environment image
+        layout_builder.add_binding(13, DescriptorType::SAMPLED_IMAGE)?;
+        // Environment distribution
+        layout_builder.add_binding(14, DescriptorType::STORAGE_BUFFER)?;
 
         // Build the layout
         let device = vulkan_context.get_device()?;
@@ -326,6 +678,21 @@ impl RaytracingPipeline {
             .allocate(device, descriptor_set_layout)?;
 
         // Send the data to the GPU
+        self.write_set_0(vulkan_context, descriptor_set)?;
+
+        Ok(Descriptor {
+            set: descriptor_set,
+            set_layout: descriptor_set_layout,
+        })
+    }
+
+    /// Point the bindings of set 0 to the current buffers
+    fn write_set_0(
+        &self,
+        vulkan_context: &VulkanContext,
+        descriptor_set: DescriptorSet,
+    ) -> Result<(), ErrorCode> {
+        let device = vulkan_context.get_device()?;
         // TODO: add other things
         // Framebuffer
         let descriptor_framebuffer_info = [DescriptorImageInfo::default()
@@ -347,9 +714,66 @@ impl RaytracingPipeline {
             .buffer(self.buffers.materials_ssbo.buffer)
             .range(WHOLE_SIZE)
             .offset(0)];
+        // Heatmap stats
+        let descriptor_heatmap_stats_info = [DescriptorBufferInfo::default()
+            .buffer(self.buffers.heatmap_stats_ssbo.buffer)
+            .range(WHOLE_SIZE)
+            .offset(0)];
+        // Spheres
+        let descriptor_spheres_info = [DescriptorBufferInfo::default()
+            .buffer(self.buffers.spheres_ssbo.buffer)
+            .range(WHOLE_SIZE)
+            .offset(0)];
+        // Cuboids
+        let descriptor_cuboids_info = [DescriptorBufferInfo::default()
+            .buffer(self.buffers.cuboids_ssbo.buffer)
+            .range(WHOLE_SIZE)
+            .offset(0)];
+        // Vertices
+        let descriptor_vertices_info = [DescriptorBufferInfo::default()
+            .buffer(self.buffers.vertices_ssbo.buffer)
+            .range(WHOLE_SIZE)
+            .offset(0)];
+        // Textures
+        let descriptor_textures_info: Vec<DescriptorImageInfo> = self
+            .buffers
+            .textures
+            .iter()
+            .map(|texture| {
+                DescriptorImageInfo::default()
+                    .image_view(texture.image_view)
+                    .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            })
+            .collect();
+        // Texture sampler
+        let descriptor_sampler_info =
+            [DescriptorImageInfo::default().sampler(self.buffers.texture_sampler)];
+        // Lights
+        let descriptor_lights_info = [DescriptorBufferInfo::default()
+            .buffer(self.buffers.lights_ssbo.buffer)
+            .range(WHOLE_SIZE)
+            .offset(0)];
+        // Emissive triangles
+        let descriptor_emissive_triangles_info = [DescriptorBufferInfo::default()
+            .buffer(self.buffers.emissive_triangles_ssbo.buffer)
+            .range(WHOLE_SIZE)
+            .offset(0)];
+        // Accumulation image
+        let descriptor_accumulation_info = [DescriptorImageInfo::default()
+            .image_view(self.buffers.accumulation_image.image_view)
+            .image_layout(ImageLayout::GENERAL)];
+        // Environment image
+        let descriptor_environment_info = [DescriptorImageInfo::default()
+            .image_view(self.buffers.environment_image.image_view)
+            .image_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        // Environment distribution
+        let descriptor_environment_distribution_info = [DescriptorBufferInfo::default()
+            .buffer(self.buffers.environment_distribution_ssbo.buffer)
+            .range(WHOLE_SIZE)
+            .offset(0)];
 
         // Updates to perform
-        let writes_descriptor_set = [
+        let mut writes_descriptor_set = vec![
             // TODO: add other things
             // Framebuffer
             WriteDescriptorSet::default()
@@ -379,20 +803,97 @@ impl RaytracingPipeline {
                 .descriptor_count(1)
                 .descriptor_type(DescriptorType::STORAGE_BUFFER)
                 .buffer_info(&descriptor_materials_info),
+            // Heatmap stats
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(4)
+                .descriptor_count(1)
+                .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&descriptor_heatmap_stats_info),
+            // Spheres
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(5)
+                .descriptor_count(1)
+                .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&descriptor_spheres_info),
+            // Cuboids
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(6)
+                .descriptor_count(1)
+                .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&descriptor_cuboids_info),
+            // Vertices
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(7)
+                .descriptor_count(1)
+                .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&descriptor_vertices_info),
+            // Texture sampler
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(9)
+                .descriptor_count(1)
+                .descriptor_type(DescriptorType::SAMPLER)
+                .image_info(&descriptor_sampler_info),
+            // Lights
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(10)
+                .descriptor_count(1)
+                .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&descriptor_lights_info),
+            // Emissive triangles
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(11)
+                .descriptor_count(1)
+                .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&descriptor_emissive_triangles_info),
+            // Accumulation image
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(12)
+                .descriptor_count(1)
+                .descriptor_type(DescriptorType::STORAGE_IMAGE)
+                .image_info(&descriptor_accumulation_info),
+            // Environment image
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(13)
+                .descriptor_count(1)
+                .descriptor_type(DescriptorType::SAMPLED_IMAGE)
+                .image_info(&descriptor_environment_info),
+            // Environment distribution
+            WriteDescriptorSet::default()
+                .dst_set(descriptor_set)
+                .dst_binding(14)
+                .descriptor_count(1)
+                .descriptor_type(DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&descriptor_environment_distribution_info),
         ];
+        // Textures, the array is partially bound so the unused end is not written
+        if !descriptor_textures_info.is_empty() {
+            writes_descriptor_set.push(
+                WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(8)
+                    .descriptor_count(descriptor_textures_info.len() as u32)
+                    .descriptor_type(DescriptorType::SAMPLED_IMAGE)
+                    .image_info(&descriptor_textures_info),
+            );
+        }
 
         unsafe { device.update_descriptor_sets(&writes_descriptor_set, &[]) };
-
-        Ok(Descriptor {
-            set: descriptor_set,
-            set_layout: descriptor_set_layout,
-        })
+        Ok(())
     }
 
     fn init_set_1(
         &mut self,
         vulkan_context: &VulkanContext,
-        scene: &Scene,
+        _scene: &Scene,
     ) -> Result<Descriptor, ErrorCode> {
         // Organize the set layout
         let mut layout_builder = DescriptorLayoutBuilder::default();
@@ -410,38 +911,17 @@ impl RaytracingPipeline {
             DescriptorSetLayoutCreateFlags::empty(),
         )?;
 
-        // Allocate the set
-        let descriptor_set = self
-            .base
-            .descriptor_allocator
-            .allocate(device, descriptor_set_layout)?;
-
-        // Send the data to the GPU
-        // TODO: add other things
-        // BVHs
-        if scene.bvh_type != BvhType::None {
-            let descriptor_bvhs_info = [DescriptorBufferInfo::default()
-                .buffer(self.buffers.bvhs_ssbo.as_ref().unwrap().buffer)
-                .range(WHOLE_SIZE)
-                .offset(0)];
-
-            // Updates to perform
-            let writes_descriptor_set = [
-                // TODO: add other things
-                // BVHs
-                WriteDescriptorSet::default()
-                    .dst_set(descriptor_set)
-                    .dst_binding(0)
-                    .descriptor_count(1)
-                    .descriptor_type(DescriptorType::STORAGE_BUFFER)
-                    .buffer_info(&descriptor_bvhs_info),
-            ];
-
-            unsafe { device.update_descriptor_sets(&writes_descriptor_set, &[]) };
+        // Allocate one set per frame in flight, they are written in `update_bvh_set`
+        for bvh_set in &mut self.bvh_sets {
+            *bvh_set = self
+                .base
+                .descriptor_allocator
+                .allocate(device, descriptor_set_layout)?;
         }
+        self.are_bvh_sets_stale = [true; FRAME_OVERLAP];
 
         Ok(Descriptor {
-            set: descriptor_set,
+            set: self.bvh_sets[0],
             set_layout: descriptor_set_layout,
         })
     }
@@ -583,13 +1063,15 @@ impl ComputePipeline for RaytracingPipeline {
             )
         };
 
-        // Bind the descriptor sets
-        let descriptor_sets = self
+        // Bind the descriptor sets, the bvhs have one set per frame
+        self.update_bvh_set(vulkan_context)?;
+        let mut descriptor_sets = self
             .base
             .descriptors
             .iter()
             .map(|d| d.set)
             .collect::<Vec<DescriptorSet>>();
+        descriptor_sets[1] = self.bvh_sets[vulkan_context.frame_index % FRAME_OVERLAP];
         unsafe {
             device.cmd_bind_descriptor_sets(
                 command_buffer,
@@ -601,14 +1083,33 @@ impl ComputePipeline for RaytracingPipeline {
             )
         };
 
+        // Reset the heatmap stats of this frame, even when unused so no stale stats are read
+        self.reset_heatmap_stats(vulkan_context, vulkan_context.frame_index % FRAME_OVERLAP)?;
+
         // TODO: add push constants if needed
         let push_constant = RaytracingPushConstant {
-            nb_triangles: scene.triangles.len() as u32,
+            nb_triangles: scene.mesh.triangles.len() as u32,
             is_wireframe_on: scene.is_wireframe_on as u32,
             bvh_type: scene.bvh_last_type as u32,
             should_display_bvh: scene.should_display_bvh as u32,
             bvh_depth_to_display: scene.bvh_depth_to_display,
             time: scene.current_time,
+            heatmap_type: scene.heatmap_type as u32,
+            heatmap_max: scene.heatmap_stats.max,
+            heatmap_stats_offset: ((vulkan_context.frame_index % FRAME_OVERLAP)
+                * HEATMAP_STATS_SIZE) as u32,
+            nb_spheres: scene.spheres.len() as u32,
+            nb_cuboids: scene.cuboids.len() as u32,
+            nb_textures: self.buffers.textures.len() as u32,
+            nb_lights: scene.lights.len() as u32,
+            nb_emissive_triangles: self.buffers.nb_emissive_triangles,
+            has_transparent_materials: scene.has_transparent_materials() as u32,
+            frame_index: vulkan_context.frame_index as u32,
+            nb_accumulated_frames: scene.nb_accumulated_frames,
+            integrator_settings: scene.integrator_settings.get_gpu_data(),
+            environment_settings: scene
+                .environment_settings
+                .get_gpu_data(scene.environment.is_some()),
         };
         unsafe {
             device.cmd_push_constants(
@@ -667,6 +1168,61 @@ impl ComputePipeline for RaytracingPipeline {
                 descriptor_type: DescriptorType::STORAGE_BUFFER,
                 ratio: 1.0,
             },
+            // Heatmap stats
+            DescriptorPoolSizeRatio {
+                descriptor_type: DescriptorType::STORAGE_BUFFER,
+                ratio: 1.0,
+            },
+            // Spheres
+            DescriptorPoolSizeRatio {
+                descriptor_type: DescriptorType::STORAGE_BUFFER,
+                ratio: 1.0,
+            },
+            // Cuboids
+            DescriptorPoolSizeRatio {
+                descriptor_type: DescriptorType::STORAGE_BUFFER,
+                ratio: 1.0,
+            },
+            // Vertices
+            DescriptorPoolSizeRatio {
+                descriptor_type: DescriptorType::STORAGE_BUFFER,
+                ratio: 1.0,
+            },
+            // Textures
+            DescriptorPoolSizeRatio {
+                descriptor_type: DescriptorType::SAMPLED_IMAGE,
+                ratio: MAX_TEXTURES as f32,
+            },
+            // Texture sampler
+            DescriptorPoolSizeRatio {
+                descriptor_type: DescriptorType::SAMPLER,
+                ratio: 1.0,
+            },
+            // Lights
+            DescriptorPoolSizeRatio {
+                descriptor_type: DescriptorType::STORAGE_BUFFER,
+                ratio: 1.0,
+            },
+            // Emissive triangles
+            DescriptorPoolSizeRatio {
+                descriptor_type: DescriptorType::STORAGE_BUFFER,
+                ratio: 1.0,
+            },
+            // Accumulation image
+            DescriptorPoolSizeRatio {
+                descriptor_type: DescriptorType::STORAGE_IMAGE,
+                ratio: 1.0,
+            },
+            // Environment image
+            DescriptorPoolSizeRatio {
+                descriptor_type: DescriptorType::SAMPLED_IMAGE,
+                ratio: 1.0,
+            },
+            // Environment distribution
+            DescriptorPoolSizeRatio {
+                descriptor_type: DescriptorType::STORAGE_BUFFER,
+                ratio: 1.0,
+            },
             // BVHs
             DescriptorPoolSizeRatio {
                 descriptor_type: DescriptorType::STORAGE_BUFFER,
@@ -714,3 +1270,91 @@ impl ComputePipeline for RaytracingPipeline {
         Ok(())
     }
 }
+
+/// A buffer can't be empty, so an unused element is sent when there is no data
+fn map_scene_data<T: Copy + Default>(
+    vulkan_context: &VulkanContext,
+    data: &[T],
+    name: &str,
+) -> Result<AllocatedBuffer, ErrorCode> {
+    let default_data = [T::default()];
+    let data = if data.is_empty() { &default_data } else { data };
+    match vulkan_context.map_data_to_buffer(data, BufferUsageFlags::STORAGE_BUFFER) {
+        Ok(buffer) => Ok(buffer),
+        Err(err) => {
+            error!(
+                "Failed to create the {} ssbo for the raytracing pipeline: {:?}",
+                name, err
+            );
+            Err(ErrorCode::InitializationFailure)
+        }
+    }
+}
+
+/// Upload the textures of the scene that are not on the GPU yet, the ones above
+/// `MAX_TEXTURES` are not sampled
+fn upload_new_textures(
+    vulkan_context: &VulkanContext,
+    scene: &Scene,
+    textures: &mut Vec<AllocatedImage>,
+) -> Result<(), ErrorCode> {
+    if scene.texture_images.len() > MAX_TEXTURES && textures.len() < MAX_TEXTURES {
+        warn!(
+            "Only {} of the {} textures of the scene are sampled",
+            MAX_TEXTURES,
+            scene.texture_images.len()
+        );
+    }
+    let nb_textures = scene.texture_images.len().min(MAX_TEXTURES);
+    for texture in &scene.texture_images[textures.len().min(nb_textures)..nb_textures] {
+        match vulkan_context.map_texture_to_image(texture) {
+            Ok(image) => textures.push(image),
+            Err(err) => {
+                error!(
+                    "Failed to create a texture image for the raytracing pipeline: {:?}",
+                    err
+                );
+                return Err(ErrorCode::InitializationFailure);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get_environment_path(scene: &Scene) -> PathBuf {
+    scene
+        .environment
+        .as_ref()
+        .map(|environment| environment.path.clone())
+        .unwrap_or_default()
+}
+
+/// Image and distribution of the environment map of the scene, or of a placeholder
+fn upload_environment(
+    vulkan_context: &VulkanContext,
+    scene: &Scene,
+) -> Result<(AllocatedImage, AllocatedBuffer, PathBuf), ErrorCode> {
+    let placeholder = EnvironmentMap::placeholder();
+    let environment = scene.environment.as_ref().unwrap_or(&placeholder);
+    let image = match vulkan_context.map_pixels_to_image(
+        environment.width,
+        environment.height,
+        &environment.pixels,
+        ENVIRONMENT_IMAGE_FORMAT,
+    ) {
+        Ok(image) => image,
+        Err(err) => {
+            error!(
+                "Failed to create the environment image for the raytracing pipeline: {:?}",
+                err
+            );
+            return Err(ErrorCode::InitializationFailure);
+        }
+    };
+    let distribution_ssbo = map_scene_data(
+        vulkan_context,
+        &environment.distribution,
+        "environment distribution",
+    )?;
+    Ok((image, distribution_ssbo, get_environment_path(scene)))
+}
@@ -0,0 +1,67 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+// Squared roughness below which the highlight of a punctual light is too thin to be sampled
+pub const MIN_ALPHA: f32 = 1e-3;
+
+/// Inputs of the GGX BRDF at a hit point, the directions are normalized and point away from it
+#[derive(Debug, Clone, Copy)]
+pub struct GgxInputs {
+    pub normal: Vec3,
+    pub view_direction: Vec3,
+    pub light_direction: Vec3,
+    pub base_color: Vec3,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub specular_factor: f32,
+}
+
+/// GGX (Trowbridge-Reitz) distribution of the microfacet normals
+pub fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha_2 = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha_2 - 1.) + 1.;
+    alpha_2 / (PI * denominator * denominator)
+}
+
+/// Smith masking of one direction for the GGX distribution
+pub fn smith_g1(n_dot_x: f32, alpha: f32) -> f32 {
+    let alpha_2 = alpha * alpha;
+    2. * n_dot_x / (n_dot_x + f32::sqrt(alpha_2 + (1. - alpha_2) * n_dot_x * n_dot_x))
+}
+
+/// Separable Smith masking-shadowing
+pub fn smith_masking(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    smith_g1(n_dot_v, alpha) * smith_g1(n_dot_l, alpha)
+}
+
+/// Schlick approximation of the Fresnel reflectance
+pub fn schlick_fresnel(f0: Vec3, v_dot_h: f32) -> Vec3 {
+    f0 + (1. - f0) * (1. - v_dot_h).clamp(0., 1.).powi(5)
+}
+
+/// Cook-Torrance GGX with Smith masking and Schlick Fresnel, plus a Lambert lobe for the part
+/// that isn't reflected by dielectrics. Returns the BRDF without the cosine of the light.
+/// Same as the shader, see src/shaders/raytracer/brdf.slang
+pub fn evaluate_ggx(inputs: &GgxInputs) -> Vec3 {
+    let n_dot_l = inputs.normal.dot(inputs.light_direction);
+    let n_dot_v = inputs.normal.dot(inputs.view_direction);
+    if n_dot_l <= 0. || n_dot_v <= 0. {
+        return Vec3::ZERO;
+    }
+    let half_vector = (inputs.light_direction + inputs.view_direction).normalize();
+    let n_dot_h = inputs.normal.dot(half_vector).max(0.);
+    let v_dot_h = inputs.view_direction.dot(half_vector).max(0.);
+
+    // Perceptual roughness, squared to get the width of the distribution
+    let alpha = f32::max(inputs.roughness * inputs.roughness, MIN_ALPHA);
+    let dielectric_f0 = Vec3::splat(0.08 * inputs.specular_factor);
+    let f0 = dielectric_f0.lerp(inputs.base_color, inputs.metallic);
+    let fresnel = schlick_fresnel(f0, v_dot_h);
+
+    let specular =
+        fresnel * ggx_distribution(n_dot_h, alpha) * smith_masking(n_dot_v, n_dot_l, alpha)
+            / (4. * n_dot_l * n_dot_v);
+    let diffuse = (1. - fresnel) * (1. - inputs.metallic) * inputs.base_color / PI;
+    diffuse + specular
+}
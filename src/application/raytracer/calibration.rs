@@ -0,0 +1,143 @@
+use std::time::Instant;
+
+use glam::Vec2;
+use log::error;
+
+use crate::application::{
+    core::error::ErrorCode,
+    scene::{
+        bvh::{
+            build_job::BvhBuildProgress,
+            sah::{CalibrationSample, SahConstants},
+            BvhType,
+        },
+        Scene,
+    },
+};
+
+use super::{ray::Ray, CpuRaytracer, TraversalStats};
+
+/// Constants given to the top down sah builder so that the fit sees different trees
+const TRIAL_SAH_CONSTANTS: [SahConstants; 3] = [
+    SahConstants {
+        cost_traverse_internal: 0.25,
+        cost_triangle_intersection: 1.,
+    },
+    SahConstants {
+        cost_traverse_internal: 1.,
+        cost_triangle_intersection: 1.,
+    },
+    SahConstants {
+        cost_traverse_internal: 4.,
+        cost_triangle_intersection: 1.,
+    },
+];
+
+/// Builders that do not depend on the constants
+const OTHER_BVH_TYPES: [BvhType; 2] = [BvhType::DefaultTopDown, BvhType::Ploc];
+
+/// Without a bvh every ray tests every triangle, which is too slow on big models
+const MAX_BRUTE_FORCE_TRIANGLES: usize = 10_000;
+
+/// The fastest of the repetitions is kept to filter out the noise
+const NB_REPETITIONS: u32 = 3;
+
+/// Cost of tracing the sample rays through one bvh of one scene
+#[derive(Debug, Clone, Copy)]
+pub struct CalibrationMeasure {
+    pub scene_index: usize,
+    pub bvh_type: BvhType,
+    // Constants used to build the bvh
+    pub build_sah_constants: SahConstants,
+    pub sample: CalibrationSample,
+}
+
+/// One primary ray per pixel of a `image_size` x `image_size` image
+pub fn get_sample_rays(scene: &Scene, image_size: u32) -> Vec<Ray> {
+    let camera = scene.camera.get_gpu_data();
+    (0..image_size * image_size)
+        .map(|pixel_index| {
+            let (x, y) = (pixel_index % image_size, pixel_index / image_size);
+            let position = (Vec2::new(x as f32, y as f32) + 0.5) / image_size as f32;
+            Ray::from_camera(position, &camera)
+        })
+        .collect::<Vec<Ray>>()
+}
+
+/// Trace the rays on the current thread so the timing is not skewed by the other ones
+pub fn measure(raytracer: &CpuRaytracer, rays: &[Ray]) -> CalibrationSample {
+    let mut best_nanoseconds = f64::MAX;
+    let mut stats = TraversalStats::default();
+    for _ in 0..NB_REPETITIONS {
+        stats = TraversalStats::default();
+        let start = Instant::now();
+        for ray in rays {
+            let _ = raytracer.get_closest_hit(ray, f32::INFINITY, &mut stats);
+        }
+        best_nanoseconds = best_nanoseconds.min(start.elapsed().as_nanos() as f64);
+    }
+
+    CalibrationSample {
+        node_tests_per_ray: stats.get_node_tests_per_ray(),
+        triangle_tests_per_ray: stats.get_triangle_tests_per_ray(),
+        nanoseconds_per_ray: best_nanoseconds / stats.nb_rays.max(1) as f64,
+    }
+}
+
+fn measure_bvh(
+    scene: &Scene,
+    scene_index: usize,
+    bvh_type: BvhType,
+    rays: &[Ray],
+) -> Result<CalibrationMeasure, ErrorCode> {
+    let bvh = match scene.build_bvh(bvh_type, &BvhBuildProgress::default()) {
+        Ok(bvh) => bvh,
+        Err(err) => {
+            error!(
+                "Failed to build the `{:?}' bvh for the calibration: {:?}",
+                bvh_type, err
+            );
+            return Err(ErrorCode::Unknown);
+        }
+    };
+    let raytracer = CpuRaytracer::new(scene, &bvh);
+    Ok(CalibrationMeasure {
+        scene_index,
+        bvh_type,
+        build_sah_constants: scene.sah_constants,
+        sample: measure(&raytracer, rays),
+    })
+}
+
+/// Time the traversal of bvhs built with different constants and fit the constants to it
+/// The constants are in nanoseconds per test on the CPU traversal, which mirrors the shader
+pub fn calibrate_sah_constants(
+    scenes: &mut [Scene],
+    image_size: u32,
+) -> Result<(SahConstants, Vec<CalibrationMeasure>), ErrorCode> {
+    let mut measures = Vec::new();
+    for (scene_index, scene) in scenes.iter_mut().enumerate() {
+        let rays = get_sample_rays(scene, image_size);
+        let scene_sah_constants = scene.sah_constants;
+
+        for sah_constants in TRIAL_SAH_CONSTANTS {
+            scene.sah_constants = sah_constants;
+            let measure = measure_bvh(scene, scene_index, BvhType::TopDownSah, &rays);
+            scene.sah_constants = scene_sah_constants;
+            measures.push(measure?);
+        }
+        for bvh_type in OTHER_BVH_TYPES {
+            measures.push(measure_bvh(scene, scene_index, bvh_type, &rays)?);
+        }
+        if scene.mesh.triangles.len() <= MAX_BRUTE_FORCE_TRIANGLES {
+            measures.push(measure_bvh(scene, scene_index, BvhType::None, &rays)?);
+        }
+    }
+
+    let samples = measures
+        .iter()
+        .map(|measure| measure.sample)
+        .collect::<Vec<CalibrationSample>>();
+    let sah_constants = SahConstants::fit(&samples)?;
+    Ok((sah_constants, measures))
+}
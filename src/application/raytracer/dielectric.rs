@@ -0,0 +1,121 @@
+use glam::Vec3;
+
+use crate::application::scene::material::{Brdf, Material};
+
+use super::{
+    brdf::{smith_g1, MIN_ALPHA},
+    path_tracing::{get_local_direction, to_world, Bounce},
+    random::Random,
+    shading::Surface,
+};
+
+/// Fraction of the light reflected by a smooth dielectric, for an unpolarized light. `eta` is
+/// the index of refraction of the incident side over the one of the other side
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0., 1.);
+    let sin_t_2 = eta * eta * (1. - cos_i * cos_i);
+    // Total internal reflection
+    if sin_t_2 >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin_t_2).sqrt();
+    let r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_s * r_s + r_p * r_p)
+}
+
+pub fn reflect(direction: Vec3, normal: Vec3) -> Vec3 {
+    direction - 2. * direction.dot(normal) * normal
+}
+
+/// Direction refracted through a surface whose normal faces the incoming side, None for a
+/// total internal reflection. `eta` is the ratio of the indices of refraction
+pub fn refract(direction: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = -normal.dot(direction);
+    let k = 1. - eta * eta * (1. - cos_i * cos_i);
+    if k < 0. {
+        return None;
+    }
+    Some((eta * direction + (eta * cos_i - k.sqrt()) * normal).normalize())
+}
+
+/// Attenuation of the light along a segment leaving a surface of outward `normal`, following
+/// Beer's law when the segment goes through the inside of a dielectric
+pub fn get_transmittance(
+    material: &Material,
+    normal: Vec3,
+    direction: Vec3,
+    distance: f32,
+) -> Vec3 {
+    if !material.get_brdf().is_dielectric() || normal.dot(direction) >= 0. {
+        return Vec3::ONE;
+    }
+    (-material.absorption.truncate() * distance).exp()
+}
+
+/// Reflection of a mirror, or reflection or refraction of a dielectric picked with the
+/// probability of the Fresnel term, which then cancels out of the weight. Same as
+/// `sample_specular` in src/shaders/raytracer/dielectric.slang
+pub fn sample_specular(
+    surface: &Surface,
+    view_direction: Vec3,
+    random: &mut Random,
+) -> Option<Bounce> {
+    let material = &surface.material;
+    let brdf = material.get_brdf();
+    // Seen from the inside of a dielectric
+    let is_inside = surface.normal.dot(view_direction) < 0.;
+    let normal = if is_inside {
+        -surface.normal
+    } else {
+        surface.normal
+    };
+    if brdf == Brdf::Mirror {
+        return Some(Bounce {
+            direction: reflect(-view_direction, normal),
+            weight: surface.diffuse,
+        });
+    }
+
+    let ior = material.index_of_refraction.max(1e-3);
+    let eta = if is_inside { ior } else { 1. / ior };
+    let u = random.next_f32();
+    if brdf != Brdf::RoughDielectric {
+        let fresnel = fresnel_dielectric(normal.dot(view_direction), eta);
+        let direction = refract(-view_direction, normal, eta)
+            .filter(|_| u >= fresnel)
+            .unwrap_or_else(|| reflect(-view_direction, normal));
+        return Some(Bounce {
+            direction,
+            weight: Vec3::ONE,
+        });
+    }
+
+    // Microfacet normal sampled with the density D(m) (m.n), like the GGX specular lobe
+    let alpha = f32::max(surface.roughness * surface.roughness, MIN_ALPHA);
+    let u0 = random.next_f32();
+    let u1 = random.next_f32();
+    let n_dot_m = ((1. - u0) / (1. + (alpha * alpha - 1.) * u0)).sqrt();
+    let microfacet_normal = to_world(normal, get_local_direction(n_dot_m, u1));
+    let n_dot_v = normal.dot(view_direction);
+    let v_dot_m = view_direction.dot(microfacet_normal);
+    if n_dot_v <= 0. || v_dot_m <= 0. {
+        return None;
+    }
+    let fresnel = fresnel_dielectric(v_dot_m, eta);
+    let refracted_direction =
+        refract(-view_direction, microfacet_normal, eta).filter(|_| u >= fresnel);
+    let direction =
+        refracted_direction.unwrap_or_else(|| reflect(-view_direction, microfacet_normal));
+    // The reflections must stay above the surface and the refractions go below it
+    let n_dot_l = normal.dot(direction);
+    if (n_dot_l < 0.) != refracted_direction.is_some() || n_dot_l == 0. {
+        return None;
+    }
+    // Walter et al. 2007, the distribution and the Fresnel term cancel out with the density
+    let masking = smith_g1(n_dot_v, alpha) * smith_g1(n_dot_l.abs(), alpha);
+    Some(Bounce {
+        direction,
+        weight: Vec3::splat(v_dot_m * masking / (n_dot_v * n_dot_m)),
+    })
+}
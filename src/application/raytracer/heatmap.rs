@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use glam::Vec3;
+
+use crate::application::core::error::ErrorCode;
+
+use super::{output::write_ppm, TraversalStats};
+
+/// What is counted per pixel, values should match the shader
+/// see src/shaders/raytracer/heatmap.slang
+#[derive(Debug, Default, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum HeatmapType {
+    #[default]
+    None = 0,
+    NodeTests = 1,
+    TriangleTests = 2,
+}
+
+impl HeatmapType {
+    pub fn get_count(&self, stats: &TraversalStats) -> u64 {
+        match self {
+            HeatmapType::None => 0,
+            HeatmapType::NodeTests => stats.nb_node_tests,
+            HeatmapType::TriangleTests => stats.nb_triangle_tests,
+        }
+    }
+}
+
+/// Range of the per pixel counts of a frame, used by the legend
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HeatmapStats {
+    pub min: u32,
+    pub max: u32,
+    pub avg: f32,
+}
+
+impl HeatmapStats {
+    pub fn from_counts(counts: &[u32]) -> Self {
+        if counts.is_empty() {
+            return Self::default();
+        }
+        let sum = counts.iter().map(|&count| count as u64).sum::<u64>();
+        Self {
+            min: counts.iter().copied().min().unwrap_or(0),
+            max: counts.iter().copied().max().unwrap_or(0),
+            avg: (sum as f64 / counts.len() as f64) as f32,
+        }
+    }
+}
+
+const HEATMAP_RAMP: [Vec3; 5] = [
+    Vec3::new(0., 0., 0.5),
+    Vec3::new(0., 0.5, 1.),
+    Vec3::new(0., 0.8, 0.),
+    Vec3::new(1., 0.9, 0.),
+    Vec3::new(1., 0., 0.),
+];
+
+/// Map `t` in [0;1] from dark blue to red, same ramp as the shader
+pub fn get_heatmap_color(t: f32) -> Vec3 {
+    let scaled_t = t.clamp(0., 1.) * (HEATMAP_RAMP.len() - 1) as f32;
+    let index = usize::min(scaled_t as usize, HEATMAP_RAMP.len() - 2);
+    let fraction = scaled_t - index as f32;
+    HEATMAP_RAMP[index].lerp(HEATMAP_RAMP[index + 1], fraction)
+}
+
+/// Write the counts through the color ramp with the ramp itself as a legend below the image
+pub fn write_heatmap_ppm(
+    path: &Path,
+    counts: &[u32],
+    width: u32,
+    height: u32,
+    stats: &HeatmapStats,
+) -> Result<(), ErrorCode> {
+    let legend_height = u32::max(height / 20, 1);
+    let mut pixels = counts
+        .iter()
+        .map(|&count| get_heatmap_color(count as f32 / u32::max(stats.max, 1) as f32))
+        .collect::<Vec<Vec3>>();
+    for _ in 0..legend_height {
+        pixels.extend((0..width).map(|x| get_heatmap_color(x as f32 / (width - 1) as f32)));
+    }
+    write_ppm(path, &pixels, width, height + legend_height)
+}
@@ -0,0 +1,221 @@
+use glam::{Mat4, Vec3};
+
+use crate::application::scene::{bvh::aabb::Aabb, primitive::PrimitiveType};
+
+use super::ray::Ray;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub distance: f32,
+    pub primitive_type: PrimitiveType,
+    // Index in the primitives of its type
+    pub primitive_index: usize,
+    // Only meaningful for triangles
+    pub barycentric_coordinates: Vec3,
+}
+
+/// Same test as the compute shader
+/// see src/shaders/raytracer/hit.slang
+pub fn ray_triangle_intersection(
+    ray: &Ray,
+    triangle_index: usize,
+    triangle: &[Vec3; 3],
+) -> Option<Hit> {
+    let [p0, p1, p2] = *triangle;
+    let e0 = p1 - p0;
+    let e1 = p2 - p0;
+
+    let tmp = e0.cross(e1);
+    if tmp.length() == 0. {
+        return None;
+    }
+
+    let n = tmp.normalize();
+    let q = ray.direction.cross(e1);
+    let a = e0.dot(q);
+
+    // Back faces are culled for primary rays
+    if !ray.hits_back_faces && n.dot(ray.direction) >= 0. {
+        return None;
+    }
+
+    let epsilon = 1e-6;
+    if a.abs() < epsilon {
+        return None;
+    }
+
+    let s = (ray.origin - p0) / a;
+    let r = s.cross(e0);
+
+    let b0 = s.dot(q);
+    let b1 = r.dot(ray.direction);
+    let b2 = 1. - b0 - b1;
+    if b0 < 0. || b1 < 0. || b2 < 0. {
+        return None;
+    }
+
+    let t = e1.dot(r);
+    if !(1e-4..=1e6).contains(&t) {
+        return None;
+    }
+
+    Some(Hit {
+        distance: t,
+        primitive_type: PrimitiveType::Triangle,
+        primitive_index: triangle_index,
+        barycentric_coordinates: Vec3::new(b2, b0, b1),
+    })
+}
+
+impl Hit {
+    /// Hit on a sphere or a box, which have no barycentric coordinates
+    pub fn from_analytic(
+        distance: f32,
+        primitive_type: PrimitiveType,
+        primitive_index: usize,
+    ) -> Self {
+        Self {
+            distance,
+            primitive_type,
+            primitive_index,
+            barycentric_coordinates: Vec3::ONE,
+        }
+    }
+}
+
+/// Hits kept by a traversal, should match the `Hit[8]` of the shader
+/// see src/shaders/raytracer/hit.slang
+pub const MAX_HITS: usize = 8;
+
+/// Closest hits of a ray sorted by distance, the farthest one is dropped when it is full
+#[derive(Debug, Clone, Copy)]
+pub struct ClosestHits {
+    hits: [Hit; MAX_HITS],
+    nb_hits: usize,
+    // At most `MAX_HITS`
+    capacity: usize,
+}
+
+impl ClosestHits {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            hits: [Hit::from_analytic(f32::INFINITY, PrimitiveType::Triangle, 0); MAX_HITS],
+            nb_hits: 0,
+            capacity: capacity.clamp(1, MAX_HITS),
+        }
+    }
+
+    pub fn insert(&mut self, hit: Hit) {
+        let index =
+            self.hits[..self.nb_hits].partition_point(|other| other.distance < hit.distance);
+        if index >= self.capacity {
+            return;
+        }
+        let last = self.nb_hits.min(self.capacity - 1);
+        self.hits.copy_within(index..last, index + 1);
+        self.hits[index] = hit;
+        self.nb_hits = (self.nb_hits + 1).min(self.capacity);
+    }
+
+    /// Distance beyond which the hits are not kept
+    pub fn get_max_distance(&self, max_distance: f32) -> f32 {
+        if self.nb_hits < self.capacity {
+            return max_distance;
+        }
+        f32::min(self.hits[self.nb_hits - 1].distance, max_distance)
+    }
+
+    pub fn as_slice(&self) -> &[Hit] {
+        &self.hits[..self.nb_hits]
+    }
+
+    pub fn first(&self) -> Option<Hit> {
+        self.as_slice().first().copied()
+    }
+}
+
+/// Closest distance in front of the ray, the inside of the sphere is only seen by
+/// shadow rays as primary rays cull back faces
+pub fn ray_sphere_intersection(ray: &Ray, center: Vec3, radius: f32) -> Option<f32> {
+    let oc = ray.origin - center;
+    let b = oc.dot(ray.direction);
+    let c = oc.dot(oc) - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0. {
+        return None;
+    }
+    let sqrt_discriminant = discriminant.sqrt();
+    let t_near = -b - sqrt_discriminant;
+    let t_far = -b + sqrt_discriminant;
+    get_closest_distance(ray, t_near, t_far)
+}
+
+/// Position or direction relative to the origin of a model whose matrix has orthogonal axes
+fn to_model_space(model_matrix: &Mat4, vector: Vec3) -> Vec3 {
+    let axes = [
+        model_matrix.x_axis,
+        model_matrix.y_axis,
+        model_matrix.z_axis,
+    ]
+    .map(|axis| axis.truncate());
+    Vec3::from_array(axes.map(|axis| vector.dot(axis) / axis.length_squared()))
+}
+
+/// Same as the sphere, for a box of the model space. The ray is moved to that space without
+/// being normalized, so the distances along it stay the same
+pub fn ray_cuboid_intersection(
+    ray: &Ray,
+    model_matrix: &Mat4,
+    mins: Vec3,
+    maxs: Vec3,
+) -> Option<f32> {
+    let origin = to_model_space(model_matrix, ray.origin - model_matrix.w_axis.truncate());
+    let inverse_direction = to_model_space(model_matrix, ray.direction).recip();
+    let t1 = (mins - origin) * inverse_direction;
+    let t2 = (maxs - origin) * inverse_direction;
+    let t_near = t1.min(t2).max_element();
+    let t_far = t1.max(t2).min_element();
+    if t_near > t_far {
+        return None;
+    }
+    get_closest_distance(ray, t_near, t_far)
+}
+
+/// World normal of the face of the box along the axis where the point is the closest to a face
+pub fn get_cuboid_normal(model_matrix: &Mat4, mins: Vec3, maxs: Vec3, position: Vec3) -> Vec3 {
+    let position = to_model_space(model_matrix, position - model_matrix.w_axis.truncate());
+    let local_position =
+        (position - 0.5 * (mins + maxs)) / (0.5 * (maxs - mins)).max(Vec3::splat(1e-6));
+    let abs_position = local_position.abs();
+    let (axis, side) = if abs_position.x >= abs_position.y && abs_position.x >= abs_position.z {
+        (model_matrix.x_axis, local_position.x)
+    } else if abs_position.y >= abs_position.z {
+        (model_matrix.y_axis, local_position.y)
+    } else {
+        (model_matrix.z_axis, local_position.z)
+    };
+    axis.truncate().normalize() * side.signum()
+}
+
+fn get_closest_distance(ray: &Ray, t_near: f32, t_far: f32) -> Option<f32> {
+    if (1e-4..=1e6).contains(&t_near) {
+        Some(t_near)
+    } else if ray.hits_back_faces && (1e-4..=1e6).contains(&t_far) {
+        Some(t_far)
+    } else {
+        None
+    }
+}
+
+/// Slab test, returns the distance at which the ray enters the box
+pub fn ray_aabb_intersection(ray: &Ray, aabb: &Aabb) -> Option<f32> {
+    let t1 = (aabb.mins - ray.origin) * ray.inverse_direction;
+    let t2 = (aabb.maxs - ray.origin) * ray.inverse_direction;
+    let t_min = t1.min(t2).max_element();
+    let t_max = t1.max(t2).min_element();
+    if t_max < 0. || t_min > t_max {
+        None
+    } else {
+        Some(f32::max(t_min, 0.))
+    }
+}
@@ -0,0 +1,443 @@
+use glam::{Vec2, Vec3};
+use rayon::prelude::*;
+
+use crate::application::scene::{camera::CameraGPU, material::Brdf};
+
+use super::{
+    brdf::schlick_fresnel,
+    dielectric::{fresnel_dielectric, get_transmittance, reflect, refract, sample_specular},
+    hit::{Hit, MAX_HITS},
+    path_tracing::{get_cosine_direction, DEFAULT_MAX_DEPTH},
+    random::Random,
+    ray::Ray,
+    shading::{get_background, Surface},
+    CpuRaytracer, TraversalStats,
+};
+
+/// Rays waiting to be traced by the Whitted integrator, should match the shader
+/// see src/shaders/raytracer/integrator.slang
+pub const WHITTED_STACK_SIZE: usize = 8;
+/// Reflected and refracted rays whose weight is below this are not traced
+const WHITTED_MIN_WEIGHT: f32 = 1e-3;
+/// Mirrors and dielectrics followed by the direct integrator before giving up, should match
+/// the shader
+pub const SPECULAR_MAX_DEPTH: u32 = 8;
+/// Transparent layers behind which less than this is visible are not composed
+const LAYER_MIN_TRANSMITTANCE: f32 = 1e-3;
+
+/// How the colour of a pixel is computed, values should match the shader
+/// see src/shaders/raytracer/integrator.slang
+#[derive(Debug, Default, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum Integrator {
+    // Lights and emissive triangles seen from the primary hit, through the mirrors and the
+    // dielectrics
+    #[default]
+    Direct = 0,
+    // Fraction of the hemisphere that is not occluded around the primary hit
+    AmbientOcclusion = 1,
+    // Direct lighting plus the mirror reflections and the refractions
+    Whitted = 2,
+    // Diffuse and specular bounces with next event estimation at every hit
+    PathTracing = 3,
+    // One attribute of the primary hit, see `DebugAov`
+    Debug = 4,
+}
+
+impl Integrator {
+    pub const ALL: [Self; 5] = [
+        Self::Direct,
+        Self::AmbientOcclusion,
+        Self::Whitted,
+        Self::PathTracing,
+        Self::Debug,
+    ];
+
+    /// Name used by the GUI and the command line
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Direct => "direct",
+            Self::AmbientOcclusion => "ao",
+            Self::Whitted => "whitted",
+            Self::PathTracing => "path",
+            Self::Debug => "debug",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|integrator| integrator.get_name() == name)
+    }
+}
+
+/// Attribute of the primary hit displayed by the debug integrator, values should match the
+/// shader, see src/shaders/raytracer/integrator.slang
+#[derive(Debug, Default, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum DebugAov {
+    // Shading normal, mapped from [-1;1] to [0;1]
+    #[default]
+    Normal = 0,
+    // Diffuse colour once textured
+    Albedo = 1,
+    // Distance to the camera, white at `IntegratorSettings::debug_max_distance`
+    Depth = 2,
+    Roughness = 3,
+}
+
+/// Integrator and the parameters of each of them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntegratorSettings {
+    pub integrator: Integrator,
+    // Bounces of the paths after the primary hit
+    pub max_depth: u32,
+    // Rays of the ambient occlusion, occluded by the hits closer than `ao_radius`
+    pub ao_nb_samples: u32,
+    pub ao_radius: f32,
+    // Reflections and refractions traced after the primary hit
+    pub whitted_max_depth: u32,
+    pub debug_aov: DebugAov,
+    pub debug_max_distance: f32,
+}
+
+/// The layout should match the shader, see src/shaders/raytracer/integrator.slang
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct IntegratorSettingsGPU {
+    pub integrator: u32,
+    pub max_depth: u32,
+    pub ao_nb_samples: u32,
+    pub ao_radius: f32,
+    pub whitted_max_depth: u32,
+    pub debug_aov: u32,
+    pub debug_max_distance: f32,
+}
+
+impl Default for IntegratorSettings {
+    fn default() -> Self {
+        Self {
+            integrator: Integrator::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            ao_nb_samples: 4,
+            ao_radius: 0.5,
+            whitted_max_depth: 4,
+            debug_aov: DebugAov::default(),
+            debug_max_distance: 10.,
+        }
+    }
+}
+
+impl IntegratorSettings {
+    pub fn get_gpu_data(&self) -> IntegratorSettingsGPU {
+        IntegratorSettingsGPU {
+            integrator: self.integrator as u32,
+            max_depth: self.max_depth,
+            ao_nb_samples: self.ao_nb_samples,
+            ao_radius: self.ao_radius,
+            whitted_max_depth: self.whitted_max_depth,
+            debug_aov: self.debug_aov as u32,
+            debug_max_distance: self.debug_max_distance,
+        }
+    }
+}
+
+/// Ray waiting to be traced by the Whitted integrator
+#[derive(Debug, Clone, Copy)]
+struct WhittedRay {
+    ray: Ray,
+    weight: Vec3,
+    depth: u32,
+    // Absorption coefficient of the dielectric the ray goes through, 0 outside
+    absorption: Vec3,
+}
+
+/// Colour of the mirror reflection of an opaque surface, `cos_theta` is the cosine of the
+/// view direction. Rough GGX surfaces blur it, which Whitted can't represent, so it fades out
+fn get_mirror_reflectance(surface: &Surface, cos_theta: f32) -> Vec3 {
+    let material = &surface.material;
+    match material.get_brdf() {
+        Brdf::Mirror => return surface.diffuse,
+        Brdf::Dielectric | Brdf::RoughDielectric => return Vec3::ZERO,
+        _ => (),
+    }
+    if material.get_brdf() == Brdf::Ggx {
+        let dielectric_f0 = Vec3::splat(0.08 * material.specular_factor);
+        let f0 = dielectric_f0.lerp(surface.diffuse, material.metallic);
+        return schlick_fresnel(f0, cos_theta) * (1. - surface.roughness);
+    }
+    // MTL `illum` 3 and above turn the ray traced reflections on
+    if material.illumination_model >= 3 {
+        return material.specular.truncate();
+    }
+    Vec3::ZERO
+}
+
+impl CpuRaytracer<'_> {
+    /// Colour of a pixel whose primary ray hits the scene, same as `get_radiance` in
+    /// src/shaders/raytracing.slang
+    pub fn get_radiance(
+        &self,
+        settings: &IntegratorSettings,
+        ray: &Ray,
+        hit: &Hit,
+        random: &mut Random,
+        stats: &mut TraversalStats,
+    ) -> Vec3 {
+        match settings.integrator {
+            Integrator::Direct => self.trace_direct(ray, hit, random, stats),
+            Integrator::AmbientOcclusion => {
+                let surface = self.get_surface(ray, hit);
+                Vec3::splat(self.get_ambient_occlusion(settings, &surface, random, stats))
+            }
+            Integrator::Whitted => self.trace_whitted(settings, ray, hit, random, stats),
+            Integrator::PathTracing => self.trace_path(ray, hit, settings.max_depth, random, stats),
+            Integrator::Debug => {
+                let surface = self.get_surface(ray, hit);
+                match settings.debug_aov {
+                    DebugAov::Normal => 0.5 * surface.normal + 0.5,
+                    DebugAov::Albedo => surface.diffuse,
+                    DebugAov::Depth => {
+                        Vec3::splat(hit.distance / settings.debug_max_distance.max(1e-6))
+                    }
+                    DebugAov::Roughness => Vec3::splat(surface.roughness),
+                }
+            }
+        }
+    }
+
+    /// Transparent layers hit by the primary ray blended front to back, the background shows
+    /// through the transmittance left after the last one. Same as `compose_layers` in
+    /// src/shaders/raytracing.slang
+    pub fn compose_layers(
+        &self,
+        settings: &IntegratorSettings,
+        ray: &Ray,
+        hits: &[Hit],
+        background: Vec3,
+        random: &mut Random,
+        stats: &mut TraversalStats,
+    ) -> Vec3 {
+        let mut color = Vec3::ZERO;
+        let mut transmittance = 1.;
+        for hit in hits {
+            let opacity = self.get_opacity(hit);
+            if opacity <= 0. {
+                continue;
+            }
+            color += transmittance * opacity * self.get_radiance(settings, ray, hit, random, stats);
+            transmittance *= 1. - opacity;
+            if transmittance <= LAYER_MIN_TRANSMITTANCE {
+                return color;
+            }
+        }
+        color + transmittance * background
+    }
+
+    /// Direct lighting of the first hit that is not a mirror or a dielectric, following one
+    /// reflection or refraction picked at random at each of them
+    pub fn trace_direct(
+        &self,
+        primary_ray: &Ray,
+        primary_hit: &Hit,
+        random: &mut Random,
+        stats: &mut TraversalStats,
+    ) -> Vec3 {
+        let mut ray = *primary_ray;
+        let mut hit = *primary_hit;
+        let mut radiance = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        for _ in 0..=SPECULAR_MAX_DEPTH {
+            let surface = self.get_surface(&ray, &hit);
+            let material = &surface.material;
+            if !material.get_brdf().is_specular() {
+                return radiance + throughput * self.shade(ray.origin, &surface, random, stats);
+            }
+            radiance += throughput * material.emission.truncate();
+            let Some(bounce) = sample_specular(&surface, -ray.direction, random) else {
+                return radiance;
+            };
+            throughput *= bounce.weight;
+            ray = Ray::new(surface.position, bounce.direction, true);
+            match self.get_visible_hit(&ray, random, stats) {
+                Some(next_hit) => {
+                    throughput *= get_transmittance(
+                        material,
+                        surface.normal,
+                        ray.direction,
+                        next_hit.distance,
+                    );
+                    hit = next_hit;
+                }
+                None => return radiance + throughput * self.get_miss_radiance(ray.direction),
+            }
+        }
+        radiance
+    }
+
+    /// Fraction of cosine weighted directions that are not occluded within `ao_radius`, the
+    /// transparent surfaces occlude them partially
+    pub fn get_ambient_occlusion(
+        &self,
+        settings: &IntegratorSettings,
+        surface: &Surface,
+        random: &mut Random,
+        stats: &mut TraversalStats,
+    ) -> f32 {
+        if settings.ao_nb_samples == 0 {
+            return 1.;
+        }
+        let mut visibility = 0.;
+        for _ in 0..settings.ao_nb_samples {
+            let direction = get_cosine_direction(surface.normal, random);
+            let ao_ray = Ray::new(surface.position, direction, true);
+            visibility += self.get_shadow_transmittance(&ao_ray, settings.ao_radius, stats);
+        }
+        visibility / settings.ao_nb_samples as f32
+    }
+
+    /// Direct lighting plus the reflected and refracted rays, traced with a bounded stack
+    /// like the shader. Same as `trace_whitted` in src/shaders/raytracing.slang
+    pub fn trace_whitted(
+        &self,
+        settings: &IntegratorSettings,
+        primary_ray: &Ray,
+        primary_hit: &Hit,
+        random: &mut Random,
+        stats: &mut TraversalStats,
+    ) -> Vec3 {
+        let mut color = Vec3::ZERO;
+        let mut stack = Vec::with_capacity(WHITTED_STACK_SIZE);
+        stack.push(WhittedRay {
+            ray: *primary_ray,
+            weight: Vec3::ONE,
+            depth: 0,
+            absorption: Vec3::ZERO,
+        });
+        let mut is_primary = true;
+        while let Some(whitted_ray) = stack.pop() {
+            let ray = whitted_ray.ray;
+            let hit = if is_primary {
+                is_primary = false;
+                *primary_hit
+            } else {
+                match self.get_visible_hit(&ray, random, stats) {
+                    Some(hit) => hit,
+                    None => {
+                        color += whitted_ray.weight * self.get_miss_radiance(ray.direction);
+                        continue;
+                    }
+                }
+            };
+            // Beer's law inside the dielectrics
+            let weight = whitted_ray.weight * (-whitted_ray.absorption * hit.distance).exp();
+
+            let mut surface = self.get_surface(&ray, &hit);
+            let material = surface.material;
+            let brdf = material.get_brdf();
+            let view_direction = -ray.direction;
+            let outward_normal = surface.normal;
+            // Leaving a transparent medium, seen from the inside
+            let is_inside = surface.normal.dot(view_direction) < 0.;
+            if is_inside {
+                surface.normal = -surface.normal;
+            }
+            color += weight * self.shade(ray.origin, &surface, random, stats);
+            if whitted_ray.depth >= settings.whitted_max_depth {
+                continue;
+            }
+
+            // The dielectrics are split by the Fresnel term, the rough ones are refracted like
+            // the smooth ones
+            let cos_theta = surface.normal.dot(view_direction).clamp(0., 1.);
+            let mut reflectance = get_mirror_reflectance(&surface, cos_theta);
+            let mut transmittance = Vec3::ZERO;
+            let mut refracted_direction = None;
+            if brdf.is_dielectric() {
+                let ior = material.index_of_refraction.max(1e-3);
+                let eta = if is_inside { ior } else { 1. / ior };
+                refracted_direction = refract(ray.direction, surface.normal, eta);
+                let fresnel = match refracted_direction {
+                    Some(_) => fresnel_dielectric(cos_theta, eta),
+                    None => 1.,
+                };
+                reflectance += fresnel;
+                transmittance = Vec3::splat(1. - fresnel);
+            }
+
+            let secondary_rays = [
+                (Some(reflect(ray.direction, surface.normal)), reflectance),
+                (refracted_direction, transmittance),
+            ];
+            for (direction, factor) in secondary_rays {
+                let weight = weight * factor;
+                let Some(direction) = direction else {
+                    continue;
+                };
+                if weight.max_element() < WHITTED_MIN_WEIGHT || stack.len() >= WHITTED_STACK_SIZE {
+                    continue;
+                }
+                let is_inside_dielectric =
+                    brdf.is_dielectric() && outward_normal.dot(direction) < 0.;
+                stack.push(WhittedRay {
+                    ray: Ray::new(surface.position, direction, brdf.is_dielectric()),
+                    weight,
+                    depth: whitted_ray.depth + 1,
+                    absorption: if is_inside_dielectric {
+                        material.absorption.truncate()
+                    } else {
+                        Vec3::ZERO
+                    },
+                });
+            }
+        }
+        color
+    }
+
+    /// Average of `nb_samples` frames per pixel with the given integrator, rows from top
+    /// to bottom
+    pub fn render(
+        &self,
+        camera: &CameraGPU,
+        width: u32,
+        height: u32,
+        settings: &IntegratorSettings,
+        nb_samples: u32,
+    ) -> Vec<Vec3> {
+        (0..width * height)
+            .into_par_iter()
+            .map(|pixel_index| {
+                let (x, y) = (pixel_index % width, pixel_index / width);
+                let position =
+                    (Vec2::new(x as f32, y as f32) + 0.5) / Vec2::new(width as f32, height as f32);
+                let ray = Ray::from_camera(position, camera);
+                let mut stats = TraversalStats::default();
+                // Every transparent layer is shaded, like the k-buffer of the shader
+                let nb_hits = if self.has_transparent_materials {
+                    MAX_HITS
+                } else {
+                    1
+                };
+                let hits = self.traverse(&ray, f32::INFINITY, false, nb_hits, &mut stats);
+                let background = match self.scene.get_environment() {
+                    Some(_) => self.get_miss_radiance(ray.direction),
+                    None => get_background(position.y),
+                };
+                if hits.as_slice().is_empty() {
+                    return background;
+                }
+                let mut color = Vec3::ZERO;
+                for frame_index in 0..nb_samples {
+                    let mut random = Random::from_pixel(x, y, frame_index);
+                    color += self.compose_layers(
+                        settings,
+                        &ray,
+                        hits.as_slice(),
+                        background,
+                        &mut random,
+                        &mut stats,
+                    );
+                }
+                color / nb_samples.max(1) as f32
+            })
+            .collect()
+    }
+}
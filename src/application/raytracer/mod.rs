@@ -0,0 +1,287 @@
+use glam::{Mat4, Vec2, Vec3};
+use heatmap::HeatmapType;
+use hit::{
+    ray_aabb_intersection, ray_cuboid_intersection, ray_sphere_intersection,
+    ray_triangle_intersection, ClosestHits, Hit, MAX_HITS,
+};
+use ray::Ray;
+use rayon::prelude::*;
+
+use super::scene::{
+    bvh::BvhNode, camera::CameraGPU, light::EmissiveTriangle, primitive::PrimitiveType, Scene,
+};
+
+pub mod brdf;
+pub mod calibration;
+pub mod dielectric;
+pub mod heatmap;
+pub mod hit;
+pub mod integrator;
+pub mod output;
+pub mod path_tracing;
+pub mod random;
+pub mod ray;
+pub mod shading;
+
+/// Number of intersection tests done while tracing rays
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TraversalStats {
+    pub nb_rays: u64,
+    pub nb_node_tests: u64,
+    // Tests against any primitive, not only triangles
+    pub nb_triangle_tests: u64,
+}
+
+impl TraversalStats {
+    pub fn merge(a: Self, b: Self) -> Self {
+        Self {
+            nb_rays: a.nb_rays + b.nb_rays,
+            nb_node_tests: a.nb_node_tests + b.nb_node_tests,
+            nb_triangle_tests: a.nb_triangle_tests + b.nb_triangle_tests,
+        }
+    }
+
+    pub fn get_node_tests_per_ray(&self) -> f64 {
+        self.nb_node_tests as f64 / self.nb_rays.max(1) as f64
+    }
+
+    pub fn get_triangle_tests_per_ray(&self) -> f64 {
+        self.nb_triangle_tests as f64 / self.nb_rays.max(1) as f64
+    }
+}
+
+/// Traces rays on the CPU with the same traversal as the compute shader
+pub struct CpuRaytracer<'a> {
+    // Primitives in world space, a vertex belongs to a single model
+    vertices: Vec<Vec3>,
+    triangles: Vec<[u32; 3]>,
+    spheres: Vec<(Vec3, f32)>,
+    // Model matrix and model space corners
+    cuboids: Vec<(Mat4, Vec3, Vec3)>,
+    // An empty bvh means that every primitive is tested
+    bvh: &'a [BvhNode],
+    // Materials, textures and lights used to shade the hits
+    scene: &'a Scene,
+    emissive_triangles: Vec<EmissiveTriangle>,
+    // Otherwise the closest hit is always the visible one
+    has_transparent_materials: bool,
+}
+
+impl<'a> CpuRaytracer<'a> {
+    pub fn new(scene: &'a Scene, bvh: &'a [BvhNode]) -> Self {
+        let mut vertices = vec![Vec3::ZERO; scene.mesh.vertices.len()];
+        for triangle in &scene.mesh.triangles {
+            let model_matrix = scene.models[triangle.model_index as usize].model_matrix;
+            for index in triangle.indices {
+                let position = scene.mesh.vertices[index as usize].position.truncate();
+                vertices[index as usize] = model_matrix.transform_point3(position);
+            }
+        }
+        let triangles = scene
+            .mesh
+            .triangles
+            .iter()
+            .map(|triangle| triangle.indices)
+            .collect::<Vec<[u32; 3]>>();
+        let spheres = scene
+            .spheres
+            .iter()
+            .map(|sphere| {
+                (
+                    sphere.get_world_center(&scene.models),
+                    sphere.get_world_radius(&scene.models),
+                )
+            })
+            .collect::<Vec<(Vec3, f32)>>();
+        let cuboids = scene
+            .cuboids
+            .iter()
+            .map(|cuboid| {
+                (
+                    scene.models[cuboid.model_index as usize].model_matrix,
+                    cuboid.mins.truncate(),
+                    cuboid.maxs.truncate(),
+                )
+            })
+            .collect::<Vec<(Mat4, Vec3, Vec3)>>();
+        Self {
+            vertices,
+            triangles,
+            spheres,
+            cuboids,
+            bvh,
+            scene,
+            emissive_triangles: scene.get_emissive_triangles(),
+            has_transparent_materials: scene.has_transparent_materials(),
+        }
+    }
+
+    pub fn get_closest_hit(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+        stats: &mut TraversalStats,
+    ) -> Option<Hit> {
+        self.traverse(ray, max_distance, false, 1, stats).first()
+    }
+
+    /// The `MAX_HITS` closest hits, like the k-buffer of the shader
+    pub fn get_closest_hits(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+        stats: &mut TraversalStats,
+    ) -> ClosestHits {
+        self.traverse(ray, max_distance, false, MAX_HITS, stats)
+    }
+
+    /// Stops at the first hit closer than `max_distance`
+    pub fn is_occluded(&self, ray: &Ray, max_distance: f32, stats: &mut TraversalStats) -> bool {
+        self.traverse(ray, max_distance, true, 1, stats)
+            .first()
+            .is_some()
+    }
+
+    fn traverse(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+        is_any_hit: bool,
+        nb_hits: usize,
+        stats: &mut TraversalStats,
+    ) -> ClosestHits {
+        stats.nb_rays += 1;
+        let mut hits = ClosestHits::new(nb_hits);
+        if self.bvh.is_empty() {
+            self.traverse_primitives(ray, max_distance, is_any_hit, &mut hits, stats);
+        } else {
+            self.traverse_bvh(ray, max_distance, is_any_hit, &mut hits, stats);
+        }
+        hits
+    }
+
+    /// Count the tests done by the primary ray of each pixel, rows from top to bottom
+    pub fn render_heatmap(
+        &self,
+        camera: &CameraGPU,
+        width: u32,
+        height: u32,
+        heatmap_type: HeatmapType,
+    ) -> Vec<u32> {
+        (0..width * height)
+            .into_par_iter()
+            .map(|pixel_index| {
+                let (x, y) = (pixel_index % width, pixel_index / width);
+                let position =
+                    (Vec2::new(x as f32, y as f32) + 0.5) / Vec2::new(width as f32, height as f32);
+                let ray = Ray::from_camera(position, camera);
+                let mut stats = TraversalStats::default();
+                let _ = self.get_closest_hit(&ray, f32::INFINITY, &mut stats);
+                heatmap_type.get_count(&stats) as u32
+            })
+            .collect::<Vec<u32>>()
+    }
+
+    fn test_primitive(
+        &self,
+        ray: &Ray,
+        primitive_type: PrimitiveType,
+        primitive_index: usize,
+        hits: &mut ClosestHits,
+        max_distance: f32,
+        stats: &mut TraversalStats,
+    ) {
+        stats.nb_triangle_tests += 1;
+        let hit = match primitive_type {
+            PrimitiveType::Triangle => {
+                let positions =
+                    self.triangles[primitive_index].map(|index| self.vertices[index as usize]);
+                ray_triangle_intersection(ray, primitive_index, &positions)
+            }
+            PrimitiveType::Sphere => {
+                let (center, radius) = self.spheres[primitive_index];
+                ray_sphere_intersection(ray, center, radius)
+                    .map(|distance| Hit::from_analytic(distance, primitive_type, primitive_index))
+            }
+            PrimitiveType::Cuboid => {
+                let (model_matrix, mins, maxs) = self.cuboids[primitive_index];
+                ray_cuboid_intersection(ray, &model_matrix, mins, maxs)
+                    .map(|distance| Hit::from_analytic(distance, primitive_type, primitive_index))
+            }
+        };
+        if let Some(hit) = hit {
+            if hit.distance < hits.get_max_distance(max_distance) {
+                hits.insert(hit);
+            }
+        }
+    }
+
+    fn traverse_primitives(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+        is_any_hit: bool,
+        hits: &mut ClosestHits,
+        stats: &mut TraversalStats,
+    ) {
+        let primitives = [
+            (PrimitiveType::Triangle, self.triangles.len()),
+            (PrimitiveType::Sphere, self.spheres.len()),
+            (PrimitiveType::Cuboid, self.cuboids.len()),
+        ];
+        for (primitive_type, nb_primitives) in primitives {
+            for primitive_index in 0..nb_primitives {
+                self.test_primitive(
+                    ray,
+                    primitive_type,
+                    primitive_index,
+                    hits,
+                    max_distance,
+                    stats,
+                );
+                if is_any_hit && hits.first().is_some() {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn traverse_bvh(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+        is_any_hit: bool,
+        hits: &mut ClosestHits,
+        stats: &mut TraversalStats,
+    ) {
+        let mut stack = vec![0_usize];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.bvh[node_index];
+            stats.nb_node_tests += 1;
+            match ray_aabb_intersection(ray, &node.bounding_box) {
+                Some(distance) if distance < hits.get_max_distance(max_distance) => (),
+                _ => continue,
+            }
+
+            if node.is_leaf() {
+                let Some(primitive_type) = node.get_primitive_type() else {
+                    continue;
+                };
+                self.test_primitive(
+                    ray,
+                    primitive_type,
+                    node.primitive_index as usize,
+                    hits,
+                    max_distance,
+                    stats,
+                );
+                if is_any_hit && hits.first().is_some() {
+                    break;
+                }
+            } else {
+                stack.push(node.right_child_index as usize);
+                stack.push(node.left_child_index as usize);
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::{fmt::Write as _, fs, path::Path};
+
+use glam::Vec3;
+use log::error;
+
+use crate::application::core::error::ErrorCode;
+
+/// Write linear colors in [0;1] as a plain text PPM image, rows from top to bottom
+pub fn write_ppm(path: &Path, pixels: &[Vec3], width: u32, height: u32) -> Result<(), ErrorCode> {
+    if pixels.len() != (width * height) as usize {
+        error!(
+            "Can't write a {}x{} image from {} pixels",
+            width,
+            height,
+            pixels.len()
+        );
+        return Err(ErrorCode::Unknown);
+    }
+
+    let mut ppm = format!("P3\n{} {}\n255\n", width, height);
+    for row in pixels.chunks(width as usize) {
+        for pixel in row {
+            let color = (pixel.clamp(Vec3::ZERO, Vec3::ONE) * 255.).round();
+            let _ = write!(ppm, "{} {} {} ", color.x, color.y, color.z);
+        }
+        ppm.push('\n');
+    }
+
+    if let Err(err) = fs::write(path, ppm) {
+        error!("Failed to write the image `{:?}': {:?}", path, err);
+        return Err(ErrorCode::IO);
+    }
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+use crate::application::scene::{material::Brdf, primitive::PrimitiveType};
+
+use super::{
+    brdf::{ggx_distribution, MIN_ALPHA},
+    dielectric::{get_transmittance, sample_specular},
+    hit::Hit,
+    random::Random,
+    ray::Ray,
+    shading::{get_background, get_reflected, Surface},
+    CpuRaytracer, TraversalStats,
+};
+
+/// Bounces after which the paths are randomly terminated, should match the shader
+/// see src/shaders/raytracer/path_tracing.slang
+pub const RUSSIAN_ROULETTE_DEPTH: u32 = 3;
+pub const DEFAULT_MAX_DEPTH: u32 = 4;
+
+/// Next direction of a path and the factor applied to the light coming from it
+#[derive(Debug, Clone, Copy)]
+pub struct Bounce {
+    pub direction: Vec3,
+    pub weight: Vec3,
+}
+
+/// Colour of the bounces that leave the scene, the same gradient as the background
+pub fn get_sky(direction: Vec3) -> Vec3 {
+    get_background(0.5 * (1. - direction.y))
+}
+
+/// Orthonormal basis around `normal`, same helper axis as the disks of the area lights
+fn get_tangent_frame(normal: Vec3) -> (Vec3, Vec3) {
+    let helper = if normal.y.abs() < 0.999 {
+        Vec3::Y
+    } else {
+        Vec3::X
+    };
+    let tangent = helper.cross(normal).normalize();
+    (tangent, normal.cross(tangent))
+}
+
+pub fn to_world(normal: Vec3, local: Vec3) -> Vec3 {
+    let (tangent, bitangent) = get_tangent_frame(normal);
+    local.x * tangent + local.y * bitangent + local.z * normal
+}
+
+/// Direction around the z axis whose polar angle has the cosine `cos_theta`
+pub fn get_local_direction(cos_theta: f32, u: f32) -> Vec3 {
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * u;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Cosine weighted direction around `normal`
+pub fn get_cosine_direction(normal: Vec3, random: &mut Random) -> Vec3 {
+    let u0 = random.next_f32();
+    let u1 = random.next_f32();
+    to_world(normal, get_local_direction(u0.sqrt(), u1))
+}
+
+/// Probability of sampling the specular lobe rather than the diffuse one
+fn get_specular_probability(surface: &Surface) -> f32 {
+    let material = &surface.material;
+    if material.get_brdf() == Brdf::Ggx {
+        return 0.5 * (1. + material.metallic);
+    }
+    if material.illumination_model < 2 {
+        return 0.;
+    }
+    let specular = material.specular.truncate().element_sum();
+    let diffuse = surface.diffuse.element_sum();
+    if specular + diffuse <= 0. {
+        return 0.;
+    }
+    specular / (specular + diffuse)
+}
+
+/// Density of the directions sampled by the specular lobe, over the solid angle
+fn get_specular_pdf(surface: &Surface, view_direction: Vec3, direction: Vec3) -> f32 {
+    let half_vector = (view_direction + direction).normalize_or_zero();
+    let n_dot_h = surface.normal.dot(half_vector).max(0.);
+    let v_dot_h = view_direction.dot(half_vector);
+    if v_dot_h <= 0. {
+        return 0.;
+    }
+    let half_vector_pdf = if surface.material.get_brdf() == Brdf::Ggx {
+        let alpha = f32::max(surface.roughness * surface.roughness, MIN_ALPHA);
+        ggx_distribution(n_dot_h, alpha) * n_dot_h
+    } else {
+        (surface.shininess + 1.) / (2. * PI) * n_dot_h.powf(surface.shininess)
+    };
+    half_vector_pdf / (4. * v_dot_h)
+}
+
+/// Pick the next direction with a mix of the cosine and specular lobes, same as
+/// `sample_bounce` in src/shaders/raytracer/path_tracing.slang
+pub fn sample_bounce(
+    surface: &Surface,
+    view_direction: Vec3,
+    random: &mut Random,
+) -> Option<Bounce> {
+    let specular_probability = get_specular_probability(surface);
+    let u = random.next_f32();
+    let u0 = random.next_f32();
+    let u1 = random.next_f32();
+    let normal = surface.normal;
+    let direction = if u < specular_probability {
+        let cos_theta = if surface.material.get_brdf() == Brdf::Ggx {
+            let alpha = f32::max(surface.roughness * surface.roughness, MIN_ALPHA);
+            ((1. - u0) / (1. + (alpha * alpha - 1.) * u0)).sqrt()
+        } else {
+            u0.powf(1. / (surface.shininess + 1.))
+        };
+        let half_vector = to_world(normal, get_local_direction(cos_theta, u1));
+        2. * view_direction.dot(half_vector) * half_vector - view_direction
+    } else {
+        to_world(normal, get_local_direction(u0.sqrt(), u1))
+    };
+
+    let n_dot_l = normal.dot(direction);
+    if n_dot_l <= 0. {
+        return None;
+    }
+    let mut pdf = (1. - specular_probability) * n_dot_l / PI;
+    if specular_probability > 0. {
+        pdf += specular_probability * get_specular_pdf(surface, view_direction, direction);
+    }
+    if pdf <= 0. {
+        return None;
+    }
+    // `get_reflected` is PI times the BRDF with the cosine
+    Some(Bounce {
+        direction,
+        weight: get_reflected(surface, view_direction, direction) / (PI * pdf),
+    })
+}
+
+impl CpuRaytracer<'_> {
+    /// Light brought back by a path starting at the primary hit, same as `trace_path` in
+    /// src/shaders/raytracing.slang
+    pub fn trace_path(
+        &self,
+        primary_ray: &Ray,
+        primary_hit: &Hit,
+        max_depth: u32,
+        random: &mut Random,
+        stats: &mut TraversalStats,
+    ) -> Vec3 {
+        let mut ray = *primary_ray;
+        let mut hit = *primary_hit;
+        let mut radiance = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        let mut depth = 0;
+        let mut is_specular_bounce = false;
+        loop {
+            let surface = self.get_surface(&ray, &hit);
+            let material = &surface.material;
+            // Emissive triangles are already sampled by the next event estimation, except
+            // behind the mirrors and the dielectrics
+            if depth == 0 || is_specular_bounce || hit.primitive_type != PrimitiveType::Triangle {
+                radiance += throughput * material.emission.truncate();
+            }
+            // Constant color, without lighting
+            if material.get_brdf() == Brdf::Lambert && material.illumination_model == 0 {
+                radiance += throughput * surface.diffuse;
+                break;
+            }
+
+            let view_direction = -ray.direction;
+            radiance +=
+                throughput * self.get_direct_lighting(&surface, view_direction, random, stats);
+            if depth >= max_depth {
+                break;
+            }
+
+            is_specular_bounce = material.get_brdf().is_specular();
+            let bounce = if is_specular_bounce {
+                sample_specular(&surface, view_direction, random)
+            } else {
+                sample_bounce(&surface, view_direction, random)
+            };
+            let Some(bounce) = bounce else {
+                break;
+            };
+            throughput *= bounce.weight;
+            if depth >= RUSSIAN_ROULETTE_DEPTH {
+                let survival_probability = throughput.max_element().clamp(0.05, 0.95);
+                if random.next_f32() >= survival_probability {
+                    break;
+                }
+                throughput /= survival_probability;
+            }
+
+            depth += 1;
+            // The dielectrics are also hit from the inside
+            ray = Ray::new(surface.position, bounce.direction, is_specular_bounce);
+            match self.get_visible_hit(&ray, random, stats) {
+                Some(next_hit) => {
+                    throughput *= get_transmittance(
+                        material,
+                        surface.normal,
+                        ray.direction,
+                        next_hit.distance,
+                    );
+                    hit = next_hit;
+                }
+                None => {
+                    // The environment map is already sampled by the next event estimation,
+                    // except behind the mirrors and the dielectrics
+                    if is_specular_bounce || self.scene.get_environment().is_none() {
+                        radiance += throughput * self.get_miss_radiance(ray.direction);
+                    }
+                    break;
+                }
+            }
+        }
+        radiance
+    }
+}
@@ -0,0 +1,40 @@
+/// PCG generator of the shader, so both renderers draw the same numbers for a pixel
+/// see src/shaders/raytracer/random.slang
+#[derive(Debug, Clone, Copy)]
+pub struct Random {
+    state: u32,
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        let mut random = Self { state: seed };
+        let _ = random.next_u32();
+        random
+    }
+
+    /// Different for every pixel and every frame
+    pub fn from_pixel(x: u32, y: u32, frame_index: u32) -> Self {
+        let pixel_seed = pcg_hash(x ^ pcg_hash(y));
+        Self::new(pixel_seed ^ pcg_hash(frame_index))
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let value = pcg_hash(self.state);
+        self.state = self
+            .state
+            .wrapping_mul(747_796_405)
+            .wrapping_add(2_891_336_453);
+        value
+    }
+
+    /// In [0;1[
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / 16_777_216.
+    }
+}
+
+fn pcg_hash(value: u32) -> u32 {
+    let state = value.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    (word >> 22) ^ word
+}
@@ -0,0 +1,39 @@
+use glam::{Vec2, Vec3, Vec4};
+
+use crate::application::scene::camera::CameraGPU;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub inverse_direction: Vec3,
+    // Set for the shadow rays and the rays going through transparent materials
+    pub hits_back_faces: bool,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3, hits_back_faces: bool) -> Self {
+        let direction = direction.normalize();
+        Self {
+            origin,
+            direction,
+            inverse_direction: direction.recip(),
+            hits_back_faces,
+        }
+    }
+
+    /// Same primary ray as the compute shader, `position` is in [0;1]
+    /// see src/shaders/raytracer/ray.slang
+    pub fn from_camera(position: Vec2, camera: &CameraGPU) -> Self {
+        let position_view_space = (position - 0.5).extend(1.)
+            * Vec3::new(camera.plane_width, -camera.plane_height, camera.plane_near);
+        let position_world_space = camera.view_matrix_inverse * position_view_space.extend(1.);
+        let origin = camera.position.truncate();
+        let direction = (position_world_space - Vec4::from((origin, 1.))).truncate();
+        Self::new(origin, direction, false)
+    }
+
+    pub fn at(&self, distance: f32) -> Vec3 {
+        self.origin + distance * self.direction
+    }
+}
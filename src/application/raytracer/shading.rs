@@ -0,0 +1,393 @@
+use std::f32::consts::PI;
+
+use glam::{Mat3, Vec2, Vec3};
+
+use crate::application::scene::{
+    light::{EmissiveTriangle, LightSample},
+    material::{Brdf, Material},
+    primitive::PrimitiveType,
+};
+
+use super::{
+    brdf::{evaluate_ggx, GgxInputs},
+    hit::{get_cuboid_normal, Hit, MAX_HITS},
+    path_tracing::get_sky,
+    random::Random,
+    ray::Ray,
+    CpuRaytracer, TraversalStats,
+};
+
+/// Number of points of the emissive triangles tested for each hit, should match the shader
+/// see src/shaders/raytracer/light.slang
+pub const EMISSIVE_SAMPLES: u32 = 4;
+/// Directions of the environment map tested for each hit, should match the shader
+/// see src/shaders/raytracer/light.slang
+pub const ENVIRONMENT_SAMPLES: u32 = 4;
+
+/// Shading attributes of a hit, once the textures of its material have been sampled
+/// see src/shaders/raytracer/surface.slang
+#[derive(Debug, Clone, Copy)]
+pub struct Surface {
+    pub position: Vec3,
+    // Interpolated normal perturbed by the normal map
+    pub normal: Vec3,
+    pub diffuse: Vec3,
+    pub shininess: f32,
+    pub roughness: f32,
+    pub material: Material,
+}
+
+/// Light reflected towards the eye for a white light of intensity 1, the Lambert term is
+/// PI times the BRDF. Same as the shader, see src/shaders/raytracer/light.slang
+pub fn get_reflected(surface: &Surface, view_direction: Vec3, light_direction: Vec3) -> Vec3 {
+    let material = &surface.material;
+    let diffuse_factor = surface.normal.dot(light_direction).clamp(0., 1.);
+    if material.get_brdf() == Brdf::Ggx {
+        let ggx = evaluate_ggx(&GgxInputs {
+            normal: surface.normal,
+            view_direction,
+            light_direction,
+            base_color: surface.diffuse,
+            metallic: material.metallic,
+            roughness: surface.roughness,
+            specular_factor: material.specular_factor,
+        });
+        return PI * ggx * diffuse_factor;
+    }
+    let mut reflected = diffuse_factor * surface.diffuse;
+    // Blinn-Phong specular term
+    if material.illumination_model >= 2 && diffuse_factor > 0. {
+        let half_vector = (light_direction + view_direction).normalize();
+        let specular_factor = surface
+            .normal
+            .dot(half_vector)
+            .clamp(0., 1.)
+            .powf(surface.shininess);
+        reflected += specular_factor * material.specular.truncate();
+    }
+    reflected
+}
+
+/// Colour of the rays that miss the scene, `y` is the position of the pixel in [0;1]
+pub fn get_background(y: f32) -> Vec3 {
+    let factor = 0.5 * y + 1.;
+    (1. - factor) * Vec3::ONE + factor * Vec3::new(0.5, 0.7, 1.)
+}
+
+/// Normal of a normal map in the world, see src/shaders/scene/triangle.slang
+fn apply_normal_map(normal: Vec3, tangent: Vec3, uv_bitangent: Vec3, tangent_normal: Vec3) -> Vec3 {
+    let projected_tangent = tangent - normal * normal.dot(tangent);
+    if projected_tangent.length_squared() == 0. {
+        return normal;
+    }
+    let t = projected_tangent.normalize();
+    let mut b = normal.cross(t);
+    if b.dot(uv_bitangent) < 0. {
+        b = -b;
+    }
+    (tangent_normal.x * t + tangent_normal.y * b + tangent_normal.z * normal).normalize_or(normal)
+}
+
+impl CpuRaytracer<'_> {
+    /// Same attributes as `get_surface` in src/shaders/raytracing.slang
+    pub fn get_surface(&self, ray: &Ray, hit: &Hit) -> Surface {
+        let scene = self.scene;
+        let position = ray.at(hit.distance);
+        let (model_index, normal, uv, tangents, vertex_color) = match hit.primitive_type {
+            PrimitiveType::Triangle => {
+                let triangle = &scene.mesh.triangles[hit.primitive_index];
+                let model_matrix = scene.models[triangle.model_index as usize].model_matrix;
+                let vertices = triangle
+                    .indices
+                    .map(|index| scene.mesh.vertices[index as usize]);
+                let b = hit.barycentric_coordinates;
+                let normal = b.x * vertices[0].normal.truncate()
+                    + b.y * vertices[1].normal.truncate()
+                    + b.z * vertices[2].normal.truncate();
+                let normal_matrix = Mat3::from_mat4(model_matrix).inverse().transpose();
+                let uv = b.x * vertices[0].uv + b.y * vertices[1].uv + b.z * vertices[2].uv;
+                (
+                    triangle.model_index,
+                    (normal_matrix * normal).normalize(),
+                    uv,
+                    self.get_world_tangents(hit.primitive_index),
+                    triangle.get_color(&scene.mesh.vertices, b).truncate(),
+                )
+            }
+            PrimitiveType::Sphere => {
+                let (center, _) = self.spheres[hit.primitive_index];
+                let model_index = scene.spheres[hit.primitive_index].model_index;
+                (
+                    model_index,
+                    (position - center).normalize(),
+                    Vec2::ZERO,
+                    None,
+                    Vec3::ONE,
+                )
+            }
+            PrimitiveType::Cuboid => {
+                let (model_matrix, mins, maxs) = self.cuboids[hit.primitive_index];
+                let model_index = scene.cuboids[hit.primitive_index].model_index;
+                let normal = get_cuboid_normal(&model_matrix, mins, maxs, position);
+                (model_index, normal, Vec2::ZERO, None, Vec3::ONE)
+            }
+        };
+
+        let material = scene.materials[scene.models[model_index as usize].material_index as usize];
+        let textures = &scene.texture_images;
+        let normal = match tangents {
+            Some((tangent, bitangent)) => apply_normal_map(
+                normal,
+                tangent,
+                bitangent,
+                material.get_tangent_normal(uv, textures),
+            ),
+            None => normal,
+        };
+        Surface {
+            position,
+            normal,
+            diffuse: vertex_color * material.get_diffuse(uv, textures).truncate(),
+            shininess: material.get_shininess(uv, textures),
+            roughness: material.get_roughness(uv, textures),
+            material,
+        }
+    }
+
+    /// Opacity of the material at the hit, same as `get_opacity` in
+    /// src/shaders/raytracing.slang
+    pub fn get_opacity(&self, hit: &Hit) -> f32 {
+        let scene = self.scene;
+        let (model_index, uv) = match hit.primitive_type {
+            PrimitiveType::Triangle => {
+                let triangle = &scene.mesh.triangles[hit.primitive_index];
+                let [uv0, uv1, uv2] = triangle
+                    .indices
+                    .map(|index| scene.mesh.vertices[index as usize].uv);
+                let b = hit.barycentric_coordinates;
+                (triangle.model_index, b.x * uv0 + b.y * uv1 + b.z * uv2)
+            }
+            PrimitiveType::Sphere => (scene.spheres[hit.primitive_index].model_index, Vec2::ZERO),
+            PrimitiveType::Cuboid => (scene.cuboids[hit.primitive_index].model_index, Vec2::ZERO),
+        };
+        let material = &scene.materials[scene.models[model_index as usize].material_index as usize];
+        material.get_opacity(uv, &scene.texture_images)
+    }
+
+    /// Closest hit that isn't skipped, the surfaces are skipped with the probability of their
+    /// transparency. Same as `trace_visible_hit` in src/shaders/raytracing.slang
+    pub fn get_visible_hit(
+        &self,
+        ray: &Ray,
+        random: &mut Random,
+        stats: &mut TraversalStats,
+    ) -> Option<Hit> {
+        if !self.has_transparent_materials {
+            return self.get_closest_hit(ray, f32::INFINITY, stats);
+        }
+        let hits = self.get_closest_hits(ray, f32::INFINITY, stats);
+        hits.as_slice().iter().copied().find(|hit| {
+            let opacity = self.get_opacity(hit);
+            opacity >= 1. || random.next_f32() < opacity
+        })
+    }
+
+    /// Fraction of the light going through the surfaces closer than `max_distance`, the ray is
+    /// traced again from the last hit while all the `MAX_HITS` are filled. Same as
+    /// `Lights.get_shadow_transmittance` in src/shaders/raytracer/light.slang
+    pub fn get_shadow_transmittance(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+        stats: &mut TraversalStats,
+    ) -> f32 {
+        if !self.has_transparent_materials {
+            return if self.is_occluded(ray, max_distance, stats) {
+                0.
+            } else {
+                1.
+            };
+        }
+        let mut ray = *ray;
+        let mut max_distance = max_distance;
+        let mut transmittance = 1.;
+        loop {
+            let closest_hits = self.get_closest_hits(&ray, max_distance, stats);
+            let hits = closest_hits.as_slice();
+            for hit in hits {
+                transmittance *= 1. - self.get_opacity(hit);
+                if transmittance <= 0. {
+                    return 0.;
+                }
+            }
+            // Fewer hits than slots means none was dropped
+            if hits.len() < MAX_HITS {
+                return transmittance;
+            }
+            let last_distance = hits[MAX_HITS - 1].distance;
+            ray = Ray::new(ray.at(last_distance), ray.direction, ray.hits_back_faces);
+            max_distance -= last_distance;
+        }
+    }
+
+    /// Directions of the u and v axes of the texture in the world, the bitangent points up
+    /// the image. None when the texture coordinates are degenerate.
+    fn get_world_tangents(&self, triangle_index: usize) -> Option<(Vec3, Vec3)> {
+        let indices = self.triangles[triangle_index];
+        let [p0, p1, p2] = indices.map(|index| self.vertices[index as usize]);
+        let [uv0, uv1, uv2] = indices.map(|index| self.scene.mesh.vertices[index as usize].uv);
+        let (e0, e1) = (p1 - p0, p2 - p0);
+        let (duv0, duv1) = (uv1 - uv0, uv2 - uv0);
+        let determinant = duv0.x * duv1.y - duv0.y * duv1.x;
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let tangent = (e0 * duv1.y - e1 * duv0.y) / determinant;
+        // The v axis goes down the image
+        let bitangent = -(e1 * duv0.x - e0 * duv1.x) / determinant;
+        Some((tangent, bitangent))
+    }
+
+    /// Direct lighting of the lights and emissive triangles of the scene, same as
+    /// `Lights.shade` in src/shaders/raytracer/light.slang
+    pub fn shade(
+        &self,
+        eye: Vec3,
+        surface: &Surface,
+        random: &mut Random,
+        stats: &mut TraversalStats,
+    ) -> Vec3 {
+        let material = &surface.material;
+        let emission = material.emission.truncate();
+        // Constant color, without lighting
+        if material.get_brdf() == Brdf::Lambert && material.illumination_model == 0 {
+            return surface.diffuse + emission;
+        }
+
+        let view_direction = (eye - surface.position).normalize();
+        emission + self.get_direct_lighting(surface, view_direction, random, stats)
+    }
+
+    /// Light coming from the rays that leave the scene, the environment map or the gradient
+    pub fn get_miss_radiance(&self, direction: Vec3) -> Vec3 {
+        match self.scene.get_environment() {
+            Some(environment) => {
+                environment.get_radiance(direction, &self.scene.environment_settings)
+            }
+            None => get_sky(direction),
+        }
+    }
+
+    /// Light received from the lights, the emissive triangles and the environment map,
+    /// without the emission of the surface itself
+    pub fn get_direct_lighting(
+        &self,
+        surface: &Surface,
+        view_direction: Vec3,
+        random: &mut Random,
+        stats: &mut TraversalStats,
+    ) -> Vec3 {
+        // Only their reflections and refractions bring light
+        if surface.material.get_brdf().is_specular() {
+            return Vec3::ZERO;
+        }
+        let mut color = Vec3::ZERO;
+        for light in &self.scene.lights {
+            let nb_samples = light.get_nb_samples();
+            for sample_index in 0..nb_samples {
+                let light_sample = light.get_sample(sample_index, surface.position);
+                color += self.get_light_contribution(surface, view_direction, &light_sample, stats)
+                    / nb_samples as f32;
+            }
+        }
+        if !self.emissive_triangles.is_empty() {
+            for _ in 0..EMISSIVE_SAMPLES {
+                if let Some(light_sample) = self.sample_emissive_triangle(surface.position, random)
+                {
+                    color +=
+                        self.get_light_contribution(surface, view_direction, &light_sample, stats)
+                            / EMISSIVE_SAMPLES as f32;
+                }
+            }
+        }
+        if let Some(environment) = self.scene.get_environment() {
+            for _ in 0..ENVIRONMENT_SAMPLES {
+                let u0 = random.next_f32();
+                let u1 = random.next_f32();
+                if let Some(light_sample) =
+                    environment.sample(&self.scene.environment_settings, u0, u1)
+                {
+                    color +=
+                        self.get_light_contribution(surface, view_direction, &light_sample, stats)
+                            / ENVIRONMENT_SAMPLES as f32;
+                }
+            }
+        }
+        color
+    }
+
+    fn get_light_contribution(
+        &self,
+        surface: &Surface,
+        view_direction: Vec3,
+        light_sample: &LightSample,
+        stats: &mut TraversalStats,
+    ) -> Vec3 {
+        if light_sample.radiance == Vec3::ZERO || surface.normal.dot(light_sample.direction) <= 0. {
+            return Vec3::ZERO;
+        }
+        let shadow_ray = Ray::new(surface.position, light_sample.direction, true);
+        let transmittance =
+            self.get_shadow_transmittance(&shadow_ray, light_sample.max_distance, stats);
+        if transmittance <= 0. {
+            return Vec3::ZERO;
+        }
+        transmittance
+            * get_reflected(surface, view_direction, light_sample.direction)
+            * light_sample.radiance
+    }
+
+    /// Point of an emissive triangle picked according to its power, None when it faces away
+    pub fn sample_emissive_triangle(
+        &self,
+        hit_position: Vec3,
+        random: &mut Random,
+    ) -> Option<LightSample> {
+        let emissive_triangle =
+            EmissiveTriangle::sample(&self.emissive_triangles, random.next_f32())?;
+        let triangle_index = emissive_triangle.triangle_index as usize;
+        let [p0, p1, p2] =
+            self.triangles[triangle_index].map(|index| self.vertices[index as usize]);
+
+        // Uniform point of the triangle
+        let sqrt_u0 = random.next_f32().sqrt();
+        let u1 = random.next_f32();
+        let light_position = (1. - sqrt_u0) * p0 + u1 * sqrt_u0 * p1 + (1. - u1) * sqrt_u0 * p2;
+
+        let to_light = light_position - hit_position;
+        let distance = to_light.length();
+        if distance <= 0. {
+            return None;
+        }
+        let direction = to_light / distance;
+        // The front face emits
+        let cos_emission = (p1 - p0).cross(p2 - p0).normalize().dot(-direction);
+        if cos_emission <= 0. {
+            return None;
+        }
+
+        let model_index = self.scene.mesh.triangles[triangle_index].model_index as usize;
+        let material_index = self.scene.models[model_index].material_index as usize;
+        let emission = self.scene.materials[material_index].emission.truncate();
+        // Divided by the solid angle density of the point, and by PI as `get_reflected` is PI
+        // times the BRDF
+        let radiance = emission * cos_emission * emissive_triangle.area
+            / (PI * distance * distance * emissive_triangle.probability);
+        Some(LightSample {
+            direction,
+            // The shadow ray must not hit the light itself
+            max_distance: 0.999 * distance,
+            radiance,
+        })
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use log::error;
+
+use crate::application::core::error::ErrorCode;
+
+/// Directories searched before the configured ones, separated like the `PATH` variable
+pub const ASSETS_PATH_VARIABLE: &str = "RUST_VULKAN_ASSETS_PATH";
+
+/// Finds the model files given by an absolute path, a path relative to the working
+/// directory or a path relative to one of the search directories
+#[derive(Debug, Clone)]
+pub struct AssetResolver {
+    pub search_directories: Vec<PathBuf>,
+    // Missing models of a scene are replaced by a placeholder instead of failing
+    pub should_use_placeholders: bool,
+}
+
+impl Default for AssetResolver {
+    fn default() -> Self {
+        Self::new(Vec::new(), false)
+    }
+}
+
+impl AssetResolver {
+    /// The directories of the environment variable come first and the default ones last
+    pub fn new(search_directories: Vec<PathBuf>, should_use_placeholders: bool) -> Self {
+        let mut directories = Vec::new();
+        if let Some(paths) = env::var_os(ASSETS_PATH_VARIABLE) {
+            directories.extend(env::split_paths(&paths));
+        }
+        directories.extend(search_directories);
+        directories.extend(Self::get_default_directories());
+        Self {
+            search_directories: directories,
+            should_use_placeholders,
+        }
+    }
+
+    /// Next to the working directory, next to the executable, then in the source tree
+    fn get_default_directories() -> Vec<PathBuf> {
+        let assets_directory = Path::new("assets/models");
+        let mut directories = vec![assets_directory.to_path_buf()];
+        if let Some(executable_directory) = env::current_exe()
+            .ok()
+            .and_then(|executable| executable.parent().map(Path::to_path_buf))
+        {
+            directories.push(executable_directory.join(assets_directory));
+        }
+        directories.push(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/assets/models"));
+        directories
+    }
+
+    pub fn resolve(&self, path: &Path) -> Result<PathBuf, ErrorCode> {
+        if path.is_file() {
+            return Ok(path.to_path_buf());
+        }
+        if path.is_relative() {
+            if let Some(resolved_path) = self
+                .search_directories
+                .iter()
+                .map(|directory| directory.join(path))
+                .find(|candidate| candidate.is_file())
+            {
+                return Ok(resolved_path);
+            }
+        }
+
+        error!(
+            "The asset `{}' was not found, the search directories are {:?}, more can be given with `{}'",
+            path.display(),
+            self.search_directories,
+            ASSETS_PATH_VARIABLE
+        );
+        Err(ErrorCode::NotFound)
+    }
+
+    /// Sorted names of the files with one of the given extensions in the search directories
+    pub fn get_file_names(&self, extensions: &[&str]) -> Vec<PathBuf> {
+        let mut file_names = self
+            .search_directories
+            .iter()
+            .filter_map(|directory| fs::read_dir(directory).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| PathBuf::from(entry.file_name()))
+            .filter(|file_name| {
+                file_name
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| extensions.contains(&extension))
+            })
+            .collect::<Vec<PathBuf>>();
+        file_names.sort();
+        file_names.dedup();
+        file_names
+    }
+}
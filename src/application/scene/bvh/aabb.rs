@@ -3,7 +3,7 @@ use std::fmt::Debug;
 
 use crate::application::{
     core::error::ErrorCode,
-    scene::{model::Model, triangle::Triangle},
+    scene::{mesh::Mesh, model::Model},
 };
 
 #[derive(Debug, Default)]
@@ -176,7 +176,7 @@ impl Aabb {
         volume_a + volume_b - intersection_volume
     }
 
-    pub fn from_points(points: &Vec<glam::Vec3>) -> Self {
+    pub fn from_points(points: &[glam::Vec3]) -> Self {
         let mut aabb = Aabb::default();
         for point in points {
             aabb.mins.x = f32::min(aabb.mins.x, point.x);
@@ -189,61 +189,18 @@ impl Aabb {
         aabb
     }
 
-    pub fn from_triangle(triangle: &Triangle, model_matrix: glam::Mat4) -> Self {
-        let mut aabb = Aabb::default();
-        // Build the AABB in world space
-        let p0 = model_matrix * triangle.p0;
-        let p1 = model_matrix * triangle.p1;
-        let p2 = model_matrix * triangle.p2;
-
-        let min_x = f32::min(f32::min(p0.x, p1.x), p2.x);
-        let max_x = f32::max(f32::max(p0.x, p1.x), p2.x);
-        let min_y = f32::min(f32::min(p0.y, p1.y), p2.y);
-        let max_y = f32::max(f32::max(p0.y, p1.y), p2.y);
-        let min_z = f32::min(f32::min(p0.z, p1.z), p2.z);
-        let max_z = f32::max(f32::max(p0.z, p1.z), p2.z);
-
-        aabb.mins.x = f32::min(aabb.mins.x, min_x);
-        aabb.mins.y = f32::min(aabb.mins.y, min_y);
-        aabb.mins.z = f32::min(aabb.mins.z, min_z);
-
-        aabb.maxs.x = f32::max(aabb.maxs.x, max_x);
-        aabb.maxs.y = f32::max(aabb.maxs.y, max_y);
-        aabb.maxs.z = f32::max(aabb.maxs.z, max_z);
-
-        aabb
-    }
-
-    pub fn from_scene(triangles: &[Triangle], models: &[Model]) -> Result<Self, ErrorCode> {
-        if triangles.is_empty() {
+    pub fn from_scene(mesh: &Mesh, models: &[Model]) -> Result<Self, ErrorCode> {
+        if mesh.triangles.is_empty() {
             error!("Can't create an AABB from an empty scene");
             return Err(ErrorCode::InitializationFailure);
         }
 
-        let mut aabb = Aabb::default();
-        for triangle in triangles {
-            // Build the AABB in world space
-            let model_matrix = models[triangle.model_index].model_matrix;
-            let p0 = model_matrix * triangle.p0;
-            let p1 = model_matrix * triangle.p1;
-            let p2 = model_matrix * triangle.p2;
-
-            let min_x = f32::min(f32::min(p0.x, p1.x), p2.x);
-            let max_x = f32::max(f32::max(p0.x, p1.x), p2.x);
-            let min_y = f32::min(f32::min(p0.y, p1.y), p2.y);
-            let max_y = f32::max(f32::max(p0.y, p1.y), p2.y);
-            let min_z = f32::min(f32::min(p0.z, p1.z), p2.z);
-            let max_z = f32::max(f32::max(p0.z, p1.z), p2.z);
-
-            aabb.mins.x = f32::min(aabb.mins.x, min_x);
-            aabb.mins.y = f32::min(aabb.mins.y, min_y);
-            aabb.mins.z = f32::min(aabb.mins.z, min_z);
-
-            aabb.maxs.x = f32::max(aabb.maxs.x, max_x);
-            aabb.maxs.y = f32::max(aabb.maxs.y, max_y);
-            aabb.maxs.z = f32::max(aabb.maxs.z, max_z);
-        }
-
-        Ok(aabb)
+        // Build the AABB in world space
+        let points: Vec<glam::Vec3> = mesh
+            .triangles
+            .iter()
+            .flat_map(|triangle| triangle.get_world_positions(&mesh.vertices, models))
+            .collect();
+        Ok(Self::from_points(&points))
     }
 }
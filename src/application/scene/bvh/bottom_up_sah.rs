@@ -1,6 +1,6 @@
 use crate::application::{core::error::ErrorCode, scene::Scene};
 
-use super::{Bvh, BvhNode};
+use super::{build_job::BvhBuildProgress, Bvh, BvhNode};
 
 #[derive(Clone, Copy)]
 pub struct BvhBottomUpSahNode {
@@ -55,25 +55,31 @@ impl<'a> BvhBottomUpSah<'a> {
     }
 
     pub fn create_leaves(&mut self) {
-        let nb_triangles = self.scene.triangles.len();
-        for index in 0..nb_triangles {
-            let triangle = self.scene.triangles[index];
-            let model = self.scene.models[triangle.model_index];
-            let leaf = BvhNode::from_triangle(&triangle, &model, index as u32);
+        let nb_primitives = self.scene.get_nb_primitives();
+        for index in 0..nb_primitives {
+            let leaf = BvhNode::from_primitive(self.scene, index);
             self.bvh.push(BvhBottomUpSahNode::new(leaf));
         }
     }
 }
 
 impl Bvh for BvhBottomUpSah<'_> {
-    fn build(scene: &Scene) -> Result<Vec<BvhNode>, ErrorCode> {
+    fn build_with_progress(
+        scene: &Scene,
+        progress: &BvhBuildProgress,
+    ) -> Result<Vec<BvhNode>, ErrorCode> {
         let mut bvh_bottom_up = BvhBottomUpSah::new(scene);
 
         // Create leaves
         bvh_bottom_up.create_leaves();
 
         // Until there is only one node
+        let nb_primitives = scene.get_nb_primitives();
         while bvh_bottom_up.nb_available() > 1 {
+            progress.check_cancelled()?;
+            progress.set_progress(
+                (bvh_bottom_up.bvh.len() - nb_primitives) as f32 / nb_primitives as f32,
+            );
             let mut best_sah = f32::MAX;
             let mut best_candidates = (0, 1);
 
@@ -103,7 +109,7 @@ impl Bvh for BvhBottomUpSah<'_> {
                     );
 
                     bvh_bottom_up.bvh.push(BvhBottomUpSahNode::new(new_node));
-                    let new_sah = new_node.get_sah_cost(&bvh_bottom_up.get_bvh(), 1., 1.);
+                    let new_sah = new_node.get_sah_cost(&bvh_bottom_up.get_bvh(), &scene.sah_constants);
 
                     if new_sah < best_sah {
                         best_sah = new_sah;
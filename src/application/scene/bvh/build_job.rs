@@ -0,0 +1,108 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::error;
+
+use crate::application::{core::error::ErrorCode, scene::Scene};
+
+use super::{BvhNode, BvhType};
+
+/// Progress of a bvh build shared between the worker thread and the render loop
+#[derive(Debug, Default)]
+pub struct BvhBuildProgress {
+    // Bits of a f32 in [0;1]
+    fraction: AtomicU32,
+    is_cancelled: AtomicBool,
+}
+
+impl BvhBuildProgress {
+    pub fn set_progress(&self, fraction: f32) {
+        let fraction = fraction.clamp(0., 1.);
+        self.fraction.store(fraction.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get_progress(&self) -> f32 {
+        f32::from_bits(self.fraction.load(Ordering::Relaxed))
+    }
+
+    pub fn cancel(&self) {
+        self.is_cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.is_cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Builders call this between steps to stop as soon as possible once cancelled
+    pub fn check_cancelled(&self) -> Result<(), ErrorCode> {
+        if self.is_cancelled() {
+            Err(ErrorCode::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A bvh being built on a worker thread
+#[derive(Debug)]
+pub struct BvhBuildJob {
+    pub bvh_type: BvhType,
+    pub progress: Arc<BvhBuildProgress>,
+    handle: JoinHandle<Result<(Vec<BvhNode>, Duration), ErrorCode>>,
+}
+
+impl BvhBuildJob {
+    /// Build a bvh on a snapshot of the scene so the render loop can keep using the original
+    pub fn spawn(bvh_type: BvhType, scene: Scene) -> Result<Self, ErrorCode> {
+        let progress = Arc::new(BvhBuildProgress::default());
+        let worker_progress = Arc::clone(&progress);
+
+        let handle = match thread::Builder::new()
+            .name(format!("bvh-{:?}", bvh_type))
+            .spawn(move || {
+                let start = Instant::now();
+                let bvh = scene.build_bvh(bvh_type, &worker_progress)?;
+                worker_progress.set_progress(1.);
+                Ok((bvh, start.elapsed()))
+            }) {
+            Ok(handle) => handle,
+            Err(err) => {
+                error!(
+                    "Failed to spawn the worker thread for the `{:?}' bvh: {:?}",
+                    bvh_type, err
+                );
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+
+        Ok(BvhBuildJob {
+            bvh_type,
+            progress,
+            handle,
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Wait for the worker thread and return the bvh with the time it took to build it
+    pub fn join(self) -> Result<(Vec<BvhNode>, Duration), ErrorCode> {
+        match self.handle.join() {
+            Ok(result) => result,
+            Err(err) => {
+                error!(
+                    "The worker thread building the `{:?}' bvh panicked: {:?}",
+                    self.bvh_type, err
+                );
+                Err(ErrorCode::Unknown)
+            }
+        }
+    }
+}
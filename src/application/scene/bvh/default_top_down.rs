@@ -2,15 +2,15 @@ use log::error;
 
 use crate::application::{
     core::error::ErrorCode,
-    scene::{bvh::aabb::AabbAxis, triangle::Triangle, Scene},
+    scene::{bvh::aabb::AabbAxis, Scene},
 };
 
-use super::{aabb::Aabb, Bvh, BvhNode};
+use super::{build_job::BvhBuildProgress, Bvh, BvhNode};
 
 #[derive(Debug)]
 pub struct BvhDefaultTopDownNode {
     pub base: BvhNode,
-    pub triangles: Vec<usize>,
+    pub primitives: Vec<usize>,
 }
 
 #[derive(Debug)]
@@ -21,7 +21,7 @@ pub struct BvhDefaultTopDown<'a> {
 
 impl<'a> BvhDefaultTopDown<'a> {
     pub fn new(scene: &'a Scene) -> Result<Self, ErrorCode> {
-        let aabb = match Aabb::from_scene(&scene.triangles, &scene.models) {
+        let aabb = match scene.get_aabb() {
             Ok(aabb) => aabb,
             Err(err) => {
                 error!(
@@ -34,16 +34,16 @@ impl<'a> BvhDefaultTopDown<'a> {
 
         let node_base = BvhNode {
             bounding_box: aabb,
-            triangle_index: 0,
+            primitive_index: 0,
             left_child_index: 0,
             right_child_index: 0,
-            padding_1: 0,
+            primitive_type: 0,
         };
-        let triangles = (0..scene.triangles.len()).collect::<Vec<usize>>();
+        let primitives = (0..scene.get_nb_primitives()).collect::<Vec<usize>>();
 
         let root_node = BvhDefaultTopDownNode {
             base: node_base,
-            triangles,
+            primitives,
         };
 
         let bvh = vec![root_node];
@@ -54,7 +54,7 @@ impl<'a> BvhDefaultTopDown<'a> {
     fn get_leaves(&self) -> Vec<&BvhDefaultTopDownNode> {
         let mut leaves = Vec::new();
         for node in &self.bvh {
-            if node.triangles.len() == 1 && node.base.is_leaf() {
+            if node.primitives.len() == 1 && node.base.is_leaf() {
                 leaves.push(node);
             }
         }
@@ -64,7 +64,7 @@ impl<'a> BvhDefaultTopDown<'a> {
     pub fn get_false_leaves_indices(&self) -> Vec<usize> {
         let mut leaves = Vec::new();
         for (index, node) in self.bvh.iter().enumerate() {
-            if node.triangles.len() > 1 && node.base.is_leaf() {
+            if node.primitives.len() > 1 && node.base.is_leaf() {
                 leaves.push(index);
             }
         }
@@ -72,7 +72,7 @@ impl<'a> BvhDefaultTopDown<'a> {
     }
 
     pub fn is_complete(&self) -> bool {
-        self.get_leaves().len() == self.scene.triangles.len()
+        self.get_leaves().len() == self.scene.get_nb_primitives()
     }
 
     pub fn add_children(
@@ -93,33 +93,17 @@ impl<'a> BvhDefaultTopDown<'a> {
 
     fn build_last_two_children(&self, bvh_node_index: usize) -> [BvhDefaultTopDownNode; 2] {
         let bvh_node = &self.bvh[bvh_node_index];
-        debug_assert!(bvh_node.triangles.len() == 2);
-        let left_triangle_index = bvh_node.triangles[0];
-        let left_triangle = self.scene.triangles[left_triangle_index];
-        let left_model_matrix = self.scene.models[left_triangle.model_index].model_matrix;
+        debug_assert!(bvh_node.primitives.len() == 2);
+        let left_primitive_index = bvh_node.primitives[0];
         let left_child = BvhDefaultTopDownNode {
-            base: BvhNode {
-                bounding_box: Aabb::from_triangle(&left_triangle, left_model_matrix),
-                triangle_index: left_triangle_index as u32,
-                left_child_index: 0,
-                right_child_index: 0,
-                padding_1: 0,
-            },
-            triangles: vec![left_triangle_index],
+            base: BvhNode::from_primitive(self.scene, left_primitive_index),
+            primitives: vec![left_primitive_index],
         };
 
-        let right_triangle_index = bvh_node.triangles[1];
-        let right_triangle = self.scene.triangles[right_triangle_index];
-        let right_model_matrix = self.scene.models[right_triangle.model_index].model_matrix;
+        let right_primitive_index = bvh_node.primitives[1];
         let right_child = BvhDefaultTopDownNode {
-            base: BvhNode {
-                bounding_box: Aabb::from_triangle(&right_triangle, right_model_matrix),
-                triangle_index: right_triangle_index as u32,
-                left_child_index: 0,
-                right_child_index: 0,
-                padding_1: 0,
-            },
-            triangles: vec![right_triangle_index],
+            base: BvhNode::from_primitive(self.scene, right_primitive_index),
+            primitives: vec![right_primitive_index],
         };
 
         [left_child, right_child]
@@ -132,7 +116,7 @@ impl<'a> BvhDefaultTopDown<'a> {
     ) -> Result<[BvhDefaultTopDownNode; 2], ErrorCode> {
         let bvh_node = &self.bvh[bvh_node_index];
         // Check if the node has only two elements
-        if bvh_node.triangles.len() == 2 {
+        if bvh_node.primitives.len() == 2 {
             return Ok(self.build_last_two_children(bvh_node_index));
         }
 
@@ -140,9 +124,8 @@ impl<'a> BvhDefaultTopDown<'a> {
         let aabb = &bvh_node.base.bounding_box;
         let longest_axis = aabb.get_longest_axis();
 
-        let is_on_the_right = |triangle: &Triangle| -> bool {
-            let model_matrix = self.scene.models[triangle.model_index].model_matrix;
-            let centroid = triangle.get_centroid(model_matrix);
+        let is_on_the_right = |primitive_index: usize| -> bool {
+            let centroid = self.scene.get_primitive_centroid(primitive_index);
             // Check if the centroid is greater than half of the length
             // of the current bounding volume in the bounding volume's biggest direction
             match longest_axis {
@@ -152,51 +135,42 @@ impl<'a> BvhDefaultTopDown<'a> {
             }
         };
 
-        let mut left_triangles = Vec::new();
-        let mut right_triangles = Vec::new();
-        let mut left_triangles_indices = Vec::new();
-        let mut right_triangles_indices = Vec::new();
+        let mut left_primitives = Vec::new();
+        let mut right_primitives = Vec::new();
 
-        'fill_tri_loop: for (i, triangle_index) in bvh_node.triangles.iter().enumerate() {
-            let triangle = &self.scene.triangles[*triangle_index];
+        'fill_tri_loop: for (i, &primitive_index) in bvh_node.primitives.iter().enumerate() {
             // For the last element, check if one of the list is empty
-            if i == (bvh_node.triangles.len() - 1) {
-                if left_triangles.is_empty() {
-                    left_triangles.push(*triangle);
-                    left_triangles_indices.push(*triangle_index);
+            if i == (bvh_node.primitives.len() - 1) {
+                if left_primitives.is_empty() {
+                    left_primitives.push(primitive_index);
                     break 'fill_tri_loop;
                 }
-                if right_triangles.is_empty() {
-                    right_triangles.push(*triangle);
-                    right_triangles_indices.push(*triangle_index);
+                if right_primitives.is_empty() {
+                    right_primitives.push(primitive_index);
                     break 'fill_tri_loop;
                 }
             }
 
-            if is_on_the_right(triangle) {
-                right_triangles.push(*triangle);
-                right_triangles_indices.push(*triangle_index);
+            if is_on_the_right(primitive_index) {
+                right_primitives.push(primitive_index);
             } else {
-                left_triangles.push(*triangle);
-                left_triangles_indices.push(*triangle_index);
+                left_primitives.push(primitive_index);
             }
         }
 
-        // An internal node must have at least one triangle in each of its children
-        debug_assert!(!left_triangles.is_empty());
-        debug_assert!(!right_triangles.is_empty());
-        debug_assert!(!left_triangles_indices.is_empty());
-        debug_assert!(!right_triangles_indices.is_empty());
+        // An internal node must have at least one primitive in each of its children
+        debug_assert!(!left_primitives.is_empty());
+        debug_assert!(!right_primitives.is_empty());
 
-        let right_aabb = match Aabb::from_scene(&right_triangles, &self.scene.models) {
+        let right_aabb = match self.scene.get_primitives_aabb(&right_primitives) {
             Ok(aabb) => aabb,
             Err(err) => {
-                error!("Failed to create the AABB for the left child in the default top down bvh: {:?}", err);
+                error!("Failed to create the AABB for the right child in the default top down bvh: {:?}", err);
                 return Err(ErrorCode::InitializationFailure);
             }
         };
 
-        let left_aabb = match Aabb::from_scene(&left_triangles, &self.scene.models) {
+        let left_aabb = match self.scene.get_primitives_aabb(&left_primitives) {
             Ok(aabb) => aabb,
             Err(err) => {
                 error!("Failed to create the AABB for the left child in the default top down bvh: {:?}", err);
@@ -207,19 +181,19 @@ impl<'a> BvhDefaultTopDown<'a> {
         let left_child = BvhDefaultTopDownNode {
             base: BvhNode {
                 bounding_box: left_aabb,
-                triangle_index: left_triangles_indices[0] as u32,
+                primitive_index: left_primitives[0] as u32,
                 ..Default::default()
             },
-            triangles: left_triangles_indices,
+            primitives: left_primitives,
         };
 
         let right_child = BvhDefaultTopDownNode {
             base: BvhNode {
                 bounding_box: right_aabb,
-                triangle_index: right_triangles_indices[0] as u32,
+                primitive_index: right_primitives[0] as u32,
                 ..Default::default()
             },
-            triangles: right_triangles_indices,
+            primitives: right_primitives,
         };
 
         Ok([left_child, right_child])
@@ -235,7 +209,14 @@ impl<'a> BvhDefaultTopDown<'a> {
 }
 
 impl Bvh for BvhDefaultTopDown<'_> {
-    fn build(scene: &Scene) -> Result<Vec<BvhNode>, ErrorCode> {
+    fn build_with_progress(
+        scene: &Scene,
+        progress: &BvhBuildProgress,
+    ) -> Result<Vec<BvhNode>, ErrorCode> {
+        if scene.get_nb_primitives() == 0 {
+            return Ok(Vec::new());
+        }
+
         // Init the first bounding box
         let mut handler = match BvhDefaultTopDown::new(scene) {
             Ok(handler) => handler,
@@ -245,8 +226,11 @@ impl Bvh for BvhDefaultTopDown<'_> {
             }
         };
 
-        // While there are triangles in the same node
+        // While there are primitives in the same node
+        let nb_nodes = 2 * scene.get_nb_primitives() - 1;
         while !handler.is_complete() {
+            progress.check_cancelled()?;
+            progress.set_progress(handler.bvh.len() as f32 / nb_nodes as f32);
             // Get all the current leaves that can still be extended
             let extendable_leaves = handler.get_false_leaves_indices();
             for leaf_index in extendable_leaves {
@@ -0,0 +1,166 @@
+use std::{fmt::Write as _, fs, ops::RangeInclusive, path::Path};
+
+use log::error;
+
+use crate::application::{
+    core::error::ErrorCode,
+    scene::{primitive::PrimitiveType, Scene},
+};
+
+use super::{BvhNode, BvhType};
+
+// Pairs of corners of a box linked by an edge, corner `i` takes its x from bit 0,
+// its y from bit 1 and its z from bit 2
+const BOX_EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+fn write_file(path: &Path, content: &str) -> Result<(), ErrorCode> {
+    if let Err(err) = fs::write(path, content) {
+        error!("Failed to write the bvh export `{:?}': {:?}", path, err);
+        return Err(ErrorCode::IO);
+    }
+    Ok(())
+}
+
+fn vec3_to_json(v: glam::Vec3) -> String {
+    format!("[{}, {}, {}]", v.x, v.y, v.z)
+}
+
+/// Write the bounding boxes of the nodes whose depth is in `depths` as an OBJ of lines
+/// There is one object per depth so they can be toggled separately in Blender
+pub fn export_obj(
+    bvh: &[BvhNode],
+    depths: RangeInclusive<u32>,
+    path: &Path,
+) -> Result<(), ErrorCode> {
+    if bvh.is_empty() {
+        error!("Can't export an empty bvh to `{:?}'", path);
+        return Err(ErrorCode::Unknown);
+    }
+
+    let node_depths = BvhNode::get_depths(bvh);
+    let mut obj = String::from("# Bvh bounding boxes\n");
+    let mut nb_vertices = 0;
+    for depth in depths {
+        let mut is_depth_empty = true;
+        for (node, _) in std::iter::zip(bvh, &node_depths).filter(|(_, d)| **d == depth) {
+            if is_depth_empty {
+                let _ = writeln!(obj, "o depth_{}", depth);
+                is_depth_empty = false;
+            }
+
+            let (mins, maxs) = (node.bounding_box.mins, node.bounding_box.maxs);
+            for corner in 0..8 {
+                let x = if corner & 1 == 0 { mins.x } else { maxs.x };
+                let y = if corner & 2 == 0 { mins.y } else { maxs.y };
+                let z = if corner & 4 == 0 { mins.z } else { maxs.z };
+                let _ = writeln!(obj, "v {} {} {}", x, y, z);
+            }
+            // OBJ indices start at 1
+            for (start, end) in BOX_EDGES {
+                let _ = writeln!(
+                    obj,
+                    "l {} {}",
+                    nb_vertices + start + 1,
+                    nb_vertices + end + 1
+                );
+            }
+            nb_vertices += 8;
+        }
+    }
+
+    write_file(path, &obj)
+}
+
+/// Write every node with its bounds, its children and the primitive of the leaves as JSON
+pub fn export_json(
+    bvh: &[BvhNode],
+    bvh_type: BvhType,
+    scene: &Scene,
+    path: &Path,
+) -> Result<(), ErrorCode> {
+    let node_depths = BvhNode::get_depths(bvh);
+    let mut json = String::new();
+    let _ = writeln!(json, "{{");
+    let _ = writeln!(json, "  \"bvh_type\": \"{:?}\",", bvh_type);
+    let _ = writeln!(json, "  \"nb_triangles\": {},", scene.mesh.triangles.len());
+    let _ = writeln!(json, "  \"nb_spheres\": {},", scene.spheres.len());
+    let _ = writeln!(json, "  \"nb_cuboids\": {},", scene.cuboids.len());
+    let _ = writeln!(json, "  \"nodes\": [");
+    for (index, (node, depth)) in std::iter::zip(bvh, &node_depths).enumerate() {
+        let _ = write!(
+            json,
+            "    {{\"index\": {}, \"depth\": {}, \"mins\": {}, \"maxs\": {}, ",
+            index,
+            depth,
+            vec3_to_json(node.bounding_box.mins),
+            vec3_to_json(node.bounding_box.maxs)
+        );
+        if node.is_leaf() {
+            let primitive_index = node.primitive_index as usize;
+            let primitive = match node.get_primitive_type() {
+                Some(PrimitiveType::Triangle) => {
+                    scene.mesh.triangles.get(primitive_index).map(|triangle| {
+                        let vertices = triangle
+                            .get_world_positions(&scene.mesh.vertices, &scene.models)
+                            .map(vec3_to_json)
+                            .join(", ");
+                        format!("\"vertices\": [{}]", vertices)
+                    })
+                }
+                Some(PrimitiveType::Sphere) => scene.spheres.get(primitive_index).map(|sphere| {
+                    format!(
+                        "\"center\": {}, \"radius\": {}",
+                        vec3_to_json(sphere.get_world_center(&scene.models)),
+                        sphere.get_world_radius(&scene.models)
+                    )
+                }),
+                Some(PrimitiveType::Cuboid) => scene.cuboids.get(primitive_index).map(|cuboid| {
+                    let corners = cuboid
+                        .get_world_corners(&scene.models)
+                        .map(vec3_to_json)
+                        .join(", ");
+                    format!("\"corners\": [{}]", corners)
+                }),
+                None => None,
+            };
+            let Some(primitive) = primitive else {
+                error!(
+                    "The leaf {} references the primitive {} of type {} which is not in the scene",
+                    index, primitive_index, node.primitive_type
+                );
+                return Err(ErrorCode::Unknown);
+            };
+            let _ = write!(
+                json,
+                "\"primitive\": {{\"type\": \"{:?}\", \"index\": {}, {}}}}}",
+                node.get_primitive_type().unwrap_or_default(),
+                primitive_index,
+                primitive
+            );
+        } else {
+            let _ = write!(
+                json,
+                "\"left_child\": {}, \"right_child\": {}}}",
+                node.left_child_index, node.right_child_index
+            );
+        }
+        let _ = writeln!(json, "{}", if index + 1 < bvh.len() { "," } else { "" });
+    }
+    let _ = writeln!(json, "  ]");
+    let _ = writeln!(json, "}}");
+
+    write_file(path, &json)
+}
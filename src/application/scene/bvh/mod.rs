@@ -1,16 +1,22 @@
 use aabb::Aabb;
+use build_job::BvhBuildProgress;
+use sah::SahConstants;
 use std::fmt::Debug;
 
 use crate::application::core::error::ErrorCode;
 
-use super::{model::Model, triangle::Triangle, Scene};
+use super::{primitive::PrimitiveType, Scene};
 
 pub mod aabb;
 // pub mod bottom_up_sah;
+pub mod build_job;
+#[allow(unused)]
 pub mod default_bottom_up;
 pub mod default_top_down;
+pub mod export;
 pub mod ploc;
 pub mod ploc_parallel;
+pub mod sah;
 pub mod top_down_sah;
 
 #[derive(Debug, Default, Hash, PartialEq, Eq, Clone, Copy)]
@@ -29,19 +35,22 @@ pub enum BvhType {
 pub struct BvhNode {
     pub bounding_box: Aabb,
     // If not leaf then dummy variable
-    pub triangle_index: u32,
+    // While building, index among all the primitives of the scene, see `Scene::get_primitive`
+    // Once built, index in the buffer of the primitives of its type
+    pub primitive_index: u32,
     // If child_index == 0 then leaf
     pub left_child_index: u32,
     pub right_child_index: u32,
-    #[allow(dead_code)]
-    pub padding_1: u32,
+    // See `PrimitiveType`
+    pub primitive_type: u32,
 }
 
 impl Debug for BvhNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f)?;
         writeln!(f, "bounding_box: {:?}", self.bounding_box)?;
-        writeln!(f, "triangle_index: {}", self.triangle_index)?;
+        writeln!(f, "primitive_index: {}", self.primitive_index)?;
+        writeln!(f, "primitive_type: {:?}", self.get_primitive_type())?;
         writeln!(f, "left_child_index: {}", self.left_child_index)?;
         writeln!(f, "right_child_index: {}", self.right_child_index)?;
         Ok(())
@@ -49,14 +58,62 @@ impl Debug for BvhNode {
 }
 
 impl BvhNode {
-    pub fn from_triangle(triangle: &Triangle, model: &Model, index: u32) -> Self {
-        let aabb = Aabb::from_triangle(triangle, model.model_matrix);
+    /// Leaf of the primitive `index` among all the primitives of the scene
+    pub fn from_primitive(scene: &Scene, index: usize) -> Self {
         Self {
-            bounding_box: aabb,
-            triangle_index: index,
+            bounding_box: scene.get_primitive_aabb(index),
+            primitive_index: index as u32,
             left_child_index: 0,
             right_child_index: 0,
-            padding_1: 0,
+            primitive_type: 0,
+        }
+    }
+
+    pub fn get_primitive_type(&self) -> Option<PrimitiveType> {
+        PrimitiveType::from_u32(self.primitive_type)
+    }
+
+    /// Replace the scene wide primitive index of the leaves, which the builders use, by the
+    /// type and the index in the buffer of that type, which the traversal uses
+    pub fn locate_primitives(bvh: &mut [BvhNode], scene: &Scene) {
+        for node in bvh.iter_mut().filter(|node| node.is_leaf()) {
+            let (primitive_type, index) =
+                scene.get_primitive_location(node.primitive_index as usize);
+            node.primitive_type = primitive_type as u32;
+            node.primitive_index = index as u32;
+        }
+    }
+
+    /// Recompute the bounding boxes after the primitives moved, the tree is kept as is
+    pub fn refit(bvh: &mut [BvhNode], scene: &Scene) {
+        // Parents come before their children in the pre-order, so the reversed order
+        // visits the children first
+        let mut pre_order = Vec::with_capacity(bvh.len());
+        let mut stack = vec![0_usize];
+        while let Some(node_index) = stack.pop() {
+            pre_order.push(node_index);
+            let node = &bvh[node_index];
+            if !node.is_leaf() {
+                stack.push(node.right_child_index as usize);
+                stack.push(node.left_child_index as usize);
+            }
+        }
+
+        for node_index in pre_order.into_iter().rev() {
+            let node = bvh[node_index];
+            bvh[node_index].bounding_box = if node.is_leaf() {
+                let Some(primitive_type) = node.get_primitive_type() else {
+                    continue;
+                };
+                scene.get_primitive_aabb(
+                    scene.get_primitive_index(primitive_type, node.primitive_index as usize),
+                )
+            } else {
+                Aabb::merge(
+                    &bvh[node.left_child_index as usize].bounding_box,
+                    &bvh[node.right_child_index as usize].bounding_box,
+                )
+            };
         }
     }
 
@@ -78,42 +135,53 @@ impl BvhNode {
         let bounding_box = Aabb::merge(&left_node.bounding_box, &right_node.bounding_box);
         BvhNode {
             bounding_box,
-            triangle_index: 0,
+            primitive_index: 0,
             left_child_index: left_index,
             right_child_index: right_index,
-            padding_1: 0,
+            primitive_type: 0,
         }
     }
 
-    #[allow(unused)]
-    pub fn get_sah_cost(
-        &self,
-        bvh: &Vec<BvhNode>,
-        cost_traverse_internal: f32,
-        cost_triangle_intersection: f32,
-    ) -> f32 {
+    /// Expected cost of a ray going through the node, relative to its surface area
+    pub fn get_sah_cost(&self, bvh: &Vec<BvhNode>, sah_constants: &SahConstants) -> f32 {
         if self.is_leaf() {
-            cost_triangle_intersection
+            sah_constants.cost_triangle_intersection
         } else {
-            let area = self.bounding_box.get_volume();
+            let area = self.bounding_box.get_surface_area();
 
             let left = bvh[self.left_child_index as usize];
             let right = bvh[self.right_child_index as usize];
 
-            let area_left = left.bounding_box.get_volume();
+            let area_left = left.bounding_box.get_surface_area();
             let factor_left = area_left / area;
-            let cost_left =
-                left.get_sah_cost(bvh, cost_traverse_internal, cost_triangle_intersection);
+            let cost_left = left.get_sah_cost(bvh, sah_constants);
 
-            let area_right = right.bounding_box.get_volume();
+            let area_right = right.bounding_box.get_surface_area();
             let factor_right = area_right / area;
-            let cost_right =
-                right.get_sah_cost(bvh, cost_traverse_internal, cost_triangle_intersection);
+            let cost_right = right.get_sah_cost(bvh, sah_constants);
 
-            cost_traverse_internal + factor_left * cost_left + factor_right * cost_right
+            sah_constants.cost_traverse_internal
+                + factor_left * cost_left
+                + factor_right * cost_right
         }
     }
 
+    /// Depth of every node, the root being at depth 0
+    pub fn get_depths(bvh: &[BvhNode]) -> Vec<u32> {
+        let mut depths = vec![0; bvh.len()];
+        let mut stack = if bvh.is_empty() { vec![] } else { vec![0] };
+        while let Some(node_index) = stack.pop() {
+            let node = &bvh[node_index];
+            if !node.is_leaf() {
+                for child_index in [node.left_child_index, node.right_child_index] {
+                    depths[child_index as usize] = depths[node_index] + 1;
+                    stack.push(child_index as usize);
+                }
+            }
+        }
+        depths
+    }
+
     #[allow(unused)]
     pub fn to_string(bvh: &Vec<BvhNode>) -> String {
         if bvh.is_empty() {
@@ -132,7 +200,7 @@ impl BvhNode {
         if node.is_leaf() {
             output.push_str(&format!(
                 "{}Leaf Node - Index: {}, Triangle Index: {}, Bounding Box: {:?}\n",
-                indent, node_index, node.triangle_index, node.bounding_box
+                indent, node_index, node.primitive_index, node.bounding_box
             ));
         } else {
             output.push_str(&format!(
@@ -162,5 +230,207 @@ impl BvhNode {
 }
 
 pub trait Bvh {
-    fn build(scene: &Scene) -> Result<Vec<BvhNode>, ErrorCode>;
+    /// Build the bvh while reporting the progress and stopping early if cancelled
+    fn build_with_progress(
+        scene: &Scene,
+        progress: &BvhBuildProgress,
+    ) -> Result<Vec<BvhNode>, ErrorCode>;
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Quat, Vec3};
+
+    use crate::application::{
+        raytracer::{ray::Ray, CpuRaytracer, TraversalStats},
+        scene::{camera::Camera, material::Material, mesh::Mesh, Scene},
+    };
+
+    use super::{aabb::Aabb, build_job::BvhBuildProgress, BvhNode, BvhType};
+
+    const BVH_TYPES: [BvhType; 5] = [
+        BvhType::DefaultTopDown,
+        BvhType::DefaultBottomUp,
+        BvhType::TopDownSah,
+        BvhType::Ploc,
+        BvhType::PlocParallel,
+    ];
+
+    fn get_empty_scene() -> Scene {
+        let camera = Camera::new(Vec3::ZERO, 1., 50., 0.1, Vec3::Y);
+        Scene::init_scene_skeleton(
+            Mesh::default(),
+            Vec::new(),
+            vec![Material::default()],
+            camera,
+        )
+        .unwrap()
+    }
+
+    /// Triangles of the Cornell box with a grid of spheres and cuboids
+    fn get_mixed_scene() -> Scene {
+        let camera = Camera::new(Vec3::ZERO, 1., 50., 0.1, Vec3::Y);
+        let mut scene = Scene::init_scene_cornell_box(camera).unwrap();
+        for i in 0..5 {
+            for j in 0..4 {
+                let center = Vec3::new(2. * i as f32, 3. * j as f32, (i * j) as f32);
+                if (i + j) % 2 == 0 {
+                    let _ = scene.add_sphere(center, 0.5 + 0.1 * j as f32, None);
+                } else {
+                    // Turned so that their bounds differ from their axes
+                    let model_index = scene.add_cuboid(center, Vec3::new(0.5, 0.2, 0.8), None);
+                    let model_matrix = Mat4::from_scale_rotation_translation(
+                        Vec3::new(0.5, 0.2, 0.8),
+                        Quat::from_rotation_y(0.3 * i as f32) * Quat::from_rotation_x(0.5),
+                        center,
+                    );
+                    scene.set_model_matrix(model_index, model_matrix).unwrap();
+                }
+            }
+        }
+        scene
+    }
+
+    fn build(scene: &Scene, bvh_type: BvhType) -> Vec<BvhNode> {
+        scene
+            .build_bvh(bvh_type, &BvhBuildProgress::default())
+            .unwrap_or_else(|err| panic!("Failed to build the `{:?}' bvh: {:?}", bvh_type, err))
+    }
+
+    fn contains(outer: &Aabb, inner: &Aabb) -> bool {
+        let epsilon = 1e-4;
+        outer.mins.cmple(inner.mins + epsilon).all() && inner.maxs.cmple(outer.maxs + epsilon).all()
+    }
+
+    /// Every node is reached once, the parents contain their children and every primitive is
+    /// in a leaf containing it. The top down sah builder can put the primitives overlapping a
+    /// split in both children, the other builders have one leaf per primitive.
+    fn check_bvh(scene: &Scene, bvh: &[BvhNode], bvh_type: BvhType) {
+        let nb_primitives = scene.get_nb_primitives();
+        if nb_primitives == 0 {
+            assert!(bvh.is_empty(), "{:?}", bvh_type);
+            return;
+        }
+        let has_spatial_splits = bvh_type == BvhType::TopDownSah;
+        if !has_spatial_splits {
+            assert_eq!(bvh.len(), 2 * nb_primitives - 1, "{:?}", bvh_type);
+        }
+
+        let mut is_node_visited = vec![false; bvh.len()];
+        let mut nb_leaves_of_primitive = vec![0; nb_primitives];
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            assert!(!is_node_visited[node_index], "{:?}", bvh_type);
+            is_node_visited[node_index] = true;
+            let node = &bvh[node_index];
+            if node.is_leaf() {
+                let primitive_type = node.get_primitive_type().unwrap();
+                let index =
+                    scene.get_primitive_index(primitive_type, node.primitive_index as usize);
+                nb_leaves_of_primitive[index] += 1;
+                assert!(
+                    contains(&node.bounding_box, &scene.get_primitive_aabb(index)),
+                    "{:?}",
+                    bvh_type
+                );
+            } else {
+                for child_index in [node.left_child_index, node.right_child_index] {
+                    let child = &bvh[child_index as usize];
+                    assert!(
+                        contains(&node.bounding_box, &child.bounding_box),
+                        "{:?}",
+                        bvh_type
+                    );
+                    stack.push(child_index as usize);
+                }
+            }
+        }
+        assert!(is_node_visited.iter().all(|&is_visited| is_visited));
+        assert!(nb_leaves_of_primitive
+            .iter()
+            .all(|&nb_leaves| nb_leaves == 1 || (has_spatial_splits && nb_leaves > 1)));
+    }
+
+    /// The closest hits found through the bvh are the ones found by testing every primitive
+    fn check_hits(scene: &Scene, bvh: &[BvhNode], bvh_type: BvhType) {
+        let with_bvh = CpuRaytracer::new(scene, bvh);
+        let without_bvh = CpuRaytracer::new(scene, &[]);
+        let aabb = scene.get_aabb().unwrap();
+        let center = 0.5 * (aabb.mins + aabb.maxs);
+        let mut stats = TraversalStats::default();
+        for origin in [center, center + 2. * (aabb.maxs - aabb.mins)] {
+            // Directions spread over the sphere
+            let nb_directions = 500;
+            for i in 0..nb_directions {
+                let z = 1. - 2. * (i as f32 + 0.5) / nb_directions as f32;
+                let phi = i as f32 * 2.399_963;
+                let r = (1. - z * z).sqrt();
+                let direction = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                let ray = Ray::new(origin, direction, false);
+                let expected = without_bvh.get_closest_hit(&ray, f32::INFINITY, &mut stats);
+                let hit = with_bvh.get_closest_hit(&ray, f32::INFINITY, &mut stats);
+                assert_eq!(
+                    hit.map(|hit| (hit.primitive_type, hit.primitive_index)),
+                    expected.map(|hit| (hit.primitive_type, hit.primitive_index)),
+                    "{:?}",
+                    bvh_type
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn empty_scene() {
+        let scene = get_empty_scene();
+        for bvh_type in BVH_TYPES {
+            check_bvh(&scene, &build(&scene, bvh_type), bvh_type);
+        }
+    }
+
+    #[test]
+    fn single_primitive() {
+        let mut scene = get_empty_scene();
+        let _ = scene.add_sphere(Vec3::new(1., 2., 3.), 0.5, None);
+        for bvh_type in BVH_TYPES {
+            let bvh = build(&scene, bvh_type);
+            check_bvh(&scene, &bvh, bvh_type);
+            assert!(bvh[0].is_leaf());
+            check_hits(&scene, &bvh, bvh_type);
+        }
+    }
+
+    #[test]
+    fn mixed_primitives() {
+        let scene = get_mixed_scene();
+        for bvh_type in BVH_TYPES {
+            let bvh = build(&scene, bvh_type);
+            check_bvh(&scene, &bvh, bvh_type);
+            check_hits(&scene, &bvh, bvh_type);
+        }
+    }
+
+    #[test]
+    fn parallel_ploc_matches_ploc() {
+        // More primitives than a block of the parallel scans
+        let mut large_scene = get_empty_scene();
+        for i in 0..5000 {
+            let center = Vec3::new((i % 17) as f32, (i % 29) as f32, (i / 493) as f32);
+            let _ = large_scene.add_sphere(center, 0.1 + 0.01 * (i % 7) as f32, None);
+        }
+        for scene in [get_mixed_scene(), large_scene] {
+            let ploc = build(&scene, BvhType::Ploc);
+            let ploc_parallel = build(&scene, BvhType::PlocParallel);
+            check_bvh(&scene, &ploc_parallel, BvhType::PlocParallel);
+            assert_eq!(ploc.len(), ploc_parallel.len());
+            let sah_constants = scene.sah_constants;
+            let ploc_cost = ploc[0].get_sah_cost(&ploc, &sah_constants);
+            let ploc_parallel_cost = ploc_parallel[0].get_sah_cost(&ploc_parallel, &sah_constants);
+            assert!(
+                (ploc_cost - ploc_parallel_cost).abs() <= 1e-3 * ploc_cost,
+                "{} {}",
+                ploc_cost,
+                ploc_parallel_cost
+            );
+        }
+    }
 }
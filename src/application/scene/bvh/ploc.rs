@@ -2,27 +2,24 @@ use std::cmp::{max, min};
 
 use log::error;
 
-use crate::application::{
-    core::error::ErrorCode,
-    scene::{triangle::Triangle, Scene},
-};
+use crate::application::{core::error::ErrorCode, scene::Scene};
 
-use super::{aabb::Aabb, Bvh, BvhNode};
+use super::{aabb::Aabb, build_job::BvhBuildProgress, Bvh, BvhNode};
 
 #[derive(Debug)]
 pub struct BvhPloc {
-    nb_triangles: usize,
+    nb_primitives: usize,
     pub clusters: Vec<Option<BvhNode>>,
     pub parents: Vec<Option<usize>>,
     pub is_leaf: Vec<bool>,
     pub left_children: Vec<Option<usize>>,
     pub right_children: Vec<Option<usize>>,
-    pub triangle_indices: Vec<usize>,
+    pub primitive_indices: Vec<usize>,
 }
 
 #[derive(Debug)]
 pub struct PlocParameters {
-    nb_triangles: usize,
+    nb_primitives: usize,
     pub search_radius: u32,
     pub nb_total_clusters: usize,
     pub iteration: usize,
@@ -58,26 +55,26 @@ fn get_morton_code(point: &glam::Vec3) -> u32 {
 }
 
 impl BvhPloc {
-    pub fn get_triangle_indices(nb_triangles: usize) -> Vec<usize> {
-        (0..nb_triangles).collect()
+    pub fn get_primitive_indices(nb_primitives: usize) -> Vec<usize> {
+        (0..nb_primitives).collect()
     }
 
     pub fn new(scene: &Scene) -> Self {
-        let nb_triangles = scene.triangles.len();
-        let triangle_indices = Self::get_triangle_indices(nb_triangles);
-        let clusters = vec![None; 2 * nb_triangles - 1];
-        let is_leaf = vec![false; 2 * nb_triangles - 1];
-        let parents = vec![None; 2 * nb_triangles - 1];
-        let left_children = vec![None; 2 * nb_triangles - 1];
-        let right_children = vec![None; 2 * nb_triangles - 1];
+        let nb_primitives = scene.get_nb_primitives();
+        let primitive_indices = Self::get_primitive_indices(nb_primitives);
+        let clusters = vec![None; 2 * nb_primitives - 1];
+        let is_leaf = vec![false; 2 * nb_primitives - 1];
+        let parents = vec![None; 2 * nb_primitives - 1];
+        let left_children = vec![None; 2 * nb_primitives - 1];
+        let right_children = vec![None; 2 * nb_primitives - 1];
         Self {
-            nb_triangles,
+            nb_primitives,
             clusters,
             parents,
             is_leaf,
             left_children,
             right_children,
-            triangle_indices,
+            primitive_indices,
         }
     }
 
@@ -111,7 +108,7 @@ impl BvhPloc {
 
     pub fn get_bvh(&self) -> Result<Vec<BvhNode>, ErrorCode> {
         let mut final_bvh = Vec::new();
-        let root_node_index = 2 * self.nb_triangles - 2;
+        let root_node_index = 2 * self.nb_primitives - 2;
         self.get_bvh_node_recursive(&mut final_bvh, root_node_index)?;
         Ok(final_bvh)
     }
@@ -290,71 +287,59 @@ impl PlocParameters {
             }
         };
         let circumscribed_cube = Aabb::get_circumscribed_cube(&scene_aabb);
-        let triangles_centroids = Triangle::get_normalized_centroids(
-            &scene.triangles,
-            &scene.models,
-            &circumscribed_cube,
-        );
+        let cube_length = circumscribed_cube.get_length_x();
 
-        Ok(triangles_centroids
-            .iter()
-            .map(get_morton_code)
+        Ok((0..scene.get_nb_primitives())
+            .map(|index| {
+                let centroid = scene.get_primitive_centroid(index);
+                get_morton_code(&((centroid - circumscribed_cube.mins) / cube_length))
+            })
             .collect::<Vec<u32>>())
     }
 
-    pub fn sort(triangle_indices: &mut [usize], morton_codes: &mut [u32]) {
+    pub fn sort(primitive_indices: &mut [usize], morton_codes: &mut [u32]) {
         // Put them in tuple
         let mut indices_codes = Vec::new();
-        debug_assert!(triangle_indices.len() == morton_codes.len());
-        for i in 0..triangle_indices.len() {
-            indices_codes.push((morton_codes[i], triangle_indices[i]));
+        debug_assert!(primitive_indices.len() == morton_codes.len());
+        for i in 0..primitive_indices.len() {
+            indices_codes.push((morton_codes[i], primitive_indices[i]));
         }
         indices_codes.sort();
         // Un-tuple them
-        for i in 0..triangle_indices.len() {
+        for i in 0..primitive_indices.len() {
             morton_codes[i] = indices_codes[i].0;
-            triangle_indices[i] = indices_codes[i].1;
+            primitive_indices[i] = indices_codes[i].1;
         }
     }
 
     pub fn preprocessing(&mut self, bvh_ploc: &mut BvhPloc, scene: &Scene) {
-        Self::sort(&mut bvh_ploc.triangle_indices, &mut self.morton_codes);
+        Self::sort(&mut bvh_ploc.primitive_indices, &mut self.morton_codes);
         // Create leaf nodes
-        for i in 0..self.nb_triangles {
-            let triangle_index = bvh_ploc.triangle_indices[i];
-            let cur_triangle = scene.triangles[triangle_index];
-            let cur_model = scene.models[cur_triangle.model_index];
-
-            let bounding_box = Aabb::from_triangle(&cur_triangle, cur_model.model_matrix);
-            let leaf_cluster = BvhNode {
-                bounding_box,
-                triangle_index: triangle_index as u32,
-                left_child_index: 0,
-                right_child_index: 0,
-                padding_1: 0,
-            };
+        for i in 0..self.nb_primitives {
+            let primitive_index = bvh_ploc.primitive_indices[i];
+            let leaf_cluster = BvhNode::from_primitive(scene, primitive_index);
 
             bvh_ploc.clusters[i] = Some(leaf_cluster);
             bvh_ploc.is_leaf[i] = true;
             self.c_in[i] = Some(i);
             self.c_out[i] = None;
         }
-        self.iteration = self.nb_triangles;
-        self.nb_total_clusters = self.nb_triangles;
+        self.iteration = self.nb_primitives;
+        self.nb_total_clusters = self.nb_primitives;
     }
 
     pub fn new(scene: &Scene) -> Result<Self, ErrorCode> {
-        let nb_triangles = scene.triangles.len();
+        let nb_primitives = scene.get_nb_primitives();
         let search_radius = 16;
         let nb_total_clusters = 0;
         let iteration = 0;
-        let c_in = vec![None; nb_triangles];
-        let c_out = vec![None; nb_triangles];
-        let nearest_neighbor_indices = vec![0; nb_triangles];
-        let prefix_scan = vec![0; nb_triangles];
+        let c_in = vec![None; nb_primitives];
+        let c_out = vec![None; nb_primitives];
+        let nearest_neighbor_indices = vec![0; nb_primitives];
+        let prefix_scan = vec![0; nb_primitives];
         let morton_codes = Self::get_morton_codes(scene)?;
         Ok(Self {
-            nb_triangles,
+            nb_primitives,
             search_radius,
             nb_total_clusters,
             iteration,
@@ -368,7 +353,14 @@ impl PlocParameters {
 }
 
 impl Bvh for BvhPloc {
-    fn build(scene: &Scene) -> Result<Vec<BvhNode>, ErrorCode> {
+    fn build_with_progress(
+        scene: &Scene,
+        progress: &BvhBuildProgress,
+    ) -> Result<Vec<BvhNode>, ErrorCode> {
+        if scene.get_nb_primitives() == 0 {
+            return Ok(Vec::new());
+        }
+
         let mut bvh_ploc = BvhPloc::new(scene);
         let mut ploc_parameters = PlocParameters::new(scene)?;
 
@@ -376,7 +368,12 @@ impl Bvh for BvhPloc {
         ploc_parameters.preprocessing(&mut bvh_ploc, scene);
 
         // Ploc main loop algorithm
+        let nb_primitives = scene.get_nb_primitives();
         while ploc_parameters.iteration > 1 {
+            progress.check_cancelled()?;
+            progress.set_progress(
+                (ploc_parameters.nb_total_clusters - nb_primitives) as f32 / nb_primitives as f32,
+            );
             // Nearest Neighbor search
             for index in 0..ploc_parameters.iteration {
                 if let Err(err) = bvh_ploc.nearest_neighbor_search(&mut ploc_parameters, index) {
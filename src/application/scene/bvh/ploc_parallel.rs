@@ -1,374 +1,271 @@
-use std::{
-    cmp::{max, min},
-    sync::Mutex,
-};
-
 use rayon::prelude::*;
 
 use log::error;
 
 use crate::application::{
     core::error::ErrorCode,
-    scene::{
-        bvh::ploc::{BvhPloc, PlocParameters},
-        Scene,
-    },
+    scene::{bvh::ploc::PlocParameters, Scene},
 };
 
-use super::{aabb::Aabb, Bvh, BvhNode};
+use super::{aabb::Aabb, build_job::BvhBuildProgress, Bvh, BvhNode};
 
 pub struct BvhPlocParallel;
 
-fn create_leaf_nodes(
-    index: usize,
-    scene: &Scene,
-    triangle_indices: &[usize],
-    mut_cluster: &mut Option<BvhNode>,
-    mut_is_leaf: &mut bool,
-    mut_c_in: &mut Option<usize>,
-) {
-    let triangle_index = triangle_indices[index];
-    let cur_triangle = scene.triangles[triangle_index];
-    let cur_model = scene.models[cur_triangle.model_index];
-
-    let bounding_box = Aabb::from_triangle(&cur_triangle, cur_model.model_matrix);
-    let leaf_cluster = BvhNode {
-        bounding_box,
-        triangle_index: triangle_index as u32,
-        left_child_index: 0,
-        right_child_index: 0,
-        padding_1: 0,
-    };
-
-    *mut_cluster = Some(leaf_cluster);
-    *mut_is_leaf = true;
-    *mut_c_in = Some(index);
+/// Radius of the window in which the nearest neighbor of a cluster is searched
+const SEARCH_RADIUS: usize = 16;
+
+/// Number of consecutive elements handled by a single task in the scan based phases
+const BLOCK_SIZE: usize = 4096;
+
+/// Sort the primitives along the morton curve
+fn get_sorted_primitive_indices(morton_codes: Vec<u32>) -> Vec<usize> {
+    let mut codes_indices = morton_codes
+        .into_par_iter()
+        .enumerate()
+        .map(|(index, code)| (code, index))
+        .collect::<Vec<(u32, usize)>>();
+    codes_indices.par_sort_unstable();
+    codes_indices
+        .into_par_iter()
+        .map(|(_, index)| index)
+        .collect::<Vec<usize>>()
 }
 
-fn nearest_neighbor_search(
-    index: usize,
-    iteration: usize,
-    search_radius: u32,
-    c_in: &[Option<usize>],
-    clusters: &[Option<BvhNode>],
-    mut_nearest_neighbor_index: &mut usize,
-) -> Result<(), ErrorCode> {
-    let current_c_in = match c_in[index] {
-        Some(c_cin) => c_cin,
-        None => return Err(ErrorCode::AccessFailure),
-    };
-    let current_index_cluster = match clusters[current_c_in] {
-        Some(node) => node,
-        None => return Err(ErrorCode::AccessFailure),
-    };
-    let start_index = max(0, index as i32 - search_radius as i32) as usize;
-    let end_index = min(iteration, 1 + index + search_radius as usize);
+fn create_leaf_nodes(scene: &Scene, primitive_indices: &[usize]) -> Vec<BvhNode> {
+    primitive_indices
+        .par_iter()
+        .map(|&primitive_index| BvhNode::from_primitive(scene, primitive_index))
+        .collect::<Vec<BvhNode>>()
+}
 
-    let mut min_dist = f32::INFINITY;
+/// Returns the position in `c_in` of the nearest neighbor of the cluster at position `index`
+fn nearest_neighbor_search(index: usize, c_in: &[usize], clusters: &[BvhNode]) -> usize {
+    let bounding_box = &clusters[c_in[index]].bounding_box;
+    let start_index = index.saturating_sub(SEARCH_RADIUS);
+    let end_index = usize::min(c_in.len(), index + SEARCH_RADIUS + 1);
 
-    #[allow(clippy::needless_range_loop)]
-    for j in start_index..end_index {
+    let mut min_dist = f32::INFINITY;
+    let mut nearest_neighbor_index = index;
+    for (j, &j_c_in) in c_in.iter().enumerate().take(end_index).skip(start_index) {
         if j == index {
             continue;
         }
-        let j_c_in = match c_in[j] {
-            Some(c_cin) => c_cin,
-            None => return Err(ErrorCode::AccessFailure),
-        };
-        let j_index_cluster = match clusters[j_c_in] {
-            Some(node) => node,
-            None => return Err(ErrorCode::AccessFailure),
-        };
-
-        let new_aabb = Aabb::merge(
-            &current_index_cluster.bounding_box,
-            &j_index_cluster.bounding_box,
-        );
+        let new_aabb = Aabb::merge(bounding_box, &clusters[j_c_in].bounding_box);
         let cur_dist = new_aabb.get_surface_area();
 
         // Update neighbour if needed
         if cur_dist < min_dist {
             min_dist = cur_dist;
-            *mut_nearest_neighbor_index = j;
+            nearest_neighbor_index = j;
         }
     }
-    Ok(())
+    nearest_neighbor_index
 }
 
-#[allow(clippy::too_many_arguments)]
-fn merging(
-    index: usize,
-    nb_total_clusters: &Mutex<&mut usize>,
-    nearest_neighbor_indices: &[usize],
-    mut_clusters: &Mutex<&mut Vec<Option<BvhNode>>>,
-    mut_left_children: &Mutex<&mut Vec<Option<usize>>>,
-    mut_right_children: &Mutex<&mut Vec<Option<usize>>>,
-    mut_parents: &Mutex<&mut Vec<Option<usize>>>,
-    mut_c_in: &Mutex<&mut Vec<Option<usize>>>,
-) -> Result<(), ErrorCode> {
-    let neighbor_index = nearest_neighbor_indices[index];
-    // If nearest neighbors of two clusters mutually correspond
-    if nearest_neighbor_indices[neighbor_index] == index {
-        // To avoid conflicts, only meging on the lower index
-        if index < neighbor_index {
-            // For global clusters arrays
-            let ci = match mut_c_in.lock().unwrap()[index] {
-                Some(c_cin) => c_cin,
-                None => return Err(ErrorCode::AccessFailure),
-            };
-            let ci_neighbor = match mut_c_in.lock().unwrap()[neighbor_index] {
-                Some(c_cin) => c_cin,
-                None => return Err(ErrorCode::AccessFailure),
-            };
-
-            // Update new clusters
-            let node = match mut_clusters.lock().unwrap()[ci] {
-                Some(node) => node,
-                None => return Err(ErrorCode::AccessFailure),
-            };
-            let node_neighbor = match mut_clusters.lock().unwrap()[ci_neighbor] {
-                Some(node) => node,
-                None => return Err(ErrorCode::AccessFailure),
-            };
-            let merged_node = BvhPloc::merge_nodes(&node, &node_neighbor);
+/// Exclusive prefix sum of the flags computed in parallel by blocks
+/// Returns the offsets and the total sum
+fn exclusive_scan_parallel(flags: &[u32]) -> (Vec<usize>, usize) {
+    // Sum of each block
+    let block_sums = flags
+        .par_chunks(BLOCK_SIZE)
+        .map(|block| block.iter().map(|&flag| flag as usize).sum::<usize>())
+        .collect::<Vec<usize>>();
+
+    // Scan of the block sums (in one thread, there are only a few blocks)
+    let mut block_offsets = Vec::with_capacity(block_sums.len());
+    let mut total = 0;
+    for block_sum in block_sums {
+        block_offsets.push(total);
+        total += block_sum;
+    }
 
-            let mut nb_clusters = nb_total_clusters.lock().unwrap();
-            let new_cluster_index: usize = **nb_clusters;
-            **nb_clusters += 1;
+    // Scan inside each block
+    let mut offsets = vec![0; flags.len()];
+    offsets
+        .par_chunks_mut(BLOCK_SIZE)
+        .zip(flags.par_chunks(BLOCK_SIZE))
+        .zip(block_offsets.par_iter())
+        .for_each(|((block_offsets, block_flags), &block_offset)| {
+            let mut sum = block_offset;
+            for (offset, &flag) in block_offsets.iter_mut().zip(block_flags) {
+                *offset = sum;
+                sum += flag as usize;
+            }
+        });
 
-            mut_clusters.lock().unwrap()[new_cluster_index] = Some(merged_node);
-            mut_left_children.lock().unwrap()[new_cluster_index] = Some(ci);
-            mut_right_children.lock().unwrap()[new_cluster_index] = Some(ci_neighbor);
-            mut_parents.lock().unwrap()[ci] = Some(new_cluster_index);
-            mut_parents.lock().unwrap()[ci_neighbor] = Some(new_cluster_index);
+    (offsets, total)
+}
 
-            // Mark merged cluster as invalid
-            mut_c_in.lock().unwrap()[neighbor_index] = None;
-            mut_c_in.lock().unwrap()[index] = Some(new_cluster_index);
-        }
+/// Write `value(index)` at `output[offsets[index]]` for every flagged index
+/// Each block owns a disjoint slice of the output so the writes need no synchronization
+fn scatter_parallel<T, F>(
+    flags: &[u32],
+    offsets: &[usize],
+    total: usize,
+    output: &mut [T],
+    value: F,
+) where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+{
+    // Split the output into one slice per block
+    let mut block_outputs = Vec::with_capacity(flags.len().div_ceil(BLOCK_SIZE));
+    let mut remaining = &mut output[..total];
+    for block_start in (0..flags.len()).step_by(BLOCK_SIZE) {
+        let block_end = usize::min(block_start + BLOCK_SIZE, flags.len());
+        let next_offset = if block_end < flags.len() {
+            offsets[block_end]
+        } else {
+            total
+        };
+        let block_size = next_offset - offsets[block_start];
+        let (block_output, rest) = std::mem::take(&mut remaining).split_at_mut(block_size);
+        block_outputs.push((block_start, block_end, block_output));
+        remaining = rest;
     }
-    Ok(())
-}
 
-fn prefix_scan_parallel(iteration: usize, c_in: &[Option<usize>], prefix_scan: &mut [usize]) {
-    ((1..iteration), &mut prefix_scan[1..iteration])
+    block_outputs
         .into_par_iter()
-        .for_each(|(index, prefix)| {
-            *prefix = if c_in[index - 1].is_some() { 1 } else { 0 };
+        .for_each(|(block_start, block_end, block_output)| {
+            let first_offset = offsets[block_start];
+            for index in block_start..block_end {
+                if flags[index] != 0 {
+                    block_output[offsets[index] - first_offset] = value(index);
+                }
+            }
         });
-
-    let mut temp = vec![0; iteration];
-    let mut step = 1;
-    while step < iteration {
-        ((step..iteration), &mut temp[step..iteration])
-            .into_par_iter()
-            .for_each(|(index, temp)| *temp = prefix_scan[index] + prefix_scan[index - step]);
-
-        (
-            &mut temp[step..iteration],
-            &mut prefix_scan[step..iteration],
-        )
-            .into_par_iter()
-            .for_each(|(temp, prefix)| {
-                *prefix = *temp;
-                *temp = 0;
-            });
-        step *= 2;
-    }
 }
 
-fn compaction(
-    index: usize,
-    c_in: &[Option<usize>],
-    prefix_scan: &[usize],
-    mut_c_out: &Mutex<&mut Vec<Option<usize>>>,
-) {
-    if let Some(ci) = c_in[index] {
-        let new_index = prefix_scan[index];
-        let mut mut_c_out = mut_c_out.lock().unwrap();
-        mut_c_out[new_index] = Some(ci)
-    }
-}
+/// Flatten the clusters into the depth first layout expected by the GPU
+fn get_bvh(nb_primitives: usize, clusters: &[BvhNode]) -> Vec<BvhNode> {
+    let mut final_bvh: Vec<BvhNode> = Vec::with_capacity(clusters.len());
+    // (cluster index, (parent position in the final bvh, is left child))
+    let mut stack: Vec<(usize, Option<(usize, bool)>)> = vec![(clusters.len() - 1, None)];
+    while let Some((cluster_index, parent)) = stack.pop() {
+        let position = final_bvh.len();
+        if let Some((parent_position, is_left_child)) = parent {
+            if is_left_child {
+                final_bvh[parent_position].left_child_index = position as u32;
+            } else {
+                final_bvh[parent_position].right_child_index = position as u32;
+            }
+        }
 
-fn get_bvh_node_recursive(
-    final_bvh: &mut Vec<BvhNode>,
-    cur_node_index: usize,
-    clusters: &[Option<BvhNode>],
-    left_children: &[Option<usize>],
-    right_children: &[Option<usize>],
-    is_leaf: &[bool],
-) -> Result<(), ErrorCode> {
-    let cur_node = match clusters[cur_node_index] {
-        Some(cur_node) => cur_node,
-        None => return Err(ErrorCode::AccessFailure),
-    };
-    let position = final_bvh.len();
-    final_bvh.push(cur_node);
-    if !is_leaf[cur_node_index] {
-        let left_child = match left_children[cur_node_index] {
-            Some(child) => child,
-            None => return Err(ErrorCode::AccessFailure),
-        };
-        final_bvh[position].left_child_index = final_bvh.len() as u32;
-        get_bvh_node_recursive(
-            final_bvh,
-            left_child,
-            clusters,
-            left_children,
-            right_children,
-            is_leaf,
-        )?;
-        let right_child = match right_children[cur_node_index] {
-            Some(child) => child,
-            None => return Err(ErrorCode::AccessFailure),
-        };
-        final_bvh[position].right_child_index = final_bvh.len() as u32;
-        get_bvh_node_recursive(
-            final_bvh,
-            right_child,
-            clusters,
-            left_children,
-            right_children,
-            is_leaf,
-        )?;
+        let cluster = clusters[cluster_index];
+        // Internal nodes are always created after the leaves
+        if cluster_index >= nb_primitives {
+            // The right child is popped after the whole left subtree
+            stack.push((cluster.right_child_index as usize, Some((position, false))));
+            stack.push((cluster.left_child_index as usize, Some((position, true))));
+        }
+        final_bvh.push(cluster);
     }
-    Ok(())
-}
-
-fn get_bvh(
-    nb_triangles: usize,
-    clusters: &[Option<BvhNode>],
-    left_children: &[Option<usize>],
-    right_children: &[Option<usize>],
-    is_leaf: &[bool],
-) -> Result<Vec<BvhNode>, ErrorCode> {
-    let mut final_bvh = Vec::new();
-    let root_node_index = 2 * nb_triangles - 2;
-    get_bvh_node_recursive(
-        &mut final_bvh,
-        root_node_index,
-        clusters,
-        left_children,
-        right_children,
-        is_leaf,
-    )?;
-    Ok(final_bvh)
+    final_bvh
 }
 
 impl Bvh for BvhPlocParallel {
-    fn build(scene: &Scene) -> Result<Vec<BvhNode>, ErrorCode> {
-        let nb_triangles = scene.triangles.len();
-        let search_radius: u32 = 16;
-
-        // Init the variables
-        let mut nb_total_clusters = nb_triangles;
-        let mut iteration = nb_triangles;
-
-        // Init the arrays
-        let mut triangle_indices: Vec<usize> = BvhPloc::get_triangle_indices(nb_triangles);
-        let mut clusters: Vec<Option<BvhNode>> = vec![None; 2 * nb_triangles - 1];
-        let mut is_leaf: Vec<bool> = vec![false; 2 * nb_triangles - 1];
-        let mut parents: Vec<Option<usize>> = vec![None; 2 * nb_triangles - 1];
-        let mut left_children: Vec<Option<usize>> = vec![None; 2 * nb_triangles - 1];
-        let mut right_children: Vec<Option<usize>> = vec![None; 2 * nb_triangles - 1];
-
-        let mut c_in: Vec<Option<usize>> = vec![None; nb_triangles];
-        let mut c_out: Vec<Option<usize>> = vec![None; nb_triangles];
-        let mut nearest_neighbor_indices: Vec<usize> = vec![0; nb_triangles];
-        let mut prefix_scan: Vec<usize> = vec![0; nb_triangles];
-        let mut morton_codes: Vec<u32> = PlocParameters::get_morton_codes(scene)?;
+    fn build_with_progress(
+        scene: &Scene,
+        progress: &BvhBuildProgress,
+    ) -> Result<Vec<BvhNode>, ErrorCode> {
+        let nb_primitives = scene.get_nb_primitives();
+        if nb_primitives == 0 {
+            return Ok(Vec::new());
+        }
 
         // Preprocessing
-        PlocParameters::sort(&mut triangle_indices, &mut morton_codes);
-        // Create leaf nodes
-        (
-            (0..nb_triangles),
-            &mut clusters[0..nb_triangles],
-            &mut is_leaf[0..nb_triangles],
-            &mut c_in,
-        )
-            .into_par_iter()
-            .for_each(|(index, cluster, is_leaf, c_in)| {
-                create_leaf_nodes(index, scene, &triangle_indices, cluster, is_leaf, c_in);
-            });
+        let morton_codes = PlocParameters::get_morton_codes(scene)?;
+        let primitive_indices = get_sorted_primitive_indices(morton_codes);
+
+        // Leaves are stored first, then each merged cluster is appended after them
+        let mut clusters = create_leaf_nodes(scene, &primitive_indices);
+        clusters.resize(2 * nb_primitives - 1, BvhNode::default());
+        let mut nb_total_clusters = nb_primitives;
+
+        let mut c_in: Vec<usize> = (0..nb_primitives).collect();
+        let mut c_out: Vec<usize> = vec![0; nb_primitives];
+
+        while c_in.len() > 1 {
+            progress.check_cancelled()?;
+            progress
+                .set_progress((nb_total_clusters - nb_primitives) as f32 / nb_primitives as f32);
+            let iteration = c_in.len();
 
-        while iteration > 1 {
             // Nearest neighbor search (in parallel)
-            (
-                (0..iteration),
-                &mut nearest_neighbor_indices[0..iteration]
-            ).into_par_iter()
-                .try_for_each(|(index, nearest_neighbor_index)|{
-                    if let Err(err) = nearest_neighbor_search(index, iteration, search_radius, &c_in, &clusters, nearest_neighbor_index){
-                        error!("Failed to do the nearest neighbor search phase in the parallel ploc algorithm: {:?}", err);
-                        return Err(ErrorCode::Unknown);
-                    };
-                    Ok(())
-                }
-            )?;
-
-            // Merging (in parallel)
-            let mutex_clusters = Mutex::new(&mut clusters);
-            let mutex_left_children = Mutex::new(&mut left_children);
-            let mutex_right_children = Mutex::new(&mut right_children);
-            let mutex_parents = Mutex::new(&mut parents);
-            let mutex_c_in = Mutex::new(&mut c_in);
-            let mutex_nb_total_clusters = Mutex::new(&mut nb_total_clusters);
-
-            (0..iteration).into_par_iter().try_for_each(|index| {
-                if let Err(err) = merging(
-                    index,
-                    &mutex_nb_total_clusters,
-                    &nearest_neighbor_indices,
-                    &mutex_clusters,
-                    &mutex_left_children,
-                    &mutex_right_children,
-                    &mutex_parents,
-                    &mutex_c_in,
-                ) {
-                    error!(
-                        "Failed to do the merging phase in the parallel ploc algorithm: {:?}",
-                        err
-                    );
-                    return Err(ErrorCode::Unknown);
-                };
-                Ok(())
-            })?;
-
-            // Prefix Scan (in parallel)
-            prefix_scan_parallel(iteration, &c_in, &mut prefix_scan);
+            let nearest_neighbor_indices = (0..iteration)
+                .into_par_iter()
+                .map(|index| nearest_neighbor_search(index, &c_in, &clusters))
+                .collect::<Vec<usize>>();
+
+            // If nearest neighbors of two clusters mutually correspond, only the lower index
+            // creates the new cluster and only the higher index disappears
+            let is_mutual = |index: usize| -> bool {
+                nearest_neighbor_indices[nearest_neighbor_indices[index]] == index
+            };
+            let merge_flags = (0..iteration)
+                .into_par_iter()
+                .map(|index| (is_mutual(index) && index < nearest_neighbor_indices[index]) as u32)
+                .collect::<Vec<u32>>();
+            let keep_flags = (0..iteration)
+                .into_par_iter()
+                .map(|index| (!is_mutual(index) || index < nearest_neighbor_indices[index]) as u32)
+                .collect::<Vec<u32>>();
+
+            // Prefix scans (in parallel)
+            let (merge_offsets, nb_merges) = exclusive_scan_parallel(&merge_flags);
+            let (keep_offsets, nb_kept) = exclusive_scan_parallel(&keep_flags);
+            if nb_merges == 0 {
+                error!(
+                    "No clusters were merged during an iteration of the parallel ploc algorithm"
+                );
+                return Err(ErrorCode::Unknown);
+            }
+
+            // Merging (in parallel), new clusters are only written after the existing ones
+            let (old_clusters, new_clusters) = clusters.split_at_mut(nb_total_clusters);
+            let old_clusters = &*old_clusters;
+            scatter_parallel(
+                &merge_flags,
+                &merge_offsets,
+                nb_merges,
+                new_clusters,
+                |index| {
+                    let left_index = c_in[index];
+                    let right_index = c_in[nearest_neighbor_indices[index]];
+                    BvhNode::merge_bottom_up(
+                        &old_clusters[left_index],
+                        &old_clusters[right_index],
+                        left_index as u32,
+                        right_index as u32,
+                    )
+                },
+            );
 
             // Compaction (in parallel)
-            let mutex_c_out = Mutex::new(&mut c_out);
-            (0..iteration).into_par_iter().for_each(|index| {
-                compaction(index, &c_in, &prefix_scan, &mutex_c_out);
+            scatter_parallel(&keep_flags, &keep_offsets, nb_kept, &mut c_out, |index| {
+                if merge_flags[index] != 0 {
+                    nb_total_clusters + merge_offsets[index]
+                } else {
+                    c_in[index]
+                }
             });
 
             // Final update (in one thread)
-            if c_in[iteration - 1].is_some() {
-                iteration = prefix_scan[iteration - 1] + 1;
-            } else {
-                iteration = prefix_scan[iteration - 1];
-            }
+            nb_total_clusters += nb_merges;
+            c_out.truncate(nb_kept);
             std::mem::swap(&mut c_in, &mut c_out);
         }
 
-        // Get the bvh to send to the GPU
-        match get_bvh(
-            nb_triangles,
-            &clusters,
-            &left_children,
-            &right_children,
-            &is_leaf,
-        ) {
-            Ok(bvh) => Ok(bvh),
-            Err(err) => {
-                error!(
-                    "Failed to build the final bvh structure in the ploc algorithm: {:?}",
-                    err
-                );
-                Err(ErrorCode::Unknown)
-            }
+        if nb_total_clusters != clusters.len() {
+            error!(
+                "The parallel ploc algorithm ended with {} clusters instead of {}",
+                nb_total_clusters,
+                clusters.len()
+            );
+            return Err(ErrorCode::Unknown);
         }
+
+        // Get the bvh to send to the GPU
+        Ok(get_bvh(nb_primitives, &clusters))
     }
 }
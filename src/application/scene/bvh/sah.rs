@@ -0,0 +1,162 @@
+use std::{fmt::Write as _, fs, path::Path};
+
+use log::{error, info};
+
+use crate::application::core::error::ErrorCode;
+
+/// File where the calibrated constants are saved, relative to the working directory
+pub const SAH_CONSTANTS_FILE: &str = "sah_constants.txt";
+
+/// Relative costs used by the surface area heuristic
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SahConstants {
+    pub cost_traverse_internal: f32,
+    pub cost_triangle_intersection: f32,
+}
+
+impl Default for SahConstants {
+    fn default() -> Self {
+        Self {
+            cost_traverse_internal: 1.,
+            cost_triangle_intersection: 1.,
+        }
+    }
+}
+
+/// Average cost of tracing one ray through a bvh
+#[derive(Debug, Clone, Copy)]
+pub struct CalibrationSample {
+    pub node_tests_per_ray: f64,
+    pub triangle_tests_per_ray: f64,
+    pub nanoseconds_per_ray: f64,
+}
+
+impl SahConstants {
+    /// Read constants saved by `save`, one `name = value` per line
+    pub fn load(path: &Path) -> Result<Self, ErrorCode> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) => {
+                error!("Failed to read the sah constants `{:?}': {:?}", path, err);
+                return Err(ErrorCode::IO);
+            }
+        };
+
+        let mut constants = Self::default();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                error!("Invalid line `{}' in the sah constants `{:?}'", line, path);
+                return Err(ErrorCode::Unknown);
+            };
+            let value = match value.trim().parse::<f32>() {
+                Ok(value) if value > 0. => value,
+                _ => {
+                    error!("Invalid value `{}' in the sah constants `{:?}'", line, path);
+                    return Err(ErrorCode::Unknown);
+                }
+            };
+            match name.trim() {
+                "cost_traverse_internal" => constants.cost_traverse_internal = value,
+                "cost_triangle_intersection" => constants.cost_triangle_intersection = value,
+                name => {
+                    error!("Unknown sah constant `{}' in `{:?}'", name, path);
+                    return Err(ErrorCode::Unknown);
+                }
+            }
+        }
+        Ok(constants)
+    }
+
+    /// The saved constants if there are any, the default ones otherwise
+    pub fn load_or_default(path: &Path) -> Self {
+        if !path.exists() {
+            return Self::default();
+        }
+        match Self::load(path) {
+            Ok(constants) => {
+                info!("Using the sah constants from `{:?}': {:?}", path, constants);
+                constants
+            }
+            Err(err) => {
+                error!(
+                    "Failed to load the sah constants, using the default ones: {:?}",
+                    err
+                );
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ErrorCode> {
+        let mut content = String::from("# Nanoseconds per test, see `sah_calibration`\n");
+        let _ = writeln!(
+            content,
+            "cost_traverse_internal = {}",
+            self.cost_traverse_internal
+        );
+        let _ = writeln!(
+            content,
+            "cost_triangle_intersection = {}",
+            self.cost_triangle_intersection
+        );
+        if let Err(err) = fs::write(path, content) {
+            error!("Failed to write the sah constants `{:?}': {:?}", path, err);
+            return Err(ErrorCode::IO);
+        }
+        Ok(())
+    }
+
+    /// Least squares fit of the time per ray from the node and triangle tests per ray
+    /// The relative error is minimized so that the slowest samples do not hide the others
+    pub fn fit(samples: &[CalibrationSample]) -> Result<Self, ErrorCode> {
+        let (mut nn, mut nt, mut tt, mut n_time, mut t_time) = (0., 0., 0., 0., 0.);
+        for sample in samples {
+            let (n, t, time) = (
+                sample.node_tests_per_ray,
+                sample.triangle_tests_per_ray,
+                sample.nanoseconds_per_ray,
+            );
+            if time <= 0. {
+                continue;
+            }
+            let weight = 1. / (time * time);
+            nn += weight * n * n;
+            nt += weight * n * t;
+            tt += weight * t * t;
+            n_time += weight * n * time;
+            t_time += weight * t * time;
+        }
+
+        let determinant = nn * tt - nt * nt;
+        if determinant.abs() <= f64::EPSILON * nn * tt {
+            error!(
+                "Can't fit the sah constants, the {} samples are not independent",
+                samples.len()
+            );
+            return Err(ErrorCode::Unknown);
+        }
+        let cost_traverse_internal = (tt * n_time - nt * t_time) / determinant;
+        let cost_triangle_intersection = (nn * t_time - nt * n_time) / determinant;
+        if cost_traverse_internal <= 0. || cost_triangle_intersection <= 0. {
+            error!(
+                "The fitted sah constants are not positive: {} {}",
+                cost_traverse_internal, cost_triangle_intersection
+            );
+            return Err(ErrorCode::Unknown);
+        }
+
+        Ok(Self {
+            cost_traverse_internal: cost_traverse_internal as f32,
+            cost_triangle_intersection: cost_triangle_intersection as f32,
+        })
+    }
+
+    /// Time predicted by the constants for a sample
+    pub fn predict(&self, sample: &CalibrationSample) -> f64 {
+        self.cost_traverse_internal as f64 * sample.node_tests_per_ray
+            + self.cost_triangle_intersection as f64 * sample.triangle_tests_per_ray
+    }
+}
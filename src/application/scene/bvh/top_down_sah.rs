@@ -1,13 +1,15 @@
 use std::collections::HashSet;
 
+use glam::Vec3;
+
 use log::error;
 
 use crate::application::{
     core::error::ErrorCode,
-    scene::{bvh::aabb::AabbAxis, triangle::Triangle, Scene},
+    scene::{bvh::aabb::AabbAxis, Scene},
 };
 
-use super::{aabb::Aabb, Bvh, BvhNode};
+use super::{aabb::Aabb, build_job::BvhBuildProgress, sah::SahConstants, Bvh, BvhNode};
 
 #[derive(Debug)]
 pub enum SahSetType {
@@ -21,18 +23,18 @@ pub enum SahSetType {
 }
 
 impl SahSetType {
-    pub fn get_initial_set(aabb: Aabb, triangle: &Triangle, scene: &Scene) -> Self {
+    pub fn get_initial_set(aabb: Aabb, primitive_aabb: &Aabb, primitive_centroid: Vec3) -> Self {
         let aabb_longest_axis = aabb.get_longest_axis();
         let half_aabb = (aabb.maxs + aabb.mins) * 0.5;
         let left_max = match aabb_longest_axis {
-            AabbAxis::X => triangle.get_max_x(scene) < half_aabb.x,
-            AabbAxis::Y => triangle.get_max_y(scene) < half_aabb.y,
-            AabbAxis::Z => triangle.get_max_z(scene) < half_aabb.z,
+            AabbAxis::X => primitive_aabb.maxs.x < half_aabb.x,
+            AabbAxis::Y => primitive_aabb.maxs.y < half_aabb.y,
+            AabbAxis::Z => primitive_aabb.maxs.z < half_aabb.z,
         };
         let left_min = match aabb_longest_axis {
-            AabbAxis::X => triangle.get_min_x(scene) > half_aabb.x,
-            AabbAxis::Y => triangle.get_min_y(scene) > half_aabb.y,
-            AabbAxis::Z => triangle.get_min_z(scene) > half_aabb.z,
+            AabbAxis::X => primitive_aabb.mins.x > half_aabb.x,
+            AabbAxis::Y => primitive_aabb.mins.y > half_aabb.y,
+            AabbAxis::Z => primitive_aabb.mins.z > half_aabb.z,
         };
 
         if left_max {
@@ -42,25 +44,23 @@ impl SahSetType {
             return Self::DisjointRight;
         } // Completely on the right
 
-        let triangle_centroid =
-            triangle.get_centroid(scene.models[triangle.model_index].model_matrix);
         match aabb_longest_axis {
             AabbAxis::X => {
-                if triangle_centroid.x < half_aabb.x {
+                if primitive_centroid.x < half_aabb.x {
                     Self::OverlapLeft
                 } else {
                     Self::OverlapRight
                 }
             }
             AabbAxis::Y => {
-                if triangle_centroid.y < half_aabb.y {
+                if primitive_centroid.y < half_aabb.y {
                     Self::OverlapLeft
                 } else {
                     Self::OverlapRight
                 }
             }
             AabbAxis::Z => {
-                if triangle_centroid.z < half_aabb.z {
+                if primitive_centroid.z < half_aabb.z {
                     Self::OverlapLeft
                 } else {
                     Self::OverlapRight
@@ -75,7 +75,7 @@ pub struct BvhTopDownSahNode {
     #[allow(unused)]
     pub set_type: SahSetType,
     pub base: BvhNode,
-    pub triangles: Vec<usize>,
+    pub primitives: Vec<usize>,
     pub is_empty: bool,
 }
 
@@ -84,7 +84,7 @@ impl BvhTopDownSahNode {
         Self {
             set_type,
             base: BvhNode::default(),
-            triangles: Vec::new(),
+            primitives: Vec::new(),
             is_empty: true,
         }
     }
@@ -103,6 +103,17 @@ impl BvhTopDownSahNode {
         }
     }
 
+    /// Cost of a subtree of `nb_primitives` leaves, its `nb_primitives - 1` internal nodes
+    /// are traversed as often as the primitives are tested
+    fn get_subtree_cost(area: f32, nb_primitives: f32, sah_constants: &SahConstants) -> f32 {
+        if nb_primitives == 0. {
+            return 0.;
+        }
+        area * (nb_primitives * sah_constants.cost_triangle_intersection
+            + (nb_primitives - 1.) * sah_constants.cost_traverse_internal)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn compute_costs(
         dr: &Self,
         dl: &Self,
@@ -110,19 +121,22 @@ impl BvhTopDownSahNode {
         ol: &Self,
         sr: &Self,
         sl: &Self,
+        sah_constants: &SahConstants,
     ) -> (f32, f32) {
         let area_dl_ol = Self::get_area(dl, ol);
         let area_dl_sl = Self::get_area(dl, sl);
         let area_dr_or = Self::get_area(dr, or);
         let area_dr_sr = Self::get_area(dr, sr);
 
-        let nb_triangles_dl_ol = (dl.triangles.len() + ol.triangles.len()) as f32;
-        let nb_triangles_dl_sl = (dl.triangles.len() + sl.triangles.len()) as f32;
-        let nb_triangles_dr_or = (dr.triangles.len() + or.triangles.len()) as f32;
-        let nb_triangles_dr_sr = (dr.triangles.len() + sr.triangles.len()) as f32;
+        let nb_primitives_dl_ol = (dl.primitives.len() + ol.primitives.len()) as f32;
+        let nb_primitives_dl_sl = (dl.primitives.len() + sl.primitives.len()) as f32;
+        let nb_primitives_dr_or = (dr.primitives.len() + or.primitives.len()) as f32;
+        let nb_primitives_dr_sr = (dr.primitives.len() + sr.primitives.len()) as f32;
 
-        let cost_overlap = area_dl_ol * nb_triangles_dl_ol + area_dr_or * nb_triangles_dr_or;
-        let cost_split = area_dl_sl * nb_triangles_dl_sl + area_dr_sr * nb_triangles_dr_sr;
+        let cost_overlap = Self::get_subtree_cost(area_dl_ol, nb_primitives_dl_ol, sah_constants)
+            + Self::get_subtree_cost(area_dr_or, nb_primitives_dr_or, sah_constants);
+        let cost_split = Self::get_subtree_cost(area_dl_sl, nb_primitives_dl_sl, sah_constants)
+            + Self::get_subtree_cost(area_dr_sr, nb_primitives_dr_sr, sah_constants);
 
         (cost_overlap, cost_split)
     }
@@ -135,41 +149,41 @@ impl BvhTopDownSahNode {
         sl: &Self,
     ) -> Self {
         if cost_overlap < cost_split {
-            let left_triangles: Vec<usize> = dl.triangles
+            let left_primitives: Vec<usize> = dl.primitives
                 .iter()
                 .copied()
-                .chain(ol.triangles.iter().copied())
+                .chain(ol.primitives.iter().copied())
                 .collect::<HashSet<_>>() // Deduplicate using HashSet
                 .into_iter() // Convert back to an iterator
                 .collect() // Collect into a Vec<usize>
             ;
-            let triangle_index = left_triangles[0];
+            let primitive_index = left_primitives[0];
             BvhTopDownSahNode {
                 set_type: SahSetType::Final,
-                triangles: left_triangles,
+                primitives: left_primitives,
                 base: BvhNode {
                     bounding_box: Aabb::merge(&dl.base.bounding_box, &ol.base.bounding_box),
-                    triangle_index: triangle_index as u32,
+                    primitive_index: primitive_index as u32,
                     ..Default::default()
                 },
                 is_empty: false,
             }
         } else {
-            let left_triangles: Vec<usize> = dl.triangles
+            let left_primitives: Vec<usize> = dl.primitives
                 .iter()
                 .copied()
-                .chain(sl.triangles.iter().copied())
+                .chain(sl.primitives.iter().copied())
                 .collect::<HashSet<_>>() // Deduplicate using HashSet
                 .into_iter() // Convert back to an iterator
                 .collect() // Collect into a Vec<usize>
             ;
-            let triangle_index = left_triangles[0];
+            let primitive_index = left_primitives[0];
             BvhTopDownSahNode {
                 set_type: SahSetType::Final,
-                triangles: left_triangles,
+                primitives: left_primitives,
                 base: BvhNode {
                     bounding_box: Aabb::merge(&dl.base.bounding_box, &sl.base.bounding_box),
-                    triangle_index: triangle_index as u32,
+                    primitive_index: primitive_index as u32,
                     ..Default::default()
                 },
                 is_empty: false,
@@ -185,41 +199,41 @@ impl BvhTopDownSahNode {
         sr: &Self,
     ) -> Self {
         if cost_overlap < cost_split {
-            let right_triangles: Vec<usize> = dr.triangles
+            let right_primitives: Vec<usize> = dr.primitives
                 .iter()
                 .copied()
-                .chain(or.triangles.iter().copied())
+                .chain(or.primitives.iter().copied())
                 .collect::<HashSet<_>>() // Deduplicate using HashSet
                 .into_iter() // Convert back to an iterator
                 .collect() // Collect into a Vec<usize>
             ;
-            let triangle_index = right_triangles[0];
+            let primitive_index = right_primitives[0];
             BvhTopDownSahNode {
                 set_type: SahSetType::Final,
-                triangles: right_triangles,
+                primitives: right_primitives,
                 base: BvhNode {
                     bounding_box: Aabb::merge(&dr.base.bounding_box, &or.base.bounding_box),
-                    triangle_index: triangle_index as u32,
+                    primitive_index: primitive_index as u32,
                     ..Default::default()
                 },
                 is_empty: false,
             }
         } else {
-            let right_triangles: Vec<usize> = dr.triangles
+            let right_primitives: Vec<usize> = dr.primitives
                 .iter()
                 .copied()
-                .chain(sr.triangles.iter().copied())
+                .chain(sr.primitives.iter().copied())
                 .collect::<HashSet<_>>() // Deduplicate using HashSet
                 .into_iter() // Convert back to an iterator
                 .collect() // Collect into a Vec<usize>
             ;
-            let triangle_index = right_triangles[0];
+            let primitive_index = right_primitives[0];
             BvhTopDownSahNode {
                 set_type: SahSetType::Final,
-                triangles: right_triangles,
+                primitives: right_primitives,
                 base: BvhNode {
                     bounding_box: Aabb::merge(&dr.base.bounding_box, &sr.base.bounding_box),
-                    triangle_index: triangle_index as u32,
+                    primitive_index: primitive_index as u32,
                     ..Default::default()
                 },
                 is_empty: false,
@@ -250,44 +264,41 @@ impl BvhTopDownSahNode {
         let mut sl = Self::new(SahSetType::SplitLeft);
 
         // Get mid point bounding box
-        let triangles: Vec<Triangle> = self
-            .triangles
+        let midpoints = self
+            .primitives
             .iter()
-            .map(|&index| scene.triangles[index])
-            .collect();
-        let midpoints = Triangle::get_centroids(&triangles, &scene.models);
+            .map(|&index| scene.get_primitive_centroid(index))
+            .collect::<Vec<Vec3>>();
         let midpoint_aabb = Aabb::from_points(&midpoints);
 
         // Partition into 4 sets
-        for &triangle_index in &self.triangles {
-            let triangle = scene.triangles[triangle_index];
-            let model_matrix = scene.models[triangle.model_index].model_matrix;
-            let triangle_aabb = Aabb::from_triangle(&triangle, model_matrix);
-            match SahSetType::get_initial_set(midpoint_aabb, &triangle, scene) {
+        for (&primitive_index, &midpoint) in std::iter::zip(&self.primitives, &midpoints) {
+            let primitive_aabb = scene.get_primitive_aabb(primitive_index);
+            match SahSetType::get_initial_set(midpoint_aabb, &primitive_aabb, midpoint) {
                 SahSetType::DisjointRight => {
-                    dr.triangles.push(triangle_index);
+                    dr.primitives.push(primitive_index);
                     dr.is_empty = false;
-                    dr.base.bounding_box = Aabb::merge(&dr.base.bounding_box, &triangle_aabb);
+                    dr.base.bounding_box = Aabb::merge(&dr.base.bounding_box, &primitive_aabb);
                 }
                 SahSetType::DisjointLeft => {
-                    dl.triangles.push(triangle_index);
+                    dl.primitives.push(primitive_index);
                     dl.is_empty = false;
-                    dl.base.bounding_box = Aabb::merge(&dl.base.bounding_box, &triangle_aabb);
+                    dl.base.bounding_box = Aabb::merge(&dl.base.bounding_box, &primitive_aabb);
                 }
                 SahSetType::OverlapRight => {
-                    or.triangles.push(triangle_index);
-                    sr.triangles.push(triangle_index);
-                    sl.triangles.push(triangle_index);
-                    or.base.bounding_box = Aabb::merge(&or.base.bounding_box, &triangle_aabb);
+                    or.primitives.push(primitive_index);
+                    sr.primitives.push(primitive_index);
+                    sl.primitives.push(primitive_index);
+                    or.base.bounding_box = Aabb::merge(&or.base.bounding_box, &primitive_aabb);
                     or.is_empty = false;
                     sr.is_empty = false;
                     sl.is_empty = false;
                 }
                 SahSetType::OverlapLeft => {
-                    ol.triangles.push(triangle_index);
-                    sr.triangles.push(triangle_index);
-                    sl.triangles.push(triangle_index);
-                    ol.base.bounding_box = Aabb::merge(&ol.base.bounding_box, &triangle_aabb);
+                    ol.primitives.push(primitive_index);
+                    sr.primitives.push(primitive_index);
+                    sl.primitives.push(primitive_index);
+                    ol.base.bounding_box = Aabb::merge(&ol.base.bounding_box, &primitive_aabb);
                     ol.is_empty = false;
                     sr.is_empty = false;
                     sl.is_empty = false;
@@ -332,7 +343,7 @@ pub struct BvhTopDownSah<'a> {
 
 impl<'a> BvhTopDownSah<'a> {
     pub fn new(scene: &'a Scene) -> Result<Self, ErrorCode> {
-        let aabb = match Aabb::from_scene(&scene.triangles, &scene.models) {
+        let aabb = match scene.get_aabb() {
             Ok(aabb) => aabb,
             Err(err) => {
                 error!(
@@ -345,17 +356,17 @@ impl<'a> BvhTopDownSah<'a> {
 
         let node_base = BvhNode {
             bounding_box: aabb,
-            triangle_index: 0,
+            primitive_index: 0,
             left_child_index: 0,
             right_child_index: 0,
-            padding_1: 0,
+            primitive_type: 0,
         };
-        let triangles = (0..scene.triangles.len()).collect::<Vec<usize>>();
+        let primitives = (0..scene.get_nb_primitives()).collect::<Vec<usize>>();
 
         let root_node = BvhTopDownSahNode {
             set_type: SahSetType::Final,
             base: node_base,
-            triangles,
+            primitives,
             is_empty: false,
         };
 
@@ -383,7 +394,7 @@ impl<'a> BvhTopDownSah<'a> {
     pub fn get_false_leaves_indices(&self) -> Vec<usize> {
         let mut leaves = Vec::new();
         for (index, node) in self.bvh.iter().enumerate() {
-            if node.triangles.len() > 1 && node.base.is_leaf() {
+            if node.primitives.len() > 1 && node.base.is_leaf() {
                 leaves.push(index);
             }
         }
@@ -394,7 +405,7 @@ impl<'a> BvhTopDownSah<'a> {
     pub fn get_leaves(&self) -> Vec<&BvhTopDownSahNode> {
         let mut leaves = Vec::new();
         for node in &self.bvh {
-            if node.triangles.len() == 1 && node.base.is_leaf() {
+            if node.primitives.len() == 1 && node.base.is_leaf() {
                 leaves.push(node);
             }
         }
@@ -413,36 +424,20 @@ impl<'a> BvhTopDownSah<'a> {
         &self,
         bvh_node: &BvhTopDownSahNode,
     ) -> (BvhTopDownSahNode, BvhTopDownSahNode) {
-        debug_assert!(bvh_node.triangles.len() == 2);
-        let left_triangle_index = bvh_node.triangles[0];
-        let left_triangle = self.scene.triangles[left_triangle_index];
-        let left_model_matrix = self.scene.models[left_triangle.model_index].model_matrix;
+        debug_assert!(bvh_node.primitives.len() == 2);
+        let left_primitive_index = bvh_node.primitives[0];
         let left_child = BvhTopDownSahNode {
             set_type: SahSetType::Final,
-            base: BvhNode {
-                bounding_box: Aabb::from_triangle(&left_triangle, left_model_matrix),
-                triangle_index: left_triangle_index as u32,
-                left_child_index: 0,
-                right_child_index: 0,
-                padding_1: 0,
-            },
-            triangles: vec![left_triangle_index],
+            base: BvhNode::from_primitive(self.scene, left_primitive_index),
+            primitives: vec![left_primitive_index],
             is_empty: false,
         };
 
-        let right_triangle_index = bvh_node.triangles[1];
-        let right_triangle = self.scene.triangles[right_triangle_index];
-        let right_model_matrix = self.scene.models[right_triangle.model_index].model_matrix;
+        let right_primitive_index = bvh_node.primitives[1];
         let right_child = BvhTopDownSahNode {
             set_type: SahSetType::Final,
-            base: BvhNode {
-                bounding_box: Aabb::from_triangle(&right_triangle, right_model_matrix),
-                triangle_index: right_triangle_index as u32,
-                left_child_index: 0,
-                right_child_index: 0,
-                padding_1: 0,
-            },
-            triangles: vec![right_triangle_index],
+            base: BvhNode::from_primitive(self.scene, right_primitive_index),
+            primitives: vec![right_primitive_index],
             is_empty: false,
         };
 
@@ -453,8 +448,8 @@ impl<'a> BvhTopDownSah<'a> {
         &self,
         bvh_node: &BvhTopDownSahNode,
     ) -> Result<(BvhTopDownSahNode, BvhTopDownSahNode), ErrorCode> {
-        // Check if there are only 2 triangles left in the node
-        if bvh_node.triangles.len() == 2 {
+        // Check if there are only 2 primitives left in the node
+        if bvh_node.primitives.len() == 2 {
             Ok(self.build_last_two_children(bvh_node))
         } else {
             // Compute DL, DR, OL, OR, SL, SR
@@ -467,20 +462,27 @@ impl<'a> BvhTopDownSah<'a> {
             };
 
             // Handle degenerate cases (due to floating point error ?)
-            if sl.triangles.is_empty() && dl.triangles.is_empty() {
-                let from_right = dr.triangles[0];
-                sl.triangles.push(from_right);
-                dr.triangles.swap_remove(0);
+            if sl.primitives.is_empty() && dl.primitives.is_empty() {
+                let from_right = dr.primitives[0];
+                sl.primitives.push(from_right);
+                dr.primitives.swap_remove(0);
             }
-            if sr.triangles.is_empty() && dr.triangles.is_empty() {
-                let from_left = dl.triangles[0];
-                sr.triangles.push(from_left);
-                dl.triangles.swap_remove(0);
+            if sr.primitives.is_empty() && dr.primitives.is_empty() {
+                let from_left = dl.primitives[0];
+                sr.primitives.push(from_left);
+                dl.primitives.swap_remove(0);
             }
 
             // Compute SAH cost CO and CS
-            let (cost_overlap, cost_split) =
-                BvhTopDownSahNode::compute_costs(&dr, &dl, &or, &ol, &sr, &sl);
+            let (cost_overlap, cost_split) = BvhTopDownSahNode::compute_costs(
+                &dr,
+                &dl,
+                &or,
+                &ol,
+                &sr,
+                &sl,
+                &self.scene.sah_constants,
+            );
 
             // Create the new left and right children
             let left =
@@ -493,7 +495,14 @@ impl<'a> BvhTopDownSah<'a> {
 }
 
 impl Bvh for BvhTopDownSah<'_> {
-    fn build(scene: &Scene) -> Result<Vec<BvhNode>, ErrorCode> {
+    fn build_with_progress(
+        scene: &Scene,
+        progress: &BvhBuildProgress,
+    ) -> Result<Vec<BvhNode>, ErrorCode> {
+        if scene.get_nb_primitives() == 0 {
+            return Ok(Vec::new());
+        }
+
         let mut top_down_sah_bvh = match BvhTopDownSah::new(scene) {
             Ok(handler) => handler,
             Err(err) => {
@@ -502,8 +511,11 @@ impl Bvh for BvhTopDownSah<'_> {
             }
         };
 
-        // While there are not as many leaves as the number of triangles in the scene
+        // While there are not as many leaves as the number of primitives in the scene
+        let nb_nodes = 2 * scene.get_nb_primitives() - 1;
         'main: loop {
+            progress.check_cancelled()?;
+            progress.set_progress(top_down_sah_bvh.bvh.len() as f32 / nb_nodes as f32);
             let expandable_leaves = top_down_sah_bvh.get_false_leaves_indices();
             if expandable_leaves.is_empty() {
                 break 'main;
@@ -524,20 +536,20 @@ impl Bvh for BvhTopDownSah<'_> {
                     }
                 };
 
-                if leaf_sah_node.triangles.len() == left.triangles.len() {
-                    'fix: for i in 0..left.triangles.len() {
-                        if right.triangles.contains(&left.triangles[i]) {
-                            left.triangles.swap_remove(i);
-                            left.base.triangle_index = left.triangles[0] as u32;
+                if leaf_sah_node.primitives.len() == left.primitives.len() {
+                    'fix: for i in 0..left.primitives.len() {
+                        if right.primitives.contains(&left.primitives[i]) {
+                            left.primitives.swap_remove(i);
+                            left.base.primitive_index = left.primitives[0] as u32;
                             break 'fix;
                         }
                     }
                 }
-                if leaf_sah_node.triangles.len() == right.triangles.len() {
-                    'fix: for i in 0..right.triangles.len() {
-                        if left.triangles.contains(&right.triangles[i]) {
-                            right.triangles.swap_remove(i);
-                            right.base.triangle_index = right.triangles[0] as u32;
+                if leaf_sah_node.primitives.len() == right.primitives.len() {
+                    'fix: for i in 0..right.primitives.len() {
+                        if left.primitives.contains(&right.primitives[i]) {
+                            right.primitives.swap_remove(i);
+                            right.base.primitive_index = right.primitives[0] as u32;
                             break 'fix;
                         }
                     }
@@ -548,7 +560,21 @@ impl Bvh for BvhTopDownSah<'_> {
             }
         }
 
-        let bvh = top_down_sah_bvh.get_bvh();
+        // The split sets are clipped at the middle, which can leave a part of a primitive out
+        // of every leaf, so the boxes are computed again from the primitives. The children are
+        // always after their parent.
+        let mut bvh = top_down_sah_bvh.get_bvh();
+        for index in (0..bvh.len()).rev() {
+            let node = bvh[index];
+            bvh[index].bounding_box = if node.is_leaf() {
+                scene.get_primitive_aabb(node.primitive_index as usize)
+            } else {
+                Aabb::merge(
+                    &bvh[node.left_child_index as usize].bounding_box,
+                    &bvh[node.right_child_index as usize].bounding_box,
+                )
+            };
+        }
         Ok(bvh)
     }
 }
@@ -1,7 +1,7 @@
 use glam::{Mat4, Vec3, Vec4};
 // use log::error;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 #[repr(C)]
 pub struct CameraGPU {
     pub view_matrix_inverse: Mat4,
@@ -20,13 +20,13 @@ pub enum CameraMovement {
     Down,
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub enum CameraMode {
     Fixed,
     Dynamic,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Camera {
     // camera Attributes
     pub eye: Vec3,
@@ -83,6 +83,14 @@ impl Camera {
         }
     }
 
+    /// Orients the camera towards a world space direction
+    pub fn look_towards(&mut self, direction: Vec3) {
+        let direction = direction.normalize();
+        self.pitch = direction.y.clamp(-1., 1.).asin().to_degrees();
+        self.yaw = direction.z.atan2(direction.x).to_degrees();
+        self.update_vectors();
+    }
+
     fn update_vectors(&mut self) {
         // calculate the new at vector
         let mut front = Vec3::ZERO;
@@ -0,0 +1,578 @@
+use std::{collections::HashMap, ops::Range, path::Path};
+
+use glam::{EulerRot, Mat4, Quat, Vec3};
+use log::{error, info};
+
+use crate::application::core::error::ErrorCode;
+
+use super::{
+    bvh::{BvhNode, BvhType},
+    gltf_import::GltfScene,
+    light::Light,
+    material::Material,
+    model::{Model, PlaneType},
+    triangle::Orientation,
+    Scene,
+};
+
+/// State of the model loading panel of the GUI
+#[derive(Debug, Clone)]
+pub struct ModelLoadParameters {
+    pub path: String,
+    // Files of the models directory that can be picked instead of typing a path
+    pub file_names: Vec<String>,
+    // The search directories are only walked again when refreshed
+    pub has_scanned_files: bool,
+    pub file_index: usize,
+    pub translation: [f32; 3],
+    // Euler angles in degrees
+    pub rotation: [f32; 3],
+    pub scale: f32,
+    // Otherwise the default material is used
+    pub has_uniform_color: bool,
+    pub color: [f32; 3],
+    // Result of the last load
+    pub status: String,
+}
+
+impl Default for ModelLoadParameters {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            file_names: Vec::new(),
+            has_scanned_files: false,
+            file_index: 0,
+            translation: [0.; 3],
+            rotation: [0.; 3],
+            scale: 1.,
+            has_uniform_color: false,
+            color: [1.; 3],
+            status: String::new(),
+        }
+    }
+}
+
+impl ModelLoadParameters {
+    pub fn get_model_matrix(&self) -> Mat4 {
+        let [x, y, z] = self.rotation.map(f32::to_radians);
+        Mat4::from_scale_rotation_translation(
+            Vec3::splat(self.scale),
+            Quat::from_euler(EulerRot::YXZ, y, x, z),
+            Vec3::from_array(self.translation),
+        )
+    }
+
+    pub fn get_material(&self) -> Option<Material> {
+        self.has_uniform_color
+            .then(|| Material::uniform(&Vec3::from_array(self.color)))
+    }
+}
+
+impl Scene {
+    /// Load a model file, its format is given by its extension, and returns the indices
+    /// of the new models
+    pub fn add_model_file(
+        &mut self,
+        path: &Path,
+        model_matrix: Mat4,
+        material: Option<Material>,
+    ) -> Result<Range<usize>, ErrorCode> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("obj" | "ply" | "stl") => self.add_mesh_file(path, model_matrix, material),
+            Some("gltf" | "glb") => self.add_gltf(path, model_matrix, material),
+            _ => {
+                error!("The format of the model file `{:?}' is not supported", path);
+                Err(ErrorCode::InitializationFailure)
+            }
+        }
+    }
+
+    /// Returns the indices of the new models, `material` replaces the default one
+    pub fn add_mesh_file(
+        &mut self,
+        path: &Path,
+        model_matrix: Mat4,
+        material: Option<Material>,
+    ) -> Result<Range<usize>, ErrorCode> {
+        let path = self.asset_resolver.resolve(path)?;
+        let first_model_index = self.models.len();
+        let first_texture_index = self.textures.len();
+        if let Err(err) = Model::add_mesh_file(
+            &path,
+            Some(model_matrix),
+            &mut self.mesh,
+            &mut self.models,
+            &mut self.materials,
+            &mut self.textures,
+        ) {
+            error!(
+                "Failed to add the object `{:?}' to the scene: {:?}",
+                path, err
+            );
+            return Err(ErrorCode::InitializationFailure);
+        }
+        if let Some(material) = material {
+            let material_index = self.materials.len() as u32;
+            self.materials.push(material);
+            for model in &mut self.models[first_model_index..] {
+                model.material_index = material_index;
+            }
+            // The materials of the file and their new textures are not used anymore
+            self.textures.truncate(first_texture_index);
+            self.remove_unused_materials();
+        }
+        self.load_textures();
+        self.on_primitives_changed();
+        Ok(first_model_index..self.models.len())
+    }
+
+    /// Returns the indices of the new models, `material` replaces the ones of the file. The
+    /// cameras of the file are ignored and its lights are added to the scene.
+    pub fn add_gltf(
+        &mut self,
+        path: &Path,
+        model_matrix: Mat4,
+        material: Option<Material>,
+    ) -> Result<Range<usize>, ErrorCode> {
+        let path = self.asset_resolver.resolve(path)?;
+        let mut gltf_scene = match GltfScene::load(&path, self.camera.aspect_ratio) {
+            Ok(gltf_scene) => gltf_scene,
+            Err(err) => {
+                error!(
+                    "Failed to add the glTF scene `{:?}' to the scene: {:?}",
+                    path, err
+                );
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+        gltf_scene.transform(model_matrix);
+        if let Some(material) = material {
+            gltf_scene.materials = vec![material];
+            gltf_scene.textures.clear();
+            for model in &mut gltf_scene.models {
+                model.material_index = 1;
+            }
+        }
+
+        let first_model_index = self.models.len();
+        Model::add_models(
+            gltf_scene.models,
+            gltf_scene.mesh,
+            gltf_scene.materials,
+            gltf_scene.textures,
+            None,
+            &mut self.mesh,
+            &mut self.models,
+            &mut self.materials,
+            &mut self.textures,
+        );
+        self.lights.append(&mut gltf_scene.lights);
+        self.load_textures();
+        self.on_primitives_changed();
+        Ok(first_model_index..self.models.len())
+    }
+
+    /// Returns the index of the new model
+    pub fn add_sphere(&mut self, center: Vec3, radius: f32, material: Option<Material>) -> usize {
+        Model::add_analytic_sphere(
+            radius,
+            center,
+            material,
+            &mut self.spheres,
+            &mut self.models,
+            &mut self.materials,
+        );
+        self.on_primitives_changed();
+        self.models.len() - 1
+    }
+
+    /// Returns the index of the new model
+    pub fn add_cuboid(
+        &mut self,
+        center: Vec3,
+        half_size: Vec3,
+        material: Option<Material>,
+    ) -> usize {
+        Model::add_cuboid(
+            half_size,
+            center,
+            material,
+            &mut self.cuboids,
+            &mut self.models,
+            &mut self.materials,
+        );
+        self.on_primitives_changed();
+        self.models.len() - 1
+    }
+
+    /// Returns the index of the new model
+    pub fn add_plane(&mut self, plane_type: &PlaneType, material: Option<Material>) -> usize {
+        Model::add_plane(
+            plane_type,
+            &Orientation::ClockWise,
+            material,
+            &mut self.mesh,
+            &mut self.models,
+            &mut self.materials,
+        );
+        self.on_primitives_changed();
+        self.models.len() - 1
+    }
+
+    /// Remove the model with its primitives and its material if no other model uses it,
+    /// the models after it are shifted by one
+    pub fn remove_model(&mut self, model_index: usize) -> Result<(), ErrorCode> {
+        if model_index >= self.models.len() {
+            error!(
+                "Can't remove the model {} as the scene only has {} models",
+                model_index,
+                self.models.len()
+            );
+            return Err(ErrorCode::Unknown);
+        }
+
+        let _ = self.models.remove(model_index);
+        self.mesh.remove_model(model_index);
+        let removed_index = model_index as u32;
+        self.spheres
+            .retain(|sphere| sphere.model_index != removed_index);
+        for sphere in &mut self.spheres {
+            if sphere.model_index > removed_index {
+                sphere.model_index -= 1;
+            }
+        }
+        self.cuboids
+            .retain(|cuboid| cuboid.model_index != removed_index);
+        for cuboid in &mut self.cuboids {
+            if cuboid.model_index > removed_index {
+                cuboid.model_index -= 1;
+            }
+        }
+        self.remove_unused_materials();
+
+        info!("Removed the model {} from the scene", model_index);
+        self.on_primitives_changed();
+        Ok(())
+    }
+
+    /// Materials shared with other models, like the default one or the ones of a file, are
+    /// never changed, the model gets its own copy instead
+    pub fn set_material(
+        &mut self,
+        model_index: usize,
+        material: Material,
+    ) -> Result<(), ErrorCode> {
+        let Some(material_index) = self
+            .models
+            .get(model_index)
+            .map(|model| model.material_index)
+        else {
+            error!(
+                "Can't change the material of the model {} as the scene only has {} models",
+                model_index,
+                self.models.len()
+            );
+            return Err(ErrorCode::Unknown);
+        };
+        let nb_users = self
+            .models
+            .iter()
+            .filter(|model| model.material_index == material_index)
+            .count();
+        if material_index == 0 || nb_users > 1 {
+            self.models[model_index].material_index = self.materials.len() as u32;
+            self.materials.push(material);
+            self.remove_unused_materials();
+        } else {
+            self.materials[material_index as usize] = material;
+        }
+        self.is_gpu_data_dirty = true;
+        Ok(())
+    }
+
+    /// Moving a model keeps the bvhs topology, so their bounding boxes are only refitted
+    pub fn set_model_matrix(
+        &mut self,
+        model_index: usize,
+        model_matrix: Mat4,
+    ) -> Result<(), ErrorCode> {
+        let Some(model) = self.models.get_mut(model_index) else {
+            error!(
+                "Can't move the model {} as the scene only has {} models",
+                model_index,
+                self.models.len()
+            );
+            return Err(ErrorCode::Unknown);
+        };
+        model.model_matrix = model_matrix;
+
+        // The running builds work on the old geometry, they are requested again when needed
+        self.cancel_bvh_jobs();
+        let mut bvhs = std::mem::take(&mut self.bvhs);
+        for bvh in bvhs.values_mut().filter(|bvh| !bvh.is_empty()) {
+            BvhNode::refit(bvh, self);
+        }
+        self.bvhs = bvhs;
+        // The selected bvh is sent again to the GPU by the next update of the application
+        self.bvh_last_type = BvhType::None;
+        self.is_gpu_data_dirty = true;
+        Ok(())
+    }
+
+    /// Translate the model so its origin is at `position`
+    pub fn move_model(&mut self, model_index: usize, position: Vec3) -> Result<(), ErrorCode> {
+        let Some(model) = self.models.get(model_index) else {
+            error!(
+                "Can't move the model {} as the scene only has {} models",
+                model_index,
+                self.models.len()
+            );
+            return Err(ErrorCode::Unknown);
+        };
+        let mut model_matrix = model.model_matrix;
+        model_matrix.w_axis = position.extend(1.);
+        self.set_model_matrix(model_index, model_matrix)
+    }
+
+    /// Returns the index of the new light
+    pub fn add_light(&mut self, light: Light) -> usize {
+        self.lights.push(light);
+        self.is_gpu_data_dirty = true;
+        self.lights.len() - 1
+    }
+
+    /// The lights after it are shifted by one
+    pub fn remove_light(&mut self, light_index: usize) -> Result<(), ErrorCode> {
+        if light_index >= self.lights.len() {
+            error!(
+                "Can't remove the light {} as the scene only has {} lights",
+                light_index,
+                self.lights.len()
+            );
+            return Err(ErrorCode::Unknown);
+        }
+        let _ = self.lights.remove(light_index);
+        info!("Removed the light {} from the scene", light_index);
+        self.is_gpu_data_dirty = true;
+        Ok(())
+    }
+
+    pub fn set_light(&mut self, light_index: usize, light: Light) -> Result<(), ErrorCode> {
+        let Some(scene_light) = self.lights.get_mut(light_index) else {
+            error!(
+                "Can't change the light {} as the scene only has {} lights",
+                light_index,
+                self.lights.len()
+            );
+            return Err(ErrorCode::Unknown);
+        };
+        *scene_light = light;
+        self.is_gpu_data_dirty = true;
+        Ok(())
+    }
+
+    /// Drop the materials no model uses anymore, except the default one
+    fn remove_unused_materials(&mut self) {
+        if self.materials.is_empty() {
+            return;
+        }
+        let mut is_used = vec![false; self.materials.len()];
+        is_used[0] = true;
+        for model in &self.models {
+            is_used[model.material_index as usize] = true;
+        }
+
+        let mut new_indices = HashMap::new();
+        let mut materials = Vec::new();
+        for (index, material) in self.materials.iter().enumerate() {
+            if is_used[index] {
+                let _ = new_indices.insert(index as u32, materials.len() as u32);
+                materials.push(*material);
+            }
+        }
+        for model in &mut self.models {
+            model.material_index = new_indices[&model.material_index];
+        }
+        self.materials = materials;
+    }
+
+    /// Adding or removing primitives invalidates every bvh, the selected one is built again
+    /// by the next update and the primitives are traversed without bvh until then
+    fn on_primitives_changed(&mut self) {
+        self.cancel_bvh_jobs();
+        self.bvhs.retain(|bvh_type, _| *bvh_type == BvhType::None);
+        self.bvhs_build_times.clear();
+        self.bvh_max_depth = None;
+        self.bvh_last_type = BvhType::None;
+        if self.get_nb_primitives() == 0 {
+            self.bvh_type = BvhType::None;
+        }
+        self.is_gpu_data_dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use glam::{Mat4, Vec3, Vec4};
+
+    use crate::application::scene::{
+        camera::Camera, light::Light, material::Material, model::PlaneType, Scene,
+    };
+
+    fn get_scene() -> Scene {
+        let camera = Camera::new(Vec3::ZERO, 1., 50., 0.1, Vec3::Y);
+        Scene::init_scene_cornell_box(camera).unwrap()
+    }
+
+    /// What the edits change, the materials by their diffuse colour
+    #[allow(clippy::type_complexity)]
+    fn get_state(scene: &Scene) -> (Vec<(Mat4, Vec4)>, usize, usize, Vec<u32>, Vec<u32>) {
+        let models = scene
+            .models
+            .iter()
+            .map(|model| {
+                let material = scene.materials[model.material_index as usize];
+                (model.model_matrix, material.diffuse)
+            })
+            .collect();
+        (
+            models,
+            scene.materials.len(),
+            scene.mesh.triangles.len(),
+            scene
+                .spheres
+                .iter()
+                .map(|sphere| sphere.model_index)
+                .collect(),
+            scene
+                .cuboids
+                .iter()
+                .map(|cuboid| cuboid.model_index)
+                .collect(),
+        )
+    }
+
+    fn colored(color: Vec3) -> Option<Material> {
+        Some(Material::uniform(&color))
+    }
+
+    #[test]
+    fn add_remove_round_trip() {
+        let mut scene = get_scene();
+        let state = get_state(&scene);
+        let nb_vertices = scene.mesh.vertices.len();
+
+        let sphere = scene.add_sphere(Vec3::ONE, 0.5, colored(Vec3::X));
+        let cuboid = scene.add_cuboid(-Vec3::ONE, Vec3::splat(0.2), colored(Vec3::Y));
+        let plane = scene.add_plane(&PlaneType::Left, None);
+        let models = scene
+            .add_model_file(Path::new("cube_ccw.obj"), Mat4::IDENTITY, colored(Vec3::Z))
+            .unwrap();
+        assert_eq!(
+            [sphere, cuboid, plane, models.start],
+            [
+                state.0.len(),
+                state.0.len() + 1,
+                state.0.len() + 2,
+                state.0.len() + 3
+            ]
+        );
+        assert_eq!(scene.spheres.len(), state.3.len() + 1);
+        assert_eq!(scene.cuboids.len(), state.4.len() + 1);
+        assert!(scene.mesh.triangles.len() > state.2 + 2);
+
+        for model_index in models.rev() {
+            scene.remove_model(model_index).unwrap();
+        }
+        scene.remove_model(plane).unwrap();
+        scene.remove_model(cuboid).unwrap();
+        scene.remove_model(sphere).unwrap();
+        assert_eq!(get_state(&scene), state);
+        assert_eq!(scene.mesh.vertices.len(), nb_vertices);
+    }
+
+    #[test]
+    fn remove_shifts_the_next_models() {
+        let mut scene = get_scene();
+        let state = get_state(&scene);
+        let first = scene.add_sphere(Vec3::ONE, 0.5, colored(Vec3::X));
+        let second = scene.add_cuboid(-Vec3::ONE, Vec3::splat(0.2), colored(Vec3::Y));
+        let third = scene.add_sphere(-Vec3::ONE, 0.5, colored(Vec3::Z));
+        let third_matrix = scene.models[third].model_matrix;
+
+        scene.remove_model(second).unwrap();
+        assert_eq!(scene.models.len(), state.0.len() + 2);
+        assert_eq!(scene.cuboids.len(), state.4.len());
+        // The last sphere follows its model, which keeps its matrix and its material
+        let last_sphere = scene.spheres.last().unwrap();
+        assert_eq!(last_sphere.model_index as usize, third - 1);
+        let model = &scene.models[third - 1];
+        assert_eq!(model.model_matrix, third_matrix);
+        let material = scene.materials[model.material_index as usize];
+        assert_eq!(material.diffuse, Vec4::new(0., 0., 1., 1.));
+        // The material of the removed model is dropped
+        assert_eq!(scene.materials.len(), state.1 + 2);
+
+        scene.remove_model(first).unwrap();
+        scene.remove_model(first).unwrap();
+        assert_eq!(get_state(&scene), state);
+    }
+
+    #[test]
+    fn shared_material_round_trip() {
+        let mut scene = get_scene();
+        let state = get_state(&scene);
+        // Both use the default material, which is copied instead of changed
+        let first = scene.add_sphere(Vec3::ONE, 0.5, None);
+        let second = scene.add_sphere(-Vec3::ONE, 0.5, None);
+        scene
+            .set_material(first, Material::uniform(&Vec3::X))
+            .unwrap();
+        assert_eq!(scene.materials.len(), state.1 + 1);
+        // The second sphere keeps the default material
+        assert_eq!(scene.models[second].material_index, 0);
+        assert_ne!(scene.models[first].material_index, 0);
+        let first_material = scene.materials[scene.models[first].material_index as usize];
+        assert_eq!(first_material.diffuse, Vec4::new(1., 0., 0., 1.));
+
+        scene.remove_model(second).unwrap();
+        scene.remove_model(first).unwrap();
+        assert_eq!(get_state(&scene), state);
+    }
+
+    #[test]
+    fn invalid_indices() {
+        let mut scene = get_scene();
+        let state = get_state(&scene);
+        let nb_models = scene.models.len();
+        assert!(scene.remove_model(nb_models).is_err());
+        assert!(scene.set_material(nb_models, Material::default()).is_err());
+        assert!(scene.set_model_matrix(nb_models, Mat4::IDENTITY).is_err());
+        assert!(scene.move_model(nb_models, Vec3::ZERO).is_err());
+        assert!(scene
+            .add_model_file(Path::new("missing.obj"), Mat4::IDENTITY, None)
+            .is_err());
+        assert!(scene
+            .add_model_file(Path::new("cube_ccw.txt"), Mat4::IDENTITY, None)
+            .is_err());
+        assert_eq!(get_state(&scene), state);
+    }
+
+    #[test]
+    fn light_round_trip() {
+        let mut scene = get_scene();
+        let nb_lights = scene.lights.len();
+        let light = scene.add_light(Light::point(Vec3::ONE, 2.));
+        assert_eq!(light, nb_lights);
+        scene
+            .set_light(light, Light::directional(Vec3::NEG_Y, 3.))
+            .unwrap();
+        assert_eq!(scene.lights[light].intensity, 3.);
+        assert!(scene.set_light(light + 1, Light::default()).is_err());
+        assert!(scene.remove_light(light + 1).is_err());
+        scene.remove_light(light).unwrap();
+        assert_eq!(scene.lights.len(), nb_lights);
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    f32::consts::PI,
+    path::{Path, PathBuf},
+};
+
+use glam::{Vec2, Vec3, Vec4};
+use log::{error, info};
+
+use crate::application::core::error::ErrorCode;
+
+use super::light::LightSample;
+
+/// Rotation and exposure of the environment map, edited in the GUI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvironmentSettings {
+    // The gradient background is used instead when disabled
+    pub is_enabled: bool,
+    // Around the Y axis, in degrees
+    pub rotation: f32,
+    // In stops, the radiance is scaled by 2^exposure
+    pub exposure: f32,
+}
+
+/// Layout should match the shader, see src/shaders/raytracer/environment.slang
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct EnvironmentSettingsGPU {
+    pub is_enabled: u32,
+    // In radians
+    pub rotation: f32,
+    pub intensity: f32,
+}
+
+impl Default for EnvironmentSettings {
+    fn default() -> Self {
+        Self {
+            is_enabled: true,
+            rotation: 0.,
+            exposure: 0.,
+        }
+    }
+}
+
+impl EnvironmentSettings {
+    /// Disabled when the scene has no environment map
+    pub fn get_gpu_data(&self, has_environment: bool) -> EnvironmentSettingsGPU {
+        EnvironmentSettingsGPU {
+            is_enabled: (self.is_enabled && has_environment) as u32,
+            rotation: self.rotation.to_radians(),
+            intensity: self.exposure.exp2(),
+        }
+    }
+}
+
+/// Equirectangular image of the light coming from infinitely far away. The rows go from +Y
+/// at the top to -Y at the bottom, the middle column faces +Z.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    // Linear radiance, the alpha is unused
+    pub pixels: Vec<Vec4>,
+    // Cdf of the rows followed by the cdf of the pixels of each row, the pixels are picked
+    // proportionally to their luminance times the solid angle they cover
+    pub distribution: Vec<f32>,
+}
+
+/// Position in the image of a direction, u goes around the Y axis and v from +Y to -Y
+fn get_uv(direction: Vec3, rotation: f32) -> Vec2 {
+    let phi = direction.x.atan2(-direction.z) - rotation;
+    let theta = direction.y.clamp(-1., 1.).acos();
+    Vec2::new((phi / (2. * PI)).rem_euclid(1.), theta / PI)
+}
+
+fn get_direction(uv: Vec2, rotation: f32) -> Vec3 {
+    let phi = 2. * PI * uv.x + rotation;
+    let theta = PI * uv.y;
+    Vec3::new(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    )
+}
+
+/// Inclusive cdf of `weights`, uniform when they are all 0
+fn get_cdf(weights: &[f32]) -> Vec<f32> {
+    let total: f32 = weights.iter().sum();
+    let nb_weights = weights.len() as f32;
+    let mut sum = 0.;
+    let mut cdf: Vec<f32> = weights
+        .iter()
+        .map(|weight| {
+            sum += if total > 0. {
+                weight / total
+            } else {
+                1. / nb_weights
+            };
+            sum
+        })
+        .collect();
+    // Rounding errors must not leave a gap at the end
+    if let Some(last) = cdf.last_mut() {
+        *last = 1.;
+    }
+    cdf
+}
+
+/// Index of the first entry of `cdf` above `u`, with the probability of picking it and the
+/// position of `u` inside of it
+fn sample_cdf(cdf: &[f32], u: f32) -> (usize, f32, f32) {
+    let index = cdf.partition_point(|&value| value <= u).min(cdf.len() - 1);
+    let previous = if index > 0 { cdf[index - 1] } else { 0. };
+    let probability = cdf[index] - previous;
+    let offset = if probability > 0. {
+        ((u - previous) / probability).clamp(0., 1.)
+    } else {
+        0.5
+    };
+    (index, probability, offset)
+}
+
+impl EnvironmentMap {
+    /// Decodes a Radiance HDR or OpenEXR file, the path is used as is
+    pub fn load(path: &Path) -> Result<Self, ErrorCode> {
+        let image = match image::open(path) {
+            Ok(image) => image.into_rgba32f(),
+            Err(err) => {
+                error!(
+                    "Failed to decode the environment map `{:?}': {:?}",
+                    path, err
+                );
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+        let (width, height) = image.dimensions();
+        let pixels = image
+            .pixels()
+            .map(|pixel| Vec4::from_array(pixel.0).max(Vec4::ZERO))
+            .collect();
+        let mut environment_map = Self {
+            path: path.to_path_buf(),
+            width,
+            height,
+            pixels,
+            distribution: Vec::new(),
+        };
+        environment_map.distribution = environment_map.get_distribution();
+        info!("Environment map `{:?}' loaded, {}x{}", path, width, height);
+        Ok(environment_map)
+    }
+
+    /// Black pixel bound on the GPU when the scene has no environment map
+    pub fn placeholder() -> Self {
+        Self {
+            path: PathBuf::new(),
+            width: 1,
+            height: 1,
+            pixels: vec![Vec4::ZERO],
+            distribution: vec![1., 1.],
+        }
+    }
+
+    fn get_distribution(&self) -> Vec<f32> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut row_weights = Vec::with_capacity(height);
+        let mut pixel_cdfs = Vec::with_capacity(width * height);
+        for (y, row) in self.pixels.chunks_exact(width).enumerate() {
+            // The rows near the poles cover a smaller solid angle
+            let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+            let weights: Vec<f32> = row
+                .iter()
+                .map(|pixel| sin_theta * pixel.truncate().dot(Vec3::new(0.2126, 0.7152, 0.0722)))
+                .collect();
+            row_weights.push(weights.iter().sum());
+            pixel_cdfs.extend(get_cdf(&weights));
+        }
+        let mut distribution = get_cdf(&row_weights);
+        distribution.extend(pixel_cdfs);
+        distribution
+    }
+
+    // The image is repeated around the Y axis and clamped at the poles
+    fn get_pixel(&self, x: i64, y: i64) -> Vec4 {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.clamp(0, self.height as i64 - 1) as usize;
+        self.pixels[y * self.width as usize + x]
+    }
+
+    /// Radiance coming from `direction` with bilinear filtering, like the sampler of the
+    /// raytracing pipeline
+    pub fn get_radiance(&self, direction: Vec3, settings: &EnvironmentSettings) -> Vec3 {
+        let uv = get_uv(direction, settings.rotation.to_radians());
+        let position = uv * Vec2::new(self.width as f32, self.height as f32) - 0.5;
+        let corner = position.floor();
+        let weights = position - corner;
+        let (x, y) = (corner.x as i64, corner.y as i64);
+        let top = self
+            .get_pixel(x, y)
+            .lerp(self.get_pixel(x + 1, y), weights.x);
+        let bottom = self
+            .get_pixel(x, y + 1)
+            .lerp(self.get_pixel(x + 1, y + 1), weights.x);
+        settings.exposure.exp2() * top.lerp(bottom, weights.y).truncate()
+    }
+
+    /// Direction picked with the distribution of the luminance, None when it is degenerate.
+    /// Same as `sample_environment` in src/shaders/raytracer/environment.slang
+    pub fn sample(&self, settings: &EnvironmentSettings, u0: f32, u1: f32) -> Option<LightSample> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let (y, row_probability, v_offset) = sample_cdf(&self.distribution[..height], u0);
+        let row_start = height + y * width;
+        let (x, pixel_probability, u_offset) =
+            sample_cdf(&self.distribution[row_start..row_start + width], u1);
+        let uv = Vec2::new(
+            (x as f32 + u_offset) / width as f32,
+            (y as f32 + v_offset) / height as f32,
+        );
+        let sin_theta = (PI * uv.y).sin();
+        // Density over the solid angle, the image covers 2 PI by PI
+        let pdf = row_probability * pixel_probability * (width * height) as f32
+            / (2. * PI * PI * sin_theta);
+        if sin_theta <= 0. || pdf <= 0. {
+            return None;
+        }
+        let direction = get_direction(uv, settings.rotation.to_radians());
+        Some(LightSample {
+            direction,
+            max_distance: f32::INFINITY,
+            // Divided by PI as `get_reflected` is PI times the BRDF
+            radiance: self.get_radiance(direction, settings) / (PI * pdf),
+        })
+    }
+}
@@ -0,0 +1,602 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use gltf::{
+    camera::Projection,
+    image::Source,
+    khr_lights_punctual::Kind,
+    material::AlphaMode,
+    mesh::Mode,
+    texture::{MagFilter, WrappingMode},
+};
+use log::{error, info, warn};
+
+use crate::application::core::error::ErrorCode;
+
+use super::{
+    camera::Camera,
+    light::{Light, LightType},
+    material::{Material, NO_TEXTURE},
+    mesh::{Mesh, MeshBuilder, Vertex},
+    model::Model,
+    normals::{generate_normals, DEFAULT_CREASE_ANGLE},
+    texture::{get_texture_index, TextureEncoding, TexturePath},
+};
+
+/// Content of a glTF 2.0 file (`.gltf` or `.glb`), every primitive of a mesh is a model
+/// with the world matrix of its node
+#[derive(Debug, Default)]
+pub struct GltfScene {
+    pub models: Vec<Model>,
+    pub mesh: Mesh,
+    // The material index of a model is shifted by one, 0 is the default material
+    pub materials: Vec<Material>,
+    // Images used by the materials, the files are relative to the working directory
+    pub textures: Vec<TexturePath>,
+    pub cameras: Vec<Camera>,
+    pub lights: Vec<Light>,
+}
+
+impl GltfScene {
+    /// The path is used as is, see `AssetResolver` to find it. Buffers and images can be
+    /// embedded in the file or external to it.
+    pub fn load(path: &Path, aspect_ratio: f32) -> Result<Self, ErrorCode> {
+        info!("Loading a new glTF scene...");
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                error!("Failed to read the glTF file `{:?}': {:?}", path, err);
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+        Self::parse(&bytes, path, aspect_ratio)
+    }
+
+    /// Same as `load` with the content of the file, the path locates the external buffers
+    /// and images and names the embedded ones
+    fn parse(bytes: &[u8], path: &Path, aspect_ratio: f32) -> Result<Self, ErrorCode> {
+        let gltf = match gltf::Gltf::from_slice(bytes) {
+            Ok(gltf) => gltf,
+            Err(err) => {
+                error!("Failed to parse the glTF file `{:?}': {:?}", path, err);
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+        let buffers = match gltf::import_buffers(&gltf.document, path.parent(), gltf.blob.clone()) {
+            Ok(buffers) => buffers,
+            Err(err) => {
+                error!(
+                    "Failed to load the buffers of the glTF file `{:?}': {:?}",
+                    path, err
+                );
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+
+        let Some(gltf_scene) = gltf
+            .document
+            .default_scene()
+            .or_else(|| gltf.document.scenes().next())
+        else {
+            error!("The glTF file `{:?}' does not contain any scene", path);
+            return Err(ErrorCode::InitializationFailure);
+        };
+
+        let images: Vec<Option<TexturePath>> = gltf
+            .document
+            .images()
+            .map(|image| Self::get_image(&image, path, &buffers))
+            .collect();
+        let mut scene = Self::default();
+        scene.materials = gltf
+            .document
+            .materials()
+            .map(|material| scene.create_material(material, &images))
+            .collect();
+        let mut mesh_builder = MeshBuilder::default();
+        let mut nodes: Vec<(gltf::Node, Mat4)> = gltf_scene
+            .nodes()
+            .map(|node| (node, Mat4::IDENTITY))
+            .collect();
+        while let Some((node, parent_matrix)) = nodes.pop() {
+            let world_matrix = parent_matrix * Mat4::from_cols_array_2d(&node.transform().matrix());
+            if let Some(mesh) = node.mesh() {
+                scene.add_mesh(&mesh, world_matrix, &buffers, &mut mesh_builder);
+            }
+            if let Some(camera) = node.camera() {
+                scene.add_camera(&camera, world_matrix, aspect_ratio);
+            }
+            if let Some(light) = node.light() {
+                scene.add_light(&light, world_matrix);
+            }
+            nodes.extend(node.children().map(|child| (child, world_matrix)));
+        }
+        scene.mesh = mesh_builder.build();
+
+        info!(
+            "# of models: {}, # of triangles: {}, # of cameras: {}, # of lights: {}",
+            scene.models.len(),
+            scene.mesh.triangles.len(),
+            scene.cameras.len(),
+            scene.lights.len()
+        );
+        Ok(scene)
+    }
+
+    /// Moves the whole content of the scene
+    pub fn transform(&mut self, matrix: Mat4) {
+        for model in &mut self.models {
+            model.model_matrix = matrix * model.model_matrix;
+        }
+        for camera in &mut self.cameras {
+            camera.eye = matrix.transform_point3(camera.eye);
+            camera.look_towards(matrix.transform_vector3(camera.at));
+        }
+        for light in &mut self.lights {
+            light.position = matrix.transform_point3(light.position);
+            light.direction = matrix
+                .transform_vector3(light.direction)
+                .normalize_or_zero();
+        }
+    }
+
+    /// External images are read from their file, the ones stored in a buffer or a data URI
+    /// keep their encoded bytes. The encoding is set by the materials using them.
+    fn get_image(
+        image: &gltf::Image,
+        gltf_path: &Path,
+        buffers: &[gltf::buffer::Data],
+    ) -> Option<TexturePath> {
+        let bytes: Arc<[u8]> = match image.source() {
+            Source::Uri { uri, .. } => {
+                let Some(data) = uri.strip_prefix("data:") else {
+                    let directory = gltf_path.parent().unwrap_or(Path::new(""));
+                    return Some(TexturePath::file(
+                        directory.join(uri),
+                        TextureEncoding::Linear,
+                    ));
+                };
+                let encoded = match data.split_once(',') {
+                    Some((media_type, encoded)) if media_type.ends_with(";base64") => encoded,
+                    _ => {
+                        warn!(
+                            "The data URI of the image {:?} is not base64 encoded",
+                            image.name()
+                        );
+                        return None;
+                    }
+                };
+                match base64::decode(encoded) {
+                    Ok(bytes) => bytes.into(),
+                    Err(err) => {
+                        warn!(
+                            "Failed to decode the data URI of the image {:?}: {:?}",
+                            image.name(),
+                            err
+                        );
+                        return None;
+                    }
+                }
+            }
+            Source::View { view, .. } => {
+                let buffer = &buffers[view.buffer().index()];
+                match buffer.get(view.offset()..view.offset() + view.length()) {
+                    Some(bytes) => bytes.into(),
+                    None => {
+                        warn!(
+                            "The buffer view of the image {:?} is out of bounds",
+                            image.name()
+                        );
+                        return None;
+                    }
+                }
+            }
+        };
+        Some(TexturePath {
+            path: PathBuf::from(format!("{}#image{}", gltf_path.display(), image.index())),
+            encoding: TextureEncoding::Linear,
+            embedded_bytes: Some(bytes),
+        })
+    }
+
+    /// The textures are repeated, filtered linearly and read with the first texture
+    /// coordinates, other settings only get a warning
+    fn check_texture_settings(texture: &gltf::Texture, tex_coord: u32) {
+        if tex_coord != 0 {
+            warn!(
+                "The texture {:?} uses the texture coordinates {}, the first ones are used instead",
+                texture.name(),
+                tex_coord
+            );
+        }
+        let sampler = texture.sampler();
+        if sampler.wrap_s() != WrappingMode::Repeat || sampler.wrap_t() != WrappingMode::Repeat {
+            warn!(
+                "The texture {:?} is not repeated, its wrapping mode is ignored",
+                texture.name()
+            );
+        }
+        if sampler.mag_filter() == Some(MagFilter::Nearest) {
+            warn!(
+                "The texture {:?} is filtered linearly instead of with the nearest texel",
+                texture.name()
+            );
+        }
+    }
+
+    // The metallic-roughness texture has the roughness in its green channel like
+    // `Material::roughness_texture`, its metallic in the blue channel is not used
+    fn create_material(
+        &mut self,
+        material: gltf::Material,
+        images: &[Option<TexturePath>],
+    ) -> Material {
+        let pbr = material.pbr_metallic_roughness();
+        let mut get_texture_index = |texture: Option<(gltf::Texture, u32)>, encoding| {
+            texture
+                .and_then(|(texture, tex_coord)| {
+                    Self::check_texture_settings(&texture, tex_coord);
+                    images[texture.source().index()].clone()
+                })
+                .map_or(NO_TEXTURE, |image| {
+                    get_texture_index(&mut self.textures, TexturePath { encoding, ..image })
+                })
+        };
+        let mut new_material = Material::pbr(
+            Vec4::from_array(pbr.base_color_factor()),
+            pbr.metallic_factor(),
+            pbr.roughness_factor(),
+        );
+        let emissive_strength = material.emissive_strength().unwrap_or(1.);
+        new_material.emission =
+            (emissive_strength * Vec3::from_array(material.emissive_factor())).extend(1.);
+        new_material.diffuse_texture = get_texture_index(
+            pbr.base_color_texture()
+                .map(|info| (info.texture(), info.tex_coord())),
+            TextureEncoding::Srgb,
+        );
+        // The alpha of the base colour is the opacity, unless the material is opaque
+        match material.alpha_mode() {
+            AlphaMode::Opaque => new_material.dissolve = 1.,
+            AlphaMode::Mask => {
+                new_material.dissolve = 1.;
+                new_material.dissolve_texture = new_material.diffuse_texture;
+                new_material.alpha_cutoff = material.alpha_cutoff().unwrap_or(0.5);
+            }
+            AlphaMode::Blend => new_material.dissolve_texture = new_material.diffuse_texture,
+        }
+        new_material.normal_texture = get_texture_index(
+            material
+                .normal_texture()
+                .map(|info| (info.texture(), info.tex_coord())),
+            TextureEncoding::Linear,
+        );
+        let metallic_roughness_texture = pbr.metallic_roughness_texture();
+        if metallic_roughness_texture.is_some() && pbr.metallic_factor() > 0. {
+            warn!(
+                "The metallic of the texture of the material {:?} is ignored, its metallic factor is used",
+                material.name()
+            );
+        }
+        new_material.roughness_texture = get_texture_index(
+            metallic_roughness_texture.map(|info| (info.texture(), info.tex_coord())),
+            TextureEncoding::Linear,
+        );
+        new_material
+    }
+
+    fn add_mesh(
+        &mut self,
+        mesh: &gltf::Mesh,
+        world_matrix: Mat4,
+        buffers: &[gltf::buffer::Data],
+        mesh_builder: &mut MeshBuilder,
+    ) {
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(positions) = reader.read_positions() else {
+                warn!("A primitive of the mesh {:?} has no positions", mesh.name());
+                continue;
+            };
+            let positions: Vec<Vec3> = positions.map(Vec3::from_array).collect();
+            let normals: Vec<Vec3> = reader
+                .read_normals()
+                .map(|normals| normals.map(Vec3::from_array).collect())
+                .unwrap_or_default();
+            let uvs: Vec<Vec2> = reader
+                .read_tex_coords(0)
+                .map(|uvs| uvs.into_f32().map(Vec2::from_array).collect())
+                .unwrap_or_default();
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+
+            let has_normals = normals.len() == positions.len();
+            let has_uvs = uvs.len() == positions.len();
+            let mut triangles = Vec::new();
+            for triangle_indices in Self::get_triangle_indices(primitive.mode(), &indices) {
+                if triangle_indices
+                    .iter()
+                    .any(|index| *index as usize >= positions.len())
+                {
+                    warn!("A triangle of the mesh {:?} is out of bounds", mesh.name());
+                    continue;
+                }
+                triangles.push(triangle_indices.map(|index| {
+                    let index = index as usize;
+                    let normal = if has_normals {
+                        normals[index]
+                    } else {
+                        Vec3::ZERO
+                    };
+                    let uv = if has_uvs { uvs[index] } else { Vec2::ZERO };
+                    Vertex::new(positions[index], normal).with_uv(uv)
+                }));
+            }
+            if !has_normals {
+                generate_normals(&mut triangles, DEFAULT_CREASE_ANGLE);
+            }
+            if triangles.is_empty() {
+                warn!(
+                    "A primitive of the mesh {:?} has no triangles, its mode is {:?}",
+                    mesh.name(),
+                    primitive.mode()
+                );
+                continue;
+            }
+
+            for triangle in triangles {
+                mesh_builder.add_triangle(triangle, self.models.len());
+            }
+            self.models.push(Model::new(
+                world_matrix,
+                primitive.material().index().map_or(0, |index| index + 1),
+            ));
+        }
+    }
+
+    fn get_triangle_indices(mode: Mode, indices: &[u32]) -> Vec<[u32; 3]> {
+        match mode {
+            Mode::Triangles => indices
+                .chunks_exact(3)
+                .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+                .collect(),
+            // Every other triangle is flipped to keep the winding order
+            Mode::TriangleStrip => indices
+                .windows(3)
+                .enumerate()
+                .map(|(i, triangle)| match i % 2 {
+                    0 => [triangle[0], triangle[1], triangle[2]],
+                    _ => [triangle[1], triangle[0], triangle[2]],
+                })
+                .collect(),
+            Mode::TriangleFan => indices
+                .windows(2)
+                .skip(1)
+                .map(|edge| [indices[0], edge[0], edge[1]])
+                .collect(),
+            Mode::Points | Mode::Lines | Mode::LineLoop | Mode::LineStrip => Vec::new(),
+        }
+    }
+
+    fn add_camera(&mut self, camera: &gltf::Camera, world_matrix: Mat4, aspect_ratio: f32) {
+        let Projection::Perspective(perspective) = camera.projection() else {
+            warn!(
+                "The orthographic camera {:?} is not supported",
+                camera.name()
+            );
+            return;
+        };
+        let mut new_camera = Camera::new(
+            world_matrix.transform_point3(Vec3::ZERO),
+            // The image keeps the shape of the window whatever the file was authored for
+            aspect_ratio,
+            perspective.yfov().to_degrees(),
+            perspective.znear(),
+            Vec3::Y,
+        );
+        // The camera looks towards -Z in its node
+        new_camera.look_towards(world_matrix.transform_vector3(Vec3::NEG_Z));
+        self.cameras.push(new_camera);
+    }
+
+    fn add_light(&mut self, light: &gltf::khr_lights_punctual::Light, world_matrix: Mat4) {
+        let light_type = match light.kind() {
+            Kind::Directional => LightType::Directional,
+            Kind::Point => LightType::Point,
+            Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => LightType::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+        };
+        // The light points towards -Z in its node
+        self.lights.push(Light {
+            light_type,
+            position: world_matrix.transform_point3(Vec3::ZERO),
+            direction: world_matrix
+                .transform_vector3(Vec3::NEG_Z)
+                .normalize_or_zero(),
+            color: Vec3::from_array(light.color()),
+            intensity: light.intensity(),
+            range: light.range(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, path::Path};
+
+    use glam::{Mat4, Vec3, Vec4};
+
+    use super::GltfScene;
+    use crate::application::scene::texture::{Texture, TextureEncoding};
+
+    /// 2x1 PNG, a red texel then a green one
+    fn get_png() -> Vec<u8> {
+        let image = image::RgbaImage::from_raw(2, 1, vec![255, 0, 0, 255, 0, 255, 0, 255]).unwrap();
+        let mut png = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    /// Positions and texture coordinates of a triangle followed by the image
+    fn get_buffer(png: &[u8]) -> Vec<u8> {
+        let values: [f32; 15] = [0., 0., 0., 1., 0., 0., 0., 1., 0., 0., 0., 1., 0., 0., 1.];
+        let mut buffer: Vec<u8> = values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        buffer.extend_from_slice(png);
+        buffer
+    }
+
+    /// A textured triangle moved along X, a camera along Z and a spot light
+    fn get_json(buffer_length: usize, png_length: usize, buffer_uri: &str, image: &str) -> String {
+        format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "extensionsUsed": ["KHR_lights_punctual"],
+                "extensions": {{ "KHR_lights_punctual": {{ "lights": [
+                    {{ "type": "spot", "intensity": 2.0, "spot": {{ "outerConeAngle": 0.5 }} }}
+                ] }} }},
+                "scene": 0,
+                "scenes": [{{ "nodes": [0, 1, 2] }}],
+                "nodes": [
+                    {{ "mesh": 0, "translation": [1.0, 0.0, 0.0] }},
+                    {{ "camera": 0, "translation": [0.0, 0.0, 5.0] }},
+                    {{ "extensions": {{ "KHR_lights_punctual": {{ "light": 0 }} }} }}
+                ],
+                "cameras": [{{ "type": "perspective", "perspective": {{ "yfov": 0.8, "znear": 0.1, "aspectRatio": 2.0 }} }}],
+                "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0, "TEXCOORD_0": 1 }}, "material": 0 }}] }}],
+                "materials": [{{
+                    "pbrMetallicRoughness": {{
+                        "baseColorFactor": [1.0, 0.5, 0.25, 1.0],
+                        "baseColorTexture": {{ "index": 0 }},
+                        "metallicFactor": 0.0,
+                        "roughnessFactor": 0.3
+                    }}
+                }}],
+                "textures": [{{ "source": 0 }}],
+                "images": [{image}],
+                "accessors": [
+                    {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] }},
+                    {{ "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" }}
+                ],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                    {{ "buffer": 0, "byteOffset": 36, "byteLength": 24 }},
+                    {{ "buffer": 0, "byteOffset": 60, "byteLength": {png_length} }}
+                ],
+                "buffers": [{{ "byteLength": {buffer_length}{buffer_uri} }}]
+            }}"#
+        )
+    }
+
+    fn get_glb(json: &str, buffer: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut buffer = buffer.to_vec();
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+        let length = 12 + 8 + json.len() + 8 + buffer.len();
+        let mut glb = Vec::new();
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2_u32.to_le_bytes());
+        glb.extend_from_slice(&(length as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&buffer);
+        glb
+    }
+
+    fn check_scene(scene: &GltfScene) {
+        assert_eq!(scene.models.len(), 1);
+        assert_eq!(scene.models[0].material_index, 1);
+        assert_eq!(
+            scene.models[0].model_matrix,
+            Mat4::from_translation(Vec3::X)
+        );
+        assert_eq!(scene.mesh.triangles.len(), 1);
+
+        assert_eq!(scene.materials.len(), 1);
+        let material = &scene.materials[0];
+        assert_eq!(material.diffuse, Vec4::new(1., 0.5, 0.25, 1.));
+        assert_eq!(material.roughness, 0.3);
+        assert_eq!(material.diffuse_texture, 0);
+
+        assert_eq!(scene.textures.len(), 1);
+        assert_eq!(scene.textures[0].encoding, TextureEncoding::Srgb);
+        assert!(scene.textures[0].embedded_bytes.is_some());
+        let texture = Texture::load(&scene.textures[0]).unwrap();
+        assert_eq!((texture.width, texture.height), (2, 1));
+        assert_eq!(texture.pixels, vec![255, 0, 0, 255, 0, 255, 0, 255]);
+
+        assert_eq!(scene.cameras.len(), 1);
+        assert!(scene.cameras[0]
+            .eye
+            .abs_diff_eq(Vec3::new(0., 0., 5.), 1e-6));
+        assert_eq!(scene.lights.len(), 1);
+        assert_eq!(scene.lights[0].intensity, 2.);
+    }
+
+    #[test]
+    fn glb_with_image_in_buffer_view() {
+        let png = get_png();
+        let buffer = get_buffer(&png);
+        let image = r#"{ "bufferView": 2, "mimeType": "image/png" }"#;
+        let json = get_json(buffer.len(), png.len(), "", image);
+        let glb = get_glb(&json, &buffer);
+        let scene = GltfScene::parse(&glb, Path::new("scene.glb"), 1.).unwrap();
+        check_scene(&scene);
+        assert_eq!(
+            scene.textures[0].path,
+            Path::new("scene.glb#image0").to_path_buf()
+        );
+    }
+
+    #[test]
+    fn gltf_with_data_uris() {
+        let png = get_png();
+        let buffer = get_buffer(&png);
+        let buffer_uri = format!(
+            r#", "uri": "data:application/octet-stream;base64,{}""#,
+            base64::encode(&buffer)
+        );
+        let image = format!(
+            r#"{{ "uri": "data:image/png;base64,{}" }}"#,
+            base64::encode(&png)
+        );
+        let json = get_json(buffer.len(), png.len(), &buffer_uri, &image);
+        let scene = GltfScene::parse(json.as_bytes(), Path::new("scene.gltf"), 1.).unwrap();
+        check_scene(&scene);
+    }
+
+    #[test]
+    fn external_image() {
+        let png = get_png();
+        let buffer = get_buffer(&png);
+        let image = r#"{ "uri": "textures/wood.png" }"#;
+        let json = get_json(buffer.len(), png.len(), "", image);
+        let glb = get_glb(&json, &buffer);
+        let scene = GltfScene::parse(&glb, Path::new("assets/scene.glb"), 1.).unwrap();
+        assert_eq!(scene.textures.len(), 1);
+        assert_eq!(
+            scene.textures[0].path,
+            Path::new("assets/textures/wood.png").to_path_buf()
+        );
+        assert!(scene.textures[0].embedded_bytes.is_none());
+    }
+}
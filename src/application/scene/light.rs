@@ -0,0 +1,388 @@
+use glam::Vec3;
+
+/// Number of points of an area light tested for each hit, should match the shader
+/// see src/shaders/raytracer/light.slang
+pub const AREA_LIGHT_SAMPLES: u32 = 4;
+const GOLDEN_ANGLE: f32 = 2.399_963;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightType {
+    Directional,
+    Point,
+    // Cone angles in radians
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+    // Disk emitting towards `direction`
+    Area {
+        radius: f32,
+    },
+}
+
+impl LightType {
+    /// Type with the default parameters, used when changing the type of a light
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Directional),
+            1 => Some(Self::Point),
+            2 => Some(Self::Spot {
+                inner_cone_angle: 0.,
+                outer_cone_angle: std::f32::consts::FRAC_PI_4,
+            }),
+            3 => Some(Self::Area { radius: 1. }),
+            _ => None,
+        }
+    }
+
+    /// Value of the type in the shader, see src/shaders/raytracer/light.slang
+    pub fn to_u32(&self) -> u32 {
+        match self {
+            LightType::Directional => 0,
+            LightType::Point => 1,
+            LightType::Spot { .. } => 2,
+            LightType::Area { .. } => 3,
+        }
+    }
+}
+
+/// Light of the scene in world space, as described by `KHR_lights_punctual` plus the area
+/// lights
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub light_type: LightType,
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub color: Vec3,
+    // Candela for point and spot lights, lux for directional ones and nits for area ones
+    pub intensity: f32,
+    // Infinite when not set
+    pub range: Option<f32>,
+}
+
+/// The layout should match the shader, see src/shaders/raytracer/light.slang
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct LightGPU {
+    pub position: Vec3,
+    pub light_type: u32,
+    pub direction: Vec3,
+    // 0 when infinite
+    pub range: f32,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub cos_inner_cone_angle: f32,
+    pub cos_outer_cone_angle: f32,
+    pub radius: f32,
+    #[allow(dead_code)]
+    pub padding: u32,
+}
+
+/// Direction and light received from one point of a light
+#[derive(Clone, Copy, Debug)]
+pub struct LightSample {
+    pub direction: Vec3,
+    pub max_distance: f32,
+    // Scale of the light reflected by the surface, see `raytracer::shading::get_reflected`
+    pub radiance: Vec3,
+}
+
+/// Triangle of an emissive material, sampled as an area light. The layout should match the
+/// shader, see src/shaders/raytracer/light.slang
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct EmissiveTriangle {
+    // Index in the triangles of the mesh
+    pub triangle_index: u32,
+    // In world space
+    pub area: f32,
+    // Of picking this triangle, proportional to its power
+    pub probability: f32,
+    // Sum of the probabilities up to this triangle included
+    pub cdf: f32,
+}
+
+impl EmissiveTriangle {
+    /// Build the cdf of triangles given with their power, the ones that emit nothing are
+    /// dropped
+    pub fn from_powers(triangles: &[(u32, f32, f32)]) -> Vec<Self> {
+        let total_power: f32 = triangles.iter().map(|(_, _, power)| power).sum();
+        if total_power <= 0. {
+            return Vec::new();
+        }
+        let mut cdf = 0.;
+        let mut emissive_triangles = Vec::new();
+        for &(triangle_index, area, power) in triangles {
+            if power <= 0. {
+                continue;
+            }
+            let probability = power / total_power;
+            cdf += probability;
+            emissive_triangles.push(Self {
+                triangle_index,
+                area,
+                probability,
+                cdf,
+            });
+        }
+        // Rounding errors must not leave a gap at the end
+        if let Some(last) = emissive_triangles.last_mut() {
+            last.cdf = 1.;
+        }
+        emissive_triangles
+    }
+
+    /// Pick a triangle with a probability proportional to its power, `u` is in [0;1[
+    pub fn sample(emissive_triangles: &[Self], u: f32) -> Option<&Self> {
+        let index = emissive_triangles.partition_point(|triangle| triangle.cdf <= u);
+        emissive_triangles
+            .get(index)
+            .or_else(|| emissive_triangles.last())
+    }
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Self {
+            light_type: LightType::Point,
+            position: Vec3::ZERO,
+            direction: Vec3::NEG_Y,
+            color: Vec3::ONE,
+            intensity: 1.,
+            range: None,
+        }
+    }
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, intensity: f32) -> Self {
+        Self {
+            light_type: LightType::Directional,
+            direction: direction.normalize_or_zero(),
+            intensity,
+            ..Default::default()
+        }
+    }
+
+    pub fn point(position: Vec3, intensity: f32) -> Self {
+        Self {
+            light_type: LightType::Point,
+            position,
+            intensity,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_gpu_data(&self) -> LightGPU {
+        let (cos_inner_cone_angle, cos_outer_cone_angle, radius) = match self.light_type {
+            LightType::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => (inner_cone_angle.cos(), outer_cone_angle.cos(), 0.),
+            LightType::Area { radius } => (0., 0., radius),
+            LightType::Directional | LightType::Point => (0., 0., 0.),
+        };
+        LightGPU {
+            position: self.position,
+            light_type: self.light_type.to_u32(),
+            direction: self.direction.normalize_or_zero(),
+            range: self.range.unwrap_or(0.),
+            color: self.color,
+            intensity: self.intensity,
+            cos_inner_cone_angle,
+            cos_outer_cone_angle,
+            radius,
+            padding: 0,
+        }
+    }
+
+    pub fn get_nb_samples(&self) -> u32 {
+        match self.light_type {
+            LightType::Area { .. } => AREA_LIGHT_SAMPLES,
+            _ => 1,
+        }
+    }
+
+    /// Same as the shader, see src/shaders/raytracer/light.slang
+    pub fn get_sample(&self, sample_index: u32, hit_position: Vec3) -> LightSample {
+        let direction = self.direction.normalize_or_zero();
+        if self.light_type == LightType::Directional {
+            return LightSample {
+                direction: -direction,
+                max_distance: f32::MAX,
+                radiance: self.intensity * self.color,
+            };
+        }
+
+        let mut light_position = self.position;
+        if let LightType::Area { radius } = self.light_type {
+            light_position += radius * get_disk_offset(direction, sample_index);
+        }
+        let to_light = light_position - hit_position;
+        let distance = to_light.length();
+        let sample_direction = to_light / distance;
+
+        let mut attenuation = 1. / (distance * distance);
+        if let Some(range) = self.range.filter(|range| *range > 0.) {
+            // Smooth window of KHR_lights_punctual
+            let ratio = distance / range;
+            attenuation *= (1. - ratio.powi(4)).clamp(0., 1.).powi(2);
+        }
+        let cos_emission = direction.dot(-sample_direction);
+        match self.light_type {
+            LightType::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => {
+                attenuation *=
+                    get_spot_falloff(outer_cone_angle.cos(), inner_cone_angle.cos(), cos_emission);
+            }
+            LightType::Area { radius } => {
+                // One-sided disk of area PI * radius^2, the PI cancels with the one of the BRDF
+                attenuation *= cos_emission.max(0.) * radius * radius;
+            }
+            LightType::Directional | LightType::Point => (),
+        }
+        LightSample {
+            direction: sample_direction,
+            max_distance: distance,
+            radiance: attenuation * self.intensity * self.color,
+        }
+    }
+}
+
+/// Point of the unit disk facing `direction`, spread on a golden angle spiral
+fn get_disk_offset(direction: Vec3, sample_index: u32) -> Vec3 {
+    let r = ((sample_index as f32 + 0.5) / AREA_LIGHT_SAMPLES as f32).sqrt();
+    let theta = sample_index as f32 * GOLDEN_ANGLE;
+    let helper = if direction.y.abs() < 0.999 {
+        Vec3::Y
+    } else {
+        Vec3::X
+    };
+    let tangent = helper.cross(direction).normalize();
+    let bitangent = direction.cross(tangent);
+    r * (theta.cos() * tangent + theta.sin() * bitangent)
+}
+
+/// Smooth from the outer cone to the inner one, a hard edge when the cones are the same
+fn get_spot_falloff(
+    cos_outer_cone_angle: f32,
+    cos_inner_cone_angle: f32,
+    cos_emission: f32,
+) -> f32 {
+    if cos_inner_cone_angle - cos_outer_cone_angle < 1e-6 {
+        return if cos_emission >= cos_outer_cone_angle {
+            1.
+        } else {
+            0.
+        };
+    }
+    let t = ((cos_emission - cos_outer_cone_angle) / (cos_inner_cone_angle - cos_outer_cone_angle))
+        .clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::{EmissiveTriangle, Light, LightType};
+    use crate::application::scene::{camera::Camera, Scene};
+
+    /// Spot at the origin lighting -Y, the result is the falloff at `angle` from its axis
+    fn get_spot_falloff(inner_cone_angle: f32, outer_cone_angle: f32, angle: f32) -> f32 {
+        let light = Light {
+            light_type: LightType::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+            ..Default::default()
+        };
+        // At a distance of 1 so there is no attenuation
+        let hit_position = Vec3::new(angle.sin(), -angle.cos(), 0.);
+        light.get_sample(0, hit_position).radiance.x
+    }
+
+    #[test]
+    fn spot_falloff() {
+        let (inner, outer) = (0.2, 0.5);
+        assert!((get_spot_falloff(inner, outer, 0.) - 1.).abs() < 1e-5);
+        assert!((get_spot_falloff(inner, outer, inner - 1e-3) - 1.).abs() < 1e-5);
+        assert!(get_spot_falloff(inner, outer, outer + 1e-3).abs() < 1e-5);
+        assert_eq!(get_spot_falloff(inner, outer, std::f32::consts::PI), 0.);
+
+        // Decreasing between the cones
+        let mut previous_falloff = 1.;
+        for i in 1..10 {
+            let angle = inner + (outer - inner) * i as f32 / 10.;
+            let falloff = get_spot_falloff(inner, outer, angle);
+            assert!(falloff > 0. && falloff < 1.);
+            assert!(falloff < previous_falloff);
+            previous_falloff = falloff;
+        }
+    }
+
+    #[test]
+    fn spot_hard_edge() {
+        // Same cones, the edge is a step instead of a division by 0
+        for angle in [0., 0.3, 0.4 - 1e-3, 0.4 + 1e-3, 1.] {
+            let falloff = get_spot_falloff(0.4, 0.4, angle);
+            assert!(!falloff.is_nan());
+            let expected_falloff = if angle < 0.4 { 1. } else { 0. };
+            assert!(
+                (falloff - expected_falloff).abs() < 1e-5,
+                "{angle}: {falloff}"
+            );
+        }
+        // An inner cone wider than the outer one behaves the same
+        assert!((get_spot_falloff(0.5, 0.4, 0.3) - 1.).abs() < 1e-5);
+        assert_eq!(get_spot_falloff(0.5, 0.4, 0.45), 0.);
+    }
+
+    #[test]
+    fn emissive_triangles_cdf() {
+        let triangles = EmissiveTriangle::from_powers(&[(0, 1., 1.), (1, 1., 0.), (2, 2., 3.)]);
+        // The triangle that emits nothing is dropped
+        assert_eq!(
+            triangles
+                .iter()
+                .map(|triangle| triangle.triangle_index)
+                .collect::<Vec<_>>(),
+            vec![0, 2]
+        );
+        assert!((triangles[0].probability - 0.25).abs() < 1e-6);
+        assert!((triangles[1].probability - 0.75).abs() < 1e-6);
+        assert!((triangles[0].cdf - 0.25).abs() < 1e-6);
+        assert_eq!(triangles[1].cdf, 1.);
+
+        let sample = |u: f32| {
+            EmissiveTriangle::sample(&triangles, u)
+                .unwrap()
+                .triangle_index
+        };
+        assert_eq!(sample(0.), 0);
+        assert_eq!(sample(0.24), 0);
+        assert_eq!(sample(0.25), 2);
+        assert_eq!(sample(0.999_999), 2);
+
+        assert!(EmissiveTriangle::from_powers(&[(0, 1., 0.)]).is_empty());
+        assert!(EmissiveTriangle::sample(&[], 0.5).is_none());
+    }
+
+    #[test]
+    fn cornell_box_emissive_triangles() {
+        let camera = Camera::new(Vec3::ZERO, 1., 50., 0.1, Vec3::Y);
+        let scene = Scene::init_scene_cornell_box(camera).unwrap();
+        let triangles = scene.get_emissive_triangles();
+        // The two triangles of the light, of the same area and power
+        assert_eq!(triangles.len(), 2);
+        let probability_sum: f32 = triangles.iter().map(|triangle| triangle.probability).sum();
+        assert!((probability_sum - 1.).abs() < 1e-6);
+        assert!((triangles[0].probability - 0.5).abs() < 1e-6);
+        assert_eq!(triangles.last().unwrap().cdf, 1.);
+        assert!(triangles.windows(2).all(|pair| pair[0].cdf < pair[1].cdf));
+        assert!((triangles[0].area - triangles[1].area).abs() < 1e-6);
+        assert!(triangles[0].area > 0.);
+    }
+}
@@ -1,16 +1,118 @@
-use glam::{Vec3, Vec4};
+use glam::{Vec2, Vec3, Vec4};
 use rand::Rng;
 
+use super::texture::Texture;
+
+/// Texture slot of a material without a texture
+pub const NO_TEXTURE: u32 = u32::MAX;
+
+/// Reflection model of a material, values should match the shader
+/// see src/shaders/raytracer/brdf.slang
+#[derive(Debug, Default, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum Brdf {
+    // Lambert diffuse with the Blinn-Phong specular term of the MTL files
+    #[default]
+    Lambert = 0,
+    // Cook-Torrance microfacets, see `raytracer::brdf`
+    Ggx = 1,
+    // Perfect reflection tinted by the diffuse colour
+    Mirror = 2,
+    // Smooth glass, reflects and refracts according to the Fresnel term of
+    // `index_of_refraction`, see `raytracer::dielectric`
+    Dielectric = 3,
+    // Glass whose microfacets follow the GGX distribution of `roughness`
+    RoughDielectric = 4,
+}
+
+impl Brdf {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Lambert),
+            1 => Some(Self::Ggx),
+            2 => Some(Self::Mirror),
+            3 => Some(Self::Dielectric),
+            4 => Some(Self::RoughDielectric),
+            _ => None,
+        }
+    }
+
+    /// Its reflections and refractions are followed by the integrators instead of being
+    /// lit by the lights
+    pub fn is_specular(&self) -> bool {
+        matches!(
+            self,
+            Self::Mirror | Self::Dielectric | Self::RoughDielectric
+        )
+    }
+
+    pub fn is_dielectric(&self) -> bool {
+        matches!(self, Self::Dielectric | Self::RoughDielectric)
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct Material {
     pub ambient: Vec4,
+    // Base colour of the GGX BRDF
+    pub diffuse: Vec4,
+    pub specular: Vec4,
+    pub emission: Vec4,
+    // Absorption coefficient of the inside of the dielectrics, per unit of distance
+    pub absorption: Vec4,
+    // PBR metallic-roughness factors, in [0, 1]
+    pub metallic: f32,
+    pub roughness: f32,
+    // Phong exponent of the specular term
+    pub shininess: f32,
+    // Opacity, 1 is opaque, multiplied by the alpha of `dissolve_texture`
+    pub dissolve: f32,
+    pub index_of_refraction: f32,
+    // MTL `illum`: 0 is a constant color, 1 is diffuse only, 2 and more add the specular term
+    pub illumination_model: u32,
+    // Indices in `Scene::textures`, `NO_TEXTURE` when not set
+    pub ambient_texture: u32,
+    pub diffuse_texture: u32,
+    pub specular_texture: u32,
+    pub normal_texture: u32,
+    pub shininess_texture: u32,
+    pub dissolve_texture: u32,
+    // Its green channel scales `roughness`
+    pub roughness_texture: u32,
+    // `Brdf` used to shade the material
+    pub brdf: u32,
+    // Reflectance of the dielectrics at normal incidence is `0.08 * specular_factor`, so 0.5
+    // gives the usual 4%
+    pub specular_factor: f32,
+    // The texels of `dissolve_texture` whose alpha is below it are cut out and the others are
+    // opaque, 0 keeps the alpha as it is
+    pub alpha_cutoff: f32,
 }
 
 impl Default for Material {
     fn default() -> Self {
         Self {
             ambient: Vec4::from_array([1., 1., 1., 1.]),
+            diffuse: Vec4::from_array([1., 1., 1., 1.]),
+            specular: Vec4::from_array([0., 0., 0., 1.]),
+            emission: Vec4::from_array([0., 0., 0., 1.]),
+            absorption: Vec4::from_array([0., 0., 0., 1.]),
+            metallic: 0.,
+            roughness: 1.,
+            shininess: 1.,
+            dissolve: 1.,
+            index_of_refraction: 1.,
+            illumination_model: 1,
+            ambient_texture: NO_TEXTURE,
+            diffuse_texture: NO_TEXTURE,
+            specular_texture: NO_TEXTURE,
+            normal_texture: NO_TEXTURE,
+            shininess_texture: NO_TEXTURE,
+            dissolve_texture: NO_TEXTURE,
+            roughness_texture: NO_TEXTURE,
+            brdf: Brdf::Lambert as u32,
+            specular_factor: 0.5,
+            alpha_cutoff: 0.,
         }
     }
 }
@@ -22,12 +124,142 @@ impl Material {
         let g = rng.gen();
         let b = rng.gen();
 
-        let ambient = Vec4::new(r, g, b, 1.);
-        Self { ambient }
+        Self::uniform(&Vec3::new(r, g, b))
     }
 
     pub fn uniform(color: &Vec3) -> Self {
         let ambient = Vec4::new(color.x, color.y, color.z, 1.);
-        Self { ambient }
+        Self {
+            ambient,
+            diffuse: ambient,
+            ..Default::default()
+        }
+    }
+
+    /// White surface emitting `radiance`, its triangles are sampled as area lights
+    pub fn emissive(radiance: Vec3) -> Self {
+        Self {
+            emission: radiance.extend(1.),
+            ..Self::uniform(&Vec3::ONE)
+        }
+    }
+
+    pub fn pbr(base_color: Vec4, metallic: f32, roughness: f32) -> Self {
+        Self {
+            ambient: base_color,
+            diffuse: base_color,
+            metallic,
+            roughness,
+            dissolve: base_color.w,
+            brdf: Brdf::Ggx as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Perfect mirror, `color` is the fraction of the light it reflects
+    pub fn mirror(color: Vec3) -> Self {
+        Self {
+            brdf: Brdf::Mirror as u32,
+            ..Self::uniform(&color)
+        }
+    }
+
+    /// Glass, smooth when `roughness` is 0. The light going through it is attenuated by
+    /// `exp(-absorption * distance)`, following Beer's law
+    pub fn dielectric(index_of_refraction: f32, absorption: Vec3, roughness: f32) -> Self {
+        let brdf = if roughness > 0. {
+            Brdf::RoughDielectric
+        } else {
+            Brdf::Dielectric
+        };
+        Self {
+            absorption: absorption.extend(1.),
+            roughness,
+            index_of_refraction,
+            brdf: brdf as u32,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_brdf(&self) -> Brdf {
+        Brdf::from_u32(self.brdf).unwrap_or_default()
+    }
+
+    /// Replaces the texture indices that are set by their new value in `new_indices`
+    pub fn remap_textures(&mut self, new_indices: &[u32]) {
+        for texture in [
+            &mut self.ambient_texture,
+            &mut self.diffuse_texture,
+            &mut self.specular_texture,
+            &mut self.normal_texture,
+            &mut self.shininess_texture,
+            &mut self.dissolve_texture,
+            &mut self.roughness_texture,
+        ] {
+            if *texture != NO_TEXTURE {
+                *texture = new_indices[*texture as usize];
+            }
+        }
+    }
+
+    fn sample_texture(texture: u32, uv: Vec2, textures: &[Texture]) -> Option<Vec4> {
+        textures
+            .get(texture as usize)
+            .map(|texture| texture.sample(uv))
+    }
+
+    /// Diffuse colour at the texture coordinates, same as the shader
+    /// see src/shaders/scene/material.slang
+    pub fn get_diffuse(&self, uv: Vec2, textures: &[Texture]) -> Vec4 {
+        match Self::sample_texture(self.diffuse_texture, uv, textures) {
+            Some(texel) => self.diffuse * texel,
+            None => self.diffuse,
+        }
+    }
+
+    pub fn get_roughness(&self, uv: Vec2, textures: &[Texture]) -> f32 {
+        match Self::sample_texture(self.roughness_texture, uv, textures) {
+            Some(texel) => self.roughness * texel.y,
+            None => self.roughness,
+        }
+    }
+
+    /// The roughness map replaces the Phong exponent when there is one
+    pub fn get_shininess(&self, uv: Vec2, textures: &[Texture]) -> f32 {
+        if Self::sample_texture(self.roughness_texture, uv, textures).is_none() {
+            return self.shininess;
+        }
+        let roughness = f32::max(self.get_roughness(uv, textures), 1e-2);
+        2. / roughness.powi(4) - 2.
+    }
+
+    /// Fraction of the light stopped by the surface, same as the shader
+    /// see src/shaders/scene/material.slang
+    pub fn get_opacity(&self, uv: Vec2, textures: &[Texture]) -> f32 {
+        let alpha = match Self::sample_texture(self.dissolve_texture, uv, textures) {
+            Some(texel) if self.alpha_cutoff > 0. => {
+                if texel.w >= self.alpha_cutoff {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            Some(texel) => texel.w,
+            None => 1.,
+        };
+        self.dissolve * alpha
+    }
+
+    /// The rays may go through it, see `get_opacity`
+    pub fn is_transparent(&self) -> bool {
+        self.dissolve < 1. || self.dissolve_texture != NO_TEXTURE
+    }
+
+    /// Normal in the tangent space of the surface, +Z when there is no normal map
+    pub fn get_tangent_normal(&self, uv: Vec2, textures: &[Texture]) -> Vec3 {
+        match Self::sample_texture(self.normal_texture, uv, textures) {
+            Some(texel) => (2. * texel.truncate() - 1.).normalize_or(Vec3::Z),
+            None => Vec3::Z,
+        }
     }
 }
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use glam::{Vec2, Vec3, Vec4};
+use log::info;
+
+use super::triangle::Triangle;
+
+/// Vertex of a mesh, the layout should match the shader
+/// see src/shaders/scene/vertex.slang
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct Vertex {
+    pub position: Vec4,
+    // Normal in model space, w = 0
+    pub normal: Vec4,
+    // Linear colour multiplying the diffuse colour of the material, the alpha is ignored
+    pub color: Vec4,
+    // Texture coordinates, (0, 0) is the top left corner of the image
+    pub uv: Vec2,
+    #[allow(dead_code)]
+    pub padding: [f32; 2],
+}
+
+impl Default for Vertex {
+    fn default() -> Self {
+        Self {
+            position: Vec4::from_array([0., 0., 0., 1.]),
+            normal: Vec4::from_array([0., 0., 1., 0.]),
+            color: Vec4::ONE,
+            uv: Vec2::ZERO,
+            padding: [0.; 2],
+        }
+    }
+}
+
+impl Vertex {
+    pub fn new(position: Vec3, normal: Vec3) -> Self {
+        Self {
+            position: position.extend(1.),
+            normal: normal.normalize_or_zero().extend(0.),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_uv(self, uv: Vec2) -> Self {
+        Self { uv, ..self }
+    }
+
+    pub fn with_color(self, color: Vec4) -> Self {
+        Self { color, ..self }
+    }
+
+    // -0 and 0 are the same vertex
+    fn get_key(&self) -> [u32; 12] {
+        let [px, py, pz] = self.position.truncate().to_array();
+        let [nx, ny, nz] = self.normal.truncate().to_array();
+        let [r, g, b, a] = self.color.to_array();
+        let [u, v] = self.uv.to_array();
+        [px, py, pz, nx, ny, nz, r, g, b, a, u, v].map(|coordinate| (coordinate + 0.).to_bits())
+    }
+}
+
+/// Indexed triangles, the triangles of a model share their identical vertices
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    // The indices point in `vertices`
+    pub triangles: Vec<Triangle>,
+}
+
+impl Mesh {
+    /// Appends the triangles of another mesh, their model indices are shifted by `model_offset`
+    pub fn append(&mut self, mut other: Mesh, model_offset: usize) {
+        let vertex_offset = self.vertices.len() as u32;
+        for triangle in &mut other.triangles {
+            triangle.indices = triangle.indices.map(|index| index + vertex_offset);
+            triangle.model_index += model_offset as u32;
+        }
+        self.vertices.append(&mut other.vertices);
+        self.triangles.append(&mut other.triangles);
+    }
+
+    /// Removes the triangles of a model with their vertices, the next models are shifted down
+    pub fn remove_model(&mut self, model_index: usize) {
+        let model_index = model_index as u32;
+        self.triangles
+            .retain(|triangle| triangle.model_index != model_index);
+        for triangle in &mut self.triangles {
+            if triangle.model_index > model_index {
+                triangle.model_index -= 1;
+            }
+        }
+
+        let mut new_indices = vec![u32::MAX; self.vertices.len()];
+        let mut vertices = Vec::new();
+        for triangle in &mut self.triangles {
+            for index in &mut triangle.indices {
+                let new_index = &mut new_indices[*index as usize];
+                if *new_index == u32::MAX {
+                    *new_index = vertices.len() as u32;
+                    vertices.push(self.vertices[*index as usize]);
+                }
+                *index = *new_index;
+            }
+        }
+        self.vertices = vertices;
+    }
+
+    /// Size of the vertex and index buffers
+    pub fn get_size_in_bytes(&self) -> usize {
+        self.vertices.len() * size_of::<Vertex>() + self.triangles.len() * size_of::<Triangle>()
+    }
+
+    /// Size of the same triangles if each of them had its own vertices
+    pub fn get_unindexed_size_in_bytes(&self) -> usize {
+        self.triangles.len() * (3 * size_of::<Vertex>() + size_of::<Triangle>())
+    }
+
+    pub fn log_size(&self) {
+        info!(
+            "# of vertices: {}, # of triangles: {}, size: {} KiB instead of {} KiB without shared vertices",
+            self.vertices.len(),
+            self.triangles.len(),
+            self.get_size_in_bytes() / 1024,
+            self.get_unindexed_size_in_bytes() / 1024
+        );
+    }
+}
+
+/// Builds a mesh from triangles given by their vertices
+#[derive(Debug, Default)]
+pub struct MeshBuilder {
+    mesh: Mesh,
+    // Vertices are only shared inside a model so that each one has a single model matrix
+    vertex_indices: HashMap<(u32, [u32; 12]), u32>,
+}
+
+impl MeshBuilder {
+    pub fn add_triangle(&mut self, vertices: [Vertex; 3], model_index: usize) {
+        let model_index = model_index as u32;
+        let indices = vertices.map(|vertex| {
+            *self
+                .vertex_indices
+                .entry((model_index, vertex.get_key()))
+                .or_insert_with(|| {
+                    self.mesh.vertices.push(vertex);
+                    self.mesh.vertices.len() as u32 - 1
+                })
+        });
+        self.mesh.triangles.push(Triangle {
+            indices,
+            model_index,
+        });
+    }
+
+    pub fn build(self) -> Mesh {
+        self.mesh
+    }
+}
@@ -0,0 +1,132 @@
+use std::{fs::File, path::Path};
+
+use glam::{Vec2, Vec3, Vec4};
+use log::{error, info};
+
+use crate::application::core::error::ErrorCode;
+
+use super::{
+    material::Material,
+    mesh::{Mesh, MeshBuilder, Vertex},
+    model::Model,
+    normals::{generate_normals, get_face_normal, DEFAULT_CREASE_ANGLE},
+    ply,
+};
+
+/// Indexed mesh read from a PLY or STL file. The optional attributes are empty when the file
+/// doesn't have them, otherwise there is one per vertex, or one per triangle for
+/// `face_normals`.
+#[derive(Debug, Default)]
+pub struct MeshData {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub colors: Vec<Vec4>,
+    pub uvs: Vec<Vec2>,
+    pub triangles: Vec<[u32; 3]>,
+    pub face_normals: Vec<Vec3>,
+}
+
+impl MeshData {
+    /// The normals of the file give the winding order of the triangles, the vertex normals are
+    /// generated when the file has none. The colours are kept in the vertices of a single model.
+    pub fn into_models(self) -> (Vec<Model>, Mesh, Vec<Material>) {
+        let mut triangles = Vec::new();
+        let mut nb_flipped_triangles = 0;
+
+        for (triangle_index, [i0, i1, i2]) in self.triangles.iter().enumerate() {
+            let [i0, i1, i2] = [*i0 as usize, *i1 as usize, *i2 as usize];
+            let mut triangle = [i0, i1, i2].map(|index| {
+                let normal = self.normals.get(index).copied().unwrap_or_default();
+                let uv = self.uvs.get(index).copied().unwrap_or_default();
+                let color = self.colors.get(index).copied().unwrap_or(Vec4::ONE);
+                Vertex::new(self.positions[index], normal)
+                    .with_uv(uv)
+                    .with_color(color)
+            });
+            let reference_normal = if !self.face_normals.is_empty() {
+                self.face_normals[triangle_index]
+            } else if !self.normals.is_empty() {
+                self.normals[i0] + self.normals[i1] + self.normals[i2]
+            } else {
+                Vec3::ZERO
+            };
+            let positions = [self.positions[i0], self.positions[i1], self.positions[i2]];
+            if get_face_normal(positions).dot(reference_normal) < 0. {
+                triangle.swap(1, 2);
+                nb_flipped_triangles += 1;
+            }
+
+            triangles.push(triangle);
+        }
+        if self.normals.is_empty() {
+            generate_normals(&mut triangles, DEFAULT_CREASE_ANGLE);
+        }
+
+        let mut mesh_builder = MeshBuilder::default();
+        for triangle in triangles {
+            mesh_builder.add_triangle(triangle, 0);
+        }
+        let mesh = mesh_builder.build();
+
+        info!(
+            "# of triangles: {}, # of triangles flipped to match the normals: {}",
+            mesh.triangles.len(),
+            nb_flipped_triangles
+        );
+        let models = if mesh.triangles.is_empty() {
+            Vec::new()
+        } else {
+            vec![Model::default()]
+        };
+        (models, mesh, Vec::new())
+    }
+}
+
+impl Model {
+    /// The path is used as is, see `AssetResolver` to find it
+    #[allow(clippy::type_complexity)]
+    pub fn from_ply(path: &Path) -> Result<(Vec<Model>, Mesh, Vec<Material>), ErrorCode> {
+        info!("Loading a new PLY mesh...");
+        Ok(ply::read_ply(path)?.into_models())
+    }
+
+    /// Reads ascii and binary STL files, the path is used as is
+    #[allow(clippy::type_complexity)]
+    pub fn from_stl(path: &Path) -> Result<(Vec<Model>, Mesh, Vec<Material>), ErrorCode> {
+        info!("Loading a new STL mesh...");
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) => {
+                error!("Failed to open the STL file `{:?}': {:?}", path, err);
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+        let stl_mesh = match stl_io::read_stl(&mut file) {
+            Ok(stl_mesh) => stl_mesh,
+            Err(err) => {
+                error!("Failed to read the STL file `{:?}': {:?}", path, err);
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+
+        let mesh_data = MeshData {
+            positions: stl_mesh
+                .vertices
+                .iter()
+                .map(|vertex| Vec3::from_array(vertex.0))
+                .collect(),
+            triangles: stl_mesh
+                .faces
+                .iter()
+                .map(|face| face.vertices.map(|index| index as u32))
+                .collect(),
+            face_normals: stl_mesh
+                .faces
+                .iter()
+                .map(|face| Vec3::from_array(face.normal.0))
+                .collect(),
+            ..Default::default()
+        };
+        Ok(mesh_data.into_models())
+    }
+}
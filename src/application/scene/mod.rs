@@ -1,21 +1,35 @@
 use std::{
     collections::HashMap,
-    path::PathBuf,
-    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+use assets::AssetResolver;
 use bvh::{
-    aabb::Aabb, default_top_down::BvhDefaultTopDown, ploc::BvhPloc, ploc_parallel::BvhPlocParallel,
-    top_down_sah::BvhTopDownSah, Bvh, BvhNode, BvhType,
+    aabb::Aabb,
+    build_job::{BvhBuildJob, BvhBuildProgress},
+    default_top_down::BvhDefaultTopDown,
+    export,
+    ploc::BvhPloc,
+    ploc_parallel::BvhPlocParallel,
+    sah::{SahConstants, SAH_CONSTANTS_FILE},
+    top_down_sah::BvhTopDownSah,
+    Bvh, BvhNode, BvhType,
 };
-use camera::{Camera, CameraMovement};
+use camera::{Camera, CameraGPU, CameraMovement};
+use edit::ModelLoadParameters;
+use environment::{EnvironmentMap, EnvironmentSettings};
 use glam::Vec3;
+use gltf_import::GltfScene;
+use light::{EmissiveTriangle, Light, LightGPU};
 use log::{error, info, warn};
 // use log::error;
 use material::Material;
+use mesh::Mesh;
 use model::{Model, PlaneType};
+use primitive::{Cuboid, MeshTriangle, Primitive, PrimitiveType, ScenePrimitive, Sphere};
 use rand::Rng;
-use triangle::Triangle;
+use texture::{Texture, TexturePath};
 use winit::{
     dpi::LogicalPosition,
     event::{DeviceId, ElementState, KeyEvent},
@@ -25,22 +39,77 @@ use winit::{
 use super::{
     core::error::ErrorCode,
     parameters::ApplicationParameters,
+    raytracer::{
+        heatmap::{write_heatmap_ppm, HeatmapStats, HeatmapType},
+        integrator::{Integrator, IntegratorSettings},
+        output::write_ppm,
+        CpuRaytracer,
+    },
     window::key_map::{Key, KeyState},
 };
 
+pub mod assets;
 pub mod bvh;
 pub mod camera;
+pub mod edit;
+pub mod environment;
+pub mod gltf_import;
+pub mod light;
 pub mod material;
+pub mod mesh;
+pub mod mesh_data;
 pub mod model;
+pub mod normals;
+pub mod ply;
+pub mod primitive;
+pub mod texture;
 pub mod triangle;
+pub mod triangulation;
+
+/// Emitted by the light of the Cornell box
+const CORNELL_BOX_LIGHT_RADIANCE: f32 = 25.;
+/// Frames averaged by the CPU renders of the integrators that draw random numbers
+const CPU_RENDER_SAMPLES: u32 = 64;
+
+/// Everything displayed by the compute shader apart from the scene data, the accumulated
+/// frames are dropped when it changes
+#[derive(Debug, PartialEq)]
+struct AccumulationKey {
+    camera: CameraGPU,
+    integrator_settings: IntegratorSettings,
+    environment_settings: EnvironmentSettings,
+    is_wireframe_on: bool,
+    bvh_last_type: BvhType,
+    should_display_bvh: bool,
+    bvh_depth_to_display: u32,
+    heatmap_type: HeatmapType,
+}
 
 #[derive(Debug)]
 pub struct Scene {
-    pub triangles: Vec<Triangle>,
+    pub mesh: Mesh,
+    pub spheres: Vec<Sphere>,
+    pub cuboids: Vec<Cuboid>,
     pub models: Vec<Model>,
     pub materials: Vec<Material>,
+    // Paths of the textures referenced by the materials
+    pub textures: Vec<TexturePath>,
+    // Decoded `textures`, see `Scene::load_textures`
+    pub texture_images: Vec<Texture>,
+    pub lights: Vec<Light>,
+    // Background and light of the rays that leave the scene, a gradient when there is none
+    pub environment: Option<EnvironmentMap>,
+    pub environment_settings: EnvironmentSettings,
     pub camera: Camera,
     pub is_wireframe_on: bool,
+    // The primitives, models or materials changed since they were sent to the GPU
+    pub is_gpu_data_dirty: bool,
+    // Model selected in the edit section of the GUI
+    pub edited_model_index: u32,
+    // Light selected in the edit section of the GUI
+    pub edited_light_index: u32,
+    pub model_load_parameters: ModelLoadParameters,
+    pub asset_resolver: AssetResolver,
     pub start_time: u128,
     pub current_time: f32,
 
@@ -49,8 +118,26 @@ pub struct Scene {
     pub bvh_last_type: BvhType, // Cheecky way to check if an update happened
     pub bvhs: HashMap<BvhType, Vec<BvhNode>>,
     pub bvhs_build_times: HashMap<BvhType, Duration>,
+    pub bvh_jobs: HashMap<BvhType, BvhBuildJob>,
+    // Cancelled builds whose worker threads have not stopped yet
+    pub cancelled_bvh_jobs: Vec<BvhBuildJob>,
     pub should_display_bvh: bool,
     pub bvh_depth_to_display: u32,
+    pub bvh_export_min_depth: u32,
+    pub bvh_export_max_depth: u32,
+    // Type and depth of the deepest node of the displayed bvh, see `get_max_bvh_depth`
+    pub bvh_max_depth: Option<(BvhType, u32)>,
+    pub sah_constants: SahConstants,
+
+    // Heatmap
+    pub heatmap_type: HeatmapType,
+    pub heatmap_stats: HeatmapStats,
+
+    // Progressive rendering
+    pub integrator_settings: IntegratorSettings,
+    // Frames already averaged in the accumulation image, 0 restarts it
+    pub nb_accumulated_frames: u32,
+    last_accumulation_key: Option<AccumulationKey>,
 
     duration: f64,
     best_duration: f64,
@@ -70,16 +157,20 @@ pub enum SceneType {
     #[allow(unused)]
     CornellBox,
     #[allow(unused)]
-    SingleSphere(u32, glam::Vec3, f32, glam::Vec3), // (resolution, position, radius, color)
+    SingleSphere(glam::Vec3, f32, glam::Vec3), // (position, radius, color)
+    #[allow(unused)]
+    MultipleSphere(u16, f32, f32, f32, f32), // (nb_spheres, min_position, max_position, min_radius, max_radius)
     #[allow(unused)]
-    MultipleSphere(u16, u32, f32, f32, f32, f32), // (nb_spheres, resolution, min_position, max_position, min_radius, max_radius)
+    MultipleCuboid(u16, f32, f32, f32, f32), // (nb_cuboids, min_position, max_position, min_half_size, max_half_size)
     #[allow(unused)]
-    MultipleObj(Vec<(PathBuf, glam::Mat4)>), // ((path to the obj file, model_matrix))
+    MultipleObj(Vec<(PathBuf, glam::Mat4)>), // ((path to the obj, ply or stl file, model_matrix))
+    #[allow(unused)]
+    Gltf(PathBuf), // (path to the .gltf or .glb file)
 }
 
 impl Scene {
     fn init_scene_skeleton(
-        triangles: Vec<Triangle>,
+        mesh: Mesh,
         models: Vec<Model>,
         materials: Vec<Material>,
         camera: Camera,
@@ -88,6 +179,7 @@ impl Scene {
         let mut bvhs: HashMap<BvhType, Vec<BvhNode>> = Default::default();
         let _ = bvhs.insert(BvhType::default(), Vec::new());
         let bvhs_build_times: HashMap<BvhType, Duration> = Default::default();
+        let bvh_jobs: HashMap<BvhType, BvhBuildJob> = Default::default();
 
         let start_time = match SystemTime::now().duration_since(UNIX_EPOCH) {
             Ok(time) => time.as_millis(),
@@ -98,19 +190,42 @@ impl Scene {
         };
 
         Ok(Scene {
-            triangles,
+            mesh,
+            spheres: Vec::new(),
+            cuboids: Vec::new(),
             models,
             materials,
+            textures: Vec::new(),
+            texture_images: Vec::new(),
+            lights: Vec::new(),
+            environment: None,
+            environment_settings: EnvironmentSettings::default(),
             camera,
             is_wireframe_on: false,
+            is_gpu_data_dirty: false,
+            edited_model_index: 0,
+            edited_light_index: 0,
+            model_load_parameters: ModelLoadParameters::default(),
+            asset_resolver: AssetResolver::default(),
             start_time,
             current_time: 0.,
             bvh_type,
             bvh_last_type: bvh_type,
             bvhs,
             bvhs_build_times,
+            bvh_jobs,
+            cancelled_bvh_jobs: Vec::new(),
             should_display_bvh: false,
             bvh_depth_to_display: 0,
+            bvh_export_min_depth: 0,
+            bvh_export_max_depth: 0,
+            bvh_max_depth: None,
+            sah_constants: SahConstants::default(),
+            heatmap_type: HeatmapType::None,
+            heatmap_stats: HeatmapStats::default(),
+            integrator_settings: IntegratorSettings::default(),
+            nb_accumulated_frames: 0,
+            last_accumulation_key: None,
             duration: 0.,
             best_duration: f64::MAX,
             worst_duration: 0.,
@@ -136,50 +251,61 @@ impl Scene {
 
     // Scene with only one centered sphere
     fn init_scene_single_sphere(
-        sphere_resolution: u32,
         sphere_position: glam::Vec3,
         sphere_radius: f32,
         sphere_color: glam::Vec3,
         camera: Camera,
     ) -> Result<Scene, ErrorCode> {
-        let mut triangles = Vec::new();
+        let mut spheres = Vec::new();
         let mut models = Vec::new();
         let mut materials = vec![Material::default()];
 
         let material = Material::uniform(&sphere_color);
-
-        if let Err(err) = Model::add_sphere(
-            sphere_resolution,
+        Model::add_analytic_sphere(
             sphere_radius,
             sphere_position,
             Some(material),
-            &mut triangles,
+            &mut spheres,
             &mut models,
             &mut materials,
-        ) {
-            error!("Failed to load a new sphere to the scene: {:?}", err);
-            return Err(ErrorCode::InitializationFailure);
-        }
+        );
 
-        Self::init_scene_skeleton(triangles, models, materials, camera)
+        let mut scene = Self::init_scene_skeleton(Mesh::default(), models, materials, camera)?;
+        scene.spheres = spheres;
+        Ok(scene)
     }
 
     fn init_scene_objs(
         objs: Vec<(PathBuf, glam::Mat4)>,
         camera: Camera,
+        asset_resolver: &AssetResolver,
     ) -> Result<Scene, ErrorCode> {
-        let mut triangles = Vec::new();
+        let mut mesh = Mesh::default();
+        let mut cuboids = Vec::new();
         let mut models = Vec::new();
         let mut materials = vec![Material::default()];
+        let mut textures = Vec::new();
 
         for (path, model_matrix) in objs {
-            if let Err(err) = Model::add_obj(
+            let path = match asset_resolver.resolve(&path) {
+                Ok(path) => path,
+                Err(ErrorCode::NotFound) if asset_resolver.should_use_placeholders => {
+                    warn!("The object `{:?}' is replaced by a placeholder", path);
+                    Model::add_placeholder(model_matrix, &mut cuboids, &mut models, &mut materials);
+                    continue;
+                }
+                Err(err) => {
+                    error!("Failed to find the object `{:?}': {:?}", path, err);
+                    return Err(err);
+                }
+            };
+            if let Err(err) = Model::add_mesh_file(
                 &path,
-                false,
                 Some(model_matrix),
-                &mut triangles,
+                &mut mesh,
                 &mut models,
                 &mut materials,
+                &mut textures,
             ) {
                 error!(
                     "Failed to load the object `{:?}' to the scene: {:?}",
@@ -189,24 +315,70 @@ impl Scene {
             }
         }
 
-        Self::init_scene_skeleton(triangles, models, materials, camera)
+        let mut scene = Self::init_scene_skeleton(mesh, models, materials, camera)?;
+        scene.cuboids = cuboids;
+        scene.textures = textures;
+        Ok(scene)
+    }
+
+    // The first camera of the file replaces the given one
+    fn init_scene_gltf(
+        path: PathBuf,
+        camera: Camera,
+        asset_resolver: &AssetResolver,
+    ) -> Result<Scene, ErrorCode> {
+        let path = asset_resolver.resolve(&path)?;
+        let gltf_scene = match GltfScene::load(&path, camera.aspect_ratio) {
+            Ok(gltf_scene) => gltf_scene,
+            Err(err) => {
+                error!("Failed to load the glTF scene `{:?}': {:?}", path, err);
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+
+        let mut mesh = Mesh::default();
+        let mut models = Vec::new();
+        let mut materials = vec![Material::default()];
+        let mut textures = Vec::new();
+        Model::add_models(
+            gltf_scene.models,
+            gltf_scene.mesh,
+            gltf_scene.materials,
+            gltf_scene.textures,
+            None,
+            &mut mesh,
+            &mut models,
+            &mut materials,
+            &mut textures,
+        );
+
+        let camera = match gltf_scene.cameras.into_iter().next() {
+            Some(mut gltf_camera) => {
+                gltf_camera.movement_speed = camera.movement_speed;
+                gltf_camera
+            }
+            None => camera,
+        };
+        let mut scene = Self::init_scene_skeleton(mesh, models, materials, camera)?;
+        scene.textures = textures;
+        scene.lights = gltf_scene.lights;
+        Ok(scene)
     }
 
     fn init_scene_multi_spheres(
         nb_spheres: u16,
-        sphere_resolution: u32,
         min_pos: f32,
         max_pos: f32,
         min_radius: f32,
         max_radius: f32,
         camera: Camera,
     ) -> Result<Scene, ErrorCode> {
-        let mut triangles = Vec::new();
+        let mut spheres = Vec::new();
         let mut models = Vec::new();
         let mut materials = vec![Material::default()];
 
+        let mut rng = rand::thread_rng();
         for _ in 0..nb_spheres {
-            let mut rng = rand::thread_rng();
             let radius = rng.gen::<f32>() * (max_radius - min_radius) + min_radius;
             let material = Material::random();
             let center = glam::Vec3::new(
@@ -214,36 +386,71 @@ impl Scene {
                 rng.gen::<f32>() * (max_pos - min_pos) + min_pos,
                 rng.gen::<f32>() * (max_pos - min_pos) + min_pos,
             );
-
-            if let Err(err) = Model::add_sphere(
-                sphere_resolution,
+            Model::add_analytic_sphere(
                 radius,
                 center,
                 Some(material),
-                &mut triangles,
+                &mut spheres,
                 &mut models,
                 &mut materials,
-            ) {
-                error!("Failed to load a new sphere to the scene: {:?}", err);
-                return Err(ErrorCode::InitializationFailure);
-            }
+            );
         }
 
-        Self::init_scene_skeleton(triangles, models, materials, camera)
+        let mut scene = Self::init_scene_skeleton(Mesh::default(), models, materials, camera)?;
+        scene.spheres = spheres;
+        Ok(scene)
+    }
+
+    fn init_scene_multi_cuboids(
+        nb_cuboids: u16,
+        min_pos: f32,
+        max_pos: f32,
+        min_half_size: f32,
+        max_half_size: f32,
+        camera: Camera,
+    ) -> Result<Scene, ErrorCode> {
+        let mut cuboids = Vec::new();
+        let mut models = Vec::new();
+        let mut materials = vec![Material::default()];
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..nb_cuboids {
+            let half_size = glam::Vec3::new(rng.gen(), rng.gen(), rng.gen())
+                * (max_half_size - min_half_size)
+                + min_half_size;
+            let material = Material::random();
+            let center = glam::Vec3::new(
+                rng.gen::<f32>() * (max_pos - min_pos) + min_pos,
+                rng.gen::<f32>() * (max_pos - min_pos) + min_pos,
+                rng.gen::<f32>() * (max_pos - min_pos) + min_pos,
+            );
+            Model::add_cuboid(
+                half_size,
+                center,
+                Some(material),
+                &mut cuboids,
+                &mut models,
+                &mut materials,
+            );
+        }
+
+        let mut scene = Self::init_scene_skeleton(Mesh::default(), models, materials, camera)?;
+        scene.cuboids = cuboids;
+        Ok(scene)
     }
 
     fn init_scene_cornell_box(camera: Camera) -> Result<Scene, ErrorCode> {
-        let mut triangles = Vec::new();
+        let mut mesh = Mesh::default();
         let mut models = Vec::new();
         let mut materials = vec![Material::default()];
 
         let orientation = triangle::Orientation::ClockWise;
         let plane_materials = vec![
-            Material::uniform(&glam::Vec3::X),    // Left
-            Material::uniform(&glam::Vec3::Y),    // Right
-            Material::uniform(&glam::Vec3::ONE),  // Top
-            Material::uniform(&glam::Vec3::ONE),  // Bottom
-            Material::uniform(&glam::Vec3::ONE),  // Back
+            Material::uniform(&glam::Vec3::X),   // Left
+            Material::uniform(&glam::Vec3::Y),   // Right
+            Material::uniform(&glam::Vec3::ONE), // Top
+            Material::uniform(&glam::Vec3::ONE), // Bottom
+            Material::uniform(&glam::Vec3::ONE), // Back
         ];
         let plane_types = vec![
             PlaneType::Left,
@@ -257,61 +464,144 @@ impl Scene {
                 &plane_type,
                 &orientation,
                 Some(material),
-                &mut triangles,
+                &mut mesh,
                 &mut models,
                 &mut materials,
             );
         }
 
-        Self::init_scene_skeleton(triangles, models, materials, camera)
+        // Square light a bit below the ceiling, facing down
+        Model::add_plane(
+            &PlaneType::Top,
+            &orientation,
+            Some(Material::emissive(glam::Vec3::splat(
+                CORNELL_BOX_LIGHT_RADIANCE,
+            ))),
+            &mut mesh,
+            &mut models,
+            &mut materials,
+        );
+        if let Some(light_model) = models.last_mut() {
+            light_model.model_matrix = glam::Mat4::from_translation(glam::Vec3::new(0., -0.01, 0.))
+                * glam::Mat4::from_scale(glam::Vec3::new(0.25, 1., 0.25));
+        }
+
+        // A mirror ball at the back and a slightly green glass ball at the front
+        let mut spheres = Vec::new();
+        let sphere_materials = [
+            (
+                glam::Vec3::new(-0.45, -0.6, 0.35),
+                0.4,
+                Material::mirror(glam::Vec3::splat(0.95)),
+            ),
+            (
+                glam::Vec3::new(0.45, -0.65, -0.25),
+                0.35,
+                Material::dielectric(1.5, glam::Vec3::new(0.3, 0.05, 0.3), 0.),
+            ),
+        ];
+        for (position, radius, material) in sphere_materials {
+            Model::add_analytic_sphere(
+                radius,
+                position,
+                Some(material),
+                &mut spheres,
+                &mut models,
+                &mut materials,
+            );
+        }
+
+        let mut scene = Self::init_scene_skeleton(mesh, models, materials, camera)?;
+        scene.spheres = spheres;
+        Ok(scene)
     }
 
-    fn from_scene_type(scene_type: SceneType, camera: Camera) -> Result<Scene, ErrorCode> {
-        match scene_type {
-            SceneType::SingleSphere(resolution, position, radius, color) => {
-                Self::init_scene_single_sphere(resolution, position, radius, color, camera)
+    pub fn from_scene_type(
+        scene_type: SceneType,
+        camera: Camera,
+        asset_resolver: &AssetResolver,
+    ) -> Result<Scene, ErrorCode> {
+        let mut scene = match scene_type {
+            SceneType::SingleSphere(position, radius, color) => {
+                Self::init_scene_single_sphere(position, radius, color, camera)
             }
             SceneType::MultipleSphere(
                 nb_spheres,
-                resolution,
                 min_position,
                 max_position,
                 min_radius,
                 max_radius,
             ) => Self::init_scene_multi_spheres(
                 nb_spheres,
-                resolution,
                 min_position,
                 max_position,
                 min_radius,
                 max_radius,
                 camera,
             ),
-            SceneType::MultipleObj(objs) => Self::init_scene_objs(objs, camera),
+            SceneType::MultipleCuboid(
+                nb_cuboids,
+                min_position,
+                max_position,
+                min_half_size,
+                max_half_size,
+            ) => Self::init_scene_multi_cuboids(
+                nb_cuboids,
+                min_position,
+                max_position,
+                min_half_size,
+                max_half_size,
+                camera,
+            ),
+            SceneType::MultipleObj(objs) => Self::init_scene_objs(objs, camera, asset_resolver),
+            SceneType::Gltf(path) => Self::init_scene_gltf(path, camera, asset_resolver),
             SceneType::CornellBox => Self::init_scene_cornell_box(camera),
+        }?;
+        if scene.lights.is_empty() && scene.get_emissive_triangles().is_empty() {
+            scene.lights = vec![Light::directional(scene.camera.at, 1.)];
         }
+        scene.asset_resolver = asset_resolver.clone();
+        scene.load_textures();
+        scene.sah_constants = SahConstants::load_or_default(Path::new(SAH_CONSTANTS_FILE));
+        Ok(scene)
+    }
+
+    /// Single OBJ, PLY or STL file found by the default asset resolver with a camera looking at the whole of it
+    pub fn from_obj_file(file_name: &Path) -> Result<Scene, ErrorCode> {
+        let objs = vec![(file_name.to_path_buf(), glam::Mat4::IDENTITY)];
+        let camera = Camera::new(Vec3::ZERO, 1., 50., 0.1, Vec3::Y);
+        let mut scene = Self::from_scene_type(
+            SceneType::MultipleObj(objs),
+            camera,
+            &AssetResolver::default(),
+        )?;
+
+        let aabb = scene.get_aabb()?;
+        let center = 0.5 * (aabb.mins + aabb.maxs);
+        let radius = 0.5 * (aabb.maxs - aabb.mins).length();
+        let fov: f32 = 50.;
+        let distance = 1.1 * radius / (0.5 * fov.to_radians()).tan();
+        scene.camera = Camera::new(center - distance * Vec3::Z, 1., fov, 0.1, Vec3::Y);
+        Ok(scene)
     }
 
     #[allow(unused)]
     fn init_single_sphere() -> SceneType {
-        let resolution = 16;
         let position = glam::Vec3::ZERO;
         let radius = 10.;
         let color = glam::Vec3::new(0.1, 0.5, 0.5);
-        SceneType::SingleSphere(resolution, position, radius, color)
+        SceneType::SingleSphere(position, radius, color)
     }
 
     #[allow(unused)]
     fn init_multi_spheres() -> SceneType {
         let nb_spheres = 100;
-        let resolution = 132;
         let min_position = -50.;
         let max_position = 50.;
         let min_radius = 0.5;
         let max_radius = 5.;
         SceneType::MultipleSphere(
             nb_spheres,
-            resolution,
             min_position,
             max_position,
             min_radius,
@@ -319,6 +609,22 @@ impl Scene {
         )
     }
 
+    #[allow(unused)]
+    fn init_multi_cuboids() -> SceneType {
+        let nb_cuboids = 100;
+        let min_position = -50.;
+        let max_position = 50.;
+        let min_half_size = 0.5;
+        let max_half_size = 5.;
+        SceneType::MultipleCuboid(
+            nb_cuboids,
+            min_position,
+            max_position,
+            min_half_size,
+            max_half_size,
+        )
+    }
+
     #[allow(unused)]
     fn init_single_obj(path: PathBuf) -> SceneType {
         let mut objs = Vec::new();
@@ -394,7 +700,15 @@ impl Scene {
 
         let camera = Self::init_camera(parameters, Vec3::new(0., 0., -3.), 1.);
 
-        let mut scene = Self::from_scene_type(scene_type, camera)?;
+        let asset_resolver = AssetResolver::new(
+            parameters.asset_directories.clone(),
+            parameters.should_use_asset_placeholders,
+        );
+        let mut scene = Self::from_scene_type(scene_type, camera, &asset_resolver)?;
+        scene.integrator_settings.integrator = parameters.integrator;
+        if let Some(path) = &parameters.environment_path {
+            scene.load_environment(path)?;
+        }
         // First is the first one to display
         let bvhs_to_build = [BvhType::PlocParallel];
 
@@ -404,23 +718,15 @@ impl Scene {
             bvhs_to_build[0]
         };
 
+        // Nothing is displayed until the first bvh has been built
         scene.bvh_type = displayed_bvh_type;
-        scene.bvh_last_type = displayed_bvh_type;
+        scene.bvh_last_type = BvhType::None;
 
         for bvh_type in bvhs_to_build {
-            let time = match scene.init_bvh(bvh_type) {
-                Ok(time) => time,
-                Err(err) => {
-                    error!("Failed to init a bvh: {:?}", err);
-                    return Err(ErrorCode::Unknown);
-                }
-            };
-            info!(
-                "It took {:?}s to build the `{:?}' bvh",
-                time.as_secs_f32(),
-                bvh_type
-            );
-            let _ = scene.bvhs_build_times.insert(bvh_type, time);
+            if let Err(err) = scene.request_bvh(bvh_type) {
+                error!("Failed to request a bvh: {:?}", err);
+                return Err(ErrorCode::Unknown);
+            }
         }
 
         Ok(scene)
@@ -441,6 +747,13 @@ impl Scene {
         };
         self.current_time = (now - self.start_time) as f32;
 
+        // Collect the finished bvhs and build the selected one if needed
+        self.poll_bvh_jobs();
+        if let Err(err) = self.request_bvh(self.bvh_type) {
+            error!("Failed to request the selected bvh: {:?}", err);
+            return Err(ErrorCode::Unknown);
+        }
+
         // Delta time computation
         self.nb_frames += 1;
         self.duration += delta_time;
@@ -488,97 +801,204 @@ impl Scene {
             self.camera
                 .on_keyboard_input(CameraMovement::Down, delta_time);
         }
+
+        // Restart the accumulation when the displayed image changes
+        let accumulation_key = self.get_accumulation_key();
+        if self.is_gpu_data_dirty || self.last_accumulation_key.as_ref() != Some(&accumulation_key)
+        {
+            self.nb_accumulated_frames = 0;
+        } else {
+            self.nb_accumulated_frames = self.nb_accumulated_frames.saturating_add(1);
+        }
+        self.last_accumulation_key = Some(accumulation_key);
         Ok(())
     }
 
-    /// Build a bvh and return the time in seconds it took to build it
-    fn init_bvh(&mut self, bvh_type: BvhType) -> Result<Duration, ErrorCode> {
-        match bvh_type {
+    fn get_accumulation_key(&self) -> AccumulationKey {
+        AccumulationKey {
+            camera: self.camera.get_gpu_data(),
+            integrator_settings: self.integrator_settings,
+            environment_settings: self.environment_settings,
+            is_wireframe_on: self.is_wireframe_on,
+            bvh_last_type: self.bvh_last_type,
+            should_display_bvh: self.should_display_bvh,
+            bvh_depth_to_display: self.bvh_depth_to_display,
+            heatmap_type: self.heatmap_type,
+        }
+    }
+
+    /// Build a bvh, this is called from the worker threads on a snapshot of the scene
+    pub fn build_bvh(
+        &self,
+        bvh_type: BvhType,
+        progress: &BvhBuildProgress,
+    ) -> Result<Vec<BvhNode>, ErrorCode> {
+        let mut new_bvh = match bvh_type {
             BvhType::None => {
                 warn!("No bvh need to be build...");
-                Ok(Duration::default())
+                Ok(Vec::new())
             }
             BvhType::DefaultBottomUp => {
-                let start = Instant::now();
-                match BvhDefaultTopDown::build(self) {
-                    Ok(new_bvh) => {
-                        let end = Instant::now();
-                        let _ = self.bvhs.insert(BvhType::DefaultBottomUp, new_bvh);
-                        Ok(end - start)
-                    }
+                match BvhDefaultTopDown::build_with_progress(self, progress) {
+                    Ok(new_bvh) => Ok(new_bvh),
+                    Err(ErrorCode::Cancelled) => Err(ErrorCode::Cancelled),
                     Err(err) => {
                         error!("Failed to build the default bottom up bvh: {:?}", err);
                         Err(ErrorCode::Unknown)
                     }
                 }
             }
-            // BvhType::BottomUpSah => {
-            //     let start = Instant::now();
-            //     match BvhBottomUpSah::build(self) {
-            //         Ok(new_bvh) => {
-            //             let end = Instant::now();
-            //             let _ = self.bvhs.insert(BvhType::BottomUpSah, new_bvh);
-            //             Ok(end - start)
-            //         }
-            //         Err(err) => {
-            //             error!("Failed to build the bottom up sah bvh: {:?}", err);
-            //             Err(ErrorCode::Unknown)
-            //         }
+            // BvhType::BottomUpSah => match BvhBottomUpSah::build_with_progress(self, progress) {
+            //     Ok(new_bvh) => Ok(new_bvh),
+            //     Err(ErrorCode::Cancelled) => Err(ErrorCode::Cancelled),
+            //     Err(err) => {
+            //         error!("Failed to build the bottom up sah bvh: {:?}", err);
+            //         Err(ErrorCode::Unknown)
             //     }
-            // }
-            BvhType::DefaultTopDown => {
-                let start = Instant::now();
-                match BvhDefaultTopDown::build(self) {
-                    Ok(new_bvh) => {
-                        let end = Instant::now();
-                        let _ = self.bvhs.insert(BvhType::DefaultTopDown, new_bvh);
-                        Ok(end - start)
-                    }
-                    Err(err) => {
-                        error!("Failed to build the default top down bvh: {:?}", err);
-                        Err(ErrorCode::Unknown)
-                    }
+            // },
+            BvhType::DefaultTopDown => match BvhDefaultTopDown::build_with_progress(self, progress)
+            {
+                Ok(new_bvh) => Ok(new_bvh),
+                Err(ErrorCode::Cancelled) => Err(ErrorCode::Cancelled),
+                Err(err) => {
+                    error!("Failed to build the default top down bvh: {:?}", err);
+                    Err(ErrorCode::Unknown)
                 }
-            }
-            BvhType::TopDownSah => {
-                let start = Instant::now();
-                match BvhTopDownSah::build(self) {
-                    Ok(new_bvh) => {
-                        let end = Instant::now();
-                        let _ = self.bvhs.insert(BvhType::TopDownSah, new_bvh);
-                        Ok(end - start)
-                    }
-                    Err(err) => {
-                        error!("Failed to build the top down bvh sah: {:?}", err);
-                        Err(ErrorCode::Unknown)
-                    }
+            },
+            BvhType::TopDownSah => match BvhTopDownSah::build_with_progress(self, progress) {
+                Ok(new_bvh) => Ok(new_bvh),
+                Err(ErrorCode::Cancelled) => Err(ErrorCode::Cancelled),
+                Err(err) => {
+                    error!("Failed to build the top down bvh sah: {:?}", err);
+                    Err(ErrorCode::Unknown)
+                }
+            },
+            BvhType::Ploc => match BvhPloc::build_with_progress(self, progress) {
+                Ok(new_bvh) => Ok(new_bvh),
+                Err(ErrorCode::Cancelled) => Err(ErrorCode::Cancelled),
+                Err(err) => {
+                    error!("Failed to build the ploc bvh: {:?}", err);
+                    Err(ErrorCode::Unknown)
                 }
+            },
+            BvhType::PlocParallel => match BvhPlocParallel::build_with_progress(self, progress) {
+                Ok(new_bvh) => Ok(new_bvh),
+                Err(ErrorCode::Cancelled) => Err(ErrorCode::Cancelled),
+                Err(err) => {
+                    error!("Failed to build the ploc bvh in parallel: {:?}", err);
+                    Err(ErrorCode::Unknown)
+                }
+            },
+        }?;
+        BvhNode::locate_primitives(&mut new_bvh, self);
+        Ok(new_bvh)
+    }
+
+    /// Copy of the geometry of the scene that can be sent to a worker thread
+    fn snapshot(&self) -> Result<Scene, ErrorCode> {
+        let mut snapshot = Self::init_scene_skeleton(
+            self.mesh.clone(),
+            self.models.clone(),
+            self.materials.clone(),
+            self.camera.clone(),
+        )?;
+        snapshot.spheres = self.spheres.clone();
+        snapshot.cuboids = self.cuboids.clone();
+        snapshot.sah_constants = self.sah_constants;
+        Ok(snapshot)
+    }
+
+    /// Start building a bvh on a worker thread if it is neither built nor being built
+    pub fn request_bvh(&mut self, bvh_type: BvhType) -> Result<(), ErrorCode> {
+        if self.bvhs.contains_key(&bvh_type) || self.bvh_jobs.contains_key(&bvh_type) {
+            return Ok(());
+        }
+
+        let job = match BvhBuildJob::spawn(bvh_type, self.snapshot()?) {
+            Ok(job) => job,
+            Err(err) => {
+                error!(
+                    "Failed to start building the `{:?}' bvh: {:?}",
+                    bvh_type, err
+                );
+                return Err(ErrorCode::Unknown);
             }
-            BvhType::Ploc => {
-                let start = Instant::now();
-                match BvhPloc::build(self) {
-                    Ok(new_bvh) => {
-                        let end = Instant::now();
-                        let _ = self.bvhs.insert(BvhType::Ploc, new_bvh);
-                        Ok(end - start)
-                    }
-                    Err(err) => {
-                        error!("Failed to build the ploc bvh: {:?}", err);
-                        Err(ErrorCode::Unknown)
-                    }
+        };
+        info!("Started building the `{:?}' bvh", bvh_type);
+        let _ = self.bvh_jobs.insert(bvh_type, job);
+        Ok(())
+    }
+
+    pub fn cancel_bvh(&self, bvh_type: BvhType) {
+        if let Some(job) = self.bvh_jobs.get(&bvh_type) {
+            job.progress.cancel();
+        }
+    }
+
+    /// Cancel every running build without waiting for the worker threads, they are joined
+    /// once they stop in `poll_bvh_jobs`
+    pub fn cancel_bvh_jobs(&mut self) {
+        for (_, job) in self.bvh_jobs.drain() {
+            job.progress.cancel();
+            self.cancelled_bvh_jobs.push(job);
+        }
+    }
+
+    /// Returns the progress in [0;1] of the bvh if it is being built
+    pub fn get_bvh_build_progress(&self, bvh_type: BvhType) -> Option<f32> {
+        self.bvh_jobs
+            .get(&bvh_type)
+            .map(|job| job.progress.get_progress())
+    }
+
+    /// Move the finished bvhs into the scene
+    fn poll_bvh_jobs(&mut self) {
+        let (stopped_jobs, running_jobs) = std::mem::take(&mut self.cancelled_bvh_jobs)
+            .into_iter()
+            .partition(BvhBuildJob::is_finished);
+        self.cancelled_bvh_jobs = running_jobs;
+        for job in stopped_jobs {
+            let bvh_type = job.bvh_type;
+            if let Err(err) = job.join() {
+                if !matches!(err, ErrorCode::Cancelled) {
+                    warn!(
+                        "The `{:?}' bvh build failed while being cancelled: {:?}",
+                        bvh_type, err
+                    );
                 }
             }
-            BvhType::PlocParallel => {
-                let start = Instant::now();
-                match BvhPlocParallel::build(self) {
-                    Ok(new_bvh) => {
-                        let end = Instant::now();
-                        let _ = self.bvhs.insert(BvhType::PlocParallel, new_bvh);
-                        Ok(end - start)
+        }
+
+        let finished_types = self
+            .bvh_jobs
+            .iter()
+            .filter(|(_, job)| job.is_finished())
+            .map(|(bvh_type, _)| *bvh_type)
+            .collect::<Vec<BvhType>>();
+
+        for bvh_type in finished_types {
+            let Some(job) = self.bvh_jobs.remove(&bvh_type) else {
+                continue;
+            };
+            match job.join() {
+                Ok((bvh, time)) => {
+                    info!(
+                        "It took {:?}s to build the `{:?}' bvh",
+                        time.as_secs_f32(),
+                        bvh_type
+                    );
+                    let _ = self.bvhs.insert(bvh_type, bvh);
+                    let _ = self.bvhs_build_times.insert(bvh_type, time);
+                }
+                Err(err) => {
+                    if matches!(err, ErrorCode::Cancelled) {
+                        info!("The `{:?}' bvh build has been cancelled", bvh_type);
+                    } else {
+                        error!("Failed to build the `{:?}' bvh: {:?}", bvh_type, err);
                     }
-                    Err(err) => {
-                        error!("Failed to build the ploc bvh in parallel: {:?}", err);
-                        Err(ErrorCode::Unknown)
+                    // Go back to the bvh currently on the GPU instead of requesting it again
+                    if self.bvh_type == bvh_type {
+                        self.bvh_type = self.bvh_last_type;
                     }
                 }
             }
@@ -586,7 +1006,116 @@ impl Scene {
     }
 
     pub fn get_aabb(&self) -> Result<Aabb, ErrorCode> {
-        Aabb::from_scene(&self.triangles, &self.models)
+        let indices = (0..self.get_nb_primitives()).collect::<Vec<usize>>();
+        self.get_primitives_aabb(&indices)
+    }
+
+    pub fn get_lights_gpu_data(&self) -> Vec<LightGPU> {
+        self.lights.iter().map(Light::get_gpu_data).collect()
+    }
+
+    /// Replaces the environment map, the path is searched like the models
+    pub fn load_environment(&mut self, path: &Path) -> Result<(), ErrorCode> {
+        let path = self.asset_resolver.resolve(path)?;
+        self.environment = Some(EnvironmentMap::load(&path)?);
+        self.is_gpu_data_dirty = true;
+        Ok(())
+    }
+
+    /// The environment map lighting the scene, None when there is none or it is disabled
+    pub fn get_environment(&self) -> Option<&EnvironmentMap> {
+        self.environment
+            .as_ref()
+            .filter(|_| self.environment_settings.is_enabled)
+    }
+
+    /// Otherwise the rays can stop at their closest hit
+    pub fn has_transparent_materials(&self) -> bool {
+        self.materials.iter().any(Material::is_transparent)
+    }
+
+    /// Triangles whose material emits light, with their probability of being sampled
+    pub fn get_emissive_triangles(&self) -> Vec<EmissiveTriangle> {
+        let powers = self
+            .mesh
+            .triangles
+            .iter()
+            .enumerate()
+            .filter_map(|(triangle_index, triangle)| {
+                let model = &self.models[triangle.model_index as usize];
+                let emission = self.materials[model.material_index as usize]
+                    .emission
+                    .truncate();
+                if emission.max_element() <= 0. {
+                    return None;
+                }
+                let [p0, p1, p2] = triangle.indices.map(|index| {
+                    let position = self.mesh.vertices[index as usize].position.truncate();
+                    model.model_matrix.transform_point3(position)
+                });
+                let area = 0.5 * (p1 - p0).cross(p2 - p0).length();
+                let luminance = emission.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+                Some((triangle_index as u32, area, luminance * area))
+            })
+            .collect::<Vec<(u32, f32, f32)>>();
+        EmissiveTriangle::from_powers(&powers)
+    }
+
+    pub fn get_nb_primitives(&self) -> usize {
+        self.mesh.triangles.len() + self.spheres.len() + self.cuboids.len()
+    }
+
+    /// Type of the primitive `index` of the scene and its index among the primitives of its type
+    pub fn get_primitive_location(&self, index: usize) -> (PrimitiveType, usize) {
+        let nb_triangles = self.mesh.triangles.len();
+        let nb_spheres = self.spheres.len();
+        if index < nb_triangles {
+            (PrimitiveType::Triangle, index)
+        } else if index < nb_triangles + nb_spheres {
+            (PrimitiveType::Sphere, index - nb_triangles)
+        } else {
+            (PrimitiveType::Cuboid, index - nb_triangles - nb_spheres)
+        }
+    }
+
+    /// The triangles come first, then the spheres and then the cuboids
+    pub fn get_primitive(&self, index: usize) -> ScenePrimitive<'_> {
+        match self.get_primitive_location(index) {
+            (PrimitiveType::Triangle, index) => ScenePrimitive::Triangle(MeshTriangle {
+                triangle: &self.mesh.triangles[index],
+                vertices: &self.mesh.vertices,
+            }),
+            (PrimitiveType::Sphere, index) => ScenePrimitive::Sphere(&self.spheres[index]),
+            (PrimitiveType::Cuboid, index) => ScenePrimitive::Cuboid(&self.cuboids[index]),
+        }
+    }
+
+    pub fn get_primitive_aabb(&self, index: usize) -> Aabb {
+        self.get_primitive(index).get_aabb(&self.models)
+    }
+
+    /// Inverse of `get_primitive_location`
+    pub fn get_primitive_index(&self, primitive_type: PrimitiveType, index: usize) -> usize {
+        match primitive_type {
+            PrimitiveType::Triangle => index,
+            PrimitiveType::Sphere => self.mesh.triangles.len() + index,
+            PrimitiveType::Cuboid => self.mesh.triangles.len() + self.spheres.len() + index,
+        }
+    }
+
+    pub fn get_primitive_centroid(&self, index: usize) -> Vec3 {
+        self.get_primitive(index).get_centroid(&self.models)
+    }
+
+    /// Bounds of some of the primitives of the scene
+    pub fn get_primitives_aabb(&self, indices: &[usize]) -> Result<Aabb, ErrorCode> {
+        if indices.is_empty() {
+            error!("Can't create an AABB without any primitive");
+            return Err(ErrorCode::InitializationFailure);
+        }
+        Ok(indices.iter().fold(Aabb::default(), |aabb, &index| {
+            Aabb::merge(&aabb, &self.get_primitive_aabb(index))
+        }))
     }
 
     pub fn get_bvh(&self) -> Result<&Vec<BvhNode>, ErrorCode> {
@@ -640,8 +1169,116 @@ impl Scene {
         Ok(())
     }
 
-    pub fn get_max_bvh_detph(&self) -> u32 {
-        // TODO: access the max bvh depth
-        10
+    /// Write the boxes of the displayed bvh with a depth in the export range as an OBJ
+    pub fn export_bvh_obj(&self, path: &Path) -> Result<(), ErrorCode> {
+        let bvh = self.get_displayed_bvh()?;
+        let depths = self.bvh_export_min_depth..=self.bvh_export_max_depth;
+        if let Err(err) = export::export_obj(bvh, depths, path) {
+            error!(
+                "Failed to export the `{:?}' bvh to `{:?}': {:?}",
+                self.bvh_last_type, path, err
+            );
+            return Err(ErrorCode::IO);
+        }
+        info!(
+            "Exported the `{:?}' bvh to `{:?}'",
+            self.bvh_last_type, path
+        );
+        Ok(())
+    }
+
+    /// Write the whole displayed bvh as JSON
+    pub fn export_bvh_json(&self, path: &Path) -> Result<(), ErrorCode> {
+        let bvh = self.get_displayed_bvh()?;
+        if let Err(err) = export::export_json(bvh, self.bvh_last_type, self, path) {
+            error!(
+                "Failed to export the `{:?}' bvh to `{:?}': {:?}",
+                self.bvh_last_type, path, err
+            );
+            return Err(ErrorCode::IO);
+        }
+        info!(
+            "Exported the `{:?}' bvh to `{:?}'",
+            self.bvh_last_type, path
+        );
+        Ok(())
+    }
+
+    /// Render the heatmap of the current view on the CPU with the displayed bvh
+    pub fn save_cpu_heatmap(&self, path: &Path, width: u32) -> Result<HeatmapStats, ErrorCode> {
+        let heatmap_type = match self.heatmap_type {
+            HeatmapType::None => HeatmapType::NodeTests,
+            heatmap_type => heatmap_type,
+        };
+        let no_bvh = Vec::new();
+        let bvh = self.bvhs.get(&self.bvh_last_type).unwrap_or(&no_bvh);
+        let height = u32::max((width as f32 / self.camera.aspect_ratio) as u32, 1);
+
+        let raytracer = CpuRaytracer::new(self, bvh);
+        let counts =
+            raytracer.render_heatmap(&self.camera.get_gpu_data(), width, height, heatmap_type);
+        let stats = HeatmapStats::from_counts(&counts);
+        if let Err(err) = write_heatmap_ppm(path, &counts, width, height, &stats) {
+            error!("Failed to save the cpu heatmap to `{:?}': {:?}", path, err);
+            return Err(ErrorCode::IO);
+        }
+        info!(
+            "Saved the `{:?}' cpu heatmap of the `{:?}' bvh to `{:?}': {:?}",
+            heatmap_type, self.bvh_last_type, path, stats
+        );
+        Ok(stats)
+    }
+
+    /// Render the current view on the CPU with the displayed bvh and integrator, like the
+    /// compute shader
+    pub fn save_cpu_render(&self, path: &Path, width: u32) -> Result<(), ErrorCode> {
+        let no_bvh = Vec::new();
+        let bvh = self.bvhs.get(&self.bvh_last_type).unwrap_or(&no_bvh);
+        let height = u32::max((width as f32 / self.camera.aspect_ratio) as u32, 1);
+
+        let raytracer = CpuRaytracer::new(self, bvh);
+        let camera = self.camera.get_gpu_data();
+        let settings = &self.integrator_settings;
+        let nb_samples = match settings.integrator {
+            Integrator::Debug => 1,
+            _ => CPU_RENDER_SAMPLES,
+        };
+        let pixels = raytracer.render(&camera, width, height, settings, nb_samples);
+        if let Err(err) = write_ppm(path, &pixels, width, height) {
+            error!("Failed to save the cpu render to `{:?}': {:?}", path, err);
+            return Err(ErrorCode::IO);
+        }
+        info!("Saved the cpu render to `{:?}'", path);
+        Ok(())
+    }
+
+    /// The bvh currently on the GPU
+    fn get_displayed_bvh(&self) -> Result<&Vec<BvhNode>, ErrorCode> {
+        match self.bvhs.get(&self.bvh_last_type) {
+            Some(bvh) if !bvh.is_empty() => Ok(bvh),
+            _ => {
+                error!(
+                    "There is no built bvh to export for the `{:?}' bvh type",
+                    self.bvh_last_type
+                );
+                Err(ErrorCode::Unknown)
+            }
+        }
+    }
+
+    /// Depth of the deepest node of the displayed bvh, only computed again when it changes
+    pub fn get_max_bvh_depth(&mut self) -> u32 {
+        match self.bvh_max_depth {
+            Some((bvh_type, max_depth)) if bvh_type == self.bvh_last_type => max_depth,
+            _ => {
+                let max_depth = self
+                    .bvhs
+                    .get(&self.bvh_last_type)
+                    .and_then(|bvh| BvhNode::get_depths(bvh).into_iter().max())
+                    .unwrap_or(0);
+                self.bvh_max_depth = Some((self.bvh_last_type, max_depth));
+                max_depth
+            }
+        }
     }
 }
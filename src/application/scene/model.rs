@@ -1,13 +1,18 @@
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 use glam::Mat4;
-use log::{error, info};
+use log::{error, info, warn};
 
 use crate::application::core::error::ErrorCode;
 
+use super::normals::{generate_normals, get_face_normal, DEFAULT_CREASE_ANGLE};
 use super::{
-    material::Material,
-    triangle::{Orientation, Triangle},
+    material::{Brdf, Material, NO_TEXTURE},
+    mesh::{Mesh, MeshBuilder, Vertex},
+    primitive::{Cuboid, Sphere},
+    texture::{get_texture_index, TextureEncoding, TexturePath},
+    triangle::Orientation,
+    triangulation::{triangulate_face, TriangulationStats},
 };
 
 #[allow(unused)]
@@ -20,11 +25,14 @@ pub enum PlaneType {
     Bottom,
 }
 
+/// The layout should match the shader, see src/shaders/scene/model.slang
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct Model {
     pub model_matrix: Mat4,
-    pub material_index: usize,
+    pub material_index: u32,
+    #[allow(dead_code)]
+    pub padding: [u32; 3],
 }
 
 impl Default for Model {
@@ -32,30 +40,48 @@ impl Default for Model {
         Self {
             model_matrix: Mat4::IDENTITY,
             material_index: 0,
+            padding: [0; 3],
         }
     }
 }
 
 impl Model {
+    pub fn new(model_matrix: Mat4, material_index: usize) -> Self {
+        Self {
+            model_matrix,
+            material_index: material_index as u32,
+            padding: [0; 3],
+        }
+    }
+
     #[allow(unused)]
-    pub fn triangle() -> (Model, Vec<Triangle>) {
-        (Model::default(), vec![Triangle::default()])
+    pub fn triangle() -> (Model, Mesh) {
+        let normal = glam::Vec3::Z;
+        let vertices = [
+            glam::Vec3::new(-1., 0., 0.),
+            glam::Vec3::new(1., 0., 0.),
+            glam::Vec3::new(0., 1., 0.),
+        ]
+        .map(|position| Vertex::new(position, normal));
+        let mut mesh_builder = MeshBuilder::default();
+        mesh_builder.add_triangle(vertices, 0);
+        (Model::default(), mesh_builder.build())
     }
 
+    /// The path is used as is, see `AssetResolver` to find it
     #[allow(unused)]
     pub fn add_obj(
         object_file_name: &Path,
         has_material_file: bool,
         model_matrix: Option<Mat4>,
-        in_out_triangles: &mut Vec<Triangle>,
+        in_out_mesh: &mut Mesh,
         in_out_models: &mut Vec<Model>,
         in_out_materials: &mut Vec<Material>,
+        in_out_textures: &mut Vec<TexturePath>,
     ) -> Result<(), ErrorCode> {
-        let (mut new_models, mut new_triangles, mut new_materials) =
+        let (new_models, new_mesh, new_materials, new_textures) =
             match Model::from_obj(object_file_name, has_material_file) {
-                Ok((new_models, new_triangles, new_materials)) => {
-                    (new_models, new_triangles, new_materials)
-                }
+                Ok(new_objects) => new_objects,
                 Err(err) => {
                     error!(
                         "Failed to create new models from a given file `{:?}': {:?}",
@@ -65,17 +91,97 @@ impl Model {
                 }
             };
 
+        Self::add_models(
+            new_models,
+            new_mesh,
+            new_materials,
+            new_textures,
+            model_matrix,
+            in_out_mesh,
+            in_out_models,
+            in_out_materials,
+            in_out_textures,
+        );
+
+        Ok(())
+    }
+
+    /// Loads an OBJ with its MTL file, a PLY or a STL file depending on its extension, the path
+    /// is used as is
+    pub fn add_mesh_file(
+        path: &Path,
+        model_matrix: Option<Mat4>,
+        in_out_mesh: &mut Mesh,
+        in_out_models: &mut Vec<Model>,
+        in_out_materials: &mut Vec<Material>,
+        in_out_textures: &mut Vec<TexturePath>,
+    ) -> Result<(), ErrorCode> {
+        let without_textures = |(models, mesh, materials)| (models, mesh, materials, Vec::new());
+        let new_objects = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("obj") => Model::from_obj(path, true),
+            Some("ply") => Model::from_ply(path).map(without_textures),
+            Some("stl") => Model::from_stl(path).map(without_textures),
+            _ => {
+                error!("The format of the mesh file `{:?}' is not supported", path);
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+        let (new_models, new_mesh, new_materials, new_textures) = match new_objects {
+            Ok(new_objects) => new_objects,
+            Err(err) => {
+                error!(
+                    "Failed to create new models from a given file `{:?}': {:?}",
+                    path, err
+                );
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+
+        Self::add_models(
+            new_models,
+            new_mesh,
+            new_materials,
+            new_textures,
+            model_matrix,
+            in_out_mesh,
+            in_out_models,
+            in_out_materials,
+            in_out_textures,
+        );
+
+        Ok(())
+    }
+
+    /// Appends models loaded from a file, a material index of 0 is the default material and
+    /// the others start at 1 in `new_materials`. The texture indices of the new materials are
+    /// in `new_textures`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_models(
+        mut new_models: Vec<Model>,
+        new_mesh: Mesh,
+        mut new_materials: Vec<Material>,
+        new_textures: Vec<TexturePath>,
+        model_matrix: Option<Mat4>,
+        in_out_mesh: &mut Mesh,
+        in_out_models: &mut Vec<Model>,
+        in_out_materials: &mut Vec<Material>,
+        in_out_textures: &mut Vec<TexturePath>,
+    ) {
         // Update the new objects
         let new_model_first_idx = in_out_models.len();
         let new_materials_first_idx = in_out_materials.len();
-        for new_triangle in &mut new_triangles {
-            new_triangle.model_index += new_model_first_idx;
+        // Textures already used by the scene are shared
+        let new_texture_indices: Vec<u32> = new_textures
+            .into_iter()
+            .map(|new_texture| get_texture_index(in_out_textures, new_texture))
+            .collect();
+        for new_material in &mut new_materials {
+            new_material.remap_textures(&new_texture_indices);
         }
-
         for new_model in &mut new_models {
             // Be careful about default material at index = 0
             if new_model.material_index > 0 {
-                new_model.material_index += new_materials_first_idx - 1;
+                new_model.material_index += new_materials_first_idx as u32 - 1;
             }
             // Add a custom model matrix
             if let Some(model_matrix) = model_matrix {
@@ -84,16 +190,15 @@ impl Model {
         }
 
         // Update the old objects
-        in_out_triangles.append(&mut new_triangles);
+        new_mesh.log_size();
+        in_out_mesh.append(new_mesh, new_model_first_idx);
         in_out_models.append(&mut new_models);
         in_out_materials.append(&mut new_materials);
 
         info!(
             "Number of triangles after adding a new object: {}",
-            in_out_triangles.len()
+            in_out_mesh.triangles.len()
         );
-
-        Ok(())
     }
 
     fn display_material(tobj_material: &tobj::Material) {
@@ -167,7 +272,7 @@ impl Model {
         // Normals and texture coordinates are also loaded, but not printed in this example
         info!("model.vertices: {}", mesh.positions.len() / 3);
 
-        debug_assert!(mesh.positions.len() % 3 == 0);
+        debug_assert!(mesh.positions.len().is_multiple_of(3));
         for v in 0..mesh.positions.len() / 3 {
             info!(
                 "    v[{}] = ({}, {}, {})",
@@ -179,45 +284,191 @@ impl Model {
         }
     }
 
-    fn create_materials(tobj_materials: Vec<tobj::Material>) -> Vec<Material> {
+    /// The texture names are relative to the directory of the material file
+    fn create_materials(
+        tobj_materials: Vec<tobj::Material>,
+        directory: &Path,
+    ) -> (Vec<Material>, Vec<TexturePath>) {
         let mut new_materials = Vec::new();
+        let mut textures: Vec<TexturePath> = Vec::new();
+        let mut get_texture_index =
+            |texture_name: Option<&String>, encoding: TextureEncoding| -> u32 {
+                match texture_name {
+                    Some(texture_name) => get_texture_index(
+                        &mut textures,
+                        TexturePath::file(directory.join(texture_name), encoding),
+                    ),
+                    None => NO_TEXTURE,
+                }
+            };
+        let to_vec4 = |color: [f32; 3]| glam::Vec3::from_array(color).extend(1.);
 
         for material in &tobj_materials {
             let mut new_material = Material::default();
             Self::display_material(material);
-            // TODO: add other material properties
-            if let Some(ambient) = &material.ambient {
-                new_material.ambient = glam::Vec4::new(ambient[0], ambient[1], ambient[2], 1.);
+            if let Some(ambient) = material.ambient {
+                new_material.ambient = to_vec4(ambient);
+            }
+            if let Some(diffuse) = material.diffuse {
+                new_material.diffuse = to_vec4(diffuse);
+            }
+            if let Some(specular) = material.specular {
+                new_material.specular = to_vec4(specular);
+            }
+            // `Ke` isn't part of the original MTL format so tobj doesn't parse it
+            if let Some(emission) = material.unknown_param.get("Ke") {
+                let emission: Vec<f32> = emission
+                    .split_whitespace()
+                    .filter_map(|value| value.parse().ok())
+                    .collect();
+                if let [r, g, b] = emission[..] {
+                    new_material.emission = to_vec4([r, g, b]);
+                } else {
+                    warn!(
+                        "Invalid emission for the material `{}': {:?}",
+                        material.name, emission
+                    );
+                }
             }
+            if let Some(shininess) = material.shininess {
+                new_material.shininess = shininess;
+            }
+            // `Pr` and `Pm` are part of the PBR extension of the MTL format, like `Ke`, the
+            // materials that use it are shaded with GGX
+            for (name, factor) in [
+                ("Pr", &mut new_material.roughness),
+                ("Pm", &mut new_material.metallic),
+            ] {
+                let Some(value) = material.unknown_param.get(name) else {
+                    continue;
+                };
+                match value.trim().parse::<f32>() {
+                    Ok(value) => {
+                        *factor = value.clamp(0., 1.);
+                        new_material.brdf = Brdf::Ggx as u32;
+                    }
+                    Err(_) => warn!(
+                        "Invalid `{}' for the material `{}': {:?}",
+                        name, material.name, value
+                    ),
+                }
+            }
+            if let Some(dissolve) = material.dissolve {
+                new_material.dissolve = dissolve;
+            }
+            if let Some(optical_density) = material.optical_density {
+                new_material.index_of_refraction = optical_density;
+            }
+            new_material.illumination_model = match material.illumination_model {
+                Some(illumination_model) => illumination_model as u32,
+                None if material.specular.is_some() => 2,
+                None => 1,
+            };
+            // `illum` 6 and 7 refract the light, the surface is rough when `Pr` is set
+            if matches!(new_material.illumination_model, 6 | 7) {
+                let brdf = if material.unknown_param.contains_key("Pr") {
+                    Brdf::RoughDielectric
+                } else {
+                    Brdf::Dielectric
+                };
+                new_material.brdf = brdf as u32;
+            }
+            // `Tf` is the fraction of the light that goes through one unit of the material,
+            // tobj doesn't parse it either
+            if let Some(transmission) = material.unknown_param.get("Tf") {
+                let transmission: Vec<f32> = transmission
+                    .split_whitespace()
+                    .filter_map(|value| value.parse().ok())
+                    .collect();
+                if let [r, g, b] = transmission[..] {
+                    new_material.absorption =
+                        to_vec4([r, g, b].map(|value| -value.clamp(1e-4, 1.).ln()));
+                } else {
+                    warn!(
+                        "Invalid transmission filter for the material `{}': {:?}",
+                        material.name, transmission
+                    );
+                }
+            }
+            // The colour maps are sRGB, the others hold values
+            new_material.ambient_texture =
+                get_texture_index(material.ambient_texture.as_ref(), TextureEncoding::Srgb);
+            new_material.diffuse_texture =
+                get_texture_index(material.diffuse_texture.as_ref(), TextureEncoding::Srgb);
+            new_material.specular_texture =
+                get_texture_index(material.specular_texture.as_ref(), TextureEncoding::Srgb);
+            // tobj reads `map_Bump` and `bump` in `normal_texture`, these are usually height
+            // maps so only the `norm` map of the PBR extension is a tangent space normal map
+            if let Some(bump_texture) = &material.normal_texture {
+                warn!(
+                    "The bump map `{}' of the material `{}' is ignored, only the normal maps given by `norm' are used",
+                    bump_texture, material.name
+                );
+            }
+            new_material.normal_texture =
+                get_texture_index(material.unknown_param.get("norm"), TextureEncoding::Linear);
+            new_material.shininess_texture =
+                get_texture_index(material.shininess_texture.as_ref(), TextureEncoding::Linear);
+            new_material.dissolve_texture = get_texture_index(
+                material.dissolve_texture.as_ref(),
+                TextureEncoding::Dissolve,
+            );
+            // `map_Pr` is part of the PBR extension of the MTL format, like `Ke`
+            new_material.roughness_texture = get_texture_index(
+                material.unknown_param.get("map_Pr"),
+                TextureEncoding::Linear,
+            );
 
             new_materials.push(new_material);
         }
 
-        new_materials
+        (new_materials, textures)
     }
 
+    /// The normals are generated when there are none for the vertices
     fn create_triangles(
         model_index: usize,
-        faces: &Vec<Vec<usize>>,
-        vertices: &[glam::Vec4],
-    ) -> Vec<Triangle> {
-        let mut new_triangles = Vec::new();
-        for face in faces {
-            let new_triangle = Triangle {
-                p0: vertices[face[0]],
-                p1: vertices[face[1]],
-                p2: vertices[face[2]],
-                model_index,
-            };
-            new_triangles.push(new_triangle);
+        faces: &[[u32; 3]],
+        vertices: &[glam::Vec3],
+        normals: &[glam::Vec3],
+        uvs: &[glam::Vec2],
+        mesh_builder: &mut MeshBuilder,
+    ) {
+        let has_normals = normals.len() == vertices.len();
+        let has_uvs = uvs.len() == vertices.len();
+        let mut new_triangles: Vec<[Vertex; 3]> = faces
+            .iter()
+            .map(|face| {
+                face.map(|index| {
+                    let index = index as usize;
+                    let normal = if has_normals {
+                        normals[index]
+                    } else {
+                        glam::Vec3::ZERO
+                    };
+                    let uv = if has_uvs {
+                        uvs[index]
+                    } else {
+                        glam::Vec2::ZERO
+                    };
+                    Vertex::new(vertices[index], normal).with_uv(uv)
+                })
+            })
+            .collect();
+        if !has_normals {
+            generate_normals(&mut new_triangles, DEFAULT_CREASE_ANGLE);
         }
 
-        new_triangles
+        for new_triangle in new_triangles {
+            mesh_builder.add_triangle(new_triangle, model_index);
+        }
     }
 
-    fn create_models_triangles(tobj_models: Vec<tobj::Model>) -> (Vec<Model>, Vec<Triangle>) {
+    /// Each object, group and material change of the file is a model
+    fn create_models_triangles(tobj_models: Vec<tobj::Model>) -> (Vec<Model>, Mesh) {
         let mut new_models = Vec::new();
-        let mut new_triangles = Vec::new();
+        let mut mesh_builder = MeshBuilder::default();
+        let mut stats = TriangulationStats::default();
 
         for (model_index, model) in tobj_models.iter().enumerate() {
             let mut new_model = Model::default();
@@ -226,72 +477,103 @@ impl Model {
             let mesh = &model.mesh;
             if let Some(id) = mesh.material_id {
                 // +1 to avoid hitting default material
-                new_model.material_index = id + 1;
-            }
-
-            // Get the faces
-            let mut faces = Vec::new();
-            let mut next_face = 0;
-            // Assume only triangles
-            for _ in 0..(mesh.indices.len() / 3) {
-                let end = next_face + 3;
-                let face_indices: Vec<_> = mesh.indices[next_face..end].iter().collect();
-                faces.push(vec![
-                    *face_indices[0] as usize,
-                    *face_indices[1] as usize,
-                    *face_indices[2] as usize,
-                ]);
-                next_face = end;
+                new_model.material_index = id as u32 + 1;
             }
 
             // Get the vertices
-            let mut vertices = Vec::new();
-            debug_assert!(mesh.positions.len() % 3 == 0);
-            for v in 0..mesh.positions.len() / 3 {
-                let vx = mesh.positions[3 * v];
-                let vy = mesh.positions[3 * v + 1];
-                let vz = mesh.positions[3 * v + 2];
-                vertices.push(glam::Vec4::new(vx, vy, vz, 1.));
+            debug_assert!(mesh.positions.len().is_multiple_of(3));
+            let vertices: Vec<glam::Vec3> = mesh
+                .positions
+                .chunks_exact(3)
+                .map(|position| glam::Vec3::new(position[0], position[1], position[2]))
+                .collect();
+            // Indexed like the positions as the file is loaded with a single index
+            let normals: Vec<glam::Vec3> = mesh
+                .normals
+                .chunks_exact(3)
+                .map(|normal| glam::Vec3::new(normal[0], normal[1], normal[2]))
+                .collect();
+            // The v axis of the file goes up the image
+            let uvs: Vec<glam::Vec2> = mesh
+                .texcoords
+                .chunks_exact(2)
+                .map(|uv| glam::Vec2::new(uv[0], 1. - uv[1]))
+                .collect();
+
+            // Get the faces, no arities means only triangles
+            let mut faces = Vec::new();
+            let mut model_stats = TriangulationStats::default();
+            if mesh.face_arities.is_empty() {
+                for face in mesh.indices.chunks_exact(3) {
+                    triangulate_face(&vertices, face, &mut faces, &mut model_stats);
+                }
+            } else {
+                let mut next_face = 0;
+                for arity in &mesh.face_arities {
+                    let end = next_face + *arity as usize;
+                    let face = &mesh.indices[next_face..end];
+                    triangulate_face(&vertices, face, &mut faces, &mut model_stats);
+                    next_face = end;
+                }
             }
+            info!(
+                "Model `{}': {} faces, {} triangles",
+                model.name, model_stats.nb_faces, model_stats.nb_triangles
+            );
+            stats.add(&model_stats);
 
             // Create the triangles
-            let mut triangles_tmp = Self::create_triangles(model_index, &faces, &vertices);
-            new_triangles.append(&mut triangles_tmp);
+            Self::create_triangles(
+                model_index,
+                &faces,
+                &vertices,
+                &normals,
+                &uvs,
+                &mut mesh_builder,
+            );
             new_models.push(new_model);
         }
 
-        (new_models, new_triangles)
+        stats.log();
+
+        (new_models, mesh_builder.build())
     }
 
+    #[allow(clippy::type_complexity)]
     fn create_models_triangles_materials(
         tobj_models: Vec<tobj::Model>,
         tobj_materials: Vec<tobj::Material>,
-    ) -> (Vec<Model>, Vec<Triangle>, Vec<Material>) {
+        directory: &Path,
+    ) -> (Vec<Model>, Mesh, Vec<Material>, Vec<TexturePath>) {
         info!("# of models: {}", tobj_models.len());
         info!("# of materials: {}", tobj_materials.len());
-        let materials = Self::create_materials(tobj_materials);
-        let (models, triangles) = Self::create_models_triangles(tobj_models);
+        let (materials, textures) = Self::create_materials(tobj_materials, directory);
+        let (models, mesh) = Self::create_models_triangles(tobj_models);
 
-        (models, triangles, materials)
+        (models, mesh, materials, textures)
     }
 
+    /// The texture indices of the materials are in the returned texture paths
     #[allow(clippy::type_complexity)]
     fn from_obj(
         object_file_name: &Path,
         has_material_file: bool,
-    ) -> Result<(Vec<Model>, Vec<Triangle>, Vec<Material>), ErrorCode> {
+    ) -> Result<(Vec<Model>, Mesh, Vec<Material>, Vec<TexturePath>), ErrorCode> {
         info!("Loading a new object...");
-        let crate_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        let base_path = crate_path.join(Path::new("src/assets/models"));
-        let file_path = base_path.join(object_file_name);
-
-        let load_options = tobj::GPU_LOAD_OPTIONS;
-        let (models, materials) = match tobj::load_obj(&file_path, &load_options) {
+        // The polygons are triangulated by `triangulate_face`, and the points and lines are
+        // kept to be counted
+        let load_options = tobj::LoadOptions {
+            single_index: true,
+            triangulate: false,
+            ignore_points: false,
+            ignore_lines: false,
+        };
+        let (models, materials) = match tobj::load_obj(object_file_name, &load_options) {
             Ok(models) => models,
             Err(err) => {
                 error!(
                     "Failed to load the object file `{:?}': {:?}",
-                    file_path, err
+                    object_file_name, err
                 );
                 return Err(ErrorCode::InitializationFailure);
             }
@@ -301,18 +583,21 @@ impl Model {
             match materials {
                 Ok(materials) => materials,
                 Err(err) => {
-                    error!(
-                        "Failed to load the materials for the object `{:?}': {:?}",
+                    warn!(
+                        "Failed to load the materials for the object `{:?}', the default one is used: {:?}",
                         object_file_name, err
                     );
-                    return Err(ErrorCode::InitializationFailure);
+                    Vec::new()
                 }
             }
         } else {
             Vec::new()
         };
 
-        Ok(Self::create_models_triangles_materials(models, materials))
+        let directory = object_file_name.parent().unwrap_or(Path::new(""));
+        Ok(Self::create_models_triangles_materials(
+            models, materials, directory,
+        ))
     }
 
     #[allow(unused)]
@@ -321,7 +606,7 @@ impl Model {
         radius: f32,
         center: glam::Vec3,
         material: Option<Material>,
-        in_out_triangles: &mut Vec<Triangle>,
+        in_out_mesh: &mut Mesh,
         in_out_models: &mut Vec<Model>,
         in_out_materials: &mut Vec<Material>,
     ) -> Result<(), ErrorCode> {
@@ -329,6 +614,7 @@ impl Model {
         let step_theta = 2. * std::f32::consts::PI / (resolution as f32);
 
         let mut vertices = Vec::new();
+        let mut uvs = Vec::new();
         let mut indices = Vec::new();
 
         // Build the vertices
@@ -342,6 +628,7 @@ impl Model {
                 let z = phi.sin() * theta.cos();
 
                 vertices.push(glam::Vec3 { x, y, z });
+                uvs.push(glam::Vec2::new(j as f32, i as f32) / resolution as f32);
             }
         }
 
@@ -367,52 +654,113 @@ impl Model {
         }
 
         // Build the model
-        let sphere_model = Model {
-            model_matrix: glam::Mat4 {
+        let sphere_model = Model::new(
+            glam::Mat4 {
                 x_axis: glam::Vec4::new(radius, 0., 0., 0.),
                 y_axis: glam::Vec4::new(0., radius, 0., 0.),
                 z_axis: glam::Vec4::new(0., 0., radius, 0.),
                 w_axis: glam::Vec4::new(center.x, center.y, center.z, 1.),
             },
             material_index,
-        };
+        );
 
-        // Build the triangles
-        let sphere_model_index = in_out_models.len();
+        // Build the triangles, the seam and the poles have a vertex per texture coordinate
+        let mut mesh_builder = MeshBuilder::default();
         for index in indices {
-            let x = index.0 as usize;
-            let y = index.1 as usize;
-            let z = index.2 as usize;
-            let p0 = glam::Vec4::new(vertices[x].x, vertices[x].y, vertices[x].z, 1.);
-            let p1 = glam::Vec4::new(vertices[y].x, vertices[y].y, vertices[y].z, 1.);
-            let p2 = glam::Vec4::new(vertices[z].x, vertices[z].y, vertices[z].z, 1.);
-
-            let triangle = Triangle {
-                p0,
-                p1,
-                p2,
-                model_index: sphere_model_index,
-            };
-
-            in_out_triangles.push(triangle);
+            // The vertices are on the unit sphere
+            let triangle = [index.0, index.1, index.2].map(|i| {
+                let position = vertices[i as usize];
+                Vertex::new(position, position).with_uv(uvs[i as usize])
+            });
+            mesh_builder.add_triangle(triangle, 0);
         }
 
+        in_out_mesh.append(mesh_builder.build(), in_out_models.len());
         in_out_models.push(sphere_model);
 
         info!(
             "Number of triangles after adding a new sphere: {}",
-            in_out_triangles.len()
+            in_out_mesh.triangles.len()
         );
 
         Ok(())
     }
 
+    /// Unit sphere intersected analytically, scaled by the radius
+    pub fn add_analytic_sphere(
+        radius: f32,
+        center: glam::Vec3,
+        material: Option<Material>,
+        in_out_spheres: &mut Vec<Sphere>,
+        in_out_models: &mut Vec<Model>,
+        in_out_materials: &mut Vec<Material>,
+    ) {
+        let model_matrix = Mat4::from_scale_rotation_translation(
+            glam::Vec3::splat(radius),
+            glam::Quat::IDENTITY,
+            center,
+        );
+        let model_index =
+            Self::add_analytic_model(model_matrix, material, in_out_models, in_out_materials);
+        in_out_spheres.push(Sphere::new(glam::Vec3::ZERO, 1., model_index));
+    }
+
+    /// Box from -1 to 1 intersected analytically, scaled by the half size
+    pub fn add_cuboid(
+        half_size: glam::Vec3,
+        center: glam::Vec3,
+        material: Option<Material>,
+        in_out_cuboids: &mut Vec<Cuboid>,
+        in_out_models: &mut Vec<Model>,
+        in_out_materials: &mut Vec<Material>,
+    ) {
+        let model_matrix =
+            Mat4::from_scale_rotation_translation(half_size, glam::Quat::IDENTITY, center);
+        let model_index =
+            Self::add_analytic_model(model_matrix, material, in_out_models, in_out_materials);
+        in_out_cuboids.push(Cuboid::new(-glam::Vec3::ONE, glam::Vec3::ONE, model_index));
+    }
+
+    /// Magenta box standing for a model whose file is missing
+    pub fn add_placeholder(
+        model_matrix: Mat4,
+        in_out_cuboids: &mut Vec<Cuboid>,
+        in_out_models: &mut Vec<Model>,
+        in_out_materials: &mut Vec<Material>,
+    ) {
+        let material = Material::uniform(&glam::Vec3::new(1., 0., 1.));
+        let model_index = Self::add_analytic_model(
+            model_matrix,
+            Some(material),
+            in_out_models,
+            in_out_materials,
+        );
+        in_out_cuboids.push(Cuboid::new(-glam::Vec3::ONE, glam::Vec3::ONE, model_index));
+    }
+
+    fn add_analytic_model(
+        model_matrix: Mat4,
+        material: Option<Material>,
+        in_out_models: &mut Vec<Model>,
+        in_out_materials: &mut Vec<Material>,
+    ) -> usize {
+        let material_index = match material {
+            Some(material) => {
+                in_out_materials.push(material);
+                in_out_materials.len() - 1
+            }
+            None => 0,
+        };
+        in_out_models.push(Model::new(model_matrix, material_index));
+        in_out_models.len() - 1
+    }
+
     #[allow(unused)]
     pub fn add_plane(
         plane_type: &PlaneType,
         orientation: &Orientation,
         material: Option<Material>,
-        in_out_triangles: &mut Vec<Triangle>,
+        in_out_mesh: &mut Mesh,
         in_out_models: &mut Vec<Model>,
         in_out_materials: &mut Vec<Material>,
     ) {
@@ -443,36 +791,23 @@ impl Model {
         if let Some(material) = material {
             in_out_materials.push(material);
         }
-        let model = Model {
-            model_matrix: glam::Mat4::IDENTITY,
-            material_index,
-        };
+        let model = Model::new(glam::Mat4::IDENTITY, material_index);
 
-        // Build the triangles
-        let model_index = in_out_models.len();
+        // Build the triangles, the plane is flat
+        let mut mesh_builder = MeshBuilder::default();
         for index in indices {
-            let x = index.0 as usize;
-            let y = index.1 as usize;
-            let z = index.2 as usize;
-            let p0 = glam::Vec4::new(vertices[x].x, vertices[x].y, vertices[x].z, 1.);
-            let p1 = glam::Vec4::new(vertices[y].x, vertices[y].y, vertices[y].z, 1.);
-            let p2 = glam::Vec4::new(vertices[z].x, vertices[z].y, vertices[z].z, 1.);
-
-            let triangle = Triangle {
-                p0,
-                p1,
-                p2,
-                model_index,
-            };
-
-            in_out_triangles.push(triangle);
+            let positions = [index.0, index.1, index.2].map(|i: usize| vertices[i]);
+            let normal = get_face_normal(positions);
+            let triangle = positions.map(|position| Vertex::new(position, normal));
+            mesh_builder.add_triangle(triangle, 0);
         }
 
+        in_out_mesh.append(mesh_builder.build(), in_out_models.len());
         in_out_models.push(model);
 
         info!(
             "Number of triangles after adding a new plane: {}",
-            in_out_triangles.len()
+            in_out_mesh.triangles.len()
         );
     }
 
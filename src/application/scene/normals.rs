@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use glam::Vec3;
+
+use super::mesh::Vertex;
+
+/// Faces meeting with a larger angle keep a hard edge
+pub const DEFAULT_CREASE_ANGLE: f32 = std::f32::consts::FRAC_PI_3;
+
+/// Normal of a face given by its winding order
+pub fn get_face_normal([p0, p1, p2]: [Vec3; 3]) -> Vec3 {
+    (p1 - p0).cross(p2 - p0).normalize_or_zero()
+}
+
+/// Replaces the vertex normals by the average of the normals of the faces around each vertex,
+/// weighted by their angle at the vertex. Faces are neighbours when they share a position,
+/// and the ones making an angle larger than `crease_angle` with the face of the vertex are
+/// ignored.
+pub fn generate_normals(triangles: &mut [[Vertex; 3]], crease_angle: f32) {
+    let faces: Vec<(Vec3, [f32; 3])> = triangles
+        .iter()
+        .map(|triangle| {
+            let [p0, p1, p2] = triangle.map(|vertex| vertex.position.truncate());
+            let angles = [
+                (p1 - p0).angle_between(p2 - p0),
+                (p2 - p1).angle_between(p0 - p1),
+                (p0 - p2).angle_between(p1 - p2),
+            ]
+            .map(|angle| if angle.is_nan() { 0. } else { angle });
+            (get_face_normal([p0, p1, p2]), angles)
+        })
+        .collect();
+
+    let mut corners: HashMap<[u32; 3], Vec<(usize, usize)>> = HashMap::new();
+    for (triangle_index, triangle) in triangles.iter().enumerate() {
+        for (corner, vertex) in triangle.iter().enumerate() {
+            let key = get_position_key(vertex);
+            corners
+                .entry(key)
+                .or_default()
+                .push((triangle_index, corner));
+        }
+    }
+
+    let min_cos = crease_angle.cos();
+    for (triangle_index, triangle) in triangles.iter_mut().enumerate() {
+        let face_normal = faces[triangle_index].0;
+        for vertex in triangle {
+            let key = get_position_key(vertex);
+            let normal: Vec3 = corners[&key]
+                .iter()
+                .filter_map(|(other_index, other_corner)| {
+                    let (other_normal, angles) = faces[*other_index];
+                    (other_normal.dot(face_normal) >= min_cos)
+                        .then(|| angles[*other_corner] * other_normal)
+                })
+                .sum();
+            let normal = if normal.length_squared() > 0. {
+                normal
+            } else {
+                face_normal
+            };
+            vertex.normal = normal.normalize_or_zero().extend(0.);
+        }
+    }
+}
+
+/// Normal of a normal map in the world, given the interpolated normal and the tangents of
+/// the triangle. Same as the shader, see src/shaders/scene/triangle.slang
+pub fn apply_normal_map(
+    normal: Vec3,
+    [tangent, uv_bitangent]: [Vec3; 2],
+    tangent_normal: Vec3,
+) -> Vec3 {
+    let normal = normal.normalize_or_zero();
+    // Orthogonal to the normal, the bitangent of the texture only gives the handedness
+    let tangent = (tangent - normal * normal.dot(tangent)).normalize_or_zero();
+    if tangent == Vec3::ZERO {
+        return normal;
+    }
+    let mut bitangent = normal.cross(tangent);
+    if bitangent.dot(uv_bitangent) < 0. {
+        bitangent = -bitangent;
+    }
+    (tangent_normal.x * tangent + tangent_normal.y * bitangent + tangent_normal.z * normal)
+        .normalize_or(normal)
+}
+
+// -0 and 0 are the same position
+fn get_position_key(vertex: &Vertex) -> [u32; 3] {
+    vertex
+        .position
+        .truncate()
+        .to_array()
+        .map(|coordinate| (coordinate + 0.).to_bits())
+}
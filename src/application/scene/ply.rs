@@ -0,0 +1,619 @@
+use std::{path::Path, str::SplitAsciiWhitespace};
+
+use glam::{Vec2, Vec3};
+use log::{error, warn};
+
+use crate::application::core::error::ErrorCode;
+
+use super::{
+    mesh_data::MeshData,
+    triangulation::{triangulate_face, TriangulationStats},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum PlyScalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyScalar {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(Self::I8),
+            "uchar" | "uint8" => Some(Self::U8),
+            "short" | "int16" => Some(Self::I16),
+            "ushort" | "uint16" => Some(Self::U16),
+            "int" | "int32" => Some(Self::I32),
+            "uint" | "uint32" => Some(Self::U32),
+            "float" | "float32" => Some(Self::F32),
+            "double" | "float64" => Some(Self::F64),
+            _ => None,
+        }
+    }
+
+    fn get_size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    // Colours stored as integers are in [0, 255]
+    fn get_color_scale(self) -> f32 {
+        match self {
+            Self::F32 | Self::F64 => 1.,
+            _ => 1. / 255.,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PlyProperty {
+    Scalar(String, PlyScalar),
+    // (name, type of the count, type of the items)
+    List(String, PlyScalar, PlyScalar),
+}
+
+impl PlyProperty {
+    fn get_name(&self) -> &str {
+        match self {
+            Self::Scalar(name, _) | Self::List(name, _, _) => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+impl PlyElement {
+    fn get_property_index(&self, names: &[&str]) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|property| names.contains(&property.get_name()))
+    }
+}
+
+/// Reads the values of the body, all of them are converted to `f64`
+struct PlyReader<'a> {
+    format: PlyFormat,
+    bytes: &'a [u8],
+    offset: usize,
+    tokens: SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> PlyReader<'a> {
+    fn new(format: PlyFormat, bytes: &'a [u8]) -> Result<Self, ErrorCode> {
+        let text = match format {
+            PlyFormat::Ascii => match std::str::from_utf8(bytes) {
+                Ok(text) => text,
+                Err(err) => {
+                    error!(
+                        "The body of the ascii PLY file is not valid text: {:?}",
+                        err
+                    );
+                    return Err(ErrorCode::InitializationFailure);
+                }
+            },
+            _ => "",
+        };
+        Ok(Self {
+            format,
+            bytes,
+            offset: 0,
+            tokens: text.split_ascii_whitespace(),
+        })
+    }
+
+    fn read(&mut self, scalar: PlyScalar) -> Result<f64, ErrorCode> {
+        if self.format == PlyFormat::Ascii {
+            let Some(token) = self.tokens.next() else {
+                error!("The PLY file ends before all its elements are read");
+                return Err(ErrorCode::InitializationFailure);
+            };
+            return match token.parse::<f64>() {
+                Ok(value) => Ok(value),
+                Err(err) => {
+                    error!("Failed to parse the PLY value `{}': {:?}", token, err);
+                    Err(ErrorCode::InitializationFailure)
+                }
+            };
+        }
+
+        let size = scalar.get_size();
+        let Some(bytes) = self.bytes.get(self.offset..self.offset + size) else {
+            error!("The PLY file ends before all its elements are read");
+            return Err(ErrorCode::InitializationFailure);
+        };
+        self.offset += size;
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(bytes);
+        if self.format == PlyFormat::BinaryBigEndian {
+            buffer[..size].reverse();
+        }
+        let value = match scalar {
+            PlyScalar::I8 => buffer[0] as i8 as f64,
+            PlyScalar::U8 => buffer[0] as f64,
+            PlyScalar::I16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            PlyScalar::U16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            PlyScalar::I32 => {
+                i32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64
+            }
+            PlyScalar::U32 => {
+                u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64
+            }
+            PlyScalar::F32 => {
+                f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64
+            }
+            PlyScalar::F64 => f64::from_le_bytes(buffer),
+        };
+        Ok(value)
+    }
+
+    fn read_property(
+        &mut self,
+        property: &PlyProperty,
+        values: &mut Vec<f64>,
+    ) -> Result<(), ErrorCode> {
+        values.clear();
+        match property {
+            PlyProperty::Scalar(_, scalar) => values.push(self.read(*scalar)?),
+            PlyProperty::List(_, count, item) => {
+                let count = self.read(*count)? as usize;
+                for _ in 0..count {
+                    values.push(self.read(*item)?);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_header(header: &str) -> Result<(PlyFormat, Vec<PlyElement>), ErrorCode> {
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        error!("The file is not a PLY file, it does not start with `ply'");
+        return Err(ErrorCode::InitializationFailure);
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(PlyFormat::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(PlyFormat::BinaryBigEndian),
+            ["element", name, count] => {
+                let Ok(count) = count.parse() else {
+                    error!("Invalid count for the PLY element `{}': {}", name, count);
+                    return Err(ErrorCode::InitializationFailure);
+                };
+                elements.push(PlyElement {
+                    name: name.to_string(),
+                    count,
+                    properties: Vec::new(),
+                });
+            }
+            ["property", "list", count, item, name] => {
+                let (Some(count), Some(item), Some(element)) = (
+                    PlyScalar::parse(count),
+                    PlyScalar::parse(item),
+                    elements.last_mut(),
+                ) else {
+                    error!("Invalid PLY list property: `{}'", line);
+                    return Err(ErrorCode::InitializationFailure);
+                };
+                element
+                    .properties
+                    .push(PlyProperty::List(name.to_string(), count, item));
+            }
+            ["property", scalar, name] => {
+                let (Some(scalar), Some(element)) = (PlyScalar::parse(scalar), elements.last_mut())
+                else {
+                    error!("Invalid PLY property: `{}'", line);
+                    return Err(ErrorCode::InitializationFailure);
+                };
+                element
+                    .properties
+                    .push(PlyProperty::Scalar(name.to_string(), scalar));
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => warn!("Unknown PLY header line ignored: `{}'", line),
+        }
+    }
+
+    match format {
+        Some(format) => Ok((format, elements)),
+        None => {
+            error!("The PLY file has no format line");
+            Err(ErrorCode::InitializationFailure)
+        }
+    }
+}
+
+/// Reads the `vertex` and `face` elements of an ascii or binary PLY file with the optional
+/// `nx ny nz` normals, `red green blue alpha` colours and `s t` texture coordinates
+pub fn read_ply(path: &Path) -> Result<MeshData, ErrorCode> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            error!("Failed to read the PLY file `{:?}': {:?}", path, err);
+            return Err(ErrorCode::InitializationFailure);
+        }
+    };
+    match parse_ply(&bytes) {
+        Ok(mesh_data) => Ok(mesh_data),
+        Err(err) => {
+            error!("Failed to parse the PLY file `{:?}': {:?}", path, err);
+            Err(ErrorCode::InitializationFailure)
+        }
+    }
+}
+
+/// Same as `read_ply` with the content of the file
+fn parse_ply(bytes: &[u8]) -> Result<MeshData, ErrorCode> {
+    let end_header = b"end_header";
+    let Some(header_end) = bytes
+        .windows(end_header.len())
+        .position(|window| window == end_header)
+    else {
+        error!("The PLY file has no `end_header' line");
+        return Err(ErrorCode::InitializationFailure);
+    };
+    // The body starts after the end of the line
+    let Some(body_start) = bytes[header_end..]
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .map(|position| header_end + position + 1)
+    else {
+        error!("The PLY file has no body");
+        return Err(ErrorCode::InitializationFailure);
+    };
+    let (format, elements) = parse_header(&String::from_utf8_lossy(&bytes[..header_end]))?;
+
+    let mut mesh_data = MeshData::default();
+    let mut reader = PlyReader::new(format, &bytes[body_start..])?;
+    let mut values = Vec::new();
+    let mut faces = Vec::new();
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => read_vertices(element, &mut reader, &mut values, &mut mesh_data)?,
+            "face" => read_faces(element, &mut reader, &mut values, &mut faces)?,
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        reader.read_property(property, &mut values)?;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut stats = TriangulationStats::default();
+    for face in &faces {
+        triangulate_face(
+            &mesh_data.positions,
+            face,
+            &mut mesh_data.triangles,
+            &mut stats,
+        );
+    }
+    stats.log();
+    Ok(mesh_data)
+}
+
+fn read_vertices(
+    element: &PlyElement,
+    reader: &mut PlyReader,
+    values: &mut Vec<f64>,
+    mesh_data: &mut MeshData,
+) -> Result<(), ErrorCode> {
+    let get_indices = |names: [&[&str]; 3]| -> Option<[usize; 3]> {
+        Some([
+            element.get_property_index(names[0])?,
+            element.get_property_index(names[1])?,
+            element.get_property_index(names[2])?,
+        ])
+    };
+    let Some(position_indices) = get_indices([&["x"], &["y"], &["z"]]) else {
+        error!("The PLY vertices have no `x y z' properties");
+        return Err(ErrorCode::InitializationFailure);
+    };
+    let normal_indices = get_indices([&["nx"], &["ny"], &["nz"]]);
+    let color_indices = get_indices([
+        &["red", "diffuse_red", "r"],
+        &["green", "diffuse_green", "g"],
+        &["blue", "diffuse_blue", "b"],
+    ]);
+    let alpha_index = element.get_property_index(&["alpha", "a"]);
+    let uv_indices = element
+        .get_property_index(&["s", "u", "texture_u", "texture_s"])
+        .zip(element.get_property_index(&["t", "v", "texture_v", "texture_t"]));
+    let color_scale = match color_indices.map(|indices| &element.properties[indices[0]]) {
+        Some(PlyProperty::Scalar(_, scalar)) => scalar.get_color_scale(),
+        _ => 1.,
+    };
+
+    let mut vertex = vec![0.; element.properties.len()];
+    for _ in 0..element.count {
+        for (property, value) in element.properties.iter().zip(&mut vertex) {
+            reader.read_property(property, values)?;
+            *value = values.first().copied().unwrap_or_default();
+        }
+        let get_vec3 =
+            |[x, y, z]: [usize; 3]| Vec3::new(vertex[x] as f32, vertex[y] as f32, vertex[z] as f32);
+        mesh_data.positions.push(get_vec3(position_indices));
+        if let Some(normal_indices) = normal_indices {
+            mesh_data.normals.push(get_vec3(normal_indices));
+        }
+        if let Some(color_indices) = color_indices {
+            let alpha = alpha_index.map_or(1., |index| vertex[index] as f32 * color_scale);
+            mesh_data
+                .colors
+                .push((get_vec3(color_indices) * color_scale).extend(alpha));
+        }
+        // The v axis of the file goes up the image
+        if let Some((u_index, v_index)) = uv_indices {
+            mesh_data.uvs.push(Vec2::new(
+                vertex[u_index] as f32,
+                1. - vertex[v_index] as f32,
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn read_faces(
+    element: &PlyElement,
+    reader: &mut PlyReader,
+    values: &mut Vec<f64>,
+    faces: &mut Vec<Vec<u32>>,
+) -> Result<(), ErrorCode> {
+    let Some(indices_index) = element.get_property_index(&["vertex_indices", "vertex_index"])
+    else {
+        error!("The PLY faces have no `vertex_indices' property");
+        return Err(ErrorCode::InitializationFailure);
+    };
+
+    for _ in 0..element.count {
+        for (property_index, property) in element.properties.iter().enumerate() {
+            reader.read_property(property, values)?;
+            if property_index != indices_index {
+                continue;
+            }
+            faces.push(values.iter().map(|index| *index as u32).collect());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec2, Vec3, Vec4};
+
+    use super::parse_ply;
+
+    const ASCII_QUAD: &str = "ply
+format ascii 1.0
+comment counter-clockwise quad seen from +Z
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+property float s
+property float t
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 0 0 1 255 0 0 0 0
+1 0 0 0 0 1 0 255 0 1 0
+1 1 0 0 0 1 0 0 255 1 1
+0 1 0 0 0 1 255 255 255 0 1
+4 0 1 2 3
+";
+
+    /// A triangle with an element to skip and a property after the indices of the face
+    fn get_binary_triangle(is_big_endian: bool) -> Vec<u8> {
+        let format = if is_big_endian {
+            "binary_big_endian"
+        } else {
+            "binary_little_endian"
+        };
+        let mut bytes = format!(
+            "ply
+format {} 1.0
+element vertex 3
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+property uchar alpha
+element edge 1
+property int vertex1
+property int vertex2
+element face 1
+property list uchar uint vertex_indices
+property uchar flags
+end_header
+",
+            format
+        )
+        .into_bytes();
+        let mut push = |value: &[u8]| {
+            if is_big_endian {
+                bytes.extend(value.iter().rev());
+            } else {
+                bytes.extend(value);
+            }
+        };
+        for position in [[0f32, 0., 0.], [1., 0., 0.], [0., 1., 0.]] {
+            for value in position.into_iter().chain([0., 0., 1.]) {
+                push(&value.to_le_bytes());
+            }
+            for channel in [255u8, 51, 0, 255] {
+                push(&[channel]);
+            }
+        }
+        push(&0i32.to_le_bytes());
+        push(&1i32.to_le_bytes());
+        push(&[3]);
+        for index in [0u32, 1, 2] {
+            push(&index.to_le_bytes());
+        }
+        push(&[7]);
+        bytes
+    }
+
+    #[test]
+    fn ascii_quad() {
+        let mesh_data = parse_ply(ASCII_QUAD.as_bytes()).unwrap();
+        assert_eq!(
+            mesh_data.positions,
+            vec![
+                Vec3::new(0., 0., 0.),
+                Vec3::new(1., 0., 0.),
+                Vec3::new(1., 1., 0.),
+                Vec3::new(0., 1., 0.),
+            ]
+        );
+        assert_eq!(mesh_data.normals, vec![Vec3::Z; 4]);
+        // The integer colours are scaled to [0;1] and opaque without alpha
+        assert_eq!(
+            mesh_data.colors,
+            vec![
+                Vec4::new(1., 0., 0., 1.),
+                Vec4::new(0., 1., 0., 1.),
+                Vec4::new(0., 0., 1., 1.),
+                Vec4::ONE,
+            ]
+        );
+        // The v axis is flipped
+        assert_eq!(
+            mesh_data.uvs,
+            vec![
+                Vec2::new(0., 1.),
+                Vec2::new(1., 1.),
+                Vec2::new(1., 0.),
+                Vec2::new(0., 0.),
+            ]
+        );
+        // The quad is split in 2 triangles facing the normals
+        assert_eq!(mesh_data.triangles.len(), 2);
+        for [i0, i1, i2] in mesh_data.triangles {
+            let [a, b, c] = [i0, i1, i2].map(|index| mesh_data.positions[index as usize]);
+            assert!((b - a).cross(c - a).dot(Vec3::Z) > 0.);
+        }
+        assert!(mesh_data.face_normals.is_empty());
+    }
+
+    #[test]
+    fn ascii_quad_colors() {
+        let (models, mesh, materials) = parse_ply(ASCII_QUAD.as_bytes()).unwrap().into_models();
+        // A single model, the colours stay in the vertices
+        assert_eq!(models.len(), 1);
+        assert!(materials.is_empty());
+        assert_eq!(mesh.vertices.len(), 4);
+        assert!(mesh
+            .triangles
+            .iter()
+            .all(|triangle| triangle.model_index == 0));
+        let mut colors: Vec<[u32; 4]> = mesh
+            .vertices
+            .iter()
+            .map(|vertex| vertex.color.to_array().map(f32::to_bits))
+            .collect();
+        colors.sort();
+        let mut expected_colors: Vec<[u32; 4]> = [
+            Vec4::new(1., 0., 0., 1.),
+            Vec4::new(0., 1., 0., 1.),
+            Vec4::new(0., 0., 1., 1.),
+            Vec4::ONE,
+        ]
+        .iter()
+        .map(|color| color.to_array().map(f32::to_bits))
+        .collect();
+        expected_colors.sort();
+        assert_eq!(colors, expected_colors);
+
+        // Averaged at the centroid
+        for triangle in &mesh.triangles {
+            let expected_color = triangle
+                .get_vertices(&mesh.vertices)
+                .iter()
+                .map(|vertex| vertex.color)
+                .sum::<Vec4>()
+                / 3.;
+            let color = triangle.get_color(&mesh.vertices, Vec3::splat(1. / 3.));
+            assert!(color.abs_diff_eq(expected_color, 1e-6));
+        }
+    }
+
+    fn check_binary_triangle(is_big_endian: bool) {
+        let mesh_data = parse_ply(&get_binary_triangle(is_big_endian)).unwrap();
+        assert_eq!(
+            mesh_data.positions,
+            vec![Vec3::ZERO, Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.)]
+        );
+        assert_eq!(mesh_data.normals, vec![Vec3::Z; 3]);
+        assert_eq!(mesh_data.colors.len(), 3);
+        for color in mesh_data.colors {
+            assert!(color.abs_diff_eq(Vec4::new(1., 0.2, 0., 1.), 1e-6));
+        }
+        assert!(mesh_data.uvs.is_empty());
+        assert_eq!(mesh_data.triangles, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn binary_little_endian_triangle() {
+        check_binary_triangle(false);
+    }
+
+    #[test]
+    fn binary_big_endian_triangle() {
+        check_binary_triangle(true);
+    }
+
+    #[test]
+    fn invalid_files() {
+        // No header end
+        assert!(parse_ply(b"ply\nformat ascii 1.0\nelement vertex 0\n").is_err());
+        // Not a PLY file
+        assert!(parse_ply(b"obj\nformat ascii 1.0\nend_header\n").is_err());
+        // The body is shorter than the elements
+        let mut truncated = get_binary_triangle(false);
+        truncated.truncate(truncated.len() - 5);
+        assert!(parse_ply(&truncated).is_err());
+        let ascii_truncated = ASCII_QUAD.replace("4 0 1 2 3\n", "4 0 1\n");
+        assert!(parse_ply(ascii_truncated.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use glam::{BVec3, Vec3, Vec4};
+
+use crate::application::raytracer::{
+    hit::{ray_cuboid_intersection, ray_sphere_intersection, ray_triangle_intersection},
+    ray::Ray,
+};
+
+use super::{bvh::aabb::Aabb, mesh::Vertex, model::Model, triangle::Triangle};
+
+/// Kind of primitive referenced by a bvh leaf, values should match the shader
+/// see src/shaders/scene/primitive.slang
+#[derive(Debug, Default, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum PrimitiveType {
+    #[default]
+    Triangle = 0,
+    Sphere = 1,
+    Cuboid = 2,
+}
+
+impl PrimitiveType {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Triangle),
+            1 => Some(Self::Sphere),
+            2 => Some(Self::Cuboid),
+            _ => None,
+        }
+    }
+}
+
+/// What the bvh builders and the CPU raytracer need to know about a primitive
+pub trait Primitive {
+    /// Bounds in world space
+    fn get_aabb(&self, models: &[Model]) -> Aabb;
+
+    /// Position in world space used to sort the primitives
+    fn get_centroid(&self, models: &[Model]) -> Vec3 {
+        let aabb = self.get_aabb(models);
+        0.5 * (aabb.mins + aabb.maxs)
+    }
+
+    /// Distance along the ray to the closest intersection
+    fn intersect(&self, ray: &Ray, models: &[Model]) -> Option<f32>;
+}
+
+/// Triangle with the vertices of its mesh
+#[derive(Clone, Copy, Debug)]
+pub struct MeshTriangle<'a> {
+    pub triangle: &'a Triangle,
+    pub vertices: &'a [Vertex],
+}
+
+impl Primitive for MeshTriangle<'_> {
+    fn get_aabb(&self, models: &[Model]) -> Aabb {
+        Aabb::from_points(&self.triangle.get_world_positions(self.vertices, models))
+    }
+
+    fn get_centroid(&self, models: &[Model]) -> Vec3 {
+        let model_matrix = models[self.triangle.model_index as usize].model_matrix;
+        self.triangle.get_centroid(self.vertices, model_matrix)
+    }
+
+    fn intersect(&self, ray: &Ray, models: &[Model]) -> Option<f32> {
+        let positions = self.triangle.get_world_positions(self.vertices, models);
+        ray_triangle_intersection(ray, 0, &positions).map(|hit| hit.distance)
+    }
+}
+
+/// Sphere intersected analytically, the model matrix must have a uniform scale
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct Sphere {
+    pub center: Vec4,
+    pub radius: f32,
+    pub model_index: u32,
+    #[allow(dead_code)]
+    pub padding: [u32; 2],
+}
+
+impl Sphere {
+    pub fn new(center: Vec3, radius: f32, model_index: usize) -> Self {
+        Self {
+            center: center.extend(1.),
+            radius,
+            model_index: model_index as u32,
+            padding: [0; 2],
+        }
+    }
+
+    pub fn get_world_center(&self, models: &[Model]) -> Vec3 {
+        let model_matrix = models[self.model_index as usize].model_matrix;
+        (model_matrix * self.center).truncate()
+    }
+
+    pub fn get_world_radius(&self, models: &[Model]) -> f32 {
+        let model_matrix = models[self.model_index as usize].model_matrix;
+        self.radius * model_matrix.x_axis.truncate().length()
+    }
+}
+
+impl Primitive for Sphere {
+    fn get_aabb(&self, models: &[Model]) -> Aabb {
+        let center = self.get_world_center(models);
+        let radius = self.get_world_radius(models);
+        Aabb::from_points(&[center - radius, center + radius])
+    }
+
+    fn intersect(&self, ray: &Ray, models: &[Model]) -> Option<f32> {
+        let center = self.get_world_center(models);
+        let radius = self.get_world_radius(models);
+        ray_sphere_intersection(ray, center, radius)
+    }
+}
+
+/// Box of the model space, the model matrix can rotate and scale it but must not shear it
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct Cuboid {
+    pub mins: Vec4,
+    pub maxs: Vec4,
+    pub model_index: u32,
+    #[allow(dead_code)]
+    pub padding: [u32; 3],
+}
+
+impl Cuboid {
+    pub fn new(mins: Vec3, maxs: Vec3, model_index: usize) -> Self {
+        Self {
+            mins: mins.extend(1.),
+            maxs: maxs.extend(1.),
+            model_index: model_index as u32,
+            padding: [0; 3],
+        }
+    }
+
+    pub fn get_world_corners(&self, models: &[Model]) -> [Vec3; 8] {
+        let model_matrix = models[self.model_index as usize].model_matrix;
+        std::array::from_fn(|index| {
+            let is_max = BVec3::new(index & 1 != 0, index & 2 != 0, index & 4 != 0);
+            let corner = Vec3::select(is_max, self.maxs.truncate(), self.mins.truncate());
+            model_matrix.transform_point3(corner)
+        })
+    }
+}
+
+impl Primitive for Cuboid {
+    fn get_aabb(&self, models: &[Model]) -> Aabb {
+        Aabb::from_points(&self.get_world_corners(models))
+    }
+
+    fn intersect(&self, ray: &Ray, models: &[Model]) -> Option<f32> {
+        let model_matrix = models[self.model_index as usize].model_matrix;
+        ray_cuboid_intersection(
+            ray,
+            &model_matrix,
+            self.mins.truncate(),
+            self.maxs.truncate(),
+        )
+    }
+}
+
+/// Primitive of a scene, the scene indexes the triangles first, then the spheres and
+/// then the cuboids
+#[derive(Clone, Copy, Debug)]
+pub enum ScenePrimitive<'a> {
+    Triangle(MeshTriangle<'a>),
+    Sphere(&'a Sphere),
+    Cuboid(&'a Cuboid),
+}
+
+impl ScenePrimitive<'_> {
+    pub fn get_type(&self) -> PrimitiveType {
+        match self {
+            Self::Triangle(_) => PrimitiveType::Triangle,
+            Self::Sphere(_) => PrimitiveType::Sphere,
+            Self::Cuboid(_) => PrimitiveType::Cuboid,
+        }
+    }
+}
+
+impl Primitive for ScenePrimitive<'_> {
+    fn get_aabb(&self, models: &[Model]) -> Aabb {
+        match self {
+            Self::Triangle(triangle) => triangle.get_aabb(models),
+            Self::Sphere(sphere) => sphere.get_aabb(models),
+            Self::Cuboid(cuboid) => cuboid.get_aabb(models),
+        }
+    }
+
+    fn get_centroid(&self, models: &[Model]) -> Vec3 {
+        match self {
+            Self::Triangle(triangle) => triangle.get_centroid(models),
+            Self::Sphere(sphere) => sphere.get_centroid(models),
+            Self::Cuboid(cuboid) => cuboid.get_centroid(models),
+        }
+    }
+
+    fn intersect(&self, ray: &Ray, models: &[Model]) -> Option<f32> {
+        match self {
+            Self::Triangle(triangle) => triangle.intersect(ray, models),
+            Self::Sphere(sphere) => sphere.intersect(ray, models),
+            Self::Cuboid(cuboid) => cuboid.intersect(ray, models),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Quat, Vec3};
+
+    use super::{Cuboid, Primitive, Sphere};
+    use crate::application::{
+        raytracer::{hit::get_cuboid_normal, ray::Ray},
+        scene::model::Model,
+    };
+
+    fn assert_near(left: Vec3, right: Vec3) {
+        assert!(left.abs_diff_eq(right, 1e-5), "{left} != {right}");
+    }
+
+    fn get_models(scale: Vec3, rotation: Quat, translation: Vec3) -> Vec<Model> {
+        vec![Model::new(
+            Mat4::from_scale_rotation_translation(scale, rotation, translation),
+            0,
+        )]
+    }
+
+    #[test]
+    fn sphere() {
+        let models = get_models(Vec3::splat(2.), Quat::IDENTITY, Vec3::X);
+        let sphere = Sphere::new(Vec3::ZERO, 1., 0);
+
+        let aabb = sphere.get_aabb(&models);
+        assert_near(aabb.mins, Vec3::new(-1., -2., -2.));
+        assert_near(aabb.maxs, Vec3::new(3., 2., 2.));
+
+        let ray = Ray::new(Vec3::new(1., 0., -5.), Vec3::Z, false);
+        assert!((sphere.intersect(&ray, &models).unwrap() - 3.).abs() < 1e-5);
+        // From the inside, the sphere is only hit by the rays that see the back faces
+        let inside_ray = Ray::new(Vec3::X, Vec3::Z, true);
+        assert!((sphere.intersect(&inside_ray, &models).unwrap() - 2.).abs() < 1e-5);
+        let inside_ray = Ray::new(Vec3::X, Vec3::Z, false);
+        assert_eq!(sphere.intersect(&inside_ray, &models), None);
+        let missing_ray = Ray::new(Vec3::new(3.5, 0., -5.), Vec3::Z, false);
+        assert_eq!(sphere.intersect(&missing_ray, &models), None);
+    }
+
+    #[test]
+    fn cuboid_bounds() {
+        let cuboid = Cuboid::new(-Vec3::ONE, Vec3::ONE, 0);
+
+        let models = get_models(Vec3::new(1., 2., 3.), Quat::IDENTITY, Vec3::Y);
+        let aabb = cuboid.get_aabb(&models);
+        assert_near(aabb.mins, Vec3::new(-1., -1., -3.));
+        assert_near(aabb.maxs, Vec3::new(1., 3., 3.));
+
+        // The corners of a box turned by 45 degrees stick out of its unrotated bounds
+        let rotation = Quat::from_rotation_y(std::f32::consts::FRAC_PI_4);
+        let models = get_models(Vec3::ONE, rotation, Vec3::ZERO);
+        let aabb = cuboid.get_aabb(&models);
+        let half_diagonal = std::f32::consts::SQRT_2;
+        assert_near(aabb.mins, Vec3::new(-half_diagonal, -1., -half_diagonal));
+        assert_near(aabb.maxs, Vec3::new(half_diagonal, 1., half_diagonal));
+        for corner in cuboid.get_world_corners(&models) {
+            assert!(corner.cmpge(aabb.mins - 1e-5).all() && corner.cmple(aabb.maxs + 1e-5).all());
+        }
+    }
+
+    #[test]
+    fn rotated_cuboid_intersection() {
+        // Long along x in model space, along y once turned
+        let rotation = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        let models = get_models(Vec3::new(2., 0.5, 0.5), rotation, Vec3::ZERO);
+        let model_matrix = models[0].model_matrix;
+        let cuboid = Cuboid::new(-Vec3::ONE, Vec3::ONE, 0);
+
+        // Outside of the box if the rotation was ignored
+        let ray = Ray::new(Vec3::new(0., 1.5, -5.), Vec3::Z, false);
+        let distance = cuboid.intersect(&ray, &models).unwrap();
+        assert!((distance - 4.5).abs() < 1e-5);
+        let normal = get_cuboid_normal(&model_matrix, -Vec3::ONE, Vec3::ONE, ray.at(distance));
+        assert_near(normal, -Vec3::Z);
+
+        let side_ray = Ray::new(Vec3::new(-5., -1.5, 0.), Vec3::X, false);
+        let distance = cuboid.intersect(&side_ray, &models).unwrap();
+        assert!((distance - 4.5).abs() < 1e-5);
+        let normal = get_cuboid_normal(&model_matrix, -Vec3::ONE, Vec3::ONE, side_ray.at(distance));
+        assert_near(normal, -Vec3::X);
+
+        let top_ray = Ray::new(Vec3::new(0.2, 5., 0.), -Vec3::Y, false);
+        let distance = cuboid.intersect(&top_ray, &models).unwrap();
+        assert!((distance - 3.).abs() < 1e-5);
+        let normal = get_cuboid_normal(&model_matrix, -Vec3::ONE, Vec3::ONE, top_ray.at(distance));
+        assert_near(normal, Vec3::Y);
+
+        // Inside of the box if the rotation was ignored
+        let missing_ray = Ray::new(Vec3::new(1.5, 0., -5.), Vec3::Z, false);
+        assert_eq!(cuboid.intersect(&missing_ray, &models), None);
+    }
+}
@@ -0,0 +1,200 @@
+use std::{path::PathBuf, sync::Arc};
+
+use glam::{Vec2, Vec4};
+use log::{error, info, warn};
+
+use crate::application::core::error::ErrorCode;
+
+use super::{material::NO_TEXTURE, Scene};
+
+/// How the texels of a file are read, a file used in both ways is loaded twice
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureEncoding {
+    // Colours, like the diffuse and base colour maps
+    Srgb,
+    // Values, like the normal and roughness maps
+    Linear,
+    // MTL opacity maps, their luminance is the alpha when they have none
+    Dissolve,
+}
+
+/// File of a texture referenced by the materials, or image embedded in the scene file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TexturePath {
+    // Name of the embedded images, like `scene.glb#image0`
+    pub path: PathBuf,
+    pub encoding: TextureEncoding,
+    // Encoded image of a glTF buffer or data URI, read instead of the file
+    pub embedded_bytes: Option<Arc<[u8]>>,
+}
+
+impl TexturePath {
+    pub fn file(path: PathBuf, encoding: TextureEncoding) -> Self {
+        Self {
+            path,
+            encoding,
+            embedded_bytes: None,
+        }
+    }
+}
+
+/// Image of a material, the pixels are RGBA with 8 bits per channel and the rows go from the
+/// top to the bottom of the image
+#[derive(Clone, Debug)]
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    // The colour channels are sRGB encoded, the alpha is always linear
+    pub is_srgb: bool,
+    // The file couldn't be decoded, no material samples it
+    pub is_placeholder: bool,
+}
+
+impl Texture {
+    /// Decodes a PNG, JPEG or TGA image, the path is used as is
+    pub fn load(texture_path: &TexturePath) -> Result<Self, ErrorCode> {
+        let image = match &texture_path.embedded_bytes {
+            Some(bytes) => image::load_from_memory(bytes),
+            None => image::open(&texture_path.path),
+        };
+        let image = match image {
+            Ok(image) => image,
+            Err(err) => {
+                error!(
+                    "Failed to decode the texture `{:?}': {:?}",
+                    texture_path.path, err
+                );
+                return Err(ErrorCode::InitializationFailure);
+            }
+        };
+        let encoding = texture_path.encoding;
+        let has_alpha = image.color().has_alpha();
+        let mut image = image.into_rgba8();
+        // Opacity maps like `map_d` are usually grey, the others are opaque without alpha
+        if !has_alpha && encoding == TextureEncoding::Dissolve {
+            for pixel in image.pixels_mut() {
+                let [r, g, b, _] = pixel.0.map(f32::from);
+                pixel.0[3] = (0.2126 * r + 0.7152 * g + 0.0722 * b).round() as u8;
+            }
+        }
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            pixels: image.into_raw(),
+            is_srgb: encoding == TextureEncoding::Srgb,
+            is_placeholder: false,
+        })
+    }
+
+    /// White pixel taking the place of a texture that failed to load
+    pub fn placeholder() -> Self {
+        Self {
+            width: 1,
+            height: 1,
+            pixels: vec![u8::MAX; 4],
+            is_srgb: false,
+            is_placeholder: true,
+        }
+    }
+
+    // The texture is repeated outside of [0, 1]
+    fn get_texel(&self, x: i64, y: i64) -> Vec4 {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        let offset = 4 * (y * self.width as usize + x);
+        let texel: [u8; 4] = self.pixels[offset..offset + 4].try_into().unwrap();
+        let [r, g, b, a] = texel.map(|channel| channel as f32 / 255.);
+        if self.is_srgb {
+            Vec4::new(srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a)
+        } else {
+            Vec4::new(r, g, b, a)
+        }
+    }
+
+    /// Bilinear filtering with repeated texture coordinates, same as the sampler of the
+    /// raytracing pipeline, the sRGB texels are decoded before being filtered
+    pub fn sample(&self, uv: Vec2) -> Vec4 {
+        let position = uv * Vec2::new(self.width as f32, self.height as f32) - 0.5;
+        let corner = position.floor();
+        let weights = position - corner;
+        let (x, y) = (corner.x as i64, corner.y as i64);
+        let top = self
+            .get_texel(x, y)
+            .lerp(self.get_texel(x + 1, y), weights.x);
+        let bottom = self
+            .get_texel(x, y + 1)
+            .lerp(self.get_texel(x + 1, y + 1), weights.x);
+        top.lerp(bottom, weights.y)
+    }
+}
+
+// Same as the R8G8B8A8_SRGB images of the raytracing pipeline
+fn srgb_to_linear(channel: f32) -> f32 {
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Index of the texture in `textures`, it is added at the end when it isn't there yet
+pub fn get_texture_index(textures: &mut Vec<TexturePath>, texture_path: TexturePath) -> u32 {
+    let index = match textures.iter().position(|texture| *texture == texture_path) {
+        Some(index) => index,
+        None => {
+            textures.push(texture_path);
+            textures.len() - 1
+        }
+    };
+    index as u32
+}
+
+impl Scene {
+    /// Decodes the texture files that aren't loaded yet. The materials stop using the ones
+    /// that fail, they are replaced by a placeholder so the indices stay the same.
+    pub fn load_textures(&mut self) {
+        let first_new_index = self.texture_images.len();
+        for texture_path in &self.textures[first_new_index..] {
+            let texture = match Texture::load(texture_path) {
+                Ok(texture) => texture,
+                Err(err) => {
+                    warn!(
+                        "The texture `{:?}' is ignored by the materials: {:?}",
+                        texture_path.path, err
+                    );
+                    Texture::placeholder()
+                }
+            };
+            self.texture_images.push(texture);
+        }
+        if first_new_index == self.texture_images.len() {
+            return;
+        }
+
+        let new_indices: Vec<u32> = self
+            .texture_images
+            .iter()
+            .enumerate()
+            .map(|(index, texture)| {
+                if texture.is_placeholder {
+                    NO_TEXTURE
+                } else {
+                    index as u32
+                }
+            })
+            .collect();
+        for material in &mut self.materials {
+            material.remap_textures(&new_indices);
+        }
+        info!(
+            "# of textures: {}, {} KiB",
+            self.texture_images.len(),
+            self.texture_images
+                .iter()
+                .map(|texture| texture.pixels.len())
+                .sum::<usize>()
+                / 1024
+        );
+    }
+}
@@ -1,6 +1,6 @@
-use glam::Vec4;
+use glam::{Mat4, Vec2, Vec3, Vec4};
 
-use super::{bvh::aabb::Aabb, model::Model, Scene};
+use super::{bvh::aabb::Aabb, mesh::Vertex, model::Model};
 
 #[allow(unused)]
 pub enum Orientation {
@@ -8,114 +8,102 @@ pub enum Orientation {
     CounterClockWise,
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Triangle of a mesh, the layout should match the shader
+/// see src/shaders/scene/triangle.slang
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 #[repr(C)]
 pub struct Triangle {
-    pub p0: Vec4,
-    pub p1: Vec4,
-    pub p2: Vec4,
-    pub model_index: usize,
+    // Indices in the vertices of the mesh
+    pub indices: [u32; 3],
+    pub model_index: u32,
 }
 
-impl Default for Triangle {
-    fn default() -> Self {
+impl Triangle {
+    pub fn new(indices: [u32; 3], model_index: usize) -> Self {
         Self {
-            // CCW
-            p0: Vec4::from_array([-1., 0., 0., 1.]),
-            p1: Vec4::from_array([1., 0., 0., 1.]),
-            p2: Vec4::from_array([0., 1., 0., 1.]),
-            model_index: 0,
+            indices,
+            model_index: model_index as u32,
         }
     }
-}
-
-impl Triangle {
-    #[allow(unused)]
-    pub fn get_world_pos(&self, scene: &Scene) -> (Vec4, Vec4, Vec4) {
-        let model_matrix = scene.models[self.model_index].model_matrix;
-        let p0 = model_matrix * self.p0;
-        let p1 = model_matrix * self.p1;
-        let p2 = model_matrix * self.p2;
-        (p0, p1, p2)
-    }
 
-    pub fn get_max_x(&self, scene: &Scene) -> f32 {
-        let model_matrix = scene.models[self.model_index].model_matrix;
-        let p0 = model_matrix * self.p0;
-        let p1 = model_matrix * self.p1;
-        let p2 = model_matrix * self.p2;
-        f32::max(p0.x, f32::max(p1.x, p2.x))
+    pub fn get_vertices(&self, vertices: &[Vertex]) -> [Vertex; 3] {
+        self.indices.map(|index| vertices[index as usize])
     }
 
-    pub fn get_max_y(&self, scene: &Scene) -> f32 {
-        let model_matrix = scene.models[self.model_index].model_matrix;
-        let p0 = model_matrix * self.p0;
-        let p1 = model_matrix * self.p1;
-        let p2 = model_matrix * self.p2;
-        f32::max(p0.y, f32::max(p1.y, p2.y))
+    /// Positions in model space
+    pub fn get_positions(&self, vertices: &[Vertex]) -> [Vec3; 3] {
+        self.indices
+            .map(|index| vertices[index as usize].position.truncate())
     }
 
-    pub fn get_max_z(&self, scene: &Scene) -> f32 {
-        let model_matrix = scene.models[self.model_index].model_matrix;
-        let p0 = model_matrix * self.p0;
-        let p1 = model_matrix * self.p1;
-        let p2 = model_matrix * self.p2;
-        f32::max(p0.z, f32::max(p1.z, p2.z))
+    /// Interpolated texture coordinates, `barycentric_coordinates` are the weights of the
+    /// three vertices
+    pub fn get_uv(&self, vertices: &[Vertex], barycentric_coordinates: Vec3) -> Vec2 {
+        let [uv0, uv1, uv2] = self.indices.map(|index| vertices[index as usize].uv);
+        let b = barycentric_coordinates;
+        b.x * uv0 + b.y * uv1 + b.z * uv2
     }
 
-    pub fn get_min_x(&self, scene: &Scene) -> f32 {
-        let model_matrix = scene.models[self.model_index].model_matrix;
-        let p0 = model_matrix * self.p0;
-        let p1 = model_matrix * self.p1;
-        let p2 = model_matrix * self.p2;
-        f32::min(p0.x, f32::min(p1.x, p2.x))
+    /// Interpolated vertex colour
+    pub fn get_color(&self, vertices: &[Vertex], barycentric_coordinates: Vec3) -> Vec4 {
+        let [c0, c1, c2] = self.indices.map(|index| vertices[index as usize].color);
+        let b = barycentric_coordinates;
+        b.x * c0 + b.y * c1 + b.z * c2
     }
 
-    pub fn get_min_y(&self, scene: &Scene) -> f32 {
-        let model_matrix = scene.models[self.model_index].model_matrix;
-        let p0 = model_matrix * self.p0;
-        let p1 = model_matrix * self.p1;
-        let p2 = model_matrix * self.p2;
-        f32::min(p0.y, f32::min(p1.y, p2.y))
+    /// Directions of the u and v axes of the texture in model space, the bitangent points
+    /// up the image. Same as the shader, see src/shaders/scene/triangle.slang
+    pub fn get_tangents(&self, vertices: &[Vertex]) -> Option<[Vec3; 2]> {
+        let [p0, p1, p2] = self.get_positions(vertices);
+        let [uv0, uv1, uv2] = self.indices.map(|index| vertices[index as usize].uv);
+        let (e0, e1) = (p1 - p0, p2 - p0);
+        let (duv0, duv1) = (uv1 - uv0, uv2 - uv0);
+        let determinant = duv0.perp_dot(duv1);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let tangent = (e0 * duv1.y - e1 * duv0.y) / determinant;
+        // The v axis goes down the image
+        let bitangent = -(e1 * duv0.x - e0 * duv1.x) / determinant;
+        Some([tangent, bitangent])
     }
 
-    pub fn get_min_z(&self, scene: &Scene) -> f32 {
-        let model_matrix = scene.models[self.model_index].model_matrix;
-        let p0 = model_matrix * self.p0;
-        let p1 = model_matrix * self.p1;
-        let p2 = model_matrix * self.p2;
-        f32::min(p0.z, f32::min(p1.z, p2.z))
+    pub fn get_world_positions(&self, vertices: &[Vertex], models: &[Model]) -> [Vec3; 3] {
+        let model_matrix = models[self.model_index as usize].model_matrix;
+        self.get_positions(vertices)
+            .map(|position| model_matrix.transform_point3(position))
     }
 
-    pub fn get_centroid(&self, model_matrix: glam::Mat4) -> glam::Vec3 {
-        let p0 = model_matrix * self.p0;
-        let p1 = model_matrix * self.p1;
-        let p2 = model_matrix * self.p2;
-
-        let p0 = glam::Vec3::new(p0.x, p0.y, p0.z);
-        let p1 = glam::Vec3::new(p1.x, p1.y, p1.z);
-        let p2 = glam::Vec3::new(p2.x, p2.y, p2.z);
+    pub fn get_centroid(&self, vertices: &[Vertex], model_matrix: Mat4) -> Vec3 {
+        let [p0, p1, p2] = self
+            .get_positions(vertices)
+            .map(|position| model_matrix.transform_point3(position));
 
         (0.33333) * (p0 + p1 + p2)
     }
 
-    pub fn get_centroids(triangles: &[Triangle], models: &[Model]) -> Vec<glam::Vec3> {
+    pub fn get_centroids(
+        triangles: &[Triangle],
+        vertices: &[Vertex],
+        models: &[Model],
+    ) -> Vec<Vec3> {
         triangles
             .iter()
-            .map(|t| t.get_centroid(models[t.model_index].model_matrix))
-            .collect::<Vec<glam::Vec3>>()
+            .map(|t| t.get_centroid(vertices, models[t.model_index as usize].model_matrix))
+            .collect::<Vec<Vec3>>()
     }
 
     pub fn get_normalized_centroids(
         triangles: &[Triangle],
+        vertices: &[Vertex],
         models: &[Model],
         circumscribed_cube: &Aabb,
-    ) -> Vec<glam::Vec3> {
-        let centroids = Self::get_centroids(triangles, models);
+    ) -> Vec<Vec3> {
+        let centroids = Self::get_centroids(triangles, vertices, models);
         let cube_length = circumscribed_cube.get_length_x();
         centroids
             .iter()
             .map(|c| (*c - circumscribed_cube.mins) / cube_length)
-            .collect::<Vec<glam::Vec3>>()
+            .collect::<Vec<Vec3>>()
     }
 }
@@ -0,0 +1,325 @@
+use glam::{Vec2, Vec3};
+use log::{info, warn};
+
+// Squared area under which a face is degenerate, relative to its size to the power of 4
+const DEGENERATE_AREA_EPSILON: f32 = 1e-12;
+
+/// Counts of the faces of a mesh file, see `triangulate_face`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TriangulationStats {
+    pub nb_faces: usize,
+    // Faces with more than 3 vertices
+    pub nb_polygons: usize,
+    pub nb_triangles: usize,
+    // Faces without area, they are skipped
+    pub nb_degenerate_faces: usize,
+    // Points, lines and faces with invalid indices
+    pub nb_skipped_faces: usize,
+    // Self-intersecting polygons, triangulated as well as possible
+    pub nb_non_simple_polygons: usize,
+}
+
+impl TriangulationStats {
+    pub fn add(&mut self, other: &TriangulationStats) {
+        self.nb_faces += other.nb_faces;
+        self.nb_polygons += other.nb_polygons;
+        self.nb_triangles += other.nb_triangles;
+        self.nb_degenerate_faces += other.nb_degenerate_faces;
+        self.nb_skipped_faces += other.nb_skipped_faces;
+        self.nb_non_simple_polygons += other.nb_non_simple_polygons;
+    }
+
+    pub fn log(&self) {
+        info!(
+            "# of faces: {}, # of polygons: {}, # of triangles: {}",
+            self.nb_faces, self.nb_polygons, self.nb_triangles
+        );
+        if self.nb_degenerate_faces > 0
+            || self.nb_skipped_faces > 0
+            || self.nb_non_simple_polygons > 0
+        {
+            warn!(
+                "# of degenerate faces: {}, # of skipped faces: {}, # of self-intersecting polygons: {}",
+                self.nb_degenerate_faces, self.nb_skipped_faces, self.nb_non_simple_polygons
+            );
+        }
+    }
+}
+
+/// Splits a face in triangles with the same winding order, concave polygons are triangulated
+/// by ear clipping in the plane of the face
+pub fn triangulate_face(
+    positions: &[Vec3],
+    face: &[u32],
+    triangles: &mut Vec<[u32; 3]>,
+    stats: &mut TriangulationStats,
+) {
+    stats.nb_faces += 1;
+    if face.len() < 3 || face.iter().any(|index| *index as usize >= positions.len()) {
+        stats.nb_skipped_faces += 1;
+        return;
+    }
+    if face.len() > 3 {
+        stats.nb_polygons += 1;
+    }
+
+    let vertices: Vec<Vec3> = face
+        .iter()
+        .map(|index| positions[*index as usize])
+        .collect();
+    // Newell's method, robust to concave faces
+    let mut normal = Vec3::ZERO;
+    for (i, current) in vertices.iter().enumerate() {
+        let next = vertices[(i + 1) % vertices.len()];
+        normal += (*current - next).cross(*current + next);
+    }
+    let size = vertices.iter().fold(0f32, |size, vertex| {
+        size.max((*vertex - vertices[0]).length())
+    });
+    if normal.length_squared() <= DEGENERATE_AREA_EPSILON * size.powi(4) {
+        stats.nb_degenerate_faces += 1;
+        return;
+    }
+
+    let nb_triangles = triangles.len();
+    if face.len() == 3 {
+        triangles.push([face[0], face[1], face[2]]);
+    } else if !clip_ears(&vertices, normal.normalize(), face, triangles) {
+        stats.nb_non_simple_polygons += 1;
+    }
+    stats.nb_triangles += triangles.len() - nb_triangles;
+}
+
+fn get_signed_area(a: Vec2, b: Vec2, c: Vec2) -> f32 {
+    (b - a).perp_dot(c - a)
+}
+
+fn is_inside_triangle(point: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
+    get_signed_area(a, b, point) >= 0.
+        && get_signed_area(b, c, point) >= 0.
+        && get_signed_area(c, a, point) >= 0.
+}
+
+// Returns false when no ear is found before the end, the rest of the polygon is then a fan
+fn clip_ears(vertices: &[Vec3], normal: Vec3, face: &[u32], triangles: &mut Vec<[u32; 3]>) -> bool {
+    // The polygon is counter-clockwise in this basis of its plane
+    let u = normal.any_orthonormal_vector();
+    let v = normal.cross(u);
+    let points: Vec<Vec2> = vertices
+        .iter()
+        .map(|vertex| Vec2::new(vertex.dot(u), vertex.dot(v)))
+        .collect();
+
+    let mut remaining: Vec<usize> = (0..face.len()).collect();
+    while remaining.len() > 3 {
+        let nb_remaining = remaining.len();
+        let ear = (0..nb_remaining).find(|i| {
+            let previous = remaining[(i + nb_remaining - 1) % nb_remaining];
+            let current = remaining[*i];
+            let next = remaining[(i + 1) % nb_remaining];
+            let (a, b, c) = (points[previous], points[current], points[next]);
+            // Reflex and flat vertices are not ears
+            if get_signed_area(a, b, c) <= 0. {
+                return false;
+            }
+            !remaining.iter().any(|other| {
+                ![previous, current, next].contains(other)
+                    && points[*other] != a
+                    && points[*other] != b
+                    && points[*other] != c
+                    && is_inside_triangle(points[*other], a, b, c)
+            })
+        });
+        let Some(ear) = ear else {
+            for i in 1..nb_remaining - 1 {
+                triangles.push([
+                    face[remaining[0]],
+                    face[remaining[i]],
+                    face[remaining[i + 1]],
+                ]);
+            }
+            return false;
+        };
+        let previous = remaining[(ear + nb_remaining - 1) % nb_remaining];
+        let next = remaining[(ear + 1) % nb_remaining];
+        triangles.push([face[previous], face[remaining[ear]], face[next]]);
+        remaining.remove(ear);
+    }
+
+    // The last triangle can be flat when the polygon had aligned vertices
+    let (a, b, c) = (
+        points[remaining[0]],
+        points[remaining[1]],
+        points[remaining[2]],
+    );
+    if get_signed_area(a, b, c) > 0. {
+        triangles.push([face[remaining[0]], face[remaining[1]], face[remaining[2]]]);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec2, Vec3};
+
+    use super::{triangulate_face, TriangulationStats};
+
+    fn get_positions(points: &[Vec2]) -> Vec<Vec3> {
+        points.iter().map(|point| point.extend(0.)).collect()
+    }
+
+    /// Triangles of the face with their signed area in the XY plane
+    fn triangulate(
+        positions: &[Vec3],
+        face: &[u32],
+    ) -> (Vec<[u32; 3]>, Vec<f32>, TriangulationStats) {
+        let mut triangles = Vec::new();
+        let mut stats = TriangulationStats::default();
+        triangulate_face(positions, face, &mut triangles, &mut stats);
+        let areas = triangles
+            .iter()
+            .map(|triangle| {
+                let [a, b, c] = triangle.map(|index| positions[index as usize]);
+                0.5 * (b - a).cross(c - a).z
+            })
+            .collect();
+        (triangles, areas, stats)
+    }
+
+    fn get_total_area(areas: &[f32]) -> f32 {
+        areas.iter().sum()
+    }
+
+    #[test]
+    fn triangle() {
+        let positions = get_positions(&[Vec2::ZERO, Vec2::X, Vec2::Y]);
+        let (triangles, _, stats) = triangulate(&positions, &[0, 1, 2]);
+        assert_eq!(triangles, vec![[0, 1, 2]]);
+        assert_eq!(stats.nb_faces, 1);
+        assert_eq!(stats.nb_polygons, 0);
+        assert_eq!(stats.nb_triangles, 1);
+    }
+
+    #[test]
+    fn convex_quad_keeps_its_winding() {
+        let positions = get_positions(&[Vec2::ZERO, Vec2::X, Vec2::ONE, Vec2::Y]);
+        let (triangles, areas, stats) = triangulate(&positions, &[0, 1, 2, 3]);
+        assert_eq!(triangles.len(), 2);
+        assert!(areas.iter().all(|area| *area > 0.));
+        assert!((get_total_area(&areas) - 1.).abs() < 1e-6);
+        assert_eq!(stats.nb_polygons, 1);
+        assert_eq!(stats.nb_triangles, 2);
+
+        let (triangles, areas, _) = triangulate(&positions, &[3, 2, 1, 0]);
+        assert_eq!(triangles.len(), 2);
+        assert!(areas.iter().all(|area| *area < 0.));
+        assert!((get_total_area(&areas) + 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn concave_polygons() {
+        let l_shape = get_positions(&[
+            Vec2::new(0., 0.),
+            Vec2::new(2., 0.),
+            Vec2::new(2., 1.),
+            Vec2::new(1., 1.),
+            Vec2::new(1., 2.),
+            Vec2::new(0., 2.),
+        ]);
+        let (triangles, areas, stats) = triangulate(&l_shape, &[0, 1, 2, 3, 4, 5]);
+        assert_eq!(triangles.len(), 4);
+        assert!(areas.iter().all(|area| *area > 0.));
+        assert!((get_total_area(&areas) - 3.).abs() < 1e-5);
+        assert_eq!(stats.nb_non_simple_polygons, 0);
+
+        let u_shape = get_positions(&[
+            Vec2::new(0., 0.),
+            Vec2::new(3., 0.),
+            Vec2::new(3., 2.),
+            Vec2::new(2., 2.),
+            Vec2::new(2., 1.),
+            Vec2::new(1., 1.),
+            Vec2::new(1., 2.),
+            Vec2::new(0., 2.),
+        ]);
+        let face: Vec<u32> = (0..8).collect();
+        let (triangles, areas, stats) = triangulate(&u_shape, &face);
+        assert_eq!(triangles.len(), 6);
+        assert!(areas.iter().all(|area| *area > 0.));
+        assert!((get_total_area(&areas) - 5.).abs() < 1e-5);
+        assert_eq!(stats.nb_non_simple_polygons, 0);
+
+        // Clockwise, the triangles are clockwise too
+        let face: Vec<u32> = face.into_iter().rev().collect();
+        let (triangles, areas, _) = triangulate(&u_shape, &face);
+        assert_eq!(triangles.len(), 6);
+        assert!(areas.iter().all(|area| *area < 0.));
+        assert!((get_total_area(&areas) + 5.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn collinear_and_duplicate_vertices() {
+        // A vertex in the middle of the bottom edge
+        let positions = get_positions(&[
+            Vec2::new(0., 0.),
+            Vec2::new(0.5, 0.),
+            Vec2::new(1., 0.),
+            Vec2::new(1., 1.),
+            Vec2::new(0., 1.),
+            Vec2::new(1., 0.),
+        ]);
+        let (triangles, areas, stats) = triangulate(&positions, &[0, 1, 2, 3, 4]);
+        assert!(!triangles.is_empty() && triangles.len() <= 3);
+        assert!(areas.iter().all(|area| *area > 0.));
+        assert!((get_total_area(&areas) - 1.).abs() < 1e-6);
+        assert_eq!(stats.nb_triangles, triangles.len());
+
+        // The same index twice in a row, then two indices of the same point
+        for face in [[0, 2, 2, 3, 4], [0, 2, 5, 3, 4]] {
+            let (triangles, areas, stats) = triangulate(&positions, &face);
+            assert!(!triangles.is_empty());
+            assert!(areas.iter().all(|area| *area > 0.));
+            assert!((get_total_area(&areas) - 1.).abs() < 1e-6);
+            assert_eq!(stats.nb_degenerate_faces, 0);
+        }
+    }
+
+    #[test]
+    fn invalid_faces_are_counted() {
+        let positions = get_positions(&[Vec2::ZERO, Vec2::new(0.5, 0.), Vec2::X, Vec2::Y]);
+        let mut triangles = Vec::new();
+        let mut stats = TriangulationStats::default();
+        // A line, an index out of range and a flat triangle
+        for face in [&[0, 1][..], &[0, 1, 9], &[0, 1, 2]] {
+            triangulate_face(&positions, face, &mut triangles, &mut stats);
+        }
+        assert!(triangles.is_empty());
+        assert_eq!(stats.nb_faces, 3);
+        assert_eq!(stats.nb_skipped_faces, 2);
+        assert_eq!(stats.nb_degenerate_faces, 1);
+        assert_eq!(stats.nb_triangles, 0);
+
+        let mut total = TriangulationStats::default();
+        total.add(&stats);
+        total.add(&stats);
+        assert_eq!(total.nb_faces, 6);
+        assert_eq!(total.nb_skipped_faces, 4);
+        assert_eq!(total.nb_degenerate_faces, 2);
+    }
+
+    #[test]
+    fn self_intersecting_polygon_falls_back_to_a_fan() {
+        let positions = get_positions(&[
+            Vec2::new(3., 2.),
+            Vec2::new(4., 3.),
+            Vec2::new(0., 0.),
+            Vec2::new(4., 2.),
+            Vec2::new(1., 3.),
+            Vec2::new(2., 5.),
+        ]);
+        let (triangles, _, stats) = triangulate(&positions, &[0, 1, 2, 3, 4, 5]);
+        assert_eq!(stats.nb_non_simple_polygons, 1);
+        assert_eq!(triangles.len(), 4);
+        assert_eq!(stats.nb_triangles, 4);
+    }
+}
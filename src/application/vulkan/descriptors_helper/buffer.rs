@@ -88,7 +88,7 @@ impl VulkanContext<'_> {
         Ok(())
     }
 
-    fn copy_buffer_cpu<T>(
+    pub fn copy_buffer_cpu<T>(
         &self,
         dst_buffer: &AllocatedBuffer,
         data: &[T],
@@ -142,7 +142,10 @@ impl VulkanContext<'_> {
         Ok(())
     }
 
-    fn create_staging_buffer(&self, data_size: DeviceSize) -> Result<AllocatedBuffer, ErrorCode> {
+    pub fn create_staging_buffer(
+        &self,
+        data_size: DeviceSize,
+    ) -> Result<AllocatedBuffer, ErrorCode> {
         let allocator = &self.get_allocator()?.allocator;
         match AllocatedBuffer::from_usage(
             allocator,
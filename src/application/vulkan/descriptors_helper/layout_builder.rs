@@ -1,6 +1,7 @@
 use ash::{
     vk::{
-        AllocationCallbacks, DescriptorSetLayout, DescriptorSetLayoutBinding,
+        AllocationCallbacks, DescriptorBindingFlags, DescriptorSetLayout,
+        DescriptorSetLayoutBinding, DescriptorSetLayoutBindingFlagsCreateInfo,
         DescriptorSetLayoutCreateFlags, DescriptorSetLayoutCreateInfo, DescriptorType,
         ShaderStageFlags,
     },
@@ -13,6 +14,8 @@ use crate::application::core::error::ErrorCode;
 #[derive(Default)]
 pub struct DescriptorLayoutBuilder<'a> {
     pub bindings: Vec<DescriptorSetLayoutBinding<'a>>,
+    // One per binding
+    pub binding_flags: Vec<DescriptorBindingFlags>,
 }
 
 impl DescriptorLayoutBuilder<'_> {
@@ -26,12 +29,31 @@ impl DescriptorLayoutBuilder<'_> {
             .descriptor_type(descriptor_type)
             .descriptor_count(1);
         self.bindings.push(new_binding);
+        self.binding_flags.push(DescriptorBindingFlags::empty());
+        Ok(())
+    }
+
+    /// Array of descriptors where only the ones used by the shader have to be written
+    pub fn add_partially_bound_array(
+        &mut self,
+        binding: u32,
+        descriptor_type: DescriptorType,
+        descriptor_count: u32,
+    ) -> Result<(), ErrorCode> {
+        let new_binding = DescriptorSetLayoutBinding::default()
+            .binding(binding)
+            .descriptor_type(descriptor_type)
+            .descriptor_count(descriptor_count);
+        self.bindings.push(new_binding);
+        self.binding_flags
+            .push(DescriptorBindingFlags::PARTIALLY_BOUND);
         Ok(())
     }
 
     #[allow(unused)]
     pub fn clear(&mut self) -> Result<(), ErrorCode> {
         self.bindings.clear();
+        self.binding_flags.clear();
         Ok(())
     }
 
@@ -47,9 +69,12 @@ impl DescriptorLayoutBuilder<'_> {
             binding.stage_flags = shader_stage;
         }
 
+        let mut binding_flags_info =
+            DescriptorSetLayoutBindingFlagsCreateInfo::default().binding_flags(&self.binding_flags);
         let descriptor_set_layout_info = DescriptorSetLayoutCreateInfo::default()
             .bindings(self.bindings.as_slice())
-            .flags(flags);
+            .flags(flags)
+            .push_next(&mut binding_flags_info);
 
         match unsafe {
             device.create_descriptor_set_layout(&descriptor_set_layout_info, allocation_callback)
@@ -2,3 +2,4 @@ pub mod allocator;
 pub mod buffer;
 pub mod images;
 pub mod layout_builder;
+pub mod texture_image;
@@ -0,0 +1,254 @@
+use ash::vk::{
+    BorderColor, BufferImageCopy, CompareOp, Extent3D, Filter, Format, ImageAspectFlags,
+    ImageCreateInfo, ImageLayout, ImageSubresourceLayers, ImageTiling, ImageType, ImageUsageFlags,
+    ImageViewCreateInfo, ImageViewType, SampleCountFlags, Sampler, SamplerAddressMode,
+    SamplerCreateInfo, SamplerMipmapMode,
+};
+use log::error;
+use vk_mem::{Alloc, AllocationCreateInfo, MemoryUsage};
+
+use crate::application::{
+    core::error::ErrorCode,
+    scene::texture::Texture,
+    vulkan::{setup::draw_resources::AllocatedImage, types::VulkanContext},
+};
+
+use super::images::{get_default_image_subresource_range, transition_image};
+
+impl AllocatedImage {
+    pub fn clean(&mut self, vulkan_context: &VulkanContext) -> Result<(), ErrorCode> {
+        unsafe {
+            vulkan_context
+                .get_device()?
+                .destroy_image_view(self.image_view, vulkan_context.get_allocation_callback()?);
+        }
+        let allocator = vulkan_context.get_allocator()?.allocator.lock().unwrap();
+        unsafe { allocator.destroy_image(self.image, &mut self.allocation) };
+        Ok(())
+    }
+}
+
+impl VulkanContext<'_> {
+    /// Upload a texture in an image that can only be sampled by the shaders
+    pub fn map_texture_to_image(&self, texture: &Texture) -> Result<AllocatedImage, ErrorCode> {
+        // The colour maps are decoded from sRGB by the sampler, like `Texture::sample`
+        let image_format = if texture.is_srgb {
+            Format::R8G8B8A8_SRGB
+        } else {
+            Format::R8G8B8A8_UNORM
+        };
+        self.map_pixels_to_image(texture.width, texture.height, &texture.pixels, image_format)
+    }
+
+    /// Upload the pixels of an image of `image_format`, the rows go from top to bottom
+    pub fn map_pixels_to_image<T>(
+        &self,
+        width: u32,
+        height: u32,
+        pixels: &[T],
+        image_format: Format,
+    ) -> Result<AllocatedImage, ErrorCode> {
+        let image_extent = Extent3D::default().width(width).height(height).depth(1);
+
+        // Copy the texels to a staging buffer (CPU side)
+        let data_size = size_of_val(pixels);
+        let mut staging_buffer = self.create_staging_buffer(data_size as u64)?;
+        if let Err(err) = self.copy_buffer_cpu(&staging_buffer, pixels, data_size) {
+            error!("Failed to copy a texture to the staging buffer: {:?}", err);
+            return Err(ErrorCode::Unknown);
+        }
+
+        // Create the GPU side image
+        let image_create_info = ImageCreateInfo::default()
+            .image_type(ImageType::TYPE_2D)
+            .format(image_format)
+            .extent(image_extent)
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(SampleCountFlags::TYPE_1)
+            .tiling(ImageTiling::OPTIMAL)
+            .usage(ImageUsageFlags::SAMPLED | ImageUsageFlags::TRANSFER_DST);
+        let allocation_info = AllocationCreateInfo {
+            usage: MemoryUsage::AutoPreferDevice,
+            ..Default::default()
+        };
+        let (image, allocation) = {
+            let allocator = self.get_allocator()?.allocator.lock().unwrap();
+            match unsafe { allocator.create_image(&image_create_info, &allocation_info) } {
+                Ok((image, allocation)) => (image, allocation),
+                Err(err) => {
+                    error!("Failed to allocate a texture image: {:?}", err);
+                    return Err(ErrorCode::VulkanFailure);
+                }
+            }
+        };
+        let image_view_create_info = ImageViewCreateInfo::default()
+            .view_type(ImageViewType::TYPE_2D)
+            .image(image)
+            .format(image_format)
+            .subresource_range(get_default_image_subresource_range());
+        let image_view = match unsafe {
+            self.get_device()?
+                .create_image_view(&image_view_create_info, self.get_allocation_callback()?)
+        } {
+            Ok(image_view) => image_view,
+            Err(err) => {
+                error!("Failed to create a texture image view: {:?}", err);
+                return Err(ErrorCode::VulkanFailure);
+            }
+        };
+        let texture_image = AllocatedImage {
+            image,
+            image_view,
+            image_extent,
+            image_format,
+            allocation,
+        };
+
+        // Copy the staging buffer to the image (GPU side) and leave it ready to be sampled
+        if let Err(err) = self.immediate_submit(&|vulkan_context, cmd| {
+            let device = vulkan_context.get_device()?;
+            transition_image(
+                device,
+                &cmd,
+                &texture_image.image,
+                ImageLayout::UNDEFINED,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+            )?;
+            let regions = [BufferImageCopy::default()
+                .image_subresource(
+                    ImageSubresourceLayers::default()
+                        .aspect_mask(ImageAspectFlags::COLOR)
+                        .mip_level(0)
+                        .base_array_layer(0)
+                        .layer_count(1),
+                )
+                .image_extent(image_extent)];
+            unsafe {
+                device.cmd_copy_buffer_to_image(
+                    cmd,
+                    staging_buffer.buffer,
+                    texture_image.image,
+                    ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &regions,
+                )
+            };
+            transition_image(
+                device,
+                &cmd,
+                &texture_image.image,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )
+        }) {
+            error!(
+                "Failed to send an immediate submit command when uploading a texture: {:?}",
+                err
+            );
+            return Err(ErrorCode::Unknown);
+        }
+
+        // Clean the staging buffer
+        let allocator = &self.get_allocator()?.allocator;
+        if let Err(err) = staging_buffer.clean(allocator) {
+            error!("Failed to clean the staging buffer of a texture: {:?}", err);
+            return Err(ErrorCode::CleaningFailure);
+        }
+        Ok(texture_image)
+    }
+
+    /// Image read and written by the compute shaders, left in the general layout
+    pub fn create_storage_image(
+        &self,
+        image_extent: Extent3D,
+        image_format: Format,
+    ) -> Result<AllocatedImage, ErrorCode> {
+        let image_create_info = ImageCreateInfo::default()
+            .image_type(ImageType::TYPE_2D)
+            .format(image_format)
+            .extent(image_extent)
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(SampleCountFlags::TYPE_1)
+            .tiling(ImageTiling::OPTIMAL)
+            .usage(ImageUsageFlags::STORAGE);
+        let allocation_info = AllocationCreateInfo {
+            usage: MemoryUsage::AutoPreferDevice,
+            ..Default::default()
+        };
+        let (image, allocation) = {
+            let allocator = self.get_allocator()?.allocator.lock().unwrap();
+            match unsafe { allocator.create_image(&image_create_info, &allocation_info) } {
+                Ok((image, allocation)) => (image, allocation),
+                Err(err) => {
+                    error!("Failed to allocate a storage image: {:?}", err);
+                    return Err(ErrorCode::VulkanFailure);
+                }
+            }
+        };
+        let image_view_create_info = ImageViewCreateInfo::default()
+            .view_type(ImageViewType::TYPE_2D)
+            .image(image)
+            .format(image_format)
+            .subresource_range(get_default_image_subresource_range());
+        let image_view = match unsafe {
+            self.get_device()?
+                .create_image_view(&image_view_create_info, self.get_allocation_callback()?)
+        } {
+            Ok(image_view) => image_view,
+            Err(err) => {
+                error!("Failed to create a storage image view: {:?}", err);
+                return Err(ErrorCode::VulkanFailure);
+            }
+        };
+        let storage_image = AllocatedImage {
+            image,
+            image_view,
+            image_extent,
+            image_format,
+            allocation,
+        };
+
+        if let Err(err) = self.immediate_submit(&|vulkan_context, cmd| {
+            transition_image(
+                vulkan_context.get_device()?,
+                &cmd,
+                &storage_image.image,
+                ImageLayout::UNDEFINED,
+                ImageLayout::GENERAL,
+            )
+        }) {
+            error!(
+                "Failed to send an immediate submit command when creating a storage image: {:?}",
+                err
+            );
+            return Err(ErrorCode::Unknown);
+        }
+        Ok(storage_image)
+    }
+
+    /// Bilinear filtering with repeated texture coordinates, see `Texture::sample` for the
+    /// CPU side
+    pub fn create_texture_sampler(&self) -> Result<Sampler, ErrorCode> {
+        let sampler_create_info = SamplerCreateInfo::default()
+            .mag_filter(Filter::LINEAR)
+            .min_filter(Filter::LINEAR)
+            .mipmap_mode(SamplerMipmapMode::NEAREST)
+            .address_mode_u(SamplerAddressMode::REPEAT)
+            .address_mode_v(SamplerAddressMode::REPEAT)
+            .address_mode_w(SamplerAddressMode::REPEAT)
+            .compare_op(CompareOp::NEVER)
+            .border_color(BorderColor::FLOAT_OPAQUE_WHITE)
+            .max_lod(0.);
+        match unsafe {
+            self.get_device()?
+                .create_sampler(&sampler_create_info, self.get_allocation_callback()?)
+        } {
+            Ok(sampler) => Ok(sampler),
+            Err(err) => {
+                error!("Failed to create the texture sampler: {:?}", err);
+                Err(ErrorCode::VulkanFailure)
+            }
+        }
+    }
+}
@@ -1,10 +1,16 @@
-use std::{cmp::Ordering, sync::Arc, time::Duration};
+use std::{
+    cmp::Ordering,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use ash::vk::{
     AttachmentLoadOp, AttachmentStoreOp, DescriptorPool, DescriptorPoolCreateFlags,
     DescriptorPoolCreateInfo, DescriptorPoolSize, DescriptorType, ImageLayout, Offset2D, Rect2D,
     RenderingAttachmentInfo, RenderingInfo,
 };
+use glam::Vec3;
 use imgui::*;
 use imgui_rs_vulkan_renderer::*;
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
@@ -16,12 +22,70 @@ use winit::{
 
 use crate::application::{
     core::error::ErrorCode,
-    scene::{bvh::BvhType, Scene},
+    raytracer::{
+        heatmap::{get_heatmap_color, HeatmapType},
+        integrator::{DebugAov, Integrator},
+    },
+    scene::{
+        bvh::BvhType,
+        light::{Light, LightType},
+        material::{Brdf, Material, NO_TEXTURE},
+        Scene,
+    },
     window::key_map::winit_character_to_imgui_key,
 };
 
 use super::{setup::frame_data::FRAME_OVERLAP, types::VulkanContext};
 
+/// Bvhs that can be selected in the gui
+const BVH_TYPES: [(&str, BvhType); 6] = [
+    ("None", BvhType::None),
+    ("Default Top Down", BvhType::DefaultTopDown),
+    ("Default Bottom Up", BvhType::DefaultBottomUp),
+    // ("Bottom Up Sah", BvhType::BottomUpSah),
+    ("Top Down Sah", BvhType::TopDownSah),
+    ("Ploc", BvhType::Ploc),
+    ("Ploc Parallel", BvhType::PlocParallel),
+];
+
+const BRDFS: [(&str, Brdf); 5] = [
+    ("Lambert", Brdf::Lambert),
+    ("GGX", Brdf::Ggx),
+    ("Mirror", Brdf::Mirror),
+    ("Glass", Brdf::Dielectric),
+    ("Rough glass", Brdf::RoughDielectric),
+];
+const MAX_INDEX_OF_REFRACTION: f32 = 3.;
+
+const INTEGRATORS: [(&str, Integrator); 5] = [
+    ("Direct lighting", Integrator::Direct),
+    ("Ambient occlusion", Integrator::AmbientOcclusion),
+    ("Whitted", Integrator::Whitted),
+    ("Path tracing", Integrator::PathTracing),
+    ("Debug", Integrator::Debug),
+];
+
+const DEBUG_AOVS: [(&str, DebugAov); 4] = [
+    ("Normal", DebugAov::Normal),
+    ("Albedo", DebugAov::Albedo),
+    ("Depth", DebugAov::Depth),
+    ("Roughness", DebugAov::Roughness),
+];
+
+/// Highest depth of the sliders, the paths are usually stopped before by the russian roulette
+const MAX_INTEGRATOR_DEPTH: u32 = 16;
+const MAX_AO_SAMPLES: u32 = 64;
+/// Range of the exposure slider of the environment map, in stops
+const MAX_ENVIRONMENT_EXPOSURE: f32 = 10.;
+
+const LIGHT_TYPES: [&str; 4] = ["Directional", "Point", "Spot", "Area"];
+
+const HEATMAP_TYPES: [(&str, HeatmapType); 3] = [
+    ("Shading", HeatmapType::None),
+    ("Node tests", HeatmapType::NodeTests),
+    ("Triangle tests", HeatmapType::TriangleTests),
+];
+
 #[derive(Default)]
 pub struct GuiWrapper {
     pub descriptor_pool: DescriptorPool,
@@ -220,61 +284,310 @@ impl VulkanContext<'_> {
             .build(|| {
                 ui.checkbox("Toogle wireframe mode", &mut scene.is_wireframe_on);
                 ui.new_line();
+                ui.text("Integrator");
+                let settings = &mut scene.integrator_settings;
+                for (label, integrator) in INTEGRATORS {
+                    ui.radio_button(label, &mut settings.integrator, integrator);
+                }
+                match settings.integrator {
+                    Integrator::Direct => (),
+                    Integrator::AmbientOcclusion => {
+                        ui.slider("samples", 1, MAX_AO_SAMPLES, &mut settings.ao_nb_samples);
+                        ui.slider("radius", 0.01, 10., &mut settings.ao_radius);
+                    }
+                    Integrator::Whitted => {
+                        ui.slider(
+                            "max depth##whitted",
+                            0,
+                            MAX_INTEGRATOR_DEPTH,
+                            &mut settings.whitted_max_depth,
+                        );
+                    }
+                    Integrator::PathTracing => {
+                        ui.slider(
+                            "max depth##path",
+                            0,
+                            MAX_INTEGRATOR_DEPTH,
+                            &mut settings.max_depth,
+                        );
+                    }
+                    Integrator::Debug => {
+                        for (label, debug_aov) in DEBUG_AOVS {
+                            ui.radio_button(label, &mut settings.debug_aov, debug_aov);
+                        }
+                        if settings.debug_aov == DebugAov::Depth {
+                            ui.slider("max distance", 0.1, 100., &mut settings.debug_max_distance);
+                        }
+                    }
+                }
+                ui.text(format!(
+                    "Accumulated frames: {}",
+                    scene.nb_accumulated_frames + 1
+                ));
+                if let Some(environment) = &scene.environment {
+                    ui.new_line();
+                    ui.text("Environment");
+                    ui.text_wrapped(environment.path.to_string_lossy());
+                    let settings = &mut scene.environment_settings;
+                    ui.checkbox("enabled##environment", &mut settings.is_enabled);
+                    ui.slider("rotation##environment", -180., 180., &mut settings.rotation);
+                    ui.slider(
+                        "exposure##environment",
+                        -MAX_ENVIRONMENT_EXPOSURE,
+                        MAX_ENVIRONMENT_EXPOSURE,
+                        &mut settings.exposure,
+                    );
+                }
+                ui.new_line();
                 ui.text("BVH type");
 
-                let disabled = !scene.bvhs.contains_key(&BvhType::None);
-                let end = ui.begin_disabled(disabled);
-                ui.radio_button("None", &mut scene.bvh_type, BvhType::None);
-                end.end();
-
-                let disabled = !scene.bvhs.contains_key(&BvhType::DefaultTopDown);
-                let end = ui.begin_disabled(disabled);
-                ui.radio_button(
-                    "Default Top Down",
-                    &mut scene.bvh_type,
-                    BvhType::DefaultTopDown,
-                );
-                end.end();
-
-                let disabled = !scene.bvhs.contains_key(&BvhType::DefaultBottomUp);
-                let end = ui.begin_disabled(disabled);
-                ui.radio_button(
-                    "Default Bottom Up",
-                    &mut scene.bvh_type,
-                    BvhType::DefaultBottomUp,
-                );
-                end.end();
-
-                // let disabled = !scene.bvhs.contains_key(&BvhType::BottomUpSah);
-                // let end = ui.begin_disabled(disabled);
-                // ui.radio_button("Bottom Up Sah", &mut scene.bvh_type, BvhType::BottomUpSah);
-                // end.end();
-
-                let disabled = !scene.bvhs.contains_key(&BvhType::TopDownSah);
-                let end = ui.begin_disabled(disabled);
-                ui.radio_button("Top Down Sah", &mut scene.bvh_type, BvhType::TopDownSah);
-                end.end();
-
-                let disabled = !scene.bvhs.contains_key(&BvhType::Ploc);
-                let end = ui.begin_disabled(disabled);
-                ui.radio_button("Ploc", &mut scene.bvh_type, BvhType::Ploc);
-                end.end();
-
-                let disabled = !scene.bvhs.contains_key(&BvhType::PlocParallel);
-                let end = ui.begin_disabled(disabled);
-                ui.radio_button("Ploc Parallel", &mut scene.bvh_type, BvhType::PlocParallel);
-                end.end();
-
+                // Selecting a bvh that is not built yet starts building it
+                for (label, bvh_type) in BVH_TYPES {
+                    ui.radio_button(label, &mut scene.bvh_type, bvh_type);
+                    if let Some(progress) = scene.get_bvh_build_progress(bvh_type) {
+                        ProgressBar::new(progress)
+                            .size([100., 0.])
+                            .overlay_text(format!("{:.0}%", 100. * progress))
+                            .build(ui);
+                        ui.same_line();
+                        if ui.button(format!("Cancel##{:?}", bvh_type)) {
+                            scene.cancel_bvh(bvh_type);
+                        }
+                    } else if let Some(time) = scene.bvhs_build_times.get(&bvh_type) {
+                        ui.same_line();
+                        ui.text(format!("({:.3}s)", time.as_secs_f32()));
+                    }
+                }
                 ui.new_line();
                 ui.checkbox("Display Bvh", &mut scene.should_display_bvh);
                 ui.new_line();
                 ui.text("Bvh depth to display");
-                ui.slider(
-                    "depth",
+                let max_bvh_depth = scene.get_max_bvh_depth();
+                ui.slider("depth", 0, max_bvh_depth, &mut scene.bvh_depth_to_display);
+
+                ui.new_line();
+                ui.text("Heatmap");
+                for (label, heatmap_type) in HEATMAP_TYPES {
+                    ui.radio_button(label, &mut scene.heatmap_type, heatmap_type);
+                }
+                if scene.heatmap_type != HeatmapType::None {
+                    // Legend
+                    let [x, y] = ui.cursor_screen_pos();
+                    let (legend_width, legend_height) = (150., 10.);
+                    let nb_segments = 4;
+                    let draw_list = ui.get_window_draw_list();
+                    for segment in 0..nb_segments {
+                        let t_start = segment as f32 / nb_segments as f32;
+                        let t_end = (segment + 1) as f32 / nb_segments as f32;
+                        let color_start = get_heatmap_color(t_start).extend(1.).to_array();
+                        let color_end = get_heatmap_color(t_end).extend(1.).to_array();
+                        draw_list.add_rect_filled_multicolor(
+                            [x + t_start * legend_width, y],
+                            [x + t_end * legend_width, y + legend_height],
+                            color_start,
+                            color_end,
+                            color_end,
+                            color_start,
+                        );
+                    }
+                    ui.dummy([legend_width, legend_height]);
+                    ui.text(format!(
+                        "Min:{}\nAvg:{:.1}\nMax:{}",
+                        scene.heatmap_stats.min, scene.heatmap_stats.avg, scene.heatmap_stats.max,
+                    ));
+                }
+                if ui.button("Save CPU heatmap") {
+                    let file_name = format!("heatmap_{:?}.ppm", scene.bvh_last_type).to_lowercase();
+                    let _ = scene.save_cpu_heatmap(Path::new(&file_name), 800);
+                }
+                ui.same_line();
+                if ui.button("Save CPU render") {
+                    let _ = scene.save_cpu_render(Path::new("render.ppm"), 800);
+                }
+
+                ui.new_line();
+                ui.text("Bvh export depths");
+                if ui.slider(
+                    "min##export",
                     0,
-                    scene.get_max_bvh_detph(),
-                    &mut scene.bvh_depth_to_display,
+                    max_bvh_depth,
+                    &mut scene.bvh_export_min_depth,
+                ) {
+                    scene.bvh_export_max_depth =
+                        scene.bvh_export_max_depth.max(scene.bvh_export_min_depth);
+                }
+                ui.slider(
+                    "max##export",
+                    scene.bvh_export_min_depth,
+                    max_bvh_depth,
+                    &mut scene.bvh_export_max_depth,
                 );
+                let file_name = format!("bvh_{:?}", scene.bvh_last_type).to_lowercase();
+                if ui.button("Export OBJ") {
+                    let _ = scene.export_bvh_obj(Path::new(&format!("{}.obj", file_name)));
+                }
+                ui.same_line();
+                if ui.button("Export JSON") {
+                    let _ = scene.export_bvh_json(Path::new(&format!("{}.json", file_name)));
+                }
+
+                ui.new_line();
+                ui.text("Scene edit");
+                // New primitives are put in front of the camera
+                let position = scene.camera.eye + 10. * scene.camera.at;
+                if ui.button("Add sphere") {
+                    let _ = scene.add_sphere(position, 1., Some(Material::random()));
+                }
+                ui.same_line();
+                if ui.button("Add box") {
+                    let _ = scene.add_cuboid(position, Vec3::ONE, Some(Material::random()));
+                }
+                if !scene.models.is_empty() {
+                    let last_model_index = scene.models.len() as u32 - 1;
+                    scene.edited_model_index = scene.edited_model_index.min(last_model_index);
+                    ui.slider("model", 0, last_model_index, &mut scene.edited_model_index);
+                    let model_index = scene.edited_model_index as usize;
+                    if ui.button("Random material") {
+                        let _ = scene.set_material(model_index, Material::random());
+                    }
+                    // Editing the default material gives the model its own copy
+                    let material_index = scene.models[model_index].material_index as usize;
+                    let mut material = scene.materials[material_index];
+                    let mut brdf = material.get_brdf();
+                    let mut is_material_edited = false;
+                    for (label, value) in BRDFS {
+                        is_material_edited |= ui.radio_button(label, &mut brdf, value);
+                        ui.same_line();
+                    }
+                    ui.new_line();
+                    if brdf == Brdf::Ggx {
+                        is_material_edited |= ui.slider("metallic", 0., 1., &mut material.metallic);
+                        is_material_edited |=
+                            ui.slider("roughness", 0., 1., &mut material.roughness);
+                        is_material_edited |=
+                            ui.slider("specular", 0., 1., &mut material.specular_factor);
+                    }
+                    if brdf.is_dielectric() {
+                        is_material_edited |= ui.slider(
+                            "ior",
+                            1.,
+                            MAX_INDEX_OF_REFRACTION,
+                            &mut material.index_of_refraction,
+                        );
+                        if brdf == Brdf::RoughDielectric {
+                            is_material_edited |=
+                                ui.slider("roughness##glass", 0., 1., &mut material.roughness);
+                        }
+                        // Per unit of distance inside, following Beer's law
+                        let mut absorption = material.absorption.truncate().to_array();
+                        if ui.input_float3("absorption", &mut absorption).build() {
+                            material.absorption =
+                                Vec3::from_array(absorption).max(Vec3::ZERO).extend(1.);
+                            is_material_edited = true;
+                        }
+                    }
+                    // Blended with the layers behind it, and lets the shadow rays through
+                    is_material_edited |= ui.slider("opacity", 0., 1., &mut material.dissolve);
+                    if material.dissolve_texture != NO_TEXTURE {
+                        is_material_edited |=
+                            ui.slider("alpha cutoff", 0., 1., &mut material.alpha_cutoff);
+                    }
+                    // Its triangles become area lights
+                    let mut emission = material.emission.truncate().to_array();
+                    if ui.input_float3("emission", &mut emission).build() {
+                        material.emission = Vec3::from_array(emission).max(Vec3::ZERO).extend(1.);
+                        is_material_edited = true;
+                    }
+                    if is_material_edited {
+                        material.brdf = brdf as u32;
+                        let _ = scene.set_material(model_index, material);
+                    }
+                    if ui.button("Move in front") {
+                        let _ = scene.move_model(model_index, position);
+                    }
+                    ui.same_line();
+                    if ui.button("Remove") {
+                        let _ = scene.remove_model(model_index);
+                    }
+                }
+
+                ui.new_line();
+                ui.text("Lights");
+                if ui.button("Add light") {
+                    let light = Light::point(position, 100.);
+                    scene.edited_light_index = scene.add_light(light) as u32;
+                }
+                if !scene.lights.is_empty() {
+                    let last_light_index = scene.lights.len() as u32 - 1;
+                    scene.edited_light_index = scene.edited_light_index.min(last_light_index);
+                    ui.slider("light", 0, last_light_index, &mut scene.edited_light_index);
+                    let light_index = scene.edited_light_index as usize;
+                    let mut light = scene.lights[light_index];
+                    let mut light_type = light.light_type.to_u32();
+                    let mut is_light_edited = false;
+                    for (value, label) in LIGHT_TYPES.iter().enumerate() {
+                        if ui.radio_button(label, &mut light_type, value as u32) {
+                            light.light_type =
+                                LightType::from_u32(light_type).unwrap_or(light.light_type);
+                            is_light_edited = true;
+                        }
+                        ui.same_line();
+                    }
+                    ui.new_line();
+                    let mut position = light.position.to_array();
+                    let mut direction = light.direction.to_array();
+                    let mut color = light.color.to_array();
+                    let mut range = light.range.unwrap_or(0.);
+                    if light.light_type != LightType::Directional {
+                        is_light_edited |=
+                            ui.input_float3("position##light", &mut position).build();
+                    }
+                    if light.light_type != LightType::Point {
+                        is_light_edited |=
+                            ui.input_float3("direction##light", &mut direction).build();
+                    }
+                    is_light_edited |= ui.color_edit3("color##light", &mut color);
+                    is_light_edited |= ui
+                        .input_float("intensity##light", &mut light.intensity)
+                        .build();
+                    match &mut light.light_type {
+                        LightType::Spot {
+                            inner_cone_angle,
+                            outer_cone_angle,
+                        } => {
+                            is_light_edited |= ui
+                                .slider_config("inner cone", 0., *outer_cone_angle)
+                                .display_format("%.2f rad")
+                                .build(inner_cone_angle);
+                            is_light_edited |= ui
+                                .slider_config(
+                                    "outer cone",
+                                    *inner_cone_angle,
+                                    std::f32::consts::FRAC_PI_2,
+                                )
+                                .display_format("%.2f rad")
+                                .build(outer_cone_angle);
+                        }
+                        LightType::Area { radius } => {
+                            is_light_edited |= ui.input_float("radius##light", radius).build();
+                        }
+                        LightType::Directional | LightType::Point => (),
+                    }
+                    if light.light_type != LightType::Directional {
+                        // 0 for an infinite range
+                        is_light_edited |= ui.input_float("range##light", &mut range).build();
+                    }
+                    if is_light_edited {
+                        light.position = Vec3::from_array(position);
+                        light.direction = Vec3::from_array(direction);
+                        light.color = Vec3::from_array(color);
+                        light.range = (range > 0.).then_some(range);
+                        let _ = scene.set_light(light_index, light);
+                    }
+                    if ui.button("Remove light") {
+                        let _ = scene.remove_light(light_index);
+                    }
+                }
 
                 ui.new_line();
                 ui.text(format!(
@@ -288,6 +601,61 @@ impl VulkanContext<'_> {
                 ));
             });
 
+        ui.window("Load model")
+            .size([300.0, 300.0], imgui::Condition::FirstUseEver)
+            .position([220.0, 0.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                let parameters = &mut scene.model_load_parameters;
+                // Relative paths are looked for in the asset search directories
+                ui.input_text("path", &mut parameters.path).build();
+                if ui.button("Refresh") {
+                    parameters.has_scanned_files = false;
+                }
+                if !parameters.has_scanned_files {
+                    parameters.has_scanned_files = true;
+                    parameters.file_names = scene
+                        .asset_resolver
+                        .get_file_names(&["obj", "ply", "stl", "gltf", "glb"])
+                        .iter()
+                        .map(|file_name| file_name.to_string_lossy().into_owned())
+                        .collect();
+                    parameters.file_index = parameters
+                        .file_index
+                        .min(parameters.file_names.len().saturating_sub(1));
+                }
+                if ui.combo_simple_string(
+                    "browse",
+                    &mut parameters.file_index,
+                    &parameters.file_names,
+                ) {
+                    parameters.path = parameters.file_names[parameters.file_index].clone();
+                }
+
+                ui.input_float3("translation", &mut parameters.translation)
+                    .build();
+                ui.input_float3("rotation", &mut parameters.rotation)
+                    .build();
+                ui.input_float("scale", &mut parameters.scale).build();
+                ui.checkbox("uniform color", &mut parameters.has_uniform_color);
+                if parameters.has_uniform_color {
+                    ui.color_edit3("color", &mut parameters.color);
+                }
+
+                if ui.button("Add to the scene") {
+                    let path = PathBuf::from(&parameters.path);
+                    let model_matrix = parameters.get_model_matrix();
+                    let material = parameters.get_material();
+                    let status = match scene.add_model_file(&path, model_matrix, material) {
+                        Ok(model_indices) => {
+                            format!("Added {} models", model_indices.len())
+                        }
+                        Err(err) => format!("Failed to load the model: {:?}", err),
+                    };
+                    scene.model_load_parameters.status = status;
+                }
+                ui.text_wrapped(&scene.model_load_parameters.status);
+            });
+
         self.gui
             .platform
             .as_mut()
@@ -9,6 +9,7 @@ use winit::window::Window;
 use crate::application::{
     core::error::ErrorCode,
     pipelines::{compute_pipeline::ComputePipeline, Pipelines},
+    raytracer::heatmap::HeatmapType,
     scene::Scene,
 };
 
@@ -110,8 +111,14 @@ impl VulkanContext<'_> {
     fn prepare_raytracing_command(
         &self,
         pipelines: &mut Pipelines,
-        scene: &Scene,
+        scene: &mut Scene,
     ) -> Result<(), ErrorCode> {
+        // The stats of the last frame using this slot are ready since its fence was waited for
+        if scene.heatmap_type != HeatmapType::None {
+            if let Some(stats) = pipelines.raytracing_pipeline.read_heatmap_stats(self)? {
+                scene.heatmap_stats = stats;
+            }
+        }
         pipelines.raytracing_pipeline.run(self, scene)
     }
 
@@ -7,7 +7,7 @@ use log::error;
 use crate::application::{core::error::ErrorCode, vulkan::types::VulkanContext};
 
 impl VulkanContext<'_> {
-    fn get_device_queue_create_infos(&self) -> Result<Vec<DeviceQueueCreateInfo>, ErrorCode> {
+    fn get_device_queue_create_infos(&self) -> Result<Vec<DeviceQueueCreateInfo<'_>>, ErrorCode> {
         // NOTE: do not create additional queues for shared indices
         let present_shares_graphics_queue =
             self.get_queues()?.graphics_family_index == self.get_queues()?.present_family_index;
@@ -24,7 +24,9 @@ impl Default for DeviceRequirements<'_> {
             .shader_clip_distance(true);
         let features_12 = PhysicalDeviceVulkan12Features::default()
             .buffer_device_address(true)
-            .descriptor_indexing(true);
+            .descriptor_indexing(true)
+            .shader_sampled_image_array_non_uniform_indexing(true)
+            .descriptor_binding_partially_bound(true);
         let features_13 = PhysicalDeviceVulkan13Features::default()
             .synchronization2(true)
             .dynamic_rendering(true);
@@ -56,7 +58,7 @@ impl VulkanContext<'_> {
         Ok(())
     }
 
-    pub fn get_device_requirements(&self) -> Result<&DeviceRequirements, ErrorCode> {
+    pub fn get_device_requirements(&self) -> Result<&DeviceRequirements<'_>, ErrorCode> {
         match &self.device_requirements {
             Some(requirements) => Ok(requirements),
             None => {
@@ -11,6 +11,10 @@ use super::key_map::{Key, KeyState};
 
 impl Application<'_> {
     pub fn on_exit(&mut self) -> Result<(), ErrorCode> {
+        if let Some(scene) = &mut self.scene {
+            scene.cancel_bvh_jobs();
+        }
+
         if let Some(vulkan_context) = &mut self.vulkan_context {
             if let Some(pipelines) = &mut self.pipelines {
                 if let Err(err) = pipelines.clean(vulkan_context) {
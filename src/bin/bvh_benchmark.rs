@@ -0,0 +1,246 @@
+//! Compare the bvh builders on every model of `src/assets/models`
+//!
+//! cargo run --release --bin bvh_benchmark [path to the output csv]
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use glam::{Vec2, Vec3};
+use log::{error, warn};
+use rayon::prelude::*;
+use rust_vulkan::application::{
+    core::error::ErrorCode,
+    raytracer::{ray::Ray, CpuRaytracer, TraversalStats},
+    scene::{
+        assets::AssetResolver,
+        bvh::{build_job::BvhBuildProgress, BvhType},
+        Scene,
+    },
+};
+
+// `BvhType::DefaultBottomUp` is left out, it builds the same bvh as `BvhType::DefaultTopDown`
+const BVH_TYPES: [BvhType; 5] = [
+    BvhType::None,
+    BvhType::DefaultTopDown,
+    BvhType::TopDownSah,
+    BvhType::Ploc,
+    BvhType::PlocParallel,
+];
+
+/// Number of primary rays along each side of the image
+const IMAGE_SIZE: u32 = 128;
+
+const DEFAULT_CSV_PATH: &str = "bvh_benchmark.csv";
+
+struct BenchmarkResult {
+    model: String,
+    nb_triangles: usize,
+    bvh_type: BvhType,
+    nb_nodes: usize,
+    build_time: Duration,
+    // None when there is no bvh
+    sah_cost: Option<f32>,
+    trace_time: Duration,
+    stats: TraversalStats,
+}
+
+impl BenchmarkResult {
+    fn get_rays_per_second(&self) -> f64 {
+        self.stats.nb_rays as f64 / self.trace_time.as_secs_f64()
+    }
+}
+
+fn get_light_position(scene: &Scene) -> Result<Vec3, ErrorCode> {
+    let aabb = scene.get_aabb()?;
+    let center = 0.5 * (aabb.mins + aabb.maxs);
+    let radius = 0.5 * (aabb.maxs - aabb.mins).length();
+    Ok(center + Vec3::new(radius, 2. * radius, -2. * radius))
+}
+
+/// Trace one primary ray per pixel and one shadow ray per primary hit
+fn trace_rays(raytracer: &CpuRaytracer, scene: &Scene, light_position: Vec3) -> TraversalStats {
+    let camera = scene.camera.get_gpu_data();
+    (0..IMAGE_SIZE)
+        .into_par_iter()
+        .map(|y| {
+            let mut stats = TraversalStats::default();
+            for x in 0..IMAGE_SIZE {
+                let position = (Vec2::new(x as f32, y as f32) + 0.5) / IMAGE_SIZE as f32;
+                let ray = Ray::from_camera(position, &camera);
+                if let Some(hit) = raytracer.get_closest_hit(&ray, f32::INFINITY, &mut stats) {
+                    let hit_position = ray.at(hit.distance);
+                    let to_light = light_position - hit_position;
+                    let shadow_ray = Ray::new(hit_position, to_light, true);
+                    let _ = raytracer.is_occluded(&shadow_ray, to_light.length(), &mut stats);
+                }
+            }
+            stats
+        })
+        .reduce(TraversalStats::default, TraversalStats::merge)
+}
+
+fn benchmark(
+    model: &str,
+    scene: &Scene,
+    light_position: Vec3,
+    bvh_type: BvhType,
+) -> Result<BenchmarkResult, ErrorCode> {
+    let start = Instant::now();
+    let bvh = match scene.build_bvh(bvh_type, &BvhBuildProgress::default()) {
+        Ok(bvh) => bvh,
+        Err(err) => {
+            error!(
+                "Failed to build the `{:?}' bvh for `{}': {:?}",
+                bvh_type, model, err
+            );
+            return Err(ErrorCode::Unknown);
+        }
+    };
+    let build_time = start.elapsed();
+
+    let sah_cost = bvh
+        .first()
+        .map(|root| root.get_sah_cost(&bvh, &scene.sah_constants));
+
+    let raytracer = CpuRaytracer::new(scene, &bvh);
+    let start = Instant::now();
+    let stats = trace_rays(&raytracer, scene, light_position);
+    let trace_time = start.elapsed();
+
+    Ok(BenchmarkResult {
+        model: model.to_string(),
+        nb_triangles: scene.mesh.triangles.len(),
+        bvh_type,
+        nb_nodes: bvh.len(),
+        build_time,
+        sah_cost,
+        trace_time,
+        stats,
+    })
+}
+
+fn print_table(results: &[BenchmarkResult]) {
+    println!(
+        "{:<20} {:>10} {:<16} {:>10} {:>12} {:>10} {:>12} {:>12} {:>12}",
+        "model",
+        "triangles",
+        "bvh",
+        "nodes",
+        "build (ms)",
+        "sah cost",
+        "Mrays/s",
+        "nodes/ray",
+        "tris/ray"
+    );
+    for result in results {
+        let sah_cost = match result.sah_cost {
+            Some(sah_cost) => format!("{:.2}", sah_cost),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:<20} {:>10} {:<16} {:>10} {:>12.2} {:>10} {:>12.3} {:>12.2} {:>12.2}",
+            result.model,
+            result.nb_triangles,
+            format!("{:?}", result.bvh_type),
+            result.nb_nodes,
+            1000. * result.build_time.as_secs_f64(),
+            sah_cost,
+            result.get_rays_per_second() / 1e6,
+            result.stats.get_node_tests_per_ray(),
+            result.stats.get_triangle_tests_per_ray(),
+        );
+    }
+}
+
+fn write_csv(path: &Path, results: &[BenchmarkResult]) -> Result<(), ErrorCode> {
+    let mut csv = String::from(
+        "model,nb_triangles,bvh_type,nb_nodes,build_time_ms,sah_cost,rays_per_second,node_tests_per_ray,triangle_tests_per_ray\n",
+    );
+    for result in results {
+        let sah_cost = result
+            .sah_cost
+            .map(|sah_cost| sah_cost.to_string())
+            .unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{:?},{},{},{},{},{},{}\n",
+            result.model,
+            result.nb_triangles,
+            result.bvh_type,
+            result.nb_nodes,
+            1000. * result.build_time.as_secs_f64(),
+            sah_cost,
+            result.get_rays_per_second(),
+            result.stats.get_node_tests_per_ray(),
+            result.stats.get_triangle_tests_per_ray(),
+        ));
+    }
+
+    let mut file = match File::create(path) {
+        Ok(file) => file,
+        Err(err) => {
+            error!("Failed to create the csv file `{:?}': {:?}", path, err);
+            return Err(ErrorCode::IO);
+        }
+    };
+    if let Err(err) = file.write_all(csv.as_bytes()) {
+        error!("Failed to write the csv file `{:?}': {:?}", path, err);
+        return Err(ErrorCode::IO);
+    }
+    Ok(())
+}
+
+fn run() -> Result<(), ErrorCode> {
+    let csv_path = match std::env::args().nth(1) {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(DEFAULT_CSV_PATH),
+    };
+
+    // A model or a bvh that fails is skipped so that the others are still compared
+    let mut results = Vec::new();
+    for file_name in AssetResolver::default().get_file_names(&["obj", "ply", "stl"]) {
+        let model = file_name.to_string_lossy().to_string();
+        let Ok(scene) = Scene::from_obj_file(&file_name) else {
+            error!("Failed to load `{}', it is skipped", model);
+            continue;
+        };
+        if scene.get_nb_primitives() == 0 {
+            warn!("`{}' has no primitive to trace, it is skipped", model);
+            continue;
+        }
+        let Ok(light_position) = get_light_position(&scene) else {
+            error!("Failed to place the light of `{}', it is skipped", model);
+            continue;
+        };
+        for bvh_type in BVH_TYPES {
+            eprintln!("Benchmarking the `{:?}' bvh on `{}'...", bvh_type, model);
+            match benchmark(&model, &scene, light_position, bvh_type) {
+                Ok(result) => results.push(result),
+                Err(err) => error!(
+                    "Failed to benchmark the `{:?}' bvh on `{}', it is skipped: {:?}",
+                    bvh_type, model, err
+                ),
+            }
+        }
+    }
+    if results.is_empty() {
+        error!("No model could be benchmarked");
+        return Err(ErrorCode::Unknown);
+    }
+
+    print_table(&results);
+    write_csv(&csv_path, &results)?;
+    println!("Results written to `{}'", csv_path.display());
+    Ok(())
+}
+
+fn main() {
+    env_logger::init();
+
+    if let Err(err) = run() {
+        panic!("Failed to run the bvh benchmark: {:?}", err);
+    }
+}
@@ -0,0 +1,84 @@
+//! Render the Cornell box on the CPU with one of the integrators of the compute shader
+//!
+//! cargo run --release --bin cpu_render [direct|ao|whitted|path|debug] [path to the output ppm] [width] [path to an environment map]
+
+use std::path::PathBuf;
+
+use glam::Vec3;
+use log::error;
+use rust_vulkan::application::{
+    core::error::ErrorCode,
+    raytracer::integrator::Integrator,
+    scene::{
+        assets::AssetResolver,
+        bvh::{build_job::BvhBuildProgress, BvhType},
+        camera::Camera,
+        Scene, SceneType,
+    },
+};
+
+const DEFAULT_OUTPUT_PATH: &str = "render.ppm";
+const DEFAULT_WIDTH: u32 = 800;
+
+fn run() -> Result<(), ErrorCode> {
+    let mut args = std::env::args().skip(1);
+    let integrator = match args.next() {
+        Some(name) => match Integrator::from_name(&name) {
+            Some(integrator) => integrator,
+            None => {
+                let names = Integrator::ALL.map(|integrator| integrator.get_name());
+                error!("Unknown integrator `{}', expected one of {:?}", name, names);
+                return Err(ErrorCode::InitializationFailure);
+            }
+        },
+        None => Integrator::default(),
+    };
+    let output_path = match args.next() {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(DEFAULT_OUTPUT_PATH),
+    };
+    let width = match args.next().map(|width| width.parse::<u32>()) {
+        Some(Ok(width)) => width,
+        Some(Err(err)) => {
+            error!("Failed to parse the width of the image: {:?}", err);
+            return Err(ErrorCode::InitializationFailure);
+        }
+        None => DEFAULT_WIDTH,
+    };
+    let environment_path = args.next().map(PathBuf::from);
+
+    let camera = Camera::new(Vec3::new(0., 0., -3.), 1., 50., 0.1, Vec3::Y);
+    let mut scene =
+        Scene::from_scene_type(SceneType::CornellBox, camera, &AssetResolver::default())?;
+    scene.integrator_settings.integrator = integrator;
+    if let Some(path) = environment_path {
+        scene.load_environment(&path)?;
+    }
+
+    let bvh_type = BvhType::PlocParallel;
+    let bvh = match scene.build_bvh(bvh_type, &BvhBuildProgress::default()) {
+        Ok(bvh) => bvh,
+        Err(err) => {
+            error!("Failed to build the `{:?}' bvh: {:?}", bvh_type, err);
+            return Err(ErrorCode::Unknown);
+        }
+    };
+    let _ = scene.bvhs.insert(bvh_type, bvh);
+    scene.bvh_last_type = bvh_type;
+
+    eprintln!(
+        "Rendering with the `{}' integrator...",
+        integrator.get_name()
+    );
+    scene.save_cpu_render(&output_path, width)?;
+    println!("Render written to `{}'", output_path.display());
+    Ok(())
+}
+
+fn main() {
+    env_logger::init();
+
+    if let Err(err) = run() {
+        panic!("Failed to render the scene: {:?}", err);
+    }
+}
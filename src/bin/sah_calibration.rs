@@ -0,0 +1,88 @@
+//! Fit the sah constants used by the bvh builders to the measured traversal timings
+//!
+//! cargo run --release --bin sah_calibration [path to the output constants]
+
+use std::path::PathBuf;
+
+use rust_vulkan::application::{
+    core::error::ErrorCode,
+    raytracer::calibration::{calibrate_sah_constants, CalibrationMeasure},
+    scene::{
+        assets::AssetResolver,
+        bvh::{
+            sah::{SahConstants, SAH_CONSTANTS_FILE},
+            BvhType,
+        },
+        Scene,
+    },
+};
+
+/// Number of primary rays along each side of the image
+const IMAGE_SIZE: u32 = 64;
+
+fn print_table(
+    model_names: &[String],
+    sah_constants: &SahConstants,
+    measures: &[CalibrationMeasure],
+) {
+    println!(
+        "{:<20} {:<16} {:>12} {:>12} {:>12} {:>12} {:>12}",
+        "model", "bvh", "build costs", "nodes/ray", "tris/ray", "ns/ray", "predicted"
+    );
+    for measure in measures {
+        // Only the top down sah builder uses the constants
+        let build_costs = match measure.bvh_type {
+            BvhType::TopDownSah => format!(
+                "{}/{}",
+                measure.build_sah_constants.cost_traverse_internal,
+                measure.build_sah_constants.cost_triangle_intersection
+            ),
+            _ => "-".to_string(),
+        };
+        println!(
+            "{:<20} {:<16} {:>12} {:>12.2} {:>12.2} {:>12.1} {:>12.1}",
+            model_names[measure.scene_index],
+            format!("{:?}", measure.bvh_type),
+            build_costs,
+            measure.sample.node_tests_per_ray,
+            measure.sample.triangle_tests_per_ray,
+            measure.sample.nanoseconds_per_ray,
+            sah_constants.predict(&measure.sample),
+        );
+    }
+}
+
+fn run() -> Result<(), ErrorCode> {
+    let output_path = match std::env::args().nth(1) {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(SAH_CONSTANTS_FILE),
+    };
+
+    let mut model_names = Vec::new();
+    let mut scenes = Vec::new();
+    for file_name in AssetResolver::default().get_file_names(&["obj", "ply", "stl"]) {
+        model_names.push(file_name.to_string_lossy().to_string());
+        scenes.push(Scene::from_obj_file(&file_name)?);
+    }
+
+    eprintln!("Measuring the traversal of {} models...", scenes.len());
+    let (sah_constants, measures) = calibrate_sah_constants(&mut scenes, IMAGE_SIZE)?;
+    print_table(&model_names, &sah_constants, &measures);
+
+    sah_constants.save(&output_path)?;
+    println!(
+        "Node traversal: {:.2} ns, triangle intersection: {:.2} ns, written to `{}'",
+        sah_constants.cost_traverse_internal,
+        sah_constants.cost_triangle_intersection,
+        output_path.display()
+    );
+    Ok(())
+}
+
+fn main() {
+    env_logger::init();
+
+    if let Err(err) = run() {
+        panic!("Failed to calibrate the sah constants: {:?}", err);
+    }
+}
@@ -1,9 +1,7 @@
 use std::fs::File;
 use std::io::Write;
 
-use application::Application;
-
-pub mod application;
+use rust_vulkan::application::Application;
 
 fn main() {
     // Create the output logging file